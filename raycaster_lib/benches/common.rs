@@ -10,6 +10,7 @@
 use std::{
     marker::PhantomData,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use criterion::BenchmarkId;
@@ -238,7 +239,7 @@ where
         };
         let mut full_path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")); // should be library root (!not workspace dir!)
         full_path.push(path);
-        from_file(full_path, parser_add_block_side, shapes_tf).unwrap()
+        from_file(full_path, parser_add_block_side, Arc::new(shapes_tf)).unwrap()
     }
 
     pub fn get_benchmark(self) -> impl FnOnce(&mut Criterion) {
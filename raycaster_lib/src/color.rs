@@ -19,3 +19,38 @@ pub fn zero() -> RGBA {
 pub fn mono(v: f32, opacity: f32) -> RGBA {
     vector![v, v, v, opacity]
 }
+
+/// Color from HSV components.
+/// Hue `h` in degrees, saturation `s`, value `v` and opacity `a` in range `<0;1>`.
+pub fn from_hsv(h: f32, s: f32, v: f32, a: f32) -> RGBA {
+    let chroma = v * s;
+    let sector = (h / 60.0).rem_euclid(6.0);
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+
+    let (r, g, b) = match sector as u8 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    let m = v - chroma;
+    vector![(r + m) * 255.0, (g + m) * 255.0, (b + m) * 255.0, a]
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn hsv() {
+        assert_eq!(from_hsv(0.0, 1.0, 1.0, 1.0), new(255.0, 0.0, 0.0, 1.0));
+        assert_eq!(from_hsv(120.0, 1.0, 1.0, 0.5), new(0.0, 255.0, 0.0, 0.5));
+        assert_eq!(from_hsv(240.0, 1.0, 0.5, 1.0), new(0.0, 0.0, 127.5, 1.0));
+        assert_eq!(from_hsv(300.0, 0.0, 1.0, 1.0), mono(255.0, 1.0));
+        assert_eq!(from_hsv(360.0, 1.0, 1.0, 1.0), from_hsv(0.0, 1.0, 1.0, 1.0));
+    }
+}
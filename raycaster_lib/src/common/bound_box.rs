@@ -4,14 +4,44 @@
     Date: 2022-05-05
 */
 
-use nalgebra::{point, Point3, Vector3};
+use nalgebra::{point, Matrix4, Point3, Vector3};
 
 use super::Ray;
 
+/// Affine transformation of oriented [`BoundBox`] from its frame into world space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Orientation {
+    world_from_frame: Matrix4<f32>,
+    frame_from_world: Matrix4<f32>,
+}
+
+impl Orientation {
+    /// Returns `None` if `world_from_frame` is not an invertible affine transformation
+    pub fn new(world_from_frame: Matrix4<f32>) -> Option<Orientation> {
+        let affine = world_from_frame.row(3) == Matrix4::identity().row(3);
+        let frame_from_world = world_from_frame.try_inverse().filter(|_| affine)?;
+        Some(Orientation {
+            world_from_frame,
+            frame_from_world,
+        })
+    }
+
+    pub fn world_from_frame(&self) -> &Matrix4<f32> {
+        &self.world_from_frame
+    }
+
+    pub fn frame_from_world(&self) -> &Matrix4<f32> {
+        &self.frame_from_world
+    }
+}
+
 /// Bounding box of object in world space.
 /// Defined by two points, `lower` and `upper`.
 /// Volume is defined as the space between these points.
-/// Box is axis-aligned.
+///
+/// Box is axis-aligned, unless it has an [`Orientation`].
+/// Points `lower` and `upper` are then in the frame of the box,
+/// which is placed into world space by the orientation.
 ///
 /// `BoundBox` implements [`IntoIterator`].
 /// This way, corner points can be itarated over.
@@ -29,12 +59,18 @@ pub struct BoundBox {
     pub lower: Point3<f32>,
     /// Highest point of volume
     pub upper: Point3<f32>,
+    /// Placement of box frame in world space, `None` for axis-aligned box
+    pub orientation: Option<Orientation>,
 }
 
 impl BoundBox {
     /// Construct new `BoundBox` from their defining points.
     pub fn new(lower: Point3<f32>, upper: Point3<f32>) -> BoundBox {
-        BoundBox { lower, upper }
+        BoundBox {
+            lower,
+            upper,
+            orientation: None,
+        }
     }
 
     /// Alternative construction method.
@@ -43,6 +79,7 @@ impl BoundBox {
         BoundBox {
             lower: position,
             upper: position + dimensions,
+            orientation: None,
         }
     }
 
@@ -52,16 +89,56 @@ impl BoundBox {
         BoundBox {
             lower: point![0.0, 0.0, 0.0],
             upper: point![0.0, 0.0, 0.0],
+            orientation: None,
         }
     }
 
-    /// Returns size of the volume in units
+    /// Same box, placed into world space by `orientation`
+    pub fn oriented(self, orientation: Option<Orientation>) -> BoundBox {
+        BoundBox {
+            orientation,
+            ..self
+        }
+    }
+
+    /// Returns size of the volume in units, in the frame of the box
     pub fn dims(&self) -> Vector3<f32> {
         self.upper - self.lower
     }
 
+    /// Transform point from world space into the frame of the box
+    pub fn to_frame(&self, pos: &Point3<f32>) -> Point3<f32> {
+        match &self.orientation {
+            Some(o) => o.frame_from_world.transform_point(pos),
+            None => *pos,
+        }
+    }
+
+    /// Transform direction from world space into the frame of the box.
+    /// Length of direction is not preserved for transformations with scaling.
+    pub fn direction_to_frame(&self, direction: &Vector3<f32>) -> Vector3<f32> {
+        match &self.orientation {
+            Some(o) => o.frame_from_world.transform_vector(direction),
+            None => *direction,
+        }
+    }
+
+    /// Transform point from the frame of the box into world space
+    pub fn to_world(&self, pos: &Point3<f32>) -> Point3<f32> {
+        match &self.orientation {
+            Some(o) => o.world_from_frame.transform_point(pos),
+            None => *pos,
+        }
+    }
+
+    /// Center of the box in world space
+    pub fn center(&self) -> Point3<f32> {
+        self.to_world(&(self.lower + 0.5 * self.dims()))
+    }
+
     /// Tests if `pos` is inside bounding box.
     pub fn is_in(&self, pos: &Point3<f32>) -> bool {
+        let pos = self.to_frame(pos);
         self.upper.x > pos.x
             && self.upper.y > pos.y
             && self.upper.z > pos.z
@@ -83,9 +160,13 @@ impl BoundBox {
         // Source: An Efficient and Robust Ray–Box Intersection Algorithm. Amy Williams et al. 2004.
         // http://citeseerx.ist.psu.edu/viewdoc/summary?doi=10.1.1.64.7663
 
+        // Ray in the frame of the box, affine transformation keeps t values of points
+        let origin = self.to_frame(&ray.origin);
+        let direction = self.direction_to_frame(&ray.direction);
+
         // t value of intersection with the 6 planes of a bounding box
-        let t0 = (self.lower - ray.origin).component_div(&ray.direction);
-        let t1 = (self.upper - ray.origin).component_div(&ray.direction);
+        let t0 = (self.lower - origin).component_div(&direction);
+        let t1 = (self.upper - origin).component_div(&direction);
 
         // [ (min,max) , (min,max) , (min,max) ]
         let t_minmax = t0.zip_map(&t1, |t0, t1| if t0 < t1 { (t0, t1) } else { (t1, t0) });
@@ -107,9 +188,9 @@ impl BoundBox {
 }
 
 /// Iteration structure, iterates over corners of a `BoundBox`.
+/// Corners are in world space.
 pub struct BoundBoxIterator {
-    lower: Point3<f32>,
-    upper: Point3<f32>,
+    bound_box: BoundBox,
     state: u8,
 }
 
@@ -117,19 +198,20 @@ impl Iterator for BoundBoxIterator {
     type Item = Point3<f32>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let BoundBox { lower, upper, .. } = self.bound_box;
         let p = match self.state {
-            0 => self.lower,
-            1 => point![self.upper.x, self.lower.y, self.lower.z],
-            2 => point![self.upper.x, self.upper.y, self.lower.z],
-            3 => point![self.lower.x, self.upper.y, self.lower.z],
-            4 => point![self.lower.x, self.lower.y, self.upper.z],
-            5 => point![self.upper.x, self.lower.y, self.upper.z],
-            6 => self.upper,
-            7 => point![self.lower.x, self.upper.y, self.upper.z],
+            0 => lower,
+            1 => point![upper.x, lower.y, lower.z],
+            2 => point![upper.x, upper.y, lower.z],
+            3 => point![lower.x, upper.y, lower.z],
+            4 => point![lower.x, lower.y, upper.z],
+            5 => point![upper.x, lower.y, upper.z],
+            6 => upper,
+            7 => point![lower.x, upper.y, upper.z],
             _ => return None,
         };
         self.state += 1;
-        Some(p)
+        Some(self.bound_box.to_world(&p))
     }
 }
 
@@ -140,8 +222,7 @@ impl IntoIterator for BoundBox {
 
     fn into_iter(self) -> Self::IntoIter {
         BoundBoxIterator {
-            lower: self.lower,
-            upper: self.upper,
+            bound_box: self,
             state: 0,
         }
     }
@@ -150,10 +231,19 @@ impl IntoIterator for BoundBox {
 #[cfg(test)]
 mod test {
 
-    use nalgebra::point;
+    use nalgebra::{point, vector, Rotation3};
 
     use super::*;
 
+    // Unit cube at origin, rotated by 90° around y and moved by 10 along x
+    fn rotated_cube() -> BoundBox {
+        let rotation = Rotation3::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2);
+        let world_from_frame =
+            Matrix4::new_translation(&vector![10.0, 0.0, 0.0]) * rotation.to_homogeneous();
+        BoundBox::new(point![0.0, 0.0, 0.0], point![1.0, 1.0, 1.0])
+            .oriented(Orientation::new(world_from_frame))
+    }
+
     #[test]
     fn bbox_iter() {
         let bbox = BoundBox::new(point![0.0, 0.0, 0.0], point![1.0, 1.0, 1.0]);
@@ -174,4 +264,27 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn oriented_box() {
+        let bbox = rotated_cube();
+        // Frame x axis points to world -z
+        let corner = bbox.into_iter().nth(1).unwrap();
+        assert!((corner - point![10.0, 0.0, -1.0]).norm() < 1e-5);
+        assert!((bbox.center() - point![10.5, 0.5, -0.5]).norm() < 1e-5);
+        assert!(bbox.is_in(&point![10.5, 0.5, -0.5]));
+        assert!(!bbox.is_in(&point![10.5, 0.5, 0.5]));
+
+        let ray = Ray::new(point![0.0, 0.5, -0.5], vector![1.0, 0.0, 0.0]);
+        let (t0, t1) = bbox.intersect(&ray).unwrap();
+        assert!((t0 - 10.0).abs() < 1e-5);
+        assert!((t1 - 11.0).abs() < 1e-5);
+
+        let miss = Ray::new(point![0.0, 0.5, 0.5], vector![1.0, 0.0, 0.0]);
+        assert!(bbox.intersect(&miss).is_none());
+
+        let mut singular = Matrix4::identity();
+        singular[(0, 0)] = 0.0;
+        assert!(Orientation::new(singular).is_none());
+    }
 }
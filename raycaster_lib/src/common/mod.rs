@@ -11,13 +11,13 @@ mod ray;
 mod value_range;
 mod viewport_box;
 
-pub use bound_box::{BoundBox, BoundBoxIterator};
+pub use bound_box::{BoundBox, BoundBoxIterator, Orientation};
 use nalgebra::{vector, Vector3};
 pub use ray::Ray;
 pub use value_range::ValueRange;
 pub use viewport_box::{PixelBox, ViewportBox};
 
-use crate::TF;
+use crate::TransferFunction;
 
 /// Divides volume into blocks.
 /// Rounds up.
@@ -44,7 +44,9 @@ pub fn blockify(size: Vector3<usize>, side: usize, overlap: usize) -> Vector3<us
 }
 
 /// Calculates ranges of samples yielding opaque colors, given `tf`.
-/// Assumes `tf` (transfer function) operates on values `<0.0;255.0>`.
+/// Ranges are provided by [`TransferFunction::visible_ranges`].
+/// Unless the transfer function knows its ranges exactly,
+/// it is baked into a lookup table spanning `domain` and ranges are read from the table.
 ///
 /// Returns vector of `ValueRange`.
 ///
@@ -62,36 +64,13 @@ pub fn blockify(size: Vector3<usize>, side: usize, overlap: usize) -> Vector3<us
 /// }
 /// };
 ///
-/// assert_eq!(
-/// tf_visible_range(tf),
-/// vec![
-///     (11.0..21.0).into(),
-///     (81.0..86.0).into()
-/// ]
-/// );
+/// let ranges = tf_visible_range(&tf, (0.0..255.0).into());
+/// assert_eq!(ranges.len(), 2);
+/// assert!(ranges[0].contains(10.5));
+/// assert!(!ranges[0].contains(10.0));
 /// ```
-pub fn tf_visible_range(tf: TF) -> Vec<ValueRange> {
-    let mut ranges = vec![];
-    let mut range: Option<ValueRange> = None;
-
-    for v in 0..=255 {
-        let v = v as f32;
-        let sample = tf(v);
-        if sample.w == 0.0 {
-            if let Some(mut r) = range.take() {
-                r.high = v;
-                ranges.push(r);
-            }
-        } else if range.is_none() {
-            range = Some(ValueRange::seed(v));
-        }
-    }
-
-    if let Some(mut range) = range {
-        range.high = 255.0;
-        ranges.push(range);
-    }
-    ranges
+pub fn tf_visible_range(tf: &dyn TransferFunction, domain: ValueRange) -> Vec<ValueRange> {
+    tf.visible_ranges(domain)
 }
 
 #[cfg(test)]
@@ -111,10 +90,13 @@ mod test {
             }
         };
 
-        let ranges = tf_visible_range(tf);
+        let ranges = tf_visible_range(&tf, (0.0..255.0).into());
 
         assert_eq!(ranges.len(), 1);
-        assert_eq!(ranges[0], (11.0..21.0).into());
+        assert!(!ranges[0].contains(10.0));
+        assert!(ranges[0].contains(10.5));
+        assert!(ranges[0].contains(20.5));
+        assert!(!ranges[0].contains(21.0));
     }
 
     #[test]
@@ -129,10 +111,14 @@ mod test {
             }
         };
 
-        assert_eq!(
-            tf_visible_range(tf),
-            vec![(11.0..21.0).into(), (81.0..86.0).into()]
-        );
+        let ranges = tf_visible_range(&tf, (0.0..255.0).into());
+
+        assert_eq!(ranges.len(), 2);
+        assert!(ranges[0].contains(10.5));
+        assert!(!ranges[0].contains(21.0));
+        assert!(!ranges[1].contains(80.0));
+        assert!(ranges[1].contains(85.5));
+        assert!(!ranges[1].contains(86.0));
     }
 
     #[test]
@@ -50,8 +50,10 @@ impl Ray {
             .append_nonuniform_scaling(&scale_inv);
 
         // Construct transformed vector
-        let origin = transform.transform_point(&obj_origin);
-        let direction = self.direction.component_mul(&scale_inv);
+        let origin = transform.transform_point(&bound_box.to_frame(&obj_origin));
+        let direction = bound_box
+            .direction_to_frame(&self.direction)
+            .component_mul(&scale_inv);
         Ray { origin, direction }
     }
 }
@@ -62,6 +62,15 @@ impl ValueRange {
         }
     }
 
+    /// Extend the range, so that it contains `other`.
+    pub fn union(&mut self, other: &ValueRange) {
+        if other.low.is_nan() || other.high.is_nan() {
+            return;
+        }
+        self.extend(other.low);
+        self.extend(other.high);
+    }
+
     /// Check if value is inside the range.
     pub fn contains(&self, val: f32) -> bool {
         self.low <= val && val <= self.high
@@ -139,6 +148,20 @@ mod test {
         assert_eq!(range.high, 2.0);
     }
 
+    #[test]
+    fn union() {
+        let mut range = ValueRange::empty();
+
+        range.union(&ValueRange::empty());
+        assert!(!range.contains(0.0));
+
+        range.union(&(10.0..20.0).into());
+        range.union(&ValueRange::empty());
+        range.union(&(300.0..4095.0).into());
+
+        assert_eq!(range, (10.0..4095.0).into());
+    }
+
     #[test]
     fn ranges_intersect() {
         let empty = ValueRange::empty();
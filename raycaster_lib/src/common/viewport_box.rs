@@ -44,6 +44,20 @@ impl ViewportBox {
         self.lower.y = f32::clamp(f32::min(self.lower.y, y), 0.0, 1.0);
     }
 
+    /// Expand range to include point \[x,y\], which may lie outside of the viewport.
+    pub fn extend(&mut self, x: f32, y: f32) {
+        self.upper.x = f32::max(self.upper.x, x);
+        self.upper.y = f32::max(self.upper.y, y);
+        self.lower.x = f32::min(self.lower.x, x);
+        self.lower.y = f32::min(self.lower.y, y);
+    }
+
+    /// Part of rectangle inside the viewport, bounds are clamped to range <0;1>.
+    pub fn clamped(&self) -> ViewportBox {
+        let clamp = |v: Vector2<f32>| v.map(|c| c.clamp(0.0, 1.0));
+        ViewportBox::from_points(clamp(self.lower), clamp(self.upper))
+    }
+
     /// Returns size of rectangle
     pub fn size(&self) -> Vector2<f32> {
         self.upper - self.lower
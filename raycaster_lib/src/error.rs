@@ -0,0 +1,128 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Error type of volume loading and building
+
+use std::{fmt, io, path::PathBuf};
+
+/// Error of loading, parsing or building a volume
+#[derive(Debug)]
+pub enum Error {
+    /// File could not be opened, read or written
+    Io { path: PathBuf, source: io::Error },
+    /// Malformed header, `offset` is in bytes from the start of the header
+    Parse {
+        format: &'static str,
+        offset: usize,
+        reason: &'static str,
+    },
+    /// Valid file, using a feature that is not supported
+    Unsupported(&'static str),
+    /// Type of samples in file differs from requested type
+    SampleType {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// Size, shape or layout of volume is inconsistent
+    Shape(&'static str),
+    /// Data ends before all samples, lengths are in bytes
+    Truncated { expected: usize, found: usize },
+    /// Data continues after all samples, lengths are in bytes
+    Oversized { expected: usize, found: usize },
+    /// Metadata needed to build the volume is missing
+    Missing(&'static str),
+    /// Value or parameter out of allowed range
+    Invalid(&'static str),
+    /// Format of file was not recognized
+    UnknownFormat,
+}
+
+impl Error {
+    pub fn io<P: Into<PathBuf>>(path: P, source: io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub fn parse(format: &'static str, offset: usize, reason: &'static str) -> Error {
+        Error::Parse {
+            format,
+            offset,
+            reason,
+        }
+    }
+
+    /// Check that `available` bytes hold `expected` bytes
+    pub fn check_length(expected: usize, available: usize) -> Result<(), Error> {
+        if available < expected {
+            Err(Error::Truncated {
+                expected,
+                found: available,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Parse {
+                format,
+                offset,
+                reason,
+            } => write!(f, "{format} header, offset {offset}: {reason}"),
+            Error::Unsupported(what) => write!(f, "Not supported: {what}"),
+            Error::SampleType { expected, found } => {
+                write!(f, "Samples are {found}, expected {expected}")
+            }
+            Error::Shape(reason) => write!(f, "Invalid shape: {reason}"),
+            Error::Truncated { expected, found } => {
+                write!(
+                    f,
+                    "Data truncated, expected {expected} bytes, found {found}"
+                )
+            }
+            Error::Oversized { expected, found } => {
+                write!(f, "Data too long, expected {expected} bytes, found {found}")
+            }
+            Error::Missing(what) => write!(f, "Missing {what}"),
+            Error::Invalid(reason) => write!(f, "{reason}"),
+            Error::UnknownFormat => write!(f, "Unknown volume format"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let e = Error::parse("NRRD", 12, "Invalid number");
+        assert_eq!(e.to_string(), "NRRD header, offset 12: Invalid number");
+
+        let e = Error::check_length(10, 4).unwrap_err();
+        assert_eq!(e.to_string(), "Data truncated, expected 10 bytes, found 4");
+        assert!(Error::check_length(4, 4).is_ok());
+
+        let e = Error::io("a.vol", io::Error::from(io::ErrorKind::NotFound));
+        assert!(std::error::Error::source(&e).is_some());
+    }
+}
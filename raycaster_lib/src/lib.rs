@@ -15,17 +15,16 @@
 
 pub mod color;
 pub mod common;
+mod error;
 mod perspective_camera;
 pub mod premade;
 pub mod render;
 pub mod test_helpers;
+pub mod tf;
 pub mod volumetric;
 
+pub use error::Error;
 pub use perspective_camera::PerspectiveCamera;
+pub use tf::{TransferFunction, TF};
 
-use color::RGBA;
-
-pub type TF = fn(f32) -> RGBA; // todo tf module, with trait
-
-pub type ParserFn =
-    fn(volumetric::DataSource<u8>) -> Result<volumetric::VolumeMetadata<u8>, &'static str>;
+pub type ParserFn = fn(volumetric::DataSource<u8>) -> Result<volumetric::VolumeMetadata<u8>, Error>;
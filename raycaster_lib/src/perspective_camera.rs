@@ -215,6 +215,12 @@ impl PerspectiveCamera {
     ///
     /// Resulting viewport box is the minimal orthogonal rectangular projection
     pub fn project_box(&self, bound_box: BoundBox) -> ViewportBox {
+        self.project_box_unclamped(bound_box).clamped()
+    }
+
+    /// Project bounding box of a volume to viewport, without clamping to the viewport.
+    /// Size of the result is the size of the box on screen, including the part outside of it.
+    pub fn project_box_unclamped(&self, bound_box: BoundBox) -> ViewportBox {
         // Source: https://github.com/ospray/ospray, Intel corp., Apache 2.0 license
         let mut viewbox = ViewportBox::new();
 
@@ -233,7 +239,7 @@ impl PerspectiveCamera {
                 let screen_dir = n * t - self.dir_00;
                 let x = screen_dir.dot(&dun);
                 let y = screen_dir.dot(&dvn);
-                viewbox.add_point(x, y);
+                viewbox.extend(x, y);
             }
         }
 
@@ -242,9 +248,7 @@ impl PerspectiveCamera {
 
     /// Get the distance from camera origin to the middle of a bound box
     pub fn box_distance(&self, bound_box: &BoundBox) -> f32 {
-        // Assuming blocks have the same size, lower corners can also be relatively compared
-        let center = bound_box.lower + 0.5 * (bound_box.upper - bound_box.lower);
-        (center - self.position).magnitude()
+        (bound_box.center() - self.position).magnitude()
     }
 
     /// Direction getter
@@ -261,7 +265,9 @@ impl PerspectiveCamera {
 #[cfg(test)]
 mod test {
 
-    use nalgebra::point;
+    use nalgebra::{point, Matrix4};
+
+    use crate::common::Orientation;
 
     use super::*;
 
@@ -335,6 +341,24 @@ mod test {
         compare_float(projection.upper.y, 0.0, 4.0);
     }
 
+    #[test]
+    fn project_outside() {
+        let cam_pos = point![-10.0, 0.0, 0.0];
+        let cam = PerspectiveCamera::new(cam_pos, vector![1.0, 0.0, 0.0]);
+
+        // Box reaching from the center over the right edge of viewport
+        let top = 10.0 * f32::sqrt(3.0) / 3.0;
+        let bbox = BoundBox::new(point![0.0, -1.0, 0.0], point![0.0, 1.0, 2.0 * top]);
+
+        let unclamped = cam.project_box_unclamped(bbox);
+        compare_float(unclamped.lower.x, 0.5, 4.0);
+        compare_float(unclamped.upper.x, 1.5, 4.0);
+
+        let clamped = cam.project_box(bbox);
+        compare_float(clamped.upper.x, 1.0, 4.0);
+        assert_eq!(clamped.lower, unclamped.lower);
+    }
+
     #[test]
     fn box_distance() {
         let cam_pos = point![-1.0, 0.5, 0.5];
@@ -351,5 +375,11 @@ mod test {
         dbg!(distance);
 
         assert!((distance - 1.5).abs() < f32::EPSILON);
+
+        // Same box, mirrored to the other side of camera
+        let mirrored = Orientation::new(Matrix4::new_nonuniform_scaling(&vector![-1.0, 1.0, 1.0]));
+        let distance = cam.box_distance(&origin_bbox.oriented(mirrored));
+
+        assert!((distance - 0.5).abs() < f32::EPSILON);
     }
 }
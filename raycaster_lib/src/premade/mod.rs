@@ -12,6 +12,7 @@
 use nalgebra::Vector2;
 
 pub mod parse;
+pub mod tf_file;
 pub mod transfer_functions;
 
 /// Example of a usecase - single-threaded renderer
@@ -27,6 +28,7 @@ pub fn render_frame(resolution: Vector2<u16>) -> Vec<u8> {
         PerspectiveCamera,
     };
     use nalgebra::point;
+    use std::sync::Arc;
 
     // Camera setup
     let position = point![300.0, 300.0, 300.0];
@@ -37,7 +39,7 @@ pub fn render_frame(resolution: Vector2<u16>) -> Vec<u8> {
     //
     // Choose file, parser and transfer function
     // note that the type of volume is inferred
-    let volume = from_file("volumes/Skull.vol", skull_parser, skull_tf).unwrap();
+    let volume = from_file("volumes/Skull.vol", skull_parser, Arc::new(skull_tf)).unwrap();
 
     // Render options - set resolution and optimisations
     let render_options = RenderOptions::builder()
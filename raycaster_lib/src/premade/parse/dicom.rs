@@ -0,0 +1,625 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Series of [DICOM](https://www.dicomstandard.org/) slices.
+//!
+//! Supported are uncompressed, single channel images with 8 or 16 bits per sample,
+//! in little endian transfer syntaxes (implicit or explicit VR).
+//! Slices are ordered by their position along the normal of the image plane.
+//!
+//! Oblique or tilted slices are placed by world transformation, see [`oblique_transform`].
+//! Order of axes is reversed, see [`reverse_axes`].
+
+use std::path::Path;
+
+use nalgebra::{vector, Matrix4, Vector3};
+use nom::{
+    bytes::complete::take,
+    error::ErrorKind,
+    number::complete::{le_u16, le_u32},
+    sequence::tuple,
+    IResult,
+};
+
+use crate::{
+    volumetric::{DataSource, Rescale, Sample, StorageShape, VolumeMetadata},
+    Error,
+};
+
+use super::{oblique_transform, read_samples, reverse_axes, Endian, SampleType};
+
+/// Name of the format in errors
+const FORMAT: &str = "DICOM";
+
+type Tag = (u16, u16);
+
+const TRANSFER_SYNTAX: Tag = (0x0002, 0x0010);
+const SLICE_THICKNESS: Tag = (0x0018, 0x0050);
+const INSTANCE_NUMBER: Tag = (0x0020, 0x0013);
+const IMAGE_POSITION: Tag = (0x0020, 0x0032);
+const IMAGE_ORIENTATION: Tag = (0x0020, 0x0037);
+const SAMPLES_PER_PIXEL: Tag = (0x0028, 0x0002);
+const ROWS: Tag = (0x0028, 0x0010);
+const COLUMNS: Tag = (0x0028, 0x0011);
+const PIXEL_SPACING: Tag = (0x0028, 0x0030);
+const BITS_ALLOCATED: Tag = (0x0028, 0x0100);
+const PIXEL_REPRESENTATION: Tag = (0x0028, 0x0103);
+const RESCALE_INTERCEPT: Tag = (0x0028, 0x1052);
+const RESCALE_SLOPE: Tag = (0x0028, 0x1053);
+const PIXEL_DATA: Tag = (0x7FE0, 0x0010);
+
+const ITEM_DELIMITER: Tag = (0xFFFE, 0xE00D);
+const SEQUENCE_DELIMITER: Tag = (0xFFFE, 0xE0DD);
+const UNDEFINED_LENGTH: u32 = 0xFFFF_FFFF;
+/// Sequences and items of undefined length nested deeper are rejected
+const MAX_NESTING: usize = 32;
+
+const IMPLICIT_LITTLE: &str = "1.2.840.10008.1.2";
+const EXPLICIT_LITTLE: &str = "1.2.840.10008.1.2.1";
+
+/// Value representations with 4 byte length in explicit VR
+const LONG_VRS: [&[u8]; 13] = [
+    b"OB", b"OD", b"OF", b"OL", b"OV", b"OW", b"SQ", b"SV", b"UC", b"UN", b"UR", b"UT", b"UV",
+];
+
+/// One image of a series
+#[derive(Debug, Clone, PartialEq)]
+pub struct DicomSlice {
+    pub rows: usize,
+    pub columns: usize,
+    /// Distance of rows and distance of columns
+    pub pixel_spacing: Option<[f32; 2]>,
+    /// Position of the first sample
+    pub position: Option<Vector3<f32>>,
+    /// Directions of row and of column
+    pub orientation: Option<[Vector3<f32>; 2]>,
+    pub slice_thickness: Option<f32>,
+    pub instance_number: Option<i32>,
+    pub sample_type: SampleType,
+    pub rescale: Rescale,
+    pub pixel_data: Vec<u8>,
+}
+
+/// Data element, value of undefined length includes the delimiter
+struct Element<'a> {
+    tag: Tag,
+    value: &'a [u8],
+}
+
+impl DicomSlice {
+    /// Parse DICOM file.
+    /// Returns `None` if `bytes` are not a DICOM file, or if the file has no image.
+    pub fn parse(bytes: &[u8]) -> Result<Option<DicomSlice>, Error> {
+        if bytes.len() < 132 || &bytes[128..132] != b"DICM" {
+            return Ok(None);
+        }
+
+        // File meta information is always explicit little endian
+        let mut rest = &bytes[132..];
+        let mut transfer_syntax = None;
+        while rest.len() >= 2 && rest[..2] == [0x02, 0x00] {
+            let offset = bytes.len() - rest.len();
+            let invalid = |reason| Error::parse(FORMAT, offset, reason);
+            let (r, e) = element(rest, true).map_err(|e| invalid(element_error(e)))?;
+            if e.tag == TRANSFER_SYNTAX {
+                transfer_syntax = Some(text(e.value).map_err(invalid)?);
+            }
+            rest = r;
+        }
+
+        let explicit = match transfer_syntax {
+            Some(IMPLICIT_LITTLE) => false,
+            Some(EXPLICIT_LITTLE) => true,
+            Some(_) => {
+                return Err(Error::Unsupported(
+                    "Compressed or big endian DICOM transfer syntax",
+                ))
+            }
+            None => return Err(Error::parse(FORMAT, 132, "Missing transfer syntax")),
+        };
+
+        let mut rows = None;
+        let mut columns = None;
+        let mut pixel_spacing = None;
+        let mut position = None;
+        let mut orientation = None;
+        let mut slice_thickness = None;
+        let mut instance_number = None;
+        let mut samples_per_pixel = 1;
+        let mut bits_allocated = None;
+        let mut pixel_representation = 0;
+        let mut slope = 1.0;
+        let mut intercept = 0.0;
+        let mut pixel_data = None;
+
+        while !rest.is_empty() {
+            let offset = bytes.len() - rest.len();
+            let invalid = |reason| Error::parse(FORMAT, offset, reason);
+            let (r, e) = element(rest, explicit).map_err(|e| invalid(element_error(e)))?;
+            rest = r;
+
+            match e.tag {
+                ROWS => rows = Some(unsigned(e.value).map_err(invalid)?),
+                COLUMNS => columns = Some(unsigned(e.value).map_err(invalid)?),
+                PIXEL_SPACING => {
+                    if let [row, column] = decimals(e.value).map_err(invalid)?[..] {
+                        pixel_spacing = Some([row, column]);
+                    }
+                }
+                IMAGE_POSITION => {
+                    if let [x, y, z] = decimals(e.value).map_err(invalid)?[..] {
+                        position = Some(vector![x, y, z]);
+                    }
+                }
+                IMAGE_ORIENTATION => {
+                    if let [rx, ry, rz, cx, cy, cz] = decimals(e.value).map_err(invalid)?[..] {
+                        orientation = Some([vector![rx, ry, rz], vector![cx, cy, cz]]);
+                    }
+                }
+                SLICE_THICKNESS => {
+                    slice_thickness = decimals(e.value).map_err(invalid)?.first().copied()
+                }
+                INSTANCE_NUMBER => instance_number = text(e.value).map_err(invalid)?.parse().ok(),
+                SAMPLES_PER_PIXEL => samples_per_pixel = unsigned(e.value).map_err(invalid)?,
+                BITS_ALLOCATED => bits_allocated = Some(unsigned(e.value).map_err(invalid)?),
+                PIXEL_REPRESENTATION => {
+                    pixel_representation = unsigned(e.value).map_err(invalid)?
+                }
+                RESCALE_SLOPE => {
+                    slope = decimals(e.value)
+                        .map_err(invalid)?
+                        .first()
+                        .copied()
+                        .unwrap_or(1.0)
+                }
+                RESCALE_INTERCEPT => {
+                    intercept = decimals(e.value)
+                        .map_err(invalid)?
+                        .first()
+                        .copied()
+                        .unwrap_or(0.0)
+                }
+                PIXEL_DATA => {
+                    pixel_data = Some(e.value);
+                    break;
+                }
+                _ => (), // Not needed for rendering
+            }
+        }
+
+        let pixel_data = match pixel_data {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        if samples_per_pixel != 1 {
+            return Err(Error::Unsupported("DICOM images with more channels"));
+        }
+        let sample_type = match (bits_allocated, pixel_representation) {
+            (Some(8), 0) => SampleType::U8,
+            (Some(16), 0) => SampleType::U16,
+            (Some(16), 1) => SampleType::I16,
+            _ => return Err(Error::Unsupported("DICOM pixel format")),
+        };
+
+        let rows = rows.ok_or(Error::Missing("DICOM rows"))?;
+        let columns = columns.ok_or(Error::Missing("DICOM columns"))?;
+        let length = rows * columns * sample_type.size();
+        Error::check_length(length, pixel_data.len())?;
+
+        Ok(Some(DicomSlice {
+            rows,
+            columns,
+            pixel_spacing,
+            position,
+            orientation,
+            slice_thickness,
+            instance_number,
+            sample_type,
+            rescale: Rescale::new(slope, intercept),
+            pixel_data: pixel_data[..length].to_vec(),
+        }))
+    }
+
+    /// Normal of the image plane
+    fn normal(&self) -> Vector3<f32> {
+        match self.orientation {
+            Some([row, column]) => row.cross(&column),
+            None => vector![0.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// Load series of slices from directory `dir`.
+/// Files that are not DICOM images are skipped.
+/// Samples are copied to RAM, transfer function is not set.
+pub fn dicom_series<T, P>(dir: P) -> Result<VolumeMetadata<T>, Error>
+where
+    T: Sample,
+    P: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    let mut paths = std::fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| Error::io(dir, e))?;
+    paths.sort();
+
+    let mut slices = vec![];
+    for path in paths.iter().filter(|p| p.is_file()) {
+        let bytes = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+        if let Some(slice) = DicomSlice::parse(&bytes)? {
+            slices.push(slice);
+        }
+    }
+
+    slices_metadata(slices)
+}
+
+/// Assemble volume from slices
+pub fn slices_metadata<T: Sample>(mut slices: Vec<DicomSlice>) -> Result<VolumeMetadata<T>, Error> {
+    let first = slices.first().ok_or(Error::Missing("DICOM images"))?;
+    first.sample_type.check::<T>()?;
+
+    let consistent = slices.iter().all(|s| {
+        s.rows == first.rows
+            && s.columns == first.columns
+            && s.sample_type == first.sample_type
+            && s.rescale == first.rescale
+    });
+    if !consistent {
+        return Err(Error::Shape(
+            "DICOM slices differ in size, pixel format or rescale",
+        ));
+    }
+
+    // Order slices by position along the normal, or by instance number
+    let normal = first.normal();
+    let key = |s: &DicomSlice| match s.position {
+        Some(p) => p.dot(&normal),
+        None => s.instance_number.unwrap_or(0) as f32,
+    };
+    slices.sort_by(|a, b| key(a).total_cmp(&key(b)));
+
+    let first = &slices[0];
+    let distance = match (first.position, slices.get(1).and_then(|s| s.position)) {
+        (Some(a), Some(b)) => {
+            let distance = (b - a).dot(&normal).abs();
+            if distance == 0.0 {
+                return Err(Error::Shape("Duplicate DICOM slice position"));
+            }
+            distance
+        }
+        _ => first.slice_thickness.unwrap_or(1.0),
+    };
+    let [row_spacing, column_spacing] = first.pixel_spacing.unwrap_or([1.0, 1.0]);
+
+    let size = vector![first.columns, first.rows, slices.len()];
+    let scale = vector![column_spacing, row_spacing, distance];
+    let position = first.position;
+    let rescale = first.rescale;
+
+    // Affine of voxel indexes, slices are offset by the second slice, which may be tilted
+    let world_transform = match (position, first.orientation) {
+        (Some(p), Some([row, column])) => {
+            let slice_step = match slices.get(1).and_then(|s| s.position) {
+                Some(next) => next - p,
+                None => first.normal() * distance,
+            };
+            let mut affine = Matrix4::identity();
+            affine
+                .fixed_slice_mut::<3, 1>(0, 0)
+                .copy_from(&(row * column_spacing));
+            affine
+                .fixed_slice_mut::<3, 1>(0, 1)
+                .copy_from(&(column * row_spacing));
+            affine.fixed_slice_mut::<3, 1>(0, 2).copy_from(&slice_step);
+            affine.fixed_slice_mut::<3, 1>(0, 3).copy_from(&p);
+            oblique_transform(affine)
+        }
+        _ => None,
+    };
+
+    let mut bytes = Vec::with_capacity(slices.len() * first.pixel_data.len());
+    for slice in &slices {
+        bytes.extend_from_slice(&slice.pixel_data);
+    }
+    let data = read_samples(DataSource::Vec(bytes), 0, size.product(), Endian::Little)?;
+
+    Ok(VolumeMetadata {
+        position: position.map(|p| reverse_axes(p).into()),
+        size: Some(reverse_axes(size)),
+        scale: Some(reverse_axes(scale)),
+        data: Some(data),
+        data_shape: Some(StorageShape::Linear),
+        rescale: (rescale != Rescale::default()).then_some(rescale),
+        world_transform,
+        ..Default::default()
+    })
+}
+
+fn element(s: &[u8], explicit: bool) -> IResult<&[u8], Element<'_>> {
+    nested_element(s, explicit, 0)
+}
+
+/// Reason of failed element parsing
+fn element_error(e: nom::Err<nom::error::Error<&[u8]>>) -> &'static str {
+    match e {
+        nom::Err::Failure(e) if e.code == ErrorKind::TooLarge => "Sequences nested too deep",
+        _ => "Invalid element",
+    }
+}
+
+/// Element inside `depth` sequences or items of undefined length
+fn nested_element(s: &[u8], explicit: bool, depth: usize) -> IResult<&[u8], Element<'_>> {
+    let (s, tag) = tuple((le_u16, le_u16))(s)?;
+
+    // Items and delimiters have no VR
+    let (s, length) = if !explicit || tag.0 == 0xFFFE {
+        le_u32(s)?
+    } else {
+        let (s, vr) = take(2usize)(s)?;
+        if LONG_VRS.contains(&vr) {
+            let (s, _reserved) = take(2usize)(s)?;
+            le_u32(s)?
+        } else {
+            let (s, length) = le_u16(s)?;
+            (s, length as u32)
+        }
+    };
+
+    if length != UNDEFINED_LENGTH {
+        let (s, value) = take(length)(s)?;
+        return Ok((s, Element { tag, value }));
+    }
+
+    // Sequence or item of undefined length, ends with a delimiter
+    if depth >= MAX_NESTING {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            s,
+            ErrorKind::TooLarge,
+        )));
+    }
+    let mut rest = s;
+    loop {
+        let (r, e) = nested_element(rest, explicit, depth + 1)?;
+        rest = r;
+        if e.tag == ITEM_DELIMITER || e.tag == SEQUENCE_DELIMITER {
+            break;
+        }
+    }
+    let value = &s[..s.len() - rest.len()];
+    Ok((rest, Element { tag, value }))
+}
+
+/// String value, without padding
+fn text(value: &[u8]) -> Result<&str, &'static str> {
+    std::str::from_utf8(value)
+        .map(|s| s.trim_matches(|c: char| c == '\0' || c == ' '))
+        .map_err(|_| "Invalid string")
+}
+
+/// Unsigned short value
+fn unsigned(value: &[u8]) -> Result<usize, &'static str> {
+    match le_u16::<_, ()>(value) {
+        Ok((_, v)) => Ok(v as usize),
+        Err(_) => Err("Invalid number"),
+    }
+}
+
+/// Decimal string values, separated by backslash
+fn decimals(value: &[u8]) -> Result<Vec<f32>, &'static str> {
+    let text = text(value)?;
+    if text.is_empty() {
+        return Ok(vec![]);
+    }
+    text.split('\\')
+        .map(|v| v.trim().parse().map_err(|_| "Invalid number"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use nalgebra::point;
+
+    fn explicit(tag: Tag, vr: &[u8; 2], value: &[u8]) -> Vec<u8> {
+        let mut e = [tag.0.to_le_bytes(), tag.1.to_le_bytes()].concat();
+        e.extend_from_slice(vr);
+        if LONG_VRS.contains(&&vr[..]) {
+            e.extend_from_slice(&[0, 0]);
+            e.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        } else {
+            e.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        }
+        e.extend_from_slice(value);
+        e
+    }
+
+    fn implicit(tag: Tag, value: &[u8]) -> Vec<u8> {
+        let mut e = [tag.0.to_le_bytes(), tag.1.to_le_bytes()].concat();
+        e.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        e.extend_from_slice(value);
+        e
+    }
+
+    fn file(transfer_syntax: &str, dataset: &[u8]) -> Vec<u8> {
+        let mut uid = transfer_syntax.as_bytes().to_vec();
+        if uid.len() % 2 == 1 {
+            uid.push(0);
+        }
+        let mut f = vec![0; 128];
+        f.extend_from_slice(b"DICM");
+        f.extend(explicit(TRANSFER_SYNTAX, b"UI", &uid));
+        f.extend_from_slice(dataset);
+        f
+    }
+
+    /// 2×2 slice of u16 samples, explicit VR
+    fn slice(z: f32, samples: [u16; 4]) -> Vec<u8> {
+        let position = format!("-10\\20\\{} ", z);
+        let pixels: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+
+        // Sequence of undefined length, with one item of undefined length
+        let mut sequence = [0x08u16.to_le_bytes(), 0x1140u16.to_le_bytes()].concat();
+        sequence.extend_from_slice(b"SQ\0\0");
+        sequence.extend_from_slice(&UNDEFINED_LENGTH.to_le_bytes());
+        sequence.extend(implicit((0xFFFE, 0xE000), &[]).iter().take(4));
+        sequence.extend_from_slice(&UNDEFINED_LENGTH.to_le_bytes());
+        sequence.extend(explicit((0x0008, 0x1150), b"UI", b"1.2\0"));
+        sequence.extend(implicit(ITEM_DELIMITER, &[]));
+        sequence.extend(implicit(SEQUENCE_DELIMITER, &[]));
+
+        let dataset = [
+            sequence,
+            explicit(IMAGE_POSITION, b"DS", position.as_bytes()),
+            explicit(IMAGE_ORIENTATION, b"DS", b"1\\0\\0\\0\\1\\0 "),
+            explicit(ROWS, b"US", &2u16.to_le_bytes()),
+            explicit(COLUMNS, b"US", &2u16.to_le_bytes()),
+            explicit(PIXEL_SPACING, b"DS", b"0.5\\0.25"),
+            explicit(BITS_ALLOCATED, b"US", &16u16.to_le_bytes()),
+            explicit(PIXEL_REPRESENTATION, b"US", &0u16.to_le_bytes()),
+            explicit(RESCALE_INTERCEPT, b"DS", b"-1024 "),
+            explicit(RESCALE_SLOPE, b"DS", b"1 "),
+            explicit(PIXEL_DATA, b"OW", &pixels),
+        ]
+        .concat();
+        file(EXPLICIT_LITTLE, &dataset)
+    }
+
+    #[test]
+    fn explicit_slice() {
+        let slice = DicomSlice::parse(&slice(2.5, [1, 2, 3, 4]))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!((slice.rows, slice.columns), (2, 2));
+        assert_eq!(slice.pixel_spacing, Some([0.5, 0.25]));
+        assert_eq!(slice.position, Some(vector![-10.0, 20.0, 2.5]));
+        assert_eq!(slice.normal(), vector![0.0, 0.0, 1.0]);
+        assert_eq!(slice.sample_type, SampleType::U16);
+        assert_eq!(slice.rescale, Rescale::new(1.0, -1024.0));
+        assert_eq!(slice.pixel_data, vec![1, 0, 2, 0, 3, 0, 4, 0]);
+    }
+
+    #[test]
+    fn implicit_slice() {
+        let dataset = [
+            implicit(ROWS, &1u16.to_le_bytes()),
+            implicit(COLUMNS, &3u16.to_le_bytes()),
+            implicit(BITS_ALLOCATED, &8u16.to_le_bytes()),
+            implicit(INSTANCE_NUMBER, b"7 "),
+            implicit(PIXEL_DATA, &[5, 6, 7, 0]),
+        ]
+        .concat();
+
+        let slice = DicomSlice::parse(&file(IMPLICIT_LITTLE, &dataset))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!((slice.rows, slice.columns), (1, 3));
+        assert_eq!(slice.instance_number, Some(7));
+        assert_eq!(slice.sample_type, SampleType::U8);
+        assert_eq!(slice.pixel_data, vec![5, 6, 7]);
+    }
+
+    #[test]
+    fn nested_sequences() {
+        let nested = |depth: usize| {
+            let mut dataset = vec![];
+            for _ in 0..depth {
+                dataset.extend(implicit((0x0008, 0x1140), &[]).iter().take(4));
+                dataset.extend_from_slice(&UNDEFINED_LENGTH.to_le_bytes());
+            }
+            for _ in 0..depth {
+                dataset.extend(implicit(SEQUENCE_DELIMITER, &[]));
+            }
+            dataset.extend(implicit(ROWS, &1u16.to_le_bytes()));
+            dataset.extend(implicit(COLUMNS, &1u16.to_le_bytes()));
+            dataset.extend(implicit(BITS_ALLOCATED, &8u16.to_le_bytes()));
+            dataset.extend(implicit(PIXEL_DATA, &[1, 0]));
+            file(IMPLICIT_LITTLE, &dataset)
+        };
+
+        assert!(DicomSlice::parse(&nested(MAX_NESTING)).unwrap().is_some());
+        assert!(matches!(
+            DicomSlice::parse(&nested(MAX_NESTING + 1)),
+            Err(Error::Parse {
+                reason: "Sequences nested too deep",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn not_images() {
+        // No pixel data
+        let dataset = implicit(ROWS, &1u16.to_le_bytes());
+        assert_eq!(
+            DicomSlice::parse(&file(IMPLICIT_LITTLE, &dataset)).unwrap(),
+            None
+        );
+
+        assert_eq!(DicomSlice::parse(b"readme").unwrap(), None);
+
+        // JPEG baseline
+        let jpeg = file("1.2.840.10008.1.2.4.50", &dataset);
+        assert!(DicomSlice::parse(&jpeg).is_err());
+    }
+
+    #[test]
+    fn series() {
+        let dir = std::env::temp_dir().join(format!("raycaster_dicom_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.dcm"), slice(5.0, [9, 10, 11, 12])).unwrap();
+        std::fs::write(dir.join("b.dcm"), slice(0.0, [1, 2, 3, 4])).unwrap();
+        std::fs::write(dir.join("c.dcm"), slice(2.5, [5, 6, 7, 8])).unwrap();
+        std::fs::write(dir.join("readme.txt"), "not a slice").unwrap();
+
+        let meta: Result<VolumeMetadata<u16>, _> = dicom_series(&dir);
+        let wrong_type: Result<VolumeMetadata<i16>, _> = dicom_series(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let meta = meta.unwrap();
+        // Axes are reversed
+        assert_eq!(meta.size, Some(vector![3, 2, 2]));
+        assert_eq!(meta.scale, Some(vector![2.5, 0.5, 0.25]));
+        assert_eq!(meta.position, Some(point![0.0, 20.0, -10.0]));
+        assert_eq!(meta.world_transform, None);
+        assert_eq!(meta.rescale, Some(Rescale::new(1.0, -1024.0)));
+        assert_eq!(
+            meta.data.unwrap().get_slice(),
+            &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]
+        );
+        assert!(wrong_type.is_err());
+    }
+
+    #[test]
+    fn tilted_series() {
+        // Every slice is moved along y by half of the slice distance
+        let slices = [0.0, 2.5]
+            .iter()
+            .map(|&z| {
+                let mut slice = DicomSlice::parse(&slice(z, [1, 2, 3, 4])).unwrap().unwrap();
+                slice.position = Some(vector![-10.0, 20.0 + z / 2.0, z]);
+                slice
+            })
+            .collect();
+
+        let meta: VolumeMetadata<u16> = slices_metadata(slices).unwrap();
+
+        // Axes are reversed, first voxel axis goes through slices
+        let transform = meta.world_transform.unwrap();
+        assert_eq!(transform.column(0), vector![2.5, 1.25, 0.0, 0.0]);
+        assert_eq!(transform.column(1), vector![0.0, 0.5, 0.0, 0.0]);
+        assert_eq!(transform.column(2), vector![0.0, 0.0, 0.25, 0.0]);
+        assert_eq!(transform.column(3), vector![0.0, 20.0, -10.0, 1.0]);
+    }
+}
@@ -0,0 +1,468 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Volumes from stacks of 2D grayscale images, one image per slice.
+//!
+//! Supported are binary PGM (`P5`) and uncompressed TIFF images with 8 or 16 bits per sample.
+//! Compressed formats (PNG, compressed TIFF) are not supported.
+//!
+//! Distance of samples in the image plane is 1, distance of slices is given by user.
+//! Order of axes is reversed, see [`reverse_axes`].
+
+use std::path::{Path, PathBuf};
+
+use nalgebra::vector;
+use nom::{
+    bytes::complete::take,
+    number::{
+        complete::{u16, u32},
+        Endianness,
+    },
+    sequence::tuple,
+    IResult,
+};
+
+use crate::{
+    volumetric::{DataSource, Sample, StorageShape, VolumeMetadata},
+    Error,
+};
+
+use super::{read_samples, reverse_axes, sample_count, Endian, SampleType};
+
+/// Extensions of files read by [`image_stack_dir`]
+const EXTENSIONS: [&str; 3] = ["pgm", "tif", "tiff"];
+
+/// Decoded grayscale image
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackImage {
+    pub width: usize,
+    pub height: usize,
+    /// `U8` or `U16`
+    pub sample_type: SampleType,
+    /// Samples, rows from top, 16-bit samples in little endian
+    pub samples: Vec<u8>,
+}
+
+impl StackImage {
+    /// Decode PGM or TIFF image, format is detected from content
+    pub fn decode(bytes: &[u8]) -> Result<StackImage, Error> {
+        match bytes {
+            [b'P', b'5', ..] => decode_pgm(bytes),
+            [b'I', b'I', 42, 0, ..] | [b'M', b'M', 0, 42, ..] => decode_tiff(bytes),
+            _ => Err(Error::UnknownFormat),
+        }
+    }
+}
+
+/// Load volume from images `paths`, in order of slices.
+/// All images must have the same dimensions and sample type.
+/// Samples are copied to RAM, transfer function is not set.
+///
+/// # Params
+/// * `paths` - images, the first one is the slice with the lowest z
+/// * `slice_spacing` - distance of slices, relative to distance of samples in image
+pub fn image_stack<T, P>(paths: &[P], slice_spacing: f32) -> Result<VolumeMetadata<T>, Error>
+where
+    T: Sample,
+    P: AsRef<Path>,
+{
+    let first = paths.first().ok_or(Error::Missing("images"))?;
+    let first = StackImage::decode(&read_file(first)?)?;
+    first.sample_type.check::<T>()?;
+    if !(slice_spacing > 0.0 && slice_spacing.is_finite()) {
+        return Err(Error::Invalid("Slice spacing must be positive"));
+    }
+
+    let mut bytes = Vec::with_capacity(paths.len() * first.samples.len());
+    bytes.extend_from_slice(&first.samples);
+    for path in &paths[1..] {
+        let image = StackImage::decode(&read_file(path)?)?;
+        if image.width != first.width
+            || image.height != first.height
+            || image.sample_type != first.sample_type
+        {
+            return Err(Error::Shape("Images have different dimensions"));
+        }
+        bytes.extend_from_slice(&image.samples);
+    }
+
+    let size = vector![first.width, first.height, paths.len()];
+    let data = read_samples(DataSource::Vec(bytes), 0, size.product(), Endian::Little)?;
+
+    Ok(VolumeMetadata {
+        size: Some(reverse_axes(size)),
+        scale: Some(reverse_axes(vector![1.0, 1.0, slice_spacing])),
+        data: Some(data),
+        data_shape: Some(StorageShape::Linear),
+        ..Default::default()
+    })
+}
+
+/// Load volume from images in directory `dir`, see [`image_stack`].
+/// Images (`.pgm`, `.tif`, `.tiff`) are ordered by file name, other files are skipped.
+pub fn image_stack_dir<T, P>(dir: P, slice_spacing: f32) -> Result<VolumeMetadata<T>, Error>
+where
+    T: Sample,
+    P: AsRef<Path>,
+{
+    image_stack(&stack_paths(dir.as_ref())?, slice_spacing)
+}
+
+/// Images in directory `dir`, ordered by file name
+pub(super) fn stack_paths(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<Result<_, _>>()
+        })
+        .map_err(|e| Error::io(dir, e))?;
+    paths.retain(|p| {
+        p.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| EXTENSIONS.contains(&e.to_lowercase().as_str()))
+    });
+    paths.sort();
+    Ok(paths)
+}
+
+pub(super) fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, Error> {
+    let path = path.as_ref();
+    std::fs::read(path).map_err(|e| Error::io(path, e))
+}
+
+/// Binary PGM, header is `P5 width height maxval`, 16-bit samples are big endian
+fn decode_pgm(bytes: &[u8]) -> Result<StackImage, Error> {
+    let mut pos = 2;
+    let mut fields = [0; 3];
+    for field in &mut fields {
+        // Skip whitespace and comments
+        loop {
+            match bytes.get(pos) {
+                Some(b'#') => {
+                    while bytes.get(pos).is_some_and(|&b| b != b'\n') {
+                        pos += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => pos += 1,
+                Some(_) => break,
+                None => return Err(Error::parse("PGM", pos, "Missing field")),
+            }
+        }
+        let start = pos;
+        while bytes.get(pos).is_some_and(u8::is_ascii_digit) {
+            pos += 1;
+        }
+        *field = std::str::from_utf8(&bytes[start..pos])
+            .unwrap()
+            .parse()
+            .map_err(|_| Error::parse("PGM", start, "Invalid number"))?;
+    }
+    // Single whitespace before samples
+    if !bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+        return Err(Error::parse("PGM", pos, "Missing whitespace"));
+    }
+    pos += 1;
+
+    let [width, height, max_value] = fields;
+    let sample_type = match max_value {
+        1..=255 => SampleType::U8,
+        256..=65535 => SampleType::U16,
+        _ => return Err(Error::Unsupported("PGM maximal value")),
+    };
+
+    let length = image_length(width, height, sample_type)?;
+    let end = pos
+        .checked_add(length)
+        .ok_or(Error::Shape("Size of image overflows"))?;
+    Error::check_length(end, bytes.len())?;
+    let samples = bytes[pos..end].to_vec();
+    let samples = match sample_type {
+        SampleType::U16 => swap_pairs(samples),
+        _ => samples,
+    };
+
+    Ok(StackImage {
+        width,
+        height,
+        sample_type,
+        samples,
+    })
+}
+
+/// First image of uncompressed TIFF file
+fn decode_tiff(bytes: &[u8]) -> Result<StackImage, Error> {
+    let endian = match bytes[0] {
+        b'I' => Endianness::Little,
+        _ => Endianness::Big,
+    };
+    let tags = match tiff_tags(bytes, endian) {
+        Ok((_, tags)) => tags,
+        Err(_) => return Err(Error::parse("TIFF", 4, "Invalid image directory")),
+    };
+    let tag = |id: u16| tags.iter().find(|t| t.0 == id).map(|t| &t.1);
+    let single = |id: u16| tag(id).and_then(|values| values.first().copied());
+
+    let width = single(256).ok_or(Error::Missing("TIFF width"))? as usize;
+    let height = single(257).ok_or(Error::Missing("TIFF height"))? as usize;
+    if single(259).unwrap_or(1) != 1 {
+        return Err(Error::Unsupported("Compressed TIFF"));
+    }
+    if single(277).unwrap_or(1) != 1 || single(339).unwrap_or(1) != 1 {
+        return Err(Error::Unsupported("TIFF other than unsigned grayscale"));
+    }
+    let sample_type = match single(258).unwrap_or(1) {
+        8 => SampleType::U8,
+        16 => SampleType::U16,
+        _ => return Err(Error::Unsupported("TIFF bits per sample")),
+    };
+    let white_is_zero = match single(262) {
+        Some(0) => true,
+        Some(1) => false,
+        _ => return Err(Error::Unsupported("TIFF other than grayscale")),
+    };
+
+    let offsets = tag(273).ok_or(Error::Missing("TIFF strips"))?;
+    let counts = tag(279).ok_or(Error::Missing("TIFF strips"))?;
+    let length = image_length(width, height, sample_type)?;
+    let strips = offsets
+        .iter()
+        .zip(counts)
+        .map(|(&offset, &count)| {
+            let start = offset as usize;
+            let end = start
+                .checked_add(count as usize)
+                .ok_or(Error::Shape("Size of TIFF strip overflows"))?;
+            Error::check_length(end, bytes.len())?;
+            Ok(&bytes[start..end])
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    // Strips are in the file, their length bounds the allocation
+    let stored = strips
+        .iter()
+        .map(|s| s.len())
+        .fold(0, usize::saturating_add);
+    Error::check_length(length, stored)?;
+
+    let mut samples = Vec::with_capacity(length);
+    for strip in strips {
+        let missing = length - samples.len();
+        samples.extend_from_slice(&strip[..strip.len().min(missing)]);
+    }
+
+    let mut samples = match (sample_type, endian) {
+        (SampleType::U16, Endianness::Big) => swap_pairs(samples),
+        _ => samples,
+    };
+    if white_is_zero {
+        for byte in &mut samples {
+            *byte = !*byte;
+        }
+    }
+
+    Ok(StackImage {
+        width,
+        height,
+        sample_type,
+        samples,
+    })
+}
+
+/// Tags of the first image directory, with values of SHORT or LONG type
+fn tiff_tags(bytes: &[u8], e: Endianness) -> IResult<&[u8], Vec<(u16, Vec<u32>)>> {
+    let (_, ifd_offset) = u32(e)(&bytes[4..])?;
+    let ifd = bytes.get(ifd_offset as usize..).unwrap_or_default();
+    let (mut rest, entries) = u16(e)(ifd)?;
+
+    let mut tags = Vec::with_capacity(entries as usize);
+    for _ in 0..entries {
+        let (r, (id, field_type, count)) = tuple((u16(e), u16(e), u32(e)))(rest)?;
+        let (r, value_field) = take(4usize)(r)?;
+        rest = r;
+
+        let size = match field_type {
+            3 => 2,
+            4 => 4,
+            _ => continue, // Not needed for reading samples
+        };
+        // Values fit into the entry, or are stored at offset
+        let values = if count <= 4 / size {
+            value_field
+        } else {
+            let (_, offset) = u32(e)(value_field)?;
+            bytes.get(offset as usize..).unwrap_or_default()
+        };
+        let (_, values) = parse_values(values, field_type, count, e)?;
+        tags.push((id, values));
+    }
+    Ok((rest, tags))
+}
+
+fn parse_values(s: &[u8], field_type: u16, count: u32, e: Endianness) -> IResult<&[u8], Vec<u32>> {
+    nom::multi::count(
+        |s| match field_type {
+            3 => u16(e)(s).map(|(s, v)| (s, v as u32)),
+            _ => u32(e)(s),
+        },
+        count as usize,
+    )(s)
+}
+
+/// Length of image samples in bytes
+fn image_length(width: usize, height: usize, sample_type: SampleType) -> Result<usize, Error> {
+    sample_count(vector![width, height, 1], 1)?
+        .checked_mul(sample_type.size())
+        .ok_or(Error::Shape("Size of image overflows"))
+}
+
+/// Swap bytes of 16-bit samples
+fn swap_pairs(mut samples: Vec<u8>) -> Vec<u8> {
+    for pair in samples.chunks_exact_mut(2) {
+        pair.swap(0, 1);
+    }
+    samples
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn pgm(width: usize, height: usize, max_value: u16, samples: &[u8]) -> Vec<u8> {
+        let mut bytes =
+            format!("P5\n# comment\n{} {}\n{}\n", width, height, max_value).into_bytes();
+        bytes.extend_from_slice(samples);
+        bytes
+    }
+
+    /// Single strip TIFF, entries are `(tag, type, value)`
+    fn tiff(big_endian: bool, entries: &[(u16, u16, u32)], samples: &[u8]) -> Vec<u8> {
+        let u16_bytes = |v: u16| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        let u32_bytes = |v: u32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+
+        let mut bytes = if big_endian {
+            b"MM\0\x2a".to_vec()
+        } else {
+            b"II\x2a\0".to_vec()
+        };
+        bytes.extend(u32_bytes(8));
+
+        let strip_offset = 8 + 2 + 12 * (entries.len() as u32 + 2) + 4;
+        let strip = [(273, 4, strip_offset), (279, 4, samples.len() as u32)];
+        bytes.extend(u16_bytes(entries.len() as u16 + 2));
+        for &(id, field_type, value) in entries.iter().chain(&strip) {
+            bytes.extend(u16_bytes(id));
+            bytes.extend(u16_bytes(field_type));
+            bytes.extend(u32_bytes(1));
+            if field_type == 3 {
+                bytes.extend(u16_bytes(value as u16));
+                bytes.extend([0, 0]);
+            } else {
+                bytes.extend(u32_bytes(value));
+            }
+        }
+        bytes.extend(u32_bytes(0)); // no next directory
+        bytes.extend_from_slice(samples);
+        bytes
+    }
+
+    #[test]
+    fn decode_pgm() {
+        let image = StackImage::decode(&pgm(3, 1, 255, &[1, 2, 3])).unwrap();
+        assert_eq!((image.width, image.height), (3, 1));
+        assert_eq!(image.sample_type, SampleType::U8);
+        assert_eq!(image.samples, vec![1, 2, 3]);
+
+        // 16-bit samples are big endian
+        let image = StackImage::decode(&pgm(1, 1, 4095, &[1, 2])).unwrap();
+        assert_eq!(image.sample_type, SampleType::U16);
+        assert_eq!(image.samples, vec![2, 1]);
+
+        assert!(StackImage::decode(&pgm(3, 1, 255, &[1, 2])).is_err());
+    }
+
+    #[test]
+    fn pgm_size_overflow() {
+        let header = b"P5 4294967296 4294967297 255\n";
+        assert!(matches!(StackImage::decode(header), Err(Error::Shape(_))));
+    }
+
+    #[test]
+    fn decode_tiff() {
+        let entries = [(256, 3, 2), (257, 4, 1), (258, 3, 16), (262, 3, 1)];
+        let image = StackImage::decode(&tiff(true, &entries, &[0, 1, 2, 0])).unwrap();
+
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.sample_type, SampleType::U16);
+        assert_eq!(image.samples, vec![1, 0, 0, 2]);
+
+        // White is zero
+        let entries = [(256, 3, 2), (257, 3, 1), (258, 3, 8), (262, 3, 0)];
+        let image = StackImage::decode(&tiff(false, &entries, &[0, 200])).unwrap();
+        assert_eq!(image.samples, vec![255, 55]);
+
+        // LZW
+        let entries = [
+            (256, 3, 2),
+            (257, 3, 1),
+            (258, 3, 8),
+            (259, 3, 5),
+            (262, 3, 1),
+        ];
+        assert!(StackImage::decode(&tiff(false, &entries, &[0, 200])).is_err());
+    }
+
+    #[test]
+    fn tiff_size() {
+        let decode = |width: u32, height: u32| {
+            let entries = [(256, 4, width), (257, 4, height), (258, 3, 16), (262, 3, 1)];
+            StackImage::decode(&tiff(false, &entries, &[0; 4]))
+        };
+
+        assert!(matches!(decode(u32::MAX, u32::MAX), Err(Error::Shape(_))));
+        // Samples are not allocated before checking the strips
+        assert!(matches!(
+            decode(1 << 20, 1 << 20),
+            Err(Error::Truncated { .. })
+        ));
+        assert_eq!(decode(2, 1).unwrap().samples.len(), 4);
+    }
+
+    #[test]
+    fn stack() {
+        let dir = std::env::temp_dir().join(format!("raycaster_stack_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let entries = [(256, 3, 2), (257, 3, 1), (258, 3, 8), (262, 3, 1)];
+        std::fs::write(dir.join("slice_0.pgm"), pgm(2, 1, 255, &[1, 2])).unwrap();
+        std::fs::write(dir.join("slice_1.tif"), tiff(false, &entries, &[3, 4])).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not an image").unwrap();
+
+        let meta: Result<VolumeMetadata<u8>, _> = image_stack_dir(&dir, 2.5);
+
+        std::fs::write(dir.join("slice_2.pgm"), pgm(1, 2, 255, &[5, 6])).unwrap();
+        let mismatched: Result<VolumeMetadata<u8>, _> = image_stack_dir(&dir, 2.5);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let meta = meta.unwrap();
+        // Axes are reversed
+        assert_eq!(meta.size, Some(vector![2, 1, 2]));
+        assert_eq!(meta.scale, Some(vector![2.5, 1.0, 1.0]));
+        assert_eq!(meta.data.unwrap().get_slice(), &[1, 2, 3, 4]);
+        assert!(mismatched.is_err());
+    }
+}
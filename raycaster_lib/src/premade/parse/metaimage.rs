@@ -0,0 +1,383 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! [MetaImage](https://itk.org/Wiki/ITK/MetaIO/Documentation) files.
+//!
+//! Supported are uncompressed 3D images, with header and samples in one file (`.mha`),
+//! or with separate files (`.mhd` and `.raw`).
+//! Images with more elements per voxel (`ElementNumberOfChannels`) are read as multi-channel volumes.
+//!
+//! Orientation (`TransformMatrix`) is not supported.
+//! Order of axes is reversed, see [`reverse_axes`].
+
+use std::path::Path;
+
+use nalgebra::{vector, Point3, Vector3};
+
+use crate::{
+    volumetric::{ChannelLayout, DataSource, Sample, StorageShape, VolumeMetadata},
+    Error,
+};
+
+use super::{read_samples, reverse_axes, sample_count, Endian, SampleType};
+
+/// Name of the format in errors
+const FORMAT: &str = "MetaImage";
+
+/// Fields of MetaImage header, needed to read a volume
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetaImageHeader {
+    pub sample_type: SampleType,
+    pub size: Vector3<usize>,
+    pub channels: usize,
+    pub spacing: Option<Vector3<f32>>,
+    pub offset: Option<Point3<f32>>,
+    pub endian: Endian,
+    /// File with samples, `None` if samples follow the header (`LOCAL`)
+    pub data_file: Option<String>,
+    /// Bytes skipped before samples, `None` if samples are at the end of file (`HeaderSize = -1`)
+    pub header_size: Option<usize>,
+    /// Length of header in bytes
+    pub header_length: usize,
+}
+
+impl MetaImageHeader {
+    /// Parse header at the beginning of `bytes`
+    pub fn parse(bytes: &[u8]) -> Result<MetaImageHeader, Error> {
+        let mut ndims = None;
+        let mut size = None;
+        let mut sample_type = None;
+        let mut channels = 1;
+        let mut spacing = None;
+        let mut element_size = None;
+        let mut offset = None;
+        let mut endian = Endian::Little;
+        let mut data_file = None;
+        let mut header_size = Some(0);
+        let mut header_length = None;
+
+        let mut line_start = 0;
+        while header_length.is_none() {
+            if line_start >= bytes.len() {
+                return Err(Error::parse(FORMAT, line_start, "Missing ElementDataFile"));
+            }
+            let line_offset = line_start;
+            let invalid = |reason| Error::parse(FORMAT, line_offset, reason);
+            let line_end = bytes[line_start..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(bytes.len(), |end| line_start + end + 1);
+            let line = std::str::from_utf8(&bytes[line_start..line_end])
+                .map_err(|_| invalid("Header is not text"))?
+                .trim();
+            line_start = line_end;
+
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid("Invalid header line"))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            match key {
+                "ObjectType" if value != "Image" => {
+                    return Err(Error::Unsupported("MetaImage object other than image"))
+                }
+                "NDims" => ndims = Some(parse_number::<usize>(value).map_err(invalid)?),
+                "DimSize" => size = Some(parse_vector::<usize>(value).map_err(invalid)?),
+                "ElementType" => sample_type = Some(parse_type(value)?),
+                "ElementNumberOfChannels" => channels = parse_number(value).map_err(invalid)?,
+                "ElementSpacing" => spacing = Some(parse_vector(value).map_err(invalid)?),
+                "ElementSize" => element_size = Some(parse_vector(value).map_err(invalid)?),
+                "Offset" | "Origin" | "Position" => {
+                    offset = Some(parse_vector(value).map_err(invalid)?.into())
+                }
+                "BinaryDataByteOrderMSB" | "ElementByteOrderMSB" => {
+                    endian = match value {
+                        "True" | "true" => Endian::Big,
+                        "False" | "false" => Endian::Little,
+                        _ => return Err(invalid("Invalid byte order")),
+                    }
+                }
+                "CompressedData" if value == "True" || value == "true" => {
+                    return Err(Error::Unsupported("Compressed MetaImage data"))
+                }
+                "HeaderSize" => {
+                    header_size = match value {
+                        "-1" => None,
+                        _ => Some(parse_number(value).map_err(invalid)?),
+                    }
+                }
+                // Last field of header
+                "ElementDataFile" => {
+                    if value == "LIST" || value.contains('%') {
+                        return Err(Error::Unsupported("MetaImage list of data files"));
+                    }
+                    if value != "LOCAL" {
+                        data_file = Some(value.to_string());
+                    }
+                    header_length = Some(line_start);
+                }
+                _ => (), // Not needed for rendering
+            }
+        }
+
+        let end = line_start;
+        if ndims != Some(3) {
+            return Err(Error::Unsupported("MetaImage dimension other than 3"));
+        }
+        if channels == 0 {
+            return Err(Error::parse(FORMAT, end, "Invalid ElementNumberOfChannels"));
+        }
+
+        Ok(MetaImageHeader {
+            sample_type: sample_type
+                .ok_or_else(|| Error::parse(FORMAT, end, "Missing ElementType"))?,
+            size: size.ok_or_else(|| Error::parse(FORMAT, end, "Missing DimSize"))?,
+            channels,
+            spacing: spacing.or(element_size),
+            offset,
+            endian,
+            data_file,
+            header_size,
+            header_length: header_length.unwrap_or_default(),
+        })
+    }
+
+    /// Build metadata, samples are read from `data_source`.
+    ///
+    /// # Params
+    /// * `data_source` - file with samples
+    /// * `start` - start of samples in `data_source`, before `HeaderSize` is applied
+    pub fn metadata<T: Sample>(
+        &self,
+        data_source: DataSource<u8>,
+        start: usize,
+    ) -> Result<VolumeMetadata<T>, Error> {
+        self.sample_type.check::<T>()?;
+
+        let count = sample_count(self.size, self.channels)?;
+        let overflow = Error::Shape("Size of volume overflows");
+        let offset = match self.header_size {
+            Some(skip) => start.checked_add(skip).ok_or(overflow)?,
+            None => {
+                let length = count.checked_mul(self.sample_type.size()).ok_or(overflow)?;
+                let available = data_source.get_slice().len();
+                Error::check_length(length, available)?;
+                available - length
+            }
+        };
+        let data = read_samples(data_source, offset, count, self.endian)?;
+
+        let (channels, channel_layout) = if self.channels > 1 {
+            (Some(self.channels), Some(ChannelLayout::Interleaved))
+        } else {
+            (None, None)
+        };
+
+        Ok(VolumeMetadata {
+            position: self.offset.map(|p| reverse_axes(p.coords).into()),
+            size: Some(reverse_axes(self.size)),
+            scale: Some(reverse_axes(
+                self.spacing.unwrap_or_else(|| vector![1.0, 1.0, 1.0]),
+            )),
+            data: Some(data),
+            data_shape: Some(StorageShape::Linear),
+            channels,
+            channel_layout,
+            ..Default::default()
+        })
+    }
+}
+
+/// MetaImage file with samples following the header (`ElementDataFile = LOCAL`).
+/// Transfer function is not set.
+///
+/// Use [`metaimage_file`] for separate header and data files.
+pub fn metaimage_parser<T: Sample>(
+    data_source: DataSource<u8>,
+) -> Result<VolumeMetadata<T>, Error> {
+    let header = MetaImageHeader::parse(data_source.get_slice())?;
+    if header.data_file.is_some() {
+        return Err(Error::Unsupported(
+            "Separate MetaImage data file, use metaimage_file",
+        ));
+    }
+    header.metadata(data_source, header.header_length)
+}
+
+/// MetaImage file, with samples in the same or in a separate file.
+/// Path of data file is relative to the header.
+/// Transfer function is not set.
+pub fn metaimage_file<T, P>(path: P) -> Result<VolumeMetadata<T>, Error>
+where
+    T: Sample,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let data_source = DataSource::from_file(path)?;
+    let header = MetaImageHeader::parse(data_source.get_slice())?;
+
+    match &header.data_file {
+        None => header.metadata(data_source, header.header_length),
+        Some(file) => {
+            let data_path = path.parent().unwrap_or_else(|| Path::new("")).join(file);
+            header.metadata(DataSource::from_file(data_path)?, 0)
+        }
+    }
+}
+
+fn parse_type(value: &str) -> Result<SampleType, Error> {
+    let sample_type = match value {
+        "MET_UCHAR" => SampleType::U8,
+        "MET_USHORT" => SampleType::U16,
+        "MET_SHORT" => SampleType::I16,
+        "MET_FLOAT" => SampleType::F32,
+        _ => return Err(Error::Unsupported("MetaImage ElementType")),
+    };
+    Ok(sample_type)
+}
+
+fn parse_number<N: std::str::FromStr>(value: &str) -> Result<N, &'static str> {
+    value.parse().map_err(|_| "Invalid number")
+}
+
+/// Three whitespace separated numbers
+fn parse_vector<N>(value: &str) -> Result<Vector3<N>, &'static str>
+where
+    N: std::str::FromStr + nalgebra::Scalar,
+{
+    let numbers = value
+        .split_whitespace()
+        .map(parse_number)
+        .collect::<Result<Vec<N>, _>>()?;
+    match <[N; 3]>::try_from(numbers) {
+        Ok(v) => Ok(v.into()),
+        Err(_) => Err("Expected three numbers"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::test_helpers::header_file;
+    use nalgebra::point;
+
+    #[test]
+    fn local() {
+        let header = "ObjectType = Image\n\
+            NDims = 3\n\
+            BinaryData = True\n\
+            BinaryDataByteOrderMSB = False\n\
+            Offset = -5 0 12.5\n\
+            ElementSpacing = 0.5 0.5 1.25\n\
+            DimSize = 2 2 1\n\
+            ElementType = MET_USHORT\n\
+            ElementDataFile = LOCAL\n";
+        let data = [1, 0, 2, 0, 3, 0, 0, 1];
+
+        let meta: VolumeMetadata<u16> = metaimage_parser(header_file(header, &data)).unwrap();
+
+        // Axes are reversed
+        assert_eq!(meta.size, Some(vector![1, 2, 2]));
+        assert_eq!(meta.scale, Some(vector![1.25, 0.5, 0.5]));
+        assert_eq!(meta.position, Some(point![12.5, 0.0, -5.0]));
+        assert_eq!(meta.data.unwrap().get_slice(), &[1, 2, 3, 256]);
+    }
+
+    #[test]
+    fn big_endian() {
+        let header = "NDims = 3\nDimSize = 1 1 2\nElementType = MET_SHORT\n\
+            ElementByteOrderMSB = True\nElementDataFile = LOCAL\r\n";
+
+        let meta: VolumeMetadata<i16> =
+            metaimage_parser(header_file(header, &[0xff, 0xfe, 0, 7])).unwrap();
+
+        assert_eq!(meta.scale, Some(vector![1.0, 1.0, 1.0]));
+        assert_eq!(meta.data.unwrap().get_slice(), &[-2, 7]);
+    }
+
+    #[test]
+    fn channels() {
+        let header = "NDims = 3\nDimSize = 2 1 1\nElementNumberOfChannels = 2\n\
+            ElementType = MET_UCHAR\nElementDataFile = LOCAL\n";
+
+        let meta: VolumeMetadata<u8> =
+            metaimage_parser(header_file(header, &[1, 2, 3, 4])).unwrap();
+
+        assert_eq!(meta.channels, Some(2));
+        assert_eq!(meta.channel_layout, Some(ChannelLayout::Interleaved));
+        assert_eq!(meta.data.unwrap().get_slice(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn separate_file() {
+        let dir = std::env::temp_dir().join(format!("raycaster_mhd_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("volume.mhd"),
+            "ObjectType = Image\nNDims = 3\nDimSize = 1 2 1\nElementSize = 2 2 2\n\
+            ElementType = MET_FLOAT\nHeaderSize = -1\nElementDataFile = volume.raw\n",
+        )
+        .unwrap();
+        let mut raw = b"skip".to_vec();
+        raw.extend(0.25f32.to_le_bytes());
+        raw.extend(100.0f32.to_le_bytes());
+        std::fs::write(dir.join("volume.raw"), raw).unwrap();
+
+        let meta: Result<VolumeMetadata<f32>, _> = metaimage_file(dir.join("volume.mhd"));
+        let local: Result<VolumeMetadata<f32>, _> =
+            metaimage_parser(DataSource::from_file(dir.join("volume.mhd")).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let meta = meta.unwrap();
+        assert_eq!(meta.scale, Some(vector![2.0, 2.0, 2.0]));
+        assert_eq!(meta.data.unwrap().get_slice(), &[0.25, 100.0]);
+        assert!(local.is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        let parse = |header: &str| metaimage_parser::<u8>(header_file(header, &[0; 8])).is_ok();
+
+        assert!(parse(
+            "NDims = 3\nDimSize = 2 2 2\nElementType = MET_UCHAR\nElementDataFile = LOCAL\n"
+        ));
+        assert!(!parse(
+            "NDims = 2\nDimSize = 2 2\nElementType = MET_UCHAR\nElementDataFile = LOCAL\n"
+        ));
+        assert!(!parse(
+            "NDims = 3\nDimSize = 2 2 3\nElementType = MET_UCHAR\nElementDataFile = LOCAL\n"
+        ));
+        assert!(!parse(
+            "NDims = 3\nDimSize = 2 2 2\nElementType = MET_DOUBLE\nElementDataFile = LOCAL\n"
+        ));
+        assert!(!parse(
+            "NDims = 3\nDimSize = 2 2 2\nElementType = MET_USHORT\nElementDataFile = LOCAL\n"
+        ));
+        assert!(!parse("NDims = 3\nDimSize = 2 2 2\nCompressedData = True\nElementType = MET_UCHAR\nElementDataFile = LOCAL\n"));
+        assert!(!parse(
+            "NDims = 3\nDimSize = 2 2 2\nElementType = MET_UCHAR\n"
+        ));
+    }
+    #[test]
+    fn size_overflow() {
+        let big = "NDims = 3\nDimSize = 4294967295 4294967295 4294967295\n\
+            ElementType = MET_FLOAT\nElementDataFile = LOCAL\n";
+        // Number of samples fits, their length in bytes does not
+        let length = "NDims = 3\nDimSize = 4294967295 4294967295 1\n\
+            ElementType = MET_FLOAT\nHeaderSize = -1\nElementDataFile = LOCAL\n";
+
+        for header in [big, length] {
+            assert!(matches!(
+                metaimage_parser::<f32>(header_file(header, &[0; 8])),
+                Err(Error::Shape(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,341 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+use std::{
+    any::TypeId,
+    mem::{align_of, size_of},
+    path::Path,
+    sync::Arc,
+};
+
+use nalgebra::{vector, Matrix3, Matrix4, Scalar, Vector3};
+use nom::{
+    bytes::complete::take,
+    number::complete::{be_f32, be_u32, le_u16},
+    sequence::tuple,
+    IResult,
+};
+
+use crate::{
+    volumetric::{BuildVolume, DataSource, Sample, StorageShape, Volume, VolumeMetadata},
+    Error, TF,
+};
+
+use super::transfer_functions::{beetle_tf, skull_tf};
+
+mod dicom;
+mod image_stack;
+mod metaimage;
+mod nifti;
+mod nrrd;
+mod registry;
+mod vol;
+mod vtk;
+
+pub use dicom::{dicom_series, slices_metadata, DicomSlice};
+pub use image_stack::{image_stack, image_stack_dir, StackImage};
+pub use metaimage::{metaimage_file, metaimage_parser, MetaImageHeader};
+pub use nifti::{nifti_file, nifti_parser, NiftiHeader};
+pub use nrrd::{nrrd_file, nrrd_parser, NrrdHeader};
+pub use registry::{
+    detect_format, formats, load_volume, register_format, register_parser, FileParserFn, Format,
+    FormatParser, LoadedVolume, SniffFn, SNIFF_LENGTH,
+};
+pub use vol::{
+    vol_parser, VolChecksum, VolHeader, VOL_FLAGS_OFFSET, VOL_FLAG_CHECKSUM, VOL_HEADER_LEN,
+    VOL_MAGIC, VOL_SPARSE_UNIFORM, VOL_V1_HEADER_LEN, VOL_VERSION,
+};
+pub use vtk::{vtk_parser, write_vtk, VtkHeader};
+
+/// Byte order of multi-byte samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    /// Byte order of the target platform
+    pub fn native() -> Endian {
+        if cfg!(target_endian = "little") {
+            Endian::Little
+        } else {
+            Endian::Big
+        }
+    }
+}
+
+/// Type of samples stored in a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleType {
+    U8,
+    U16,
+    I16,
+    F32,
+}
+
+impl SampleType {
+    /// Size of one sample in bytes
+    pub fn size(self) -> usize {
+        match self {
+            SampleType::U8 => 1,
+            SampleType::U16 | SampleType::I16 => 2,
+            SampleType::F32 => 4,
+        }
+    }
+
+    /// Name of the sample type
+    pub fn name(self) -> &'static str {
+        match self {
+            SampleType::U8 => "u8",
+            SampleType::U16 => "u16",
+            SampleType::I16 => "i16",
+            SampleType::F32 => "f32",
+        }
+    }
+
+    /// Error if samples are not of type `T`
+    pub fn check<T: Sample>(self) -> Result<(), Error> {
+        if self.matches::<T>() {
+            Ok(())
+        } else {
+            Err(Error::SampleType {
+                expected: std::any::type_name::<T>(),
+                found: self.name(),
+            })
+        }
+    }
+
+    /// Check if samples are of type `T`
+    pub fn matches<T: Sample>(self) -> bool {
+        let id = match self {
+            SampleType::U8 => TypeId::of::<u8>(),
+            SampleType::U16 => TypeId::of::<u16>(),
+            SampleType::I16 => TypeId::of::<i16>(),
+            SampleType::F32 => TypeId::of::<f32>(),
+        };
+        id == TypeId::of::<T>()
+    }
+}
+
+/// Reverse order of axes.
+///
+/// Volumes store samples with the z axis changing fastest,
+/// most file formats with the x axis changing fastest.
+/// Axes of such files are reversed, so that samples can be used without reordering.
+pub fn reverse_axes<N: Scalar>(v: Vector3<N>) -> Vector3<N> {
+    vector![v.z.clone(), v.y.clone(), v.x.clone()]
+}
+
+/// World transformation of volume with reversed axes, from `affine` of voxel indexes in file order.
+/// Returns `None` if `affine` only scales and moves the volume, position and scale place it then.
+pub fn oblique_transform(affine: Matrix4<f32>) -> Option<Matrix4<f32>> {
+    let linear = affine.fixed_slice::<3, 3>(0, 0);
+    let axis_aligned = (0..3).all(|i| {
+        (0..3).all(|j| {
+            if i == j {
+                linear[(i, j)] > 0.0
+            } else {
+                linear[(i, j)] == 0.0
+            }
+        })
+    });
+    if axis_aligned {
+        return None;
+    }
+    // Both voxel and world axes are reversed
+    let reverse = Matrix3::new(0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0).to_homogeneous();
+    Some(reverse * affine * reverse)
+}
+
+/// Read `count` samples, starting at byte `offset` of `data_source`.
+/// Data after the samples is left out.
+///
+/// Memory map is kept if samples are aligned and in native byte order,
+/// otherwise samples are copied.
+pub fn read_samples<T: Sample>(
+    data_source: DataSource<u8>,
+    offset: usize,
+    count: usize,
+    endian: Endian,
+) -> Result<DataSource<T>, Error> {
+    let size = size_of::<T>();
+    let bytes = data_source.get_slice();
+    let end = count
+        .checked_mul(size)
+        .and_then(|len| len.checked_add(offset))
+        .ok_or(Error::Shape("Size of volume overflows"))?;
+    Error::check_length(end, bytes.len())?;
+
+    let aligned = (bytes.as_ptr() as usize + offset).is_multiple_of(align_of::<T>());
+    if endian == Endian::native() && aligned {
+        let mut samples = data_source.clone_with_offset(offset).into_transmute();
+        samples.truncate(count);
+        return Ok(samples);
+    }
+
+    let mut copy = bytes[offset..end].to_vec();
+    if endian != Endian::native() {
+        for sample in copy.chunks_exact_mut(size) {
+            sample.reverse();
+        }
+    }
+    Ok(DataSource::Vec(copy).into_transmute())
+}
+
+/// Number of samples of volume with `channels` samples per voxel, error on overflow
+fn sample_count(size: Vector3<usize>, channels: usize) -> Result<usize, Error> {
+    size.iter()
+        .try_fold(channels, |count, &s| count.checked_mul(s))
+        .ok_or(Error::Shape("Size of volume overflows"))
+}
+
+// Common pattern
+pub fn from_file<P, T, M, PF>(path: P, parser: PF, tf: TF) -> Result<T, Error>
+where
+    P: AsRef<Path>,
+    T: BuildVolume<M> + Volume,
+    PF: FnOnce(DataSource<u8>) -> Result<VolumeMetadata<M>, Error>,
+{
+    let ds: DataSource<u8> = DataSource::from_file(path)?;
+    let mut metadata = parser(ds)?;
+    metadata.set_tf(tf);
+    BuildVolume::build(metadata)
+}
+
+pub fn from_data_source<T, M>(
+    ds: DataSource<u8>,
+    parser: fn(&[u8]) -> Result<VolumeMetadata<M>, Error>,
+    tf: TF,
+) -> Result<T, Error>
+where
+    T: BuildVolume<M> + Volume,
+{
+    let slice = ds.get_slice();
+    let mut metadata = parser(slice)?;
+    metadata.set_tf(tf);
+    BuildVolume::<M>::build(metadata)
+}
+
+// Little endian 2 byte values
+// Values <0;4095>
+pub fn beetle_parser(data_source: DataSource<u8>) -> Result<VolumeMetadata<u16>, Error> {
+    // Scope to drop DataSource
+    let size = {
+        let mut beetle_header = tuple((le_u16, le_u16, le_u16));
+        let slice = data_source.get_slice();
+        let parse_res: IResult<_, _> = beetle_header(slice);
+
+        let (_rest, size) = match parse_res {
+            Ok(r) => r,
+            Err(_) => return Err(Error::parse("Beetle", 0, "Header too short")),
+        };
+        size
+    };
+
+    let data_pure_samples = data_source.clone_with_offset(6);
+    let new_data_src = data_pure_samples.into_transmute();
+
+    let size = vector![size.0 as usize, size.1 as usize, size.2 as usize];
+
+    let meta = VolumeMetadata {
+        size: Some(size),
+        scale: Some(vector![1.0, 1.0, 1.0]),
+        data: Some(new_data_src),
+        data_shape: Some(StorageShape::Linear),
+        tf: Some(Arc::new(beetle_tf)),
+        ..Default::default()
+    };
+
+    Ok(meta)
+}
+
+/// Headerless file of `size` samples of type `T`, in native byte order.
+/// Transfer function is not set.
+pub fn raw_parser<T: Sample>(
+    data_source: DataSource<u8>,
+    size: Vector3<usize>,
+) -> Result<VolumeMetadata<T>, Error> {
+    let length = sample_count(size, 1)?
+        .checked_mul(size_of::<T>())
+        .ok_or(Error::Shape("Size of volume overflows"))?;
+    Error::check_length(length, data_source.get_slice().len())?;
+
+    let meta = VolumeMetadata {
+        size: Some(size),
+        scale: Some(vector![1.0, 1.0, 1.0]),
+        data: Some(data_source.into_transmute()),
+        data_shape: Some(StorageShape::Linear),
+        ..Default::default()
+    };
+
+    Ok(meta)
+}
+
+pub struct ExtractedMetaSkull {
+    offset: usize,
+    size: Vector3<usize>,
+    scale: Vector3<f32>,
+}
+
+pub fn skull_parser(data_source: DataSource<u8>) -> Result<VolumeMetadata<u8>, Error> {
+    let slice = data_source.get_slice();
+
+    let parse_res = skull_inner(slice);
+
+    let ExtractedMetaSkull {
+        offset,
+        size,
+        scale,
+    } = match parse_res {
+        Ok(r) => r.1,
+        Err(_) => return Err(Error::parse("Skull", 0, "Header too short")),
+    };
+
+    let cut_data = data_source.clone_with_offset(offset);
+
+    Ok(VolumeMetadata {
+        size: Some(size),
+        scale: Some(scale),
+        data: Some(cut_data),
+        data_shape: Some(StorageShape::Linear),
+        tf: Some(Arc::new(skull_tf)),
+        ..Default::default()
+    })
+}
+
+fn skull_inner(s: &[u8]) -> IResult<&[u8], ExtractedMetaSkull> {
+    let mut skull_header = tuple((
+        tuple((be_u32, be_u32, be_u32)),
+        take(4_u8),
+        tuple((be_f32, be_f32, be_f32)),
+    ));
+
+    let (s, (size, _, scale)) = skull_header(s)?;
+
+    let size = vector![size.0 as usize, size.1 as usize, size.2 as usize];
+    let scale = vector![scale.0, scale.1, scale.2];
+
+    // 4B * 7 = 28B
+    let offset = 28;
+
+    Ok((
+        s,
+        ExtractedMetaSkull {
+            offset,
+            size,
+            scale,
+        },
+    ))
+}
+
+/// File generated by `vol_gen`, version 1 or 2, see [`vol_parser`].
+/// Samples must be `u8`, transfer function defaults to `skull_tf`.
+pub fn generator_parser(data_source: DataSource<u8>) -> Result<VolumeMetadata<u8>, Error> {
+    let mut meta = vol_parser(data_source)?;
+    meta.set_tf(Arc::new(skull_tf));
+    Ok(meta)
+}
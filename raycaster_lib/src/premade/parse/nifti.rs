@@ -0,0 +1,445 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! [NIfTI-1](https://nifti.nimh.nih.gov/nifti-1) files.
+//!
+//! Supported are 3D volumes in a single file (`.nii`) or in a header/image pair (`.hdr` and `.img`).
+//! 5D volumes with a single time point are read as multi-channel volumes (channels stored one after another).
+//!
+//! Position and scale are taken from the sform or qform affine.
+//! Rotated or sheared affine is kept as world transformation, see [`oblique_transform`].
+//! Order of axes is reversed, see [`reverse_axes`].
+
+use std::path::Path;
+
+use nalgebra::{matrix, point, vector, Matrix4, Vector3};
+use nom::{
+    multi::count,
+    number::{
+        complete::{f32, i16},
+        Endianness,
+    },
+    sequence::tuple,
+    IResult,
+};
+
+use crate::{
+    volumetric::{ChannelLayout, DataSource, Rescale, Sample, StorageShape, VolumeMetadata},
+    Error,
+};
+
+use super::{oblique_transform, read_samples, reverse_axes, Endian, SampleType};
+
+/// Name of the format in errors
+const FORMAT: &str = "NIfTI";
+
+/// Size of header in bytes
+const HEADER_SIZE: usize = 348;
+
+/// Fields of NIfTI-1 header, needed to read a volume
+#[derive(Debug, Clone, PartialEq)]
+pub struct NiftiHeader {
+    pub endian: Endian,
+    /// `dim[0]` is the number of dimensions
+    pub dim: [i16; 8],
+    pub datatype: i16,
+    /// `pixdim[0]` is the `qfac` of qform
+    pub pixdim: [f32; 8],
+    pub vox_offset: f32,
+    pub scl_slope: f32,
+    pub scl_inter: f32,
+    pub qform_code: i16,
+    pub sform_code: i16,
+    /// Quaternion parameters `b`, `c`, `d`
+    pub quatern: [f32; 3],
+    pub qoffset: [f32; 3],
+    /// Rows of sform affine
+    pub srow: [[f32; 4]; 3],
+    /// Header is followed by samples, `n+1` magic
+    pub single_file: bool,
+}
+
+impl NiftiHeader {
+    /// Parse header at the beginning of `bytes`
+    pub fn parse(bytes: &[u8]) -> Result<NiftiHeader, Error> {
+        Error::check_length(HEADER_SIZE, bytes.len())?;
+
+        // `sizeof_hdr` is 348, byte order of the file is detected from it
+        let endian = if bytes[..4] == 348i32.to_le_bytes() {
+            Endian::Little
+        } else if bytes[..4] == 348i32.to_be_bytes() {
+            Endian::Big
+        } else {
+            return Err(Error::parse(FORMAT, 0, "Invalid sizeof_hdr"));
+        };
+
+        let single_file = match &bytes[344..348] {
+            b"n+1\0" => true,
+            b"ni1\0" => false,
+            _ => return Err(Error::parse(FORMAT, 344, "Missing magic")),
+        };
+
+        match nifti_inner(bytes, endian, single_file) {
+            Ok((_, header)) => Ok(header),
+            Err(_) => Err(Error::parse(FORMAT, 0, "Invalid header")),
+        }
+    }
+
+    /// Type of samples, from `datatype`
+    pub fn sample_type(&self) -> Result<SampleType, Error> {
+        let sample_type = match self.datatype {
+            2 => SampleType::U8,
+            4 => SampleType::I16,
+            16 => SampleType::F32,
+            512 => SampleType::U16,
+            _ => return Err(Error::Unsupported("NIfTI datatype")),
+        };
+        Ok(sample_type)
+    }
+
+    /// Size of volume and number of channels
+    pub fn size(&self) -> Result<(Vector3<usize>, usize), Error> {
+        let dim = self.dim.map(|d| d.max(1) as usize);
+        let channels = match self.dim[0] {
+            3 => 1,
+            4 if dim[4] == 1 => 1,
+            5 if dim[4] == 1 => dim[5],
+            _ => return Err(Error::Unsupported("NIfTI dimension other than 3")),
+        };
+        if self.dim[1..4].iter().any(|&d| d < 1) {
+            return Err(Error::parse(FORMAT, 40, "Invalid dim"));
+        }
+        Ok((vector![dim[1], dim[2], dim[3]], channels))
+    }
+
+    /// Affine from voxel indexes to world coordinates.
+    /// sform is preferred over qform, `None` if neither is set.
+    pub fn affine(&self) -> Option<Matrix4<f32>> {
+        if self.sform_code > 0 {
+            let [x, y, z] = self.srow;
+            return Some(matrix![
+                x[0], x[1], x[2], x[3];
+                y[0], y[1], y[2], y[3];
+                z[0], z[1], z[2], z[3];
+                0.0, 0.0, 0.0, 1.0
+            ]);
+        }
+
+        if self.qform_code > 0 {
+            let [b, c, d] = self.quatern;
+            let a = (1.0 - (b * b + c * c + d * d)).max(0.0).sqrt();
+            let qfac = if self.pixdim[0] < 0.0 { -1.0 } else { 1.0 };
+            let [dx, dy, dz] = [self.pixdim[1], self.pixdim[2], self.pixdim[3] * qfac];
+            let [ox, oy, oz] = self.qoffset;
+            return Some(matrix![
+                (a * a + b * b - c * c - d * d) * dx, 2.0 * (b * c - a * d) * dy, 2.0 * (b * d + a * c) * dz, ox;
+                2.0 * (b * c + a * d) * dx, (a * a + c * c - b * b - d * d) * dy, 2.0 * (c * d - a * b) * dz, oy;
+                2.0 * (b * d - a * c) * dx, 2.0 * (c * d + a * b) * dy, (a * a + d * d - b * b - c * c) * dz, oz;
+                0.0, 0.0, 0.0, 1.0
+            ]);
+        }
+
+        None
+    }
+
+    /// Linear scaling of samples, `None` if samples are not scaled
+    pub fn rescale(&self) -> Option<Rescale> {
+        let rescale = Rescale::new(self.scl_slope, self.scl_inter);
+        let scaled =
+            self.scl_slope != 0.0 && self.scl_slope.is_finite() && self.scl_inter.is_finite();
+        if scaled && rescale != Rescale::default() {
+            Some(rescale)
+        } else {
+            None
+        }
+    }
+
+    /// Build metadata, samples are read from `data_source`, starting at `vox_offset`.
+    pub fn metadata<T: Sample>(
+        &self,
+        data_source: DataSource<u8>,
+    ) -> Result<VolumeMetadata<T>, Error> {
+        self.sample_type()?.check::<T>()?;
+
+        let (size, channels) = self.size()?;
+        let offset = if self.vox_offset >= 0.0 {
+            self.vox_offset as usize
+        } else {
+            return Err(Error::parse(FORMAT, 108, "Invalid vox_offset"));
+        };
+        let data = read_samples(data_source, offset, size.product() * channels, self.endian)?;
+
+        let (position, scale) = match self.affine() {
+            Some(affine) => {
+                let columns = affine.fixed_slice::<3, 3>(0, 0);
+                let scale = vector![
+                    columns.column(0).norm(),
+                    columns.column(1).norm(),
+                    columns.column(2).norm()
+                ];
+                let position = point![affine[(0, 3)], affine[(1, 3)], affine[(2, 3)]];
+                (Some(position), scale)
+            }
+            None => (
+                None,
+                vector![self.pixdim[1], self.pixdim[2], self.pixdim[3]].map(f32::abs),
+            ),
+        };
+        // Unknown spacing
+        let scale = scale.map(|s| if s > 0.0 && s.is_finite() { s } else { 1.0 });
+
+        let (channels, channel_layout) = if channels > 1 {
+            (Some(channels), Some(ChannelLayout::Planar))
+        } else {
+            (None, None)
+        };
+
+        Ok(VolumeMetadata {
+            position: position.map(|p| reverse_axes(p.coords).into()),
+            size: Some(reverse_axes(size)),
+            scale: Some(reverse_axes(scale)),
+            data: Some(data),
+            data_shape: Some(StorageShape::Linear),
+            rescale: self.rescale(),
+            channels,
+            channel_layout,
+            world_transform: self.affine().and_then(oblique_transform),
+            ..Default::default()
+        })
+    }
+}
+
+/// Single file NIfTI-1 volume (`.nii`).
+/// Transfer function is not set.
+///
+/// Use [`nifti_file`] for header/image pairs.
+pub fn nifti_parser<T: Sample>(data_source: DataSource<u8>) -> Result<VolumeMetadata<T>, Error> {
+    let header = NiftiHeader::parse(data_source.get_slice())?;
+    if !header.single_file {
+        return Err(Error::Unsupported(
+            "NIfTI header without image, use nifti_file",
+        ));
+    }
+    header.metadata(data_source)
+}
+
+/// NIfTI-1 volume, single file (`.nii`) or header/image pair (`.hdr` and `.img`).
+/// For pairs, `path` leads to the header, image is expected next to it.
+/// Transfer function is not set.
+pub fn nifti_file<T, P>(path: P) -> Result<VolumeMetadata<T>, Error>
+where
+    T: Sample,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let data_source = DataSource::from_file(path)?;
+    let header = NiftiHeader::parse(data_source.get_slice())?;
+
+    if header.single_file {
+        header.metadata(data_source)
+    } else {
+        header.metadata(DataSource::from_file(path.with_extension("img"))?)
+    }
+}
+
+fn nifti_inner(s: &[u8], endian: Endian, single_file: bool) -> IResult<&[u8], NiftiHeader> {
+    let e = match endian {
+        Endian::Little => Endianness::Little,
+        Endian::Big => Endianness::Big,
+    };
+    let (_, dim) = count(i16(e), 8)(&s[40..])?;
+    let (_, datatype) = i16(e)(&s[70..])?;
+    let (_, pixdim) = count(f32(e), 8)(&s[76..])?;
+    let (_, (vox_offset, scl_slope, scl_inter)) = tuple((f32(e), f32(e), f32(e)))(&s[108..])?;
+    let (_, (qform_code, sform_code)) = tuple((i16(e), i16(e)))(&s[252..])?;
+    let (_, quatern) = count(f32(e), 3)(&s[256..])?;
+    let (_, qoffset) = count(f32(e), 3)(&s[268..])?;
+    let (rest, srow) = count(f32(e), 12)(&s[280..])?;
+
+    let row = |r: usize| {
+        [
+            srow[4 * r],
+            srow[4 * r + 1],
+            srow[4 * r + 2],
+            srow[4 * r + 3],
+        ]
+    };
+
+    Ok((
+        rest,
+        NiftiHeader {
+            endian,
+            dim: dim.try_into().unwrap(),
+            datatype,
+            pixdim: pixdim.try_into().unwrap(),
+            vox_offset,
+            scl_slope,
+            scl_inter,
+            qform_code,
+            sform_code,
+            quatern: quatern.try_into().unwrap(),
+            qoffset: qoffset.try_into().unwrap(),
+            srow: [row(0), row(1), row(2)],
+            single_file,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    /// Header of `size` volume in little endian
+    fn header(size: [i16; 3], datatype: i16, single_file: bool) -> Vec<u8> {
+        let mut h = vec![0; HEADER_SIZE];
+        h[..4].copy_from_slice(&348i32.to_le_bytes());
+        for (i, d) in [3, size[0], size[1], size[2], 1, 1, 1, 1]
+            .iter()
+            .enumerate()
+        {
+            h[40 + 2 * i..42 + 2 * i].copy_from_slice(&d.to_le_bytes());
+        }
+        h[70..72].copy_from_slice(&datatype.to_le_bytes());
+        for (i, p) in [1.0f32, 0.5, 0.5, 2.0].iter().enumerate() {
+            h[76 + 4 * i..80 + 4 * i].copy_from_slice(&p.to_le_bytes());
+        }
+        h[108..112].copy_from_slice(&352.0f32.to_le_bytes());
+        h[344..348].copy_from_slice(if single_file { b"n+1\0" } else { b"ni1\0" });
+        h
+    }
+
+    fn set_f32(h: &mut [u8], offset: usize, v: f32) {
+        h[offset..offset + 4].copy_from_slice(&v.to_le_bytes());
+    }
+
+    fn single_file(mut h: Vec<u8>, samples: &[u8]) -> DataSource<u8> {
+        h.extend_from_slice(&[0; 4]); // extension flags
+        h.extend_from_slice(samples);
+        DataSource::Vec(h)
+    }
+
+    #[test]
+    fn pixdim() {
+        let ds = single_file(header([2, 1, 1], 2, true), &[7, 9]);
+
+        let meta: VolumeMetadata<u8> = nifti_parser(ds).unwrap();
+
+        // Axes are reversed
+        assert_eq!(meta.size, Some(vector![1, 1, 2]));
+        assert_eq!(meta.scale, Some(vector![2.0, 0.5, 0.5]));
+        assert_eq!(meta.position, None);
+        assert_eq!(meta.world_transform, None);
+        assert_eq!(meta.rescale, None);
+        assert_eq!(meta.data.unwrap().get_slice(), &[7, 9]);
+    }
+
+    #[test]
+    fn sform_and_rescale() {
+        let mut h = header([1, 1, 2], 4, true);
+        h[254..256].copy_from_slice(&1i16.to_le_bytes()); // sform_code
+        for (i, v) in [
+            -0.8, 0.0, 0.0, 90.0, 0.0, 0.8, 0.0, -126.0, 0.0, 0.0, 3.0, -72.0,
+        ]
+        .iter()
+        .enumerate()
+        {
+            set_f32(&mut h, 280 + 4 * i, *v);
+        }
+        set_f32(&mut h, 112, 2.0); // scl_slope
+        set_f32(&mut h, 116, -1024.0); // scl_inter
+        let samples: Vec<u8> = [10i16, -3].iter().flat_map(|v| v.to_le_bytes()).collect();
+
+        let meta: VolumeMetadata<i16> = nifti_parser(single_file(h, &samples)).unwrap();
+
+        assert_eq!(meta.scale, Some(vector![3.0, 0.8, 0.8]));
+        assert_eq!(meta.position, Some(point![-72.0, -126.0, 90.0]));
+        assert_eq!(meta.rescale, Some(Rescale::new(2.0, -1024.0)));
+        assert_eq!(meta.data.unwrap().get_slice(), &[10, -3]);
+    }
+
+    #[test]
+    fn qform() {
+        let mut h = header([1, 1, 1], 16, true);
+        h[252..254].copy_from_slice(&1i16.to_le_bytes()); // qform_code
+        set_f32(&mut h, 264, 1.0); // quatern_d, rotation by 180° around z
+        set_f32(&mut h, 268, 5.0);
+        set_f32(&mut h, 272, 6.0);
+        set_f32(&mut h, 276, 7.0);
+
+        let header = NiftiHeader::parse(&h).unwrap();
+        let affine = header.affine().unwrap();
+
+        assert_eq!(affine[(0, 0)], -0.5);
+        assert_eq!(affine[(1, 1)], -0.5);
+        assert_eq!(affine[(2, 2)], 2.0);
+
+        let meta: VolumeMetadata<f32> =
+            nifti_parser(single_file(h, &1.5f32.to_le_bytes())).unwrap();
+        assert_eq!(meta.scale, Some(vector![2.0, 0.5, 0.5]));
+        assert_eq!(meta.position, Some(point![7.0, 6.0, 5.0]));
+        // Rotation is kept, with reversed axes
+        assert_eq!(
+            meta.world_transform,
+            Some(matrix![
+                2.0, 0.0, 0.0, 7.0;
+                0.0, -0.5, 0.0, 6.0;
+                0.0, 0.0, -0.5, 5.0;
+                0.0, 0.0, 0.0, 1.0
+            ])
+        );
+    }
+
+    #[test]
+    fn big_endian() {
+        let mut h = vec![0; HEADER_SIZE];
+        h[..4].copy_from_slice(&348i32.to_be_bytes());
+        for (i, d) in [3i16, 1, 1, 1].iter().enumerate() {
+            h[40 + 2 * i..42 + 2 * i].copy_from_slice(&d.to_be_bytes());
+        }
+        h[70..72].copy_from_slice(&512i16.to_be_bytes());
+        h[108..112].copy_from_slice(&352.0f32.to_be_bytes());
+        h[344..348].copy_from_slice(b"n+1\0");
+
+        let meta: VolumeMetadata<u16> = nifti_parser(single_file(h, &[1, 2])).unwrap();
+
+        assert_eq!(meta.scale, Some(vector![1.0, 1.0, 1.0]));
+        assert_eq!(meta.data.unwrap().get_slice(), &[258]);
+    }
+
+    #[test]
+    fn header_image_pair() {
+        let dir = std::env::temp_dir().join(format!("raycaster_nifti_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut h = header([2, 1, 1], 2, false);
+        set_f32(&mut h, 108, 0.0);
+        std::fs::write(dir.join("volume.hdr"), h).unwrap();
+        std::fs::write(dir.join("volume.img"), [3, 4]).unwrap();
+
+        let meta: Result<VolumeMetadata<u8>, _> = nifti_file(dir.join("volume.hdr"));
+        let single: Result<VolumeMetadata<u8>, _> =
+            nifti_parser(DataSource::from_file(dir.join("volume.hdr")).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let data = meta.unwrap().data.unwrap();
+        assert!(matches!(data, DataSource::Mmap(_)));
+        assert_eq!(data.get_slice(), &[3, 4]);
+        assert!(single.is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        let parse = |h: Vec<u8>| nifti_parser::<u8>(single_file(h, &[0; 2])).is_ok();
+
+        assert!(parse(header([2, 1, 1], 2, true)));
+        assert!(!parse(header([2, 2, 1], 2, true)));
+        assert!(!parse(header([2, 1, 1], 64, true)));
+        assert!(!parse(header([2, 1, 1], 4, true)));
+        assert!(!parse(header([0, 1, 1], 2, true)));
+        assert!(!parse(vec![0; 400]));
+        assert!(NiftiHeader::parse(&[0; 100]).is_err());
+    }
+}
@@ -0,0 +1,547 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! [NRRD](http://teem.sourceforge.net/nrrd/format.html) files.
+//!
+//! Supported are 3D volumes in `raw` encoding, with attached (`.nrrd`) or detached (`.nhdr`) header.
+//! 4D volumes are read as multi-channel volumes, the first (fastest) axis being channels.
+//!
+//! Orientation of axes is not supported, only lengths of `space directions` are used, as scale.
+//! Order of axes is reversed, see [`reverse_axes`].
+
+use std::path::Path;
+
+use nalgebra::{vector, Point3, Vector3};
+
+use crate::{
+    volumetric::{ChannelLayout, DataSource, Sample, StorageShape, VolumeMetadata},
+    Error,
+};
+
+use super::{read_samples, reverse_axes, sample_count, Endian, SampleType};
+
+/// Name of the format in errors
+const FORMAT: &str = "NRRD";
+
+/// Fields of NRRD header, needed to read a volume
+#[derive(Debug, Clone, PartialEq)]
+pub struct NrrdHeader {
+    pub sample_type: SampleType,
+    /// Samples per axis, fastest axis first
+    pub sizes: Vec<usize>,
+    pub endian: Option<Endian>,
+    /// Spacing of samples along every axis
+    pub spacings: Option<Vec<f32>>,
+    /// Direction of every axis, `None` for non-spatial axes
+    pub space_directions: Option<Vec<Option<Vector3<f32>>>>,
+    pub space_origin: Option<Point3<f32>>,
+    /// File with samples, if the header is detached
+    pub data_file: Option<String>,
+    pub line_skip: usize,
+    /// Bytes skipped before samples, `None` if samples are at the end of file (`byte skip: -1`)
+    pub byte_skip: Option<usize>,
+    /// Length of header in bytes
+    pub header_length: usize,
+}
+
+impl NrrdHeader {
+    /// Parse header at the beginning of `bytes`
+    pub fn parse(bytes: &[u8]) -> Result<NrrdHeader, Error> {
+        if !bytes.starts_with(b"NRRD000") {
+            return Err(Error::parse(FORMAT, 0, "Missing magic"));
+        }
+
+        // Header ends with an empty line, detached header may end with the end of file
+        let (text, header_length) = match find_empty_line(bytes) {
+            Some((end, data_start)) => (&bytes[..end], data_start),
+            None => (bytes, bytes.len()),
+        };
+        let text = std::str::from_utf8(text)
+            .map_err(|e| Error::parse(FORMAT, e.valid_up_to(), "Header is not text"))?;
+
+        let mut dimension = None;
+        let mut sample_type = None;
+        let mut sizes = None;
+        let mut endian = None;
+        let mut spacings = None;
+        let mut space_directions = None;
+        let mut space_origin = None;
+        let mut data_file = None;
+        let mut line_skip = 0;
+        let mut byte_skip = Some(0);
+        let mut encoding = false;
+
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            let line_offset = offset;
+            offset += line.len();
+            let line = line.trim_end();
+            if line_offset == 0 || line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason| Error::parse(FORMAT, line_offset, reason);
+
+            let (field, desc) = match line.split_once(": ") {
+                Some((field, desc)) if !field.contains(":=") => (field, desc.trim()),
+                _ if line.contains(":=") => continue, // key/value pair
+                _ => return Err(invalid("Invalid header line")),
+            };
+
+            match field {
+                "dimension" => dimension = Some(parse_number(desc).map_err(invalid)?),
+                "type" => sample_type = Some(parse_type(desc)?),
+                "sizes" => sizes = Some(parse_list(desc).map_err(invalid)?),
+                "endian" => {
+                    endian = match desc {
+                        "little" => Some(Endian::Little),
+                        "big" => Some(Endian::Big),
+                        _ => return Err(invalid("Unknown endian")),
+                    }
+                }
+                "encoding" if desc != "raw" => {
+                    return Err(Error::Unsupported("NRRD encoding other than raw"))
+                }
+                "encoding" => encoding = true,
+                "spacings" => spacings = Some(parse_list(desc).map_err(invalid)?),
+                "space directions" => {
+                    let directions = parse_vectors(desc)
+                        .and_then(|vectors| {
+                            vectors
+                                .into_iter()
+                                .map(|v| v.map(to_vector3).transpose())
+                                .collect::<Result<Vec<_>, _>>()
+                        })
+                        .map_err(invalid)?;
+                    space_directions = Some(directions);
+                }
+                "space origin" => {
+                    let origin = parse_vectors(desc).map_err(invalid)?;
+                    match origin.as_slice() {
+                        [Some(v)] => {
+                            space_origin = Some(to_vector3(v.clone()).map_err(invalid)?.into())
+                        }
+                        _ => return Err(invalid("Invalid space origin")),
+                    }
+                }
+                "data file" | "datafile" => {
+                    if desc == "LIST" || desc.split_whitespace().count() != 1 {
+                        return Err(Error::Unsupported("NRRD list of data files"));
+                    }
+                    data_file = Some(desc.to_string());
+                }
+                "line skip" | "lineskip" => line_skip = parse_number(desc).map_err(invalid)?,
+                "byte skip" | "byteskip" => {
+                    byte_skip = match desc {
+                        "-1" => None,
+                        _ => Some(parse_number(desc).map_err(invalid)?),
+                    }
+                }
+                _ => (), // Not needed for rendering
+            }
+        }
+
+        let end = header_length;
+        let dimension: usize =
+            dimension.ok_or_else(|| Error::parse(FORMAT, end, "Missing dimension"))?;
+        let sample_type = sample_type.ok_or_else(|| Error::parse(FORMAT, end, "Missing type"))?;
+        let sizes: Vec<usize> = sizes.ok_or_else(|| Error::parse(FORMAT, end, "Missing sizes"))?;
+        if !encoding {
+            return Err(Error::parse(FORMAT, end, "Missing encoding"));
+        }
+
+        if dimension != 3 && dimension != 4 {
+            return Err(Error::Unsupported("NRRD dimension other than 3 or 4"));
+        }
+        if sizes.len() != dimension
+            || matches!(&spacings, Some(s) if s.len() != dimension)
+            || matches!(&space_directions, Some(d) if d.len() != dimension)
+        {
+            return Err(Error::parse(FORMAT, end, "Fields do not match dimension"));
+        }
+        if endian.is_none() && sample_type.size() > 1 {
+            return Err(Error::parse(FORMAT, end, "Missing endian"));
+        }
+
+        Ok(NrrdHeader {
+            sample_type,
+            sizes,
+            endian,
+            spacings,
+            space_directions,
+            space_origin,
+            data_file,
+            line_skip,
+            byte_skip,
+            header_length,
+        })
+    }
+
+    /// Number of channels, 1 for 3D volumes
+    pub fn channels(&self) -> usize {
+        if self.sizes.len() == 4 {
+            self.sizes[0]
+        } else {
+            1
+        }
+    }
+
+    /// Size of volume, without channel axis
+    pub fn size(&self) -> Vector3<usize> {
+        let s = &self.sizes[self.sizes.len() - 3..];
+        vector![s[0], s[1], s[2]]
+    }
+
+    /// Distance of samples along spatial axes.
+    /// Taken from `space directions`, or from `spacings` if directions are not present.
+    pub fn scale(&self) -> Result<Vector3<f32>, Error> {
+        let spatial = self.sizes.len() - 3;
+
+        if let Some(directions) = &self.space_directions {
+            if directions[..spatial].iter().any(Option::is_some) {
+                return Err(Error::Unsupported(
+                    "NRRD axis order other than channels first",
+                ));
+            }
+            let lengths = directions[spatial..]
+                .iter()
+                .map(|d| {
+                    d.map(|d| d.norm())
+                        .ok_or(Error::Missing("NRRD space direction"))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(vector![lengths[0], lengths[1], lengths[2]]);
+        }
+
+        let scale = match &self.spacings {
+            Some(s) => vector![s[spatial], s[spatial + 1], s[spatial + 2]],
+            None => vector![1.0, 1.0, 1.0],
+        };
+        // Unknown spacing
+        Ok(scale.map(|s| if s.is_finite() { s } else { 1.0 }))
+    }
+
+    /// Build metadata, samples are read from `data_source`.
+    ///
+    /// # Params
+    /// * `data_source` - file with samples
+    /// * `start` - start of samples in `data_source`, before line skip and byte skip are applied
+    pub fn metadata<T: Sample>(
+        &self,
+        data_source: DataSource<u8>,
+        start: usize,
+    ) -> Result<VolumeMetadata<T>, Error> {
+        self.sample_type.check::<T>()?;
+
+        let count = sample_count(self.size(), self.channels())?;
+        let overflow = Error::Shape("Size of volume overflows");
+        let offset = {
+            let bytes = data_source.get_slice();
+            let mut offset = start;
+            for _ in 0..self.line_skip {
+                let line_end = bytes
+                    .get(offset..)
+                    .and_then(|rest| rest.iter().position(|&b| b == b'\n'))
+                    .ok_or(Error::Truncated {
+                        expected: offset + 1,
+                        found: bytes.len(),
+                    })?;
+                offset += line_end + 1;
+            }
+            match self.byte_skip {
+                Some(skip) => offset.checked_add(skip).ok_or(overflow)?,
+                None => {
+                    let length = count.checked_mul(self.sample_type.size()).ok_or(overflow)?;
+                    Error::check_length(length, bytes.len())?;
+                    bytes.len() - length
+                }
+            }
+        };
+
+        let endian = self.endian.unwrap_or_else(Endian::native);
+        let data = read_samples(data_source, offset, count, endian)?;
+
+        let (channels, channel_layout) = if self.sizes.len() == 4 {
+            (Some(self.channels()), Some(ChannelLayout::Interleaved))
+        } else {
+            (None, None)
+        };
+
+        Ok(VolumeMetadata {
+            position: self.space_origin.map(|p| reverse_axes(p.coords).into()),
+            size: Some(reverse_axes(self.size())),
+            scale: Some(reverse_axes(self.scale()?)),
+            data: Some(data),
+            data_shape: Some(StorageShape::Linear),
+            channels,
+            channel_layout,
+            ..Default::default()
+        })
+    }
+}
+
+/// NRRD file with attached header.
+/// Transfer function is not set.
+///
+/// Use [`nrrd_file`] for detached headers.
+pub fn nrrd_parser<T: Sample>(data_source: DataSource<u8>) -> Result<VolumeMetadata<T>, Error> {
+    let header = NrrdHeader::parse(data_source.get_slice())?;
+    if header.data_file.is_some() {
+        return Err(Error::Unsupported("Detached NRRD header, use nrrd_file"));
+    }
+    header.metadata(data_source, header.header_length)
+}
+
+/// NRRD file with attached or detached header.
+/// Path of detached data file is relative to the header.
+/// Transfer function is not set.
+pub fn nrrd_file<T, P>(path: P) -> Result<VolumeMetadata<T>, Error>
+where
+    T: Sample,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let data_source = DataSource::from_file(path)?;
+    let header = NrrdHeader::parse(data_source.get_slice())?;
+
+    match &header.data_file {
+        None => header.metadata(data_source, header.header_length),
+        Some(file) => {
+            let data_path = path.parent().unwrap_or_else(|| Path::new("")).join(file);
+            header.metadata(DataSource::from_file(data_path)?, 0)
+        }
+    }
+}
+
+/// Returns end of first empty line and start of the next line
+fn find_empty_line(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut line_start = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'\n' {
+            let line = &bytes[line_start..i];
+            if line.is_empty() || line == b"\r" {
+                return Some((line_start, i + 1));
+            }
+            line_start = i + 1;
+        }
+    }
+    None
+}
+
+fn parse_type(desc: &str) -> Result<SampleType, Error> {
+    let sample_type = match desc {
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => SampleType::U8,
+        "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => {
+            SampleType::I16
+        }
+        "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => {
+            SampleType::U16
+        }
+        "float" => SampleType::F32,
+        _ => return Err(Error::Unsupported("NRRD sample type")),
+    };
+    Ok(sample_type)
+}
+
+fn parse_number<N: std::str::FromStr>(desc: &str) -> Result<N, &'static str> {
+    desc.parse().map_err(|_| "Invalid number")
+}
+
+/// Whitespace separated list of numbers
+fn parse_list<N: std::str::FromStr>(desc: &str) -> Result<Vec<N>, &'static str> {
+    desc.split_whitespace().map(parse_number).collect()
+}
+
+/// List of vectors `(x,y,z)` or `none`
+fn parse_vectors(desc: &str) -> Result<Vec<Option<Vec<f32>>>, &'static str> {
+    let mut vectors = vec![];
+    let mut rest = desc.trim_start();
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix("none") {
+            vectors.push(None);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('(') {
+            let (inner, r) = r.split_once(')').ok_or("Invalid vector")?;
+            let components = inner
+                .split(',')
+                .map(|c| parse_number(c.trim()))
+                .collect::<Result<_, _>>()?;
+            vectors.push(Some(components));
+            rest = r;
+        } else {
+            return Err("Invalid vector");
+        }
+        rest = rest.trim_start();
+    }
+    Ok(vectors)
+}
+
+fn to_vector3(v: Vec<f32>) -> Result<Vector3<f32>, &'static str> {
+    match v.as_slice() {
+        &[x, y, z] => Ok(vector![x, y, z]),
+        _ => Err("Space is not 3D"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::test_helpers::header_file;
+    use nalgebra::point;
+
+    #[test]
+    fn attached() {
+        let header = "NRRD0004\n\
+            # Complete NRRD file format specification at:\n\
+            type: uchar\n\
+            dimension: 3\n\
+            sizes: 2 2 3\n\
+            spacings: 0.5 0.5 2\n\
+            encoding: raw\n\
+            author:=someone\n\
+            \n";
+        let data: Vec<u8> = (0..12).collect();
+
+        let meta: VolumeMetadata<u8> = nrrd_parser(header_file(header, &data)).unwrap();
+
+        // Axes are reversed
+        assert_eq!(meta.size, Some(vector![3, 2, 2]));
+        assert_eq!(meta.scale, Some(vector![2.0, 0.5, 0.5]));
+        assert_eq!(meta.position, None);
+        assert_eq!(meta.channels, None);
+        assert_eq!(meta.data.unwrap().get_slice(), data.as_slice());
+    }
+
+    #[test]
+    fn big_endian() {
+        let header =
+            "NRRD0004\ntype: ushort\ndimension: 3\nsizes: 2 1 1\nendian: big\nencoding: raw\n\n";
+
+        let meta: VolumeMetadata<u16> = nrrd_parser(header_file(header, &[1, 2, 0, 255])).unwrap();
+
+        assert_eq!(meta.data.unwrap().get_slice(), &[258, 255]);
+    }
+
+    #[test]
+    fn space_directions() {
+        let header = "NRRD0005\n\
+            type: short\n\
+            dimension: 3\n\
+            space: left-posterior-superior\n\
+            sizes: 1 1 1\n\
+            space directions: (0,0.7,0) (-0.7,0,0) (0, 0, 3.5)\n\
+            space origin: (-10.5,20,0.25)\n\
+            endian: little\n\
+            encoding: raw\n\
+            \n";
+
+        let meta: VolumeMetadata<i16> = nrrd_parser(header_file(header, &[0xff, 0xff])).unwrap();
+
+        assert_eq!(meta.scale, Some(vector![3.5, 0.7, 0.7]));
+        assert_eq!(meta.position, Some(point![0.25, 20.0, -10.5]));
+        assert_eq!(meta.data.unwrap().get_slice(), &[-1]);
+    }
+
+    #[test]
+    fn channels() {
+        let header = "NRRD0004\n\
+            type: uint8\n\
+            dimension: 4\n\
+            sizes: 3 2 1 1\n\
+            space directions: none (1,0,0) (0,1,0) (0,0,1)\n\
+            kinds: RGB-color domain domain domain\n\
+            encoding: raw\n\
+            \n";
+
+        let meta: VolumeMetadata<u8> = nrrd_parser(header_file(header, &[0; 6])).unwrap();
+
+        assert_eq!(meta.size, Some(vector![1, 1, 2]));
+        assert_eq!(meta.channels, Some(3));
+        assert_eq!(meta.channel_layout, Some(ChannelLayout::Interleaved));
+    }
+
+    #[test]
+    fn detached() {
+        let dir = std::env::temp_dir().join(format!("raycaster_nrrd_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("volume.nhdr"),
+            "NRRD0004\ntype: float\ndimension: 3\nsizes: 1 1 2\nendian: little\n\
+            encoding: raw\nbyte skip: -1\ndata file: volume.raw\n",
+        )
+        .unwrap();
+        let mut raw = b"skip".to_vec();
+        raw.extend(1.5f32.to_le_bytes());
+        raw.extend((-2.0f32).to_le_bytes());
+        std::fs::write(dir.join("volume.raw"), raw).unwrap();
+
+        let meta: Result<VolumeMetadata<f32>, _> = nrrd_file(dir.join("volume.nhdr"));
+        let attached: Result<VolumeMetadata<f32>, _> =
+            nrrd_parser(DataSource::from_file(dir.join("volume.nhdr")).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Aligned samples in native byte order stay memory mapped
+        let data = meta.unwrap().data.unwrap();
+        if Endian::native() == Endian::Little {
+            assert!(matches!(data, DataSource::Mmap(_)));
+        }
+        assert_eq!(data.get_slice(), &[1.5, -2.0]);
+        assert!(attached.is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        let parse = |header: &str| nrrd_parser::<u8>(header_file(header, &[0; 8])).is_ok();
+
+        assert!(parse(
+            "NRRD0004\ntype: uchar\ndimension: 3\nsizes: 2 2 2\nencoding: raw\n\n"
+        ));
+        assert!(!parse(
+            "NRRD0004\ntype: uchar\ndimension: 3\nsizes: 2 2 2\nencoding: gzip\n\n"
+        ));
+        assert!(!parse(
+            "NRRD0004\ntype: uchar\ndimension: 3\nsizes: 2 2 3\nencoding: raw\n\n"
+        ));
+        assert!(!parse(
+            "NRRD0004\ntype: uchar\ndimension: 2\nsizes: 2 2\nencoding: raw\n\n"
+        ));
+        assert!(!parse("P5 2 2 255\n"));
+
+        let error = |header: &str| nrrd_parser::<u16>(header_file(header, &[0; 8])).err();
+        assert!(matches!(
+            error("NRRD0004\ntype: ushort\ndimension: 3\nsizes: 1 1 1\nendian: little\n\n"),
+            Some(Error::Parse {
+                reason: "Missing encoding",
+                ..
+            })
+        ));
+        assert!(matches!(
+            error("NRRD0004\ntype: ushort\ndimension: 3\nsizes: 1 1 1\nencoding: raw\n\n"),
+            Some(Error::Parse {
+                reason: "Missing endian",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn size_overflow() {
+        let parse = |fields: &str| {
+            let header = format!(
+                "NRRD0004\ntype: uchar\ndimension: 3\nencoding: raw\n{}\n",
+                fields
+            );
+            nrrd_parser::<u8>(header_file(&header, &[0; 8]))
+        };
+
+        for fields in [
+            "sizes: 4294967296 4294967296 4294967296\n",
+            "sizes: 2 2 2\nbyte skip: 18446744073709551615\n",
+        ] {
+            assert!(matches!(parse(fields), Err(Error::Shape(_))));
+        }
+    }
+}
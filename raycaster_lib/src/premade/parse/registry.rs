@@ -0,0 +1,648 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Registry of parsers, with detection of file format.
+//!
+//! Each format provides a sniffing function, which looks at path and first bytes of the file.
+//! The first format, whose sniffing function accepts the file, is used to read it.
+//! Parsers registered at runtime are tried before the built-in formats.
+//! Type of samples is detected from the header, see [`LoadedVolume`].
+
+use std::{any::Any, fs::File, io::Read, path::Path};
+
+use parking_lot::{const_rwlock, RwLock};
+
+use crate::{
+    volumetric::{Sample, VolumeMetadata},
+    Error, ParserFn,
+};
+
+use super::{
+    beetle_parser, dicom_series, generator_parser, image_stack, image_stack_dir, metaimage_file,
+    nifti_file, nrrd_file, skull_parser, vol_parser, vtk_parser, DataSource, DicomSlice,
+    MetaImageHeader, NiftiHeader, NrrdHeader, SampleType, StackImage, VolHeader, VtkHeader,
+    VOL_MAGIC, VOL_V1_HEADER_LEN,
+};
+
+/// Number of bytes given to sniffing functions
+pub const SNIFF_LENGTH: usize = 1024;
+
+/// Decide if the file at path can be read by a parser.
+/// Bytes are the beginning of the file (at most [`SNIFF_LENGTH`]), empty for directories.
+pub type SniffFn = fn(&Path, &[u8]) -> bool;
+
+/// Reads a volume, which may span more files
+pub type FileParserFn = fn(&Path) -> Result<LoadedVolume, Error>;
+
+/// Metadata of loaded volume, with samples of the type stored in the file
+pub enum LoadedVolume {
+    U8(VolumeMetadata<u8>),
+    U16(VolumeMetadata<u16>),
+    I16(VolumeMetadata<i16>),
+    F32(VolumeMetadata<f32>),
+}
+
+impl LoadedVolume {
+    /// Type of samples
+    pub fn sample_type(&self) -> SampleType {
+        match self {
+            LoadedVolume::U8(_) => SampleType::U8,
+            LoadedVolume::U16(_) => SampleType::U16,
+            LoadedVolume::I16(_) => SampleType::I16,
+            LoadedVolume::F32(_) => SampleType::F32,
+        }
+    }
+
+    /// Metadata with samples of type `T`, error if the file stores another type
+    pub fn into_typed<T: Sample>(self) -> Result<VolumeMetadata<T>, Error> {
+        self.sample_type().check::<T>()?;
+        let metadata: Box<dyn Any> = match self {
+            LoadedVolume::U8(m) => Box::new(m),
+            LoadedVolume::U16(m) => Box::new(m),
+            LoadedVolume::I16(m) => Box::new(m),
+            LoadedVolume::F32(m) => Box::new(m),
+        };
+        Ok(*metadata.downcast().expect("Sample type was checked"))
+    }
+}
+
+impl From<VolumeMetadata<u8>> for LoadedVolume {
+    fn from(metadata: VolumeMetadata<u8>) -> Self {
+        LoadedVolume::U8(metadata)
+    }
+}
+
+impl From<VolumeMetadata<u16>> for LoadedVolume {
+    fn from(metadata: VolumeMetadata<u16>) -> Self {
+        LoadedVolume::U16(metadata)
+    }
+}
+
+impl From<VolumeMetadata<i16>> for LoadedVolume {
+    fn from(metadata: VolumeMetadata<i16>) -> Self {
+        LoadedVolume::I16(metadata)
+    }
+}
+
+impl From<VolumeMetadata<f32>> for LoadedVolume {
+    fn from(metadata: VolumeMetadata<f32>) -> Self {
+        LoadedVolume::F32(metadata)
+    }
+}
+
+/// How a format is read
+#[derive(Clone, Copy)]
+pub enum FormatParser {
+    /// Parser of a single, memory mapped file with `u8` samples
+    Data(ParserFn),
+    /// Parser given the path, for formats with detached data
+    File(FileParserFn),
+}
+
+/// Format known to the registry
+#[derive(Clone, Copy)]
+pub struct Format {
+    pub name: &'static str,
+    pub sniff: SniffFn,
+    pub parser: FormatParser,
+}
+
+/// Formats registered at runtime, newest first
+static CUSTOM_FORMATS: RwLock<Vec<Format>> = const_rwlock(Vec::new());
+
+/// Register a parser of a custom format.
+/// It takes precedence over built-in formats and formats registered earlier.
+pub fn register_parser(name: &'static str, sniff: SniffFn, parser: ParserFn) {
+    register_format(Format {
+        name,
+        sniff,
+        parser: FormatParser::Data(parser),
+    });
+}
+
+/// Register a custom format, see [`register_parser`]
+pub fn register_format(format: Format) {
+    CUSTOM_FORMATS.write().insert(0, format);
+}
+
+/// All formats in the order they are tried
+pub fn formats() -> Vec<Format> {
+    let mut formats = CUSTOM_FORMATS.read().clone();
+    formats.extend_from_slice(&BUILTIN_FORMATS);
+    formats
+}
+
+/// Find format of file or directory at `path`
+pub fn detect_format<P: AsRef<Path>>(path: P) -> Result<Format, Error> {
+    let path = path.as_ref();
+    let bytes = sniff_bytes(path)?;
+    formats()
+        .into_iter()
+        .find(|format| (format.sniff)(path, &bytes))
+        .ok_or(Error::UnknownFormat)
+}
+
+/// Read volume at `path`, parser is picked by detecting the format.
+///
+/// Transfer function is set only by some parsers, set it before building the volume.
+///
+/// # Example
+/// ```no_run
+/// # use raycaster_lib::{premade::{parse::load_volume, transfer_functions::skull_tf}, volumetric::{volumes::LinearVolume, BuildVolume}};
+/// # use std::sync::Arc;
+/// let mut metadata = load_volume("volumes/head.nrrd").unwrap().into_typed::<u16>().unwrap();
+/// metadata.set_tf(Arc::new(skull_tf));
+/// let volume: LinearVolume<u16> = BuildVolume::build(metadata).unwrap();
+/// ```
+pub fn load_volume<P: AsRef<Path>>(path: P) -> Result<LoadedVolume, Error> {
+    let path = path.as_ref();
+    match detect_format(path)?.parser {
+        FormatParser::Data(parser) => parser(DataSource::from_file(path)?).map(LoadedVolume::U8),
+        FormatParser::File(parser) => parser(path),
+    }
+}
+
+/// Beginning of the file, empty for directories
+fn sniff_bytes(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::with_capacity(SNIFF_LENGTH);
+    if path.is_dir() {
+        return Ok(bytes);
+    }
+    File::open(path)
+        .and_then(|file| file.take(SNIFF_LENGTH as u64).read_to_end(&mut bytes))
+        .map_err(|e| Error::io(path, e))?;
+    Ok(bytes)
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.iter().any(|x| e.eq_ignore_ascii_case(x)))
+}
+
+fn file_length(path: &Path) -> u64 {
+    std::fs::metadata(path).map_or(0, |m| m.len())
+}
+
+/// Product of three 32 bit numbers, `None` on overflow or zero size
+fn volume_length(size: [u32; 3]) -> Option<u64> {
+    let [x, y, z] = size.map(u64::from);
+    x.checked_mul(y)?.checked_mul(z).filter(|&n| n > 0)
+}
+
+fn sniff_nrrd(_path: &Path, bytes: &[u8]) -> bool {
+    bytes.starts_with(b"NRRD000")
+}
+
+fn sniff_nifti(_path: &Path, bytes: &[u8]) -> bool {
+    bytes
+        .get(344..348)
+        .is_some_and(|magic| magic == b"n+1\0" || magic == b"ni1\0")
+}
+
+fn sniff_metaimage(path: &Path, bytes: &[u8]) -> bool {
+    has_extension(path, &["mha", "mhd"])
+        || bytes.starts_with(b"ObjectType")
+        || bytes.starts_with(b"NDims")
+}
+
+fn sniff_vtk(_path: &Path, bytes: &[u8]) -> bool {
+    bytes.starts_with(b"# vtk DataFile")
+}
+
+fn sniff_dicom(path: &Path, bytes: &[u8]) -> bool {
+    let is_dicom = |bytes: &[u8]| bytes.get(128..132) == Some(b"DICM");
+    if !path.is_dir() {
+        return is_dicom(bytes);
+    }
+    // Directory with at least one DICOM file
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .any(|entry| sniff_bytes(&entry.path()).is_ok_and(|bytes| is_dicom(&bytes)))
+}
+
+fn sniff_image_stack(path: &Path, _bytes: &[u8]) -> bool {
+    match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .any(|entry| has_extension(&entry.path(), &["pgm", "tif", "tiff"])),
+        Err(_) => false,
+    }
+}
+
+/// Format of `vol_gen`, version 2, see [`vol_parser`]
+fn sniff_vol(_path: &Path, bytes: &[u8]) -> bool {
+    bytes.starts_with(&VOL_MAGIC)
+}
+
+/// Format of `vol_gen`, version 1, see [`generator_parser`]
+fn sniff_generator(path: &Path, bytes: &[u8]) -> bool {
+    if bytes.len() < VOL_V1_HEADER_LEN {
+        return false;
+    }
+    let word = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    let samples = match volume_length([word(0), word(4), word(8)]) {
+        Some(samples) => samples,
+        None => return false,
+    };
+    let scale_valid = (0..3).all(|i| {
+        let scale = f32::from_bits(word(12 + 4 * i));
+        scale.is_finite() && scale > 0.0
+    });
+    let length = file_length(path);
+    // Z order pads data to whole blocks
+    let shape_valid = match bytes[24] {
+        1 => length == VOL_V1_HEADER_LEN as u64 + samples,
+        2 => length >= VOL_V1_HEADER_LEN as u64 + samples,
+        _ => false,
+    };
+    scale_valid && shape_valid
+}
+
+/// Format of the skull example volume, see [`skull_parser`]
+fn sniff_skull(path: &Path, bytes: &[u8]) -> bool {
+    if bytes.len() < 28 {
+        return false;
+    }
+    let word = |i: usize| u32::from_be_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    volume_length([word(0), word(4), word(8)])
+        .is_some_and(|samples| file_length(path) == 28 + samples)
+}
+
+/// Format of the beetle example volume, see [`beetle_parser`]
+fn sniff_beetle(path: &Path, bytes: &[u8]) -> bool {
+    if bytes.len() < 6 {
+        return false;
+    }
+    let half = |i: usize| u32::from(u16::from_le_bytes([bytes[i], bytes[i + 1]]));
+    volume_length([half(0), half(2), half(4)])
+        .is_some_and(|samples| file_length(path) == 6 + 2 * samples)
+}
+
+/// Format with samples of any type, read with samples of the type found in the header
+trait TypedFormat {
+    fn sample_type(path: &Path) -> Result<SampleType, Error>;
+
+    fn read<T: Sample>(path: &Path) -> Result<VolumeMetadata<T>, Error>;
+}
+
+fn read_typed<F: TypedFormat>(path: &Path) -> Result<LoadedVolume, Error> {
+    let volume = match F::sample_type(path)? {
+        SampleType::U8 => F::read::<u8>(path)?.into(),
+        SampleType::U16 => F::read::<u16>(path)?.into(),
+        SampleType::I16 => F::read::<i16>(path)?.into(),
+        SampleType::F32 => F::read::<f32>(path)?.into(),
+    };
+    Ok(volume)
+}
+
+struct Nrrd;
+
+impl TypedFormat for Nrrd {
+    fn sample_type(path: &Path) -> Result<SampleType, Error> {
+        Ok(NrrdHeader::parse(DataSource::from_file(path)?.get_slice())?.sample_type)
+    }
+
+    fn read<T: Sample>(path: &Path) -> Result<VolumeMetadata<T>, Error> {
+        nrrd_file(path)
+    }
+}
+
+struct Nifti;
+
+impl TypedFormat for Nifti {
+    fn sample_type(path: &Path) -> Result<SampleType, Error> {
+        NiftiHeader::parse(&sniff_bytes(path)?)?.sample_type()
+    }
+
+    fn read<T: Sample>(path: &Path) -> Result<VolumeMetadata<T>, Error> {
+        nifti_file(path)
+    }
+}
+
+struct MetaImage;
+
+impl TypedFormat for MetaImage {
+    fn sample_type(path: &Path) -> Result<SampleType, Error> {
+        Ok(MetaImageHeader::parse(DataSource::from_file(path)?.get_slice())?.sample_type)
+    }
+
+    fn read<T: Sample>(path: &Path) -> Result<VolumeMetadata<T>, Error> {
+        metaimage_file(path)
+    }
+}
+
+struct Vtk;
+
+impl TypedFormat for Vtk {
+    fn sample_type(path: &Path) -> Result<SampleType, Error> {
+        Ok(VtkHeader::parse(DataSource::from_file(path)?.get_slice())?.sample_type)
+    }
+
+    fn read<T: Sample>(path: &Path) -> Result<VolumeMetadata<T>, Error> {
+        vtk_parser(DataSource::from_file(path)?)
+    }
+}
+
+/// Whole series is read, also when path leads to one of the slices
+struct Dicom;
+
+impl Dicom {
+    fn series_dir(path: &Path) -> Result<&Path, Error> {
+        if path.is_dir() {
+            Ok(path)
+        } else {
+            path.parent()
+                .ok_or(Error::Missing("directory of DICOM series"))
+        }
+    }
+}
+
+impl TypedFormat for Dicom {
+    /// Type of samples of the first slice, series must not mix types
+    fn sample_type(path: &Path) -> Result<SampleType, Error> {
+        let dir = Dicom::series_dir(path)?;
+        let mut paths = std::fs::read_dir(dir)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| Error::io(dir, e))?;
+        paths.sort();
+
+        for path in paths.iter().filter(|p| p.is_file()) {
+            let bytes = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+            if let Some(slice) = DicomSlice::parse(&bytes)? {
+                return Ok(slice.sample_type);
+            }
+        }
+        Err(Error::Missing("DICOM slices"))
+    }
+
+    fn read<T: Sample>(path: &Path) -> Result<VolumeMetadata<T>, Error> {
+        dicom_series(Dicom::series_dir(path)?)
+    }
+}
+
+struct Vol;
+
+impl TypedFormat for Vol {
+    fn sample_type(path: &Path) -> Result<SampleType, Error> {
+        Ok(VolHeader::parse(DataSource::from_file(path)?.get_slice())?.sample_type)
+    }
+
+    fn read<T: Sample>(path: &Path) -> Result<VolumeMetadata<T>, Error> {
+        vol_parser(DataSource::from_file(path)?)
+    }
+}
+
+struct ImageStack;
+
+impl TypedFormat for ImageStack {
+    /// Type of samples of the first image, stack must not mix types
+    fn sample_type(path: &Path) -> Result<SampleType, Error> {
+        let paths = image_stack::stack_paths(path)?;
+        let first = paths.first().ok_or(Error::Missing("images"))?;
+        Ok(StackImage::decode(&image_stack::read_file(first)?)?.sample_type)
+    }
+
+    fn read<T: Sample>(path: &Path) -> Result<VolumeMetadata<T>, Error> {
+        image_stack_dir(path, 1.0)
+    }
+}
+
+fn read_beetle(path: &Path) -> Result<LoadedVolume, Error> {
+    beetle_parser(DataSource::from_file(path)?).map(LoadedVolume::U16)
+}
+
+/// Formats of the library, more reliable sniffing functions first
+static BUILTIN_FORMATS: [Format; 10] = [
+    Format {
+        name: "NRRD",
+        sniff: sniff_nrrd,
+        parser: FormatParser::File(read_typed::<Nrrd>),
+    },
+    Format {
+        name: "NIfTI",
+        sniff: sniff_nifti,
+        parser: FormatParser::File(read_typed::<Nifti>),
+    },
+    Format {
+        name: "VTK",
+        sniff: sniff_vtk,
+        parser: FormatParser::File(read_typed::<Vtk>),
+    },
+    Format {
+        name: "DICOM",
+        sniff: sniff_dicom,
+        parser: FormatParser::File(read_typed::<Dicom>),
+    },
+    Format {
+        name: "MetaImage",
+        sniff: sniff_metaimage,
+        parser: FormatParser::File(read_typed::<MetaImage>),
+    },
+    Format {
+        name: "Image stack",
+        sniff: sniff_image_stack,
+        parser: FormatParser::File(read_typed::<ImageStack>),
+    },
+    Format {
+        name: "vol_gen",
+        sniff: sniff_vol,
+        parser: FormatParser::File(read_typed::<Vol>),
+    },
+    Format {
+        name: "vol_gen v1",
+        sniff: sniff_generator,
+        parser: FormatParser::Data(generator_parser),
+    },
+    Format {
+        name: "Skull",
+        sniff: sniff_skull,
+        parser: FormatParser::Data(skull_parser),
+    },
+    Format {
+        name: "Beetle",
+        sniff: sniff_beetle,
+        parser: FormatParser::File(read_beetle),
+    },
+];
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::{
+        premade::parse::{Endian, VOL_HEADER_LEN, VOL_VERSION},
+        volumetric::StorageShape,
+    };
+    use nalgebra::{point, vector};
+    use std::path::PathBuf;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!(
+                "raycaster_registry_{}_{}",
+                name,
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn file(&self, name: &str, bytes: &[u8]) -> PathBuf {
+            let path = self.0.join(name);
+            std::fs::write(&path, bytes).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn generator_file(shape: u8) -> Vec<u8> {
+        let mut bytes = vec![];
+        for d in [2_u32, 2, 2] {
+            bytes.extend(d.to_le_bytes());
+        }
+        for s in [1.0_f32, 1.0, 1.0] {
+            bytes.extend(s.to_le_bytes());
+        }
+        bytes.extend([shape, 0]);
+        bytes.extend([7; 8]);
+        bytes
+    }
+
+    #[test]
+    fn detect() {
+        let dir = TempDir::new("detect");
+        let nrrd = dir.file(
+            "a.nrrd",
+            b"NRRD0004\ntype: uchar\ndimension: 3\nsizes: 1 1 2\nencoding: raw\n\n\x01\x02",
+        );
+        let vtk = dir.file(
+            "a.vtk",
+            b"# vtk DataFile Version 3.0\nt\nBINARY\nDATASET STRUCTURED_POINTS\n\
+            DIMENSIONS 1 1 2\nPOINT_DATA 2\nSCALARS s unsigned_char\nLOOKUP_TABLE default\n\x01\x02",
+        );
+        let generated = dir.file("a.vol", &generator_file(1));
+        let mut skull = vec![0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1];
+        skull.extend([0; 16 + 2]);
+        let skull = dir.file("skull.vol", &skull);
+        let unknown = dir.file("a.txt", b"hello");
+
+        assert_eq!(detect_format(&nrrd).unwrap().name, "NRRD");
+        assert_eq!(detect_format(&vtk).unwrap().name, "VTK");
+        assert_eq!(detect_format(&generated).unwrap().name, "vol_gen v1");
+        assert_eq!(detect_format(&skull).unwrap().name, "Skull");
+        assert!(detect_format(&unknown).is_err());
+        assert!(detect_format(dir.0.join("missing")).is_err());
+
+        let meta = load_volume(&vtk).unwrap().into_typed::<u8>().unwrap();
+        assert_eq!(meta.data.unwrap().get_slice(), &[1, 2]);
+        let meta = load_volume(&generated).unwrap().into_typed::<u8>().unwrap();
+        assert_eq!(meta.size, Some(nalgebra::vector![2, 2, 2]));
+    }
+
+    #[test]
+    fn sample_types() {
+        let dir = TempDir::new("sample_types");
+        let nrrd = dir.file(
+            "a.nrrd",
+            b"NRRD0004\ntype: ushort\ndimension: 3\nsizes: 1 1 2\nendian: little\nencoding: raw\n\n\x01\x01\x02\x01",
+        );
+        let mut beetle = vec![];
+        for d in [2_u16, 1, 1] {
+            beetle.extend(d.to_le_bytes());
+        }
+        for v in [3_u16, 4000] {
+            beetle.extend(v.to_le_bytes());
+        }
+        let beetle = dir.file("beetle.dat", &beetle);
+
+        let loaded = load_volume(&nrrd).unwrap();
+        assert_eq!(loaded.sample_type(), SampleType::U16);
+        let meta = loaded.into_typed::<u16>().unwrap();
+        assert_eq!(meta.data.unwrap().get_slice(), &[257, 258]);
+        assert!(matches!(
+            load_volume(&nrrd).unwrap().into_typed::<u8>(),
+            Err(Error::SampleType { found: "u16", .. })
+        ));
+
+        assert_eq!(detect_format(&beetle).unwrap().name, "Beetle");
+        let meta = load_volume(&beetle).unwrap().into_typed::<u16>().unwrap();
+        assert_eq!(meta.size, Some(nalgebra::vector![2, 1, 1]));
+        assert_eq!(meta.data.unwrap().get_slice(), &[3, 4000]);
+
+        let stack = TempDir::new("stack_u16");
+        stack.file("a.pgm", b"P5 1 1 4095\n\x01\x02");
+        stack.file("b.pgm", b"P5 1 1 4095\n\x03\x04");
+        let loaded = load_volume(&stack.0).unwrap();
+        assert_eq!(loaded.sample_type(), SampleType::U16);
+        let meta = loaded.into_typed::<u16>().unwrap();
+        assert_eq!(meta.data.unwrap().get_slice(), &[0x0102, 0x0304]);
+
+        let mut vol = VolHeader {
+            version: VOL_VERSION,
+            sample_type: SampleType::U16,
+            endian: Endian::Little,
+            size: vector![2, 1, 1],
+            scale: vector![1.0, 1.0, 1.0],
+            position: point![0.0, 0.0, 0.0],
+            data_shape: StorageShape::Linear,
+            block_overlap: 0,
+            checksum: None,
+            lod_levels: 1,
+            block_offsets: None,
+            sparse_table: None,
+            header_length: VOL_HEADER_LEN,
+        }
+        .to_bytes();
+        for v in [5_u16, 3000] {
+            vol.extend(v.to_le_bytes());
+        }
+        let vol = dir.file("a.vol", &vol);
+
+        let loaded = load_volume(&vol).unwrap();
+        assert_eq!(loaded.sample_type(), SampleType::U16);
+        let meta = loaded.into_typed::<u16>().unwrap();
+        assert!(meta.tf.is_none());
+        assert_eq!(meta.data.unwrap().get_slice(), &[5, 3000]);
+    }
+
+    #[test]
+    fn custom_parser() {
+        let dir = TempDir::new("custom");
+        let path = dir.file("a.custom", &[1, 2]);
+
+        fn sniff(path: &Path, _: &[u8]) -> bool {
+            has_extension(path, &["custom"])
+        }
+        fn parser(_: DataSource<u8>) -> Result<VolumeMetadata<u8>, Error> {
+            Err(Error::Invalid("Custom parser"))
+        }
+
+        assert!(detect_format(&path).is_err());
+        register_parser("Custom", sniff, parser);
+        assert_eq!(detect_format(&path).unwrap().name, "Custom");
+        assert!(matches!(
+            load_volume(&path),
+            Err(Error::Invalid("Custom parser"))
+        ));
+    }
+}
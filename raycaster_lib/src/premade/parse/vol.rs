@@ -0,0 +1,672 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Volume format of `vol_gen`.
+//!
+//! # Version 2
+//!
+//! Header is little endian, [`VOL_HEADER_LEN`] bytes long:
+//!
+//! | Offset | Type      | Field                                               |
+//! |--------|-----------|-----------------------------------------------------|
+//! | 0      | 4B        | magic `RVOL`                                        |
+//! | 4      | u16       | version                                             |
+//! | 6      | u8        | sample type (0 `u8`, 1 `u16`, 2 `i16`, 3 `f32`)     |
+//! | 7      | u8        | byte order of samples (0 little, 1 big)             |
+//! | 8      | 3x u32    | size                                                |
+//! | 20     | 3x f32    | spacing                                             |
+//! | 32     | 3x f32    | origin                                              |
+//! | 44     | u8        | storage shape (1 linear, 2 Z order)                 |
+//! | 45     | u8        | side of block (Z order)                             |
+//! | 46     | u8        | overlap of blocks (Z order)                         |
+//! | 47     | u8        | flags, bits: 0 checksum, 1 compressed, 2 sparse     |
+//! | 48     | u64       | checksum of samples, see [`VolChecksum`]            |
+//! | 56     | u32       | length of header, samples start at this offset      |
+//! | 60     | u8        | levels of detail (Z order), 0 and 1 mean none       |
+//! | 61     | 3B        | reserved                                            |
+//!
+//! Axes are in the order of the library, samples are stored with z axis changing fastest.
+//! Levels of detail follow full resolution blocks, see [`LodVolume`](crate::volumetric::volumes::LodVolume).
+//!
+//! # Compressed blocks
+//!
+//! With flag bit 1, blocks of Z order are compressed independently, see [`compress_block`].
+//! Header is followed by table of `block count + 1` u64 offsets, relative to the end of header.
+//! Block `i` takes bytes `offsets[i]..offsets[i + 1]`, the last offset is the end of data.
+//! Length of header includes the table. Checksum covers compressed bytes.
+//!
+//! # Sparse blocks
+//!
+//! With flag bit 2, uniform blocks of Z order are not stored, see [`SparseBlocks`].
+//! Header is followed by table of `block count` u64 entries.
+//! Entry with bit [`VOL_SPARSE_UNIFORM`] set is a uniform block, its value is in the lowest bytes
+//! of the little endian entry, in byte order of samples. Other entries are indexes of stored blocks.
+//! Length of header includes the table. Checksum covers stored blocks.
+//!
+//! # Version 1
+//!
+//! Header without magic, 26 bytes long: size (3x u32), spacing (3x f32),
+//! storage shape and side of block (2x u8). Samples are `u8`.
+
+use nalgebra::{point, vector, Point3, Vector3};
+
+use crate::{
+    volumetric::{
+        lod_block_len, CompressedBlocks, DataSource, Sample, SparseBlock, SparseBlocks,
+        StorageShape, VolumeMetadata,
+    },
+    Error,
+};
+
+use super::{read_samples, Endian, SampleType};
+
+const FORMAT: &str = "Volume file";
+
+/// Magic bytes at the beginning of version 2 file
+pub const VOL_MAGIC: [u8; 4] = *b"RVOL";
+/// Newest version of the format
+pub const VOL_VERSION: u16 = 2;
+/// Length of version 2 header
+pub const VOL_HEADER_LEN: usize = 64;
+/// Length of version 1 header
+pub const VOL_V1_HEADER_LEN: usize = 26;
+/// Offset of flags, followed by checksum
+pub const VOL_FLAGS_OFFSET: usize = 47;
+/// Flag of present checksum
+pub const VOL_FLAG_CHECKSUM: u8 = 1;
+/// Flag of compressed blocks
+pub const VOL_FLAG_COMPRESSED: u8 = 2;
+/// Flag of sparse blocks
+pub const VOL_FLAG_SPARSE: u8 = 4;
+/// Bit of sparse table entry marking uniform block
+pub const VOL_SPARSE_UNIFORM: u64 = 1 << 63;
+
+/// Checksum of samples, 64 bit FNV-1a.
+/// Can be computed incrementally, while samples are written.
+#[derive(Debug, Clone, Copy)]
+pub struct VolChecksum {
+    state: u64,
+}
+
+impl VolChecksum {
+    pub fn new() -> VolChecksum {
+        VolChecksum {
+            state: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.state ^= b as u64;
+            self.state = self.state.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+impl Default for VolChecksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Header of `vol_gen` file, either version
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolHeader {
+    pub version: u16,
+    pub sample_type: SampleType,
+    pub endian: Endian,
+    pub size: Vector3<usize>,
+    pub scale: Vector3<f32>,
+    pub position: Point3<f32>,
+    pub data_shape: StorageShape,
+    /// Overlap of blocks, `vol_gen` blocks overlap by one sample
+    pub block_overlap: u8,
+    pub checksum: Option<u64>,
+    /// Levels of detail of blocks, 1 if there are none
+    pub lod_levels: u8,
+    /// Offsets of compressed blocks after header, followed by the end of data.
+    /// `None` if samples are not compressed.
+    pub block_offsets: Option<Vec<u64>>,
+    /// Entries of sparse blocks, see [`VOL_SPARSE_UNIFORM`].
+    /// `None` if all blocks are stored.
+    pub sparse_table: Option<Vec<u64>>,
+    /// Length of header in bytes
+    pub header_length: usize,
+}
+
+impl VolHeader {
+    /// Parse header of version 2, or version 1 if magic is missing
+    pub fn parse(bytes: &[u8]) -> Result<VolHeader, Error> {
+        if bytes.starts_with(&VOL_MAGIC) {
+            Self::parse_v2(bytes)
+        } else {
+            Self::parse_v1(bytes)
+        }
+    }
+
+    fn parse_v1(bytes: &[u8]) -> Result<VolHeader, Error> {
+        if bytes.len() < VOL_V1_HEADER_LEN {
+            return Err(Error::parse(FORMAT, 0, "Header too short"));
+        }
+        let data_shape = match bytes[24] {
+            1 => StorageShape::Linear,
+            2 => StorageShape::Z(bytes[25]),
+            _ => return Err(Error::parse(FORMAT, 24, "Unknown data shape")),
+        };
+
+        Ok(VolHeader {
+            version: 1,
+            sample_type: SampleType::U8,
+            endian: Endian::Little,
+            size: read_u32s(bytes, 0).map(|v| v as usize),
+            scale: read_u32s(bytes, 12).map(f32::from_bits),
+            position: point![0.0, 0.0, 0.0],
+            data_shape,
+            block_overlap: 1,
+            checksum: None,
+            lod_levels: 1,
+            block_offsets: None,
+            sparse_table: None,
+            header_length: VOL_V1_HEADER_LEN,
+        })
+    }
+
+    fn parse_v2(bytes: &[u8]) -> Result<VolHeader, Error> {
+        if bytes.len() < VOL_HEADER_LEN {
+            return Err(Error::parse(FORMAT, 0, "Header too short"));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VOL_VERSION {
+            return Err(Error::Unsupported("Version of volume file"));
+        }
+        let sample_type = match bytes[6] {
+            0 => SampleType::U8,
+            1 => SampleType::U16,
+            2 => SampleType::I16,
+            3 => SampleType::F32,
+            _ => return Err(Error::parse(FORMAT, 6, "Unknown sample type")),
+        };
+        let endian = match bytes[7] {
+            0 => Endian::Little,
+            1 => Endian::Big,
+            _ => return Err(Error::parse(FORMAT, 7, "Unknown byte order")),
+        };
+        let data_shape = match bytes[44] {
+            1 => StorageShape::Linear,
+            2 => StorageShape::Z(bytes[45]),
+            _ => return Err(Error::parse(FORMAT, 44, "Unknown data shape")),
+        };
+        let checksum = if bytes[VOL_FLAGS_OFFSET] & VOL_FLAG_CHECKSUM != 0 {
+            let mut checksum = [0; 8];
+            checksum.copy_from_slice(&bytes[48..56]);
+            Some(u64::from_le_bytes(checksum))
+        } else {
+            None
+        };
+        let header_length = u32::from_le_bytes([bytes[56], bytes[57], bytes[58], bytes[59]]);
+        let header_length = header_length as usize;
+        if header_length < VOL_HEADER_LEN {
+            return Err(Error::parse(FORMAT, 56, "Invalid header length"));
+        }
+
+        let mut header = VolHeader {
+            version,
+            sample_type,
+            endian,
+            size: read_u32s(bytes, 8).map(|v| v as usize),
+            scale: read_u32s(bytes, 20).map(f32::from_bits),
+            position: read_u32s(bytes, 32).map(f32::from_bits).into(),
+            data_shape,
+            block_overlap: bytes[46],
+            checksum,
+            lod_levels: bytes[60].max(1),
+            block_offsets: None,
+            sparse_table: None,
+            header_length,
+        };
+
+        let flags = bytes[VOL_FLAGS_OFFSET];
+        if flags & VOL_FLAG_COMPRESSED != 0 && flags & VOL_FLAG_SPARSE != 0 {
+            return Err(Error::parse(
+                FORMAT,
+                VOL_FLAGS_OFFSET,
+                "Blocks both compressed and sparse",
+            ));
+        }
+        if flags & VOL_FLAG_COMPRESSED != 0 {
+            let entries = header.block_count()? + 1;
+            header.block_offsets = Some(read_block_table(bytes, header_length, entries)?);
+        }
+        if flags & VOL_FLAG_SPARSE != 0 {
+            let entries = header.block_count()?;
+            header.sparse_table = Some(read_block_table(bytes, header_length, entries)?);
+        }
+        Ok(header)
+    }
+
+    /// Encode as version 2 header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(VOL_HEADER_LEN);
+        bytes.extend_from_slice(&VOL_MAGIC);
+        bytes.extend(VOL_VERSION.to_le_bytes());
+        bytes.push(match self.sample_type {
+            SampleType::U8 => 0,
+            SampleType::U16 => 1,
+            SampleType::I16 => 2,
+            SampleType::F32 => 3,
+        });
+        bytes.push(match self.endian {
+            Endian::Little => 0,
+            Endian::Big => 1,
+        });
+        for v in self.size.iter() {
+            bytes.extend((*v as u32).to_le_bytes());
+        }
+        for v in self.scale.iter().chain(self.position.iter()) {
+            bytes.extend(v.to_le_bytes());
+        }
+        match self.data_shape {
+            StorageShape::Linear => bytes.extend([1, 0, 0]),
+            StorageShape::Z(side) => bytes.extend([2, side, self.block_overlap]),
+        }
+        let mut flags = 0;
+        if self.checksum.is_some() {
+            flags |= VOL_FLAG_CHECKSUM;
+        }
+        if self.block_offsets.is_some() {
+            flags |= VOL_FLAG_COMPRESSED;
+        }
+        if self.sparse_table.is_some() {
+            flags |= VOL_FLAG_SPARSE;
+        }
+        bytes.push(flags);
+        bytes.extend(self.checksum.unwrap_or(0).to_le_bytes());
+        let table = self
+            .block_offsets
+            .as_deref()
+            .or(self.sparse_table.as_deref())
+            .unwrap_or(&[]);
+        bytes.extend(((VOL_HEADER_LEN + 8 * table.len()) as u32).to_le_bytes());
+        bytes.push(self.lod_levels);
+        bytes.extend([0; 3]);
+        for offset in table {
+            bytes.extend(offset.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Number of blocks of Z order, without levels of detail
+    pub fn block_count(&self) -> Result<usize, Error> {
+        let side = match self.data_shape {
+            StorageShape::Z(side) => side,
+            StorageShape::Linear => return Err(Error::Shape("Blocks need Z order storage shape")),
+        };
+        let cells = side
+            .checked_sub(self.block_overlap)
+            .filter(|&c| c > 0)
+            .ok_or(Error::Shape("Invalid block side"))? as usize;
+        let blocks = self.size.map(|s| (s.max(1) - 1).div_ceil(cells).max(1));
+        checked_product(blocks).ok_or(Error::Shape("Number of blocks overflows"))
+    }
+
+    /// Number of samples in file, Z order is padded to whole blocks.
+    /// Levels of detail are included.
+    pub fn sample_count(&self) -> Result<usize, Error> {
+        match self.data_shape {
+            StorageShape::Linear => checked_product(self.size),
+            StorageShape::Z(side) => self
+                .block_count()?
+                .checked_mul(lod_block_len(side as usize, self.lod_levels)),
+        }
+        .ok_or(Error::Shape("Size of volume overflows"))
+    }
+
+    /// Build metadata, samples are read from `data_source` after the header.
+    /// Checksum is verified, if present.
+    pub fn metadata<T: Sample>(
+        &self,
+        data_source: DataSource<u8>,
+    ) -> Result<VolumeMetadata<T>, Error> {
+        self.sample_type.check::<T>()?;
+        if matches!(self.data_shape, StorageShape::Z(_)) && self.block_overlap != 1 {
+            return Err(Error::Unsupported("Block overlap other than one sample"));
+        }
+
+        let count = match &self.sparse_table {
+            Some(table) => {
+                let stored = table.iter().filter(|&e| e & VOL_SPARSE_UNIFORM == 0);
+                stored
+                    .count()
+                    .checked_mul(self.block_side().pow(3))
+                    .ok_or(Error::Shape("Size of volume overflows"))?
+            }
+            None => self.sample_count()?,
+        };
+        let data_length = match &self.block_offsets {
+            Some(offsets) => usize::try_from(*offsets.last().unwrap_or(&0))
+                .map_err(|_| Error::Shape("Size of compressed data overflows"))?,
+            None => count
+                .checked_mul(self.sample_type.size())
+                .ok_or(Error::Shape("Size of volume overflows"))?,
+        };
+        if let Some(checksum) = self.checksum {
+            let start = self.header_length;
+            let end = start
+                .checked_add(data_length)
+                .ok_or(Error::Shape("Size of volume overflows"))?;
+            let bytes = data_source.get_slice();
+            Error::check_length(end, bytes.len())?;
+            let mut computed = VolChecksum::new();
+            computed.update(&bytes[start..end]);
+            if computed.finish() != checksum {
+                return Err(Error::Invalid("Checksum does not match"));
+            }
+        }
+        if self.sparse_table.is_some() && self.lod_levels > 1 {
+            return Err(Error::Unsupported("Sparse levels of detail"));
+        }
+        let (data, compressed_blocks) = match &self.block_offsets {
+            Some(offsets) => {
+                if self.lod_levels > 1 {
+                    return Err(Error::Unsupported("Compressed levels of detail"));
+                }
+                if self.sample_type.size() > 1 && self.endian != Endian::native() {
+                    return Err(Error::Unsupported(
+                        "Compressed samples in foreign byte order",
+                    ));
+                }
+                let end = self
+                    .header_length
+                    .checked_add(data_length)
+                    .ok_or(Error::Shape("Size of compressed data overflows"))?;
+                Error::check_length(end, data_source.byte_len())?;
+                let mut data = data_source.clone_with_offset(self.header_length);
+                data.truncate(data_length);
+                let offsets = offsets.iter().map(|&o| o as usize).collect();
+                (None, Some(CompressedBlocks::new(data, offsets)))
+            }
+            None => {
+                let data = read_samples(data_source, self.header_length, count, self.endian)?;
+                (Some(data), None)
+            }
+        };
+        let (data, sparse_blocks) = match (&self.sparse_table, data) {
+            (Some(table), Some(data)) => (None, Some(self.sparse_blocks(table, data)?)),
+            (_, data) => (data, None),
+        };
+
+        Ok(VolumeMetadata {
+            position: Some(self.position),
+            size: Some(self.size),
+            scale: Some(self.scale),
+            data,
+            data_shape: Some(self.data_shape),
+            lod_levels: (self.lod_levels > 1).then_some(self.lod_levels),
+            compressed_blocks,
+            sparse_blocks,
+            ..Default::default()
+        })
+    }
+
+    /// Side of Z order blocks, 0 for linear storage
+    fn block_side(&self) -> usize {
+        match self.data_shape {
+            StorageShape::Z(side) => side as usize,
+            StorageShape::Linear => 0,
+        }
+    }
+
+    /// Decode table of sparse blocks, values of uniform blocks are in byte order of samples
+    fn sparse_blocks<T: Sample>(
+        &self,
+        table: &[u64],
+        data: DataSource<T>,
+    ) -> Result<SparseBlocks<T>, Error> {
+        let size = self.sample_type.size();
+        let uniform_bytes: Vec<u8> = table
+            .iter()
+            .filter(|&e| e & VOL_SPARSE_UNIFORM != 0)
+            .flat_map(|e| e.to_le_bytes()[..size].to_vec())
+            .collect();
+        let count = uniform_bytes.len() / size;
+        let uniform = read_samples::<T>(DataSource::Vec(uniform_bytes), 0, count, self.endian)?;
+        let mut uniform = uniform.get_slice().iter();
+
+        let entries = table
+            .iter()
+            .map(|e| match e & VOL_SPARSE_UNIFORM {
+                0 => SparseBlock::Stored(*e as usize),
+                _ => SparseBlock::Uniform(*uniform.next().unwrap()),
+            })
+            .collect();
+        Ok(SparseBlocks::new(data, entries))
+    }
+}
+
+/// Table of `entries` u64 following version 2 header, it has to fit into `header_length`
+fn read_block_table(bytes: &[u8], header_length: usize, entries: usize) -> Result<Vec<u64>, Error> {
+    let table_end = entries
+        .checked_mul(8)
+        .and_then(|len| len.checked_add(VOL_HEADER_LEN))
+        .ok_or(Error::Shape("Size of block table overflows"))?;
+    if header_length < table_end {
+        return Err(Error::parse(FORMAT, 56, "Header too short for block table"));
+    }
+    Error::check_length(table_end, bytes.len())?;
+    Ok(bytes[VOL_HEADER_LEN..table_end]
+        .chunks_exact(8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .collect())
+}
+
+/// Product of sizes, `None` on overflow
+fn checked_product(v: Vector3<usize>) -> Option<usize> {
+    v.iter().try_fold(1_usize, |acc, &s| acc.checked_mul(s))
+}
+
+/// File generated by `vol_gen`, version 1 or 2.
+/// Transfer function is not set.
+pub fn vol_parser<T: Sample>(data_source: DataSource<u8>) -> Result<VolumeMetadata<T>, Error> {
+    let header = VolHeader::parse(data_source.get_slice())?;
+    header.metadata(data_source)
+}
+
+/// Three little endian 32 bit words at `offset`
+fn read_u32s(bytes: &[u8], offset: usize) -> Vector3<u32> {
+    let word = |i: usize| {
+        let i = offset + 4 * i;
+        u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+    };
+    vector![word(0), word(1), word(2)]
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::volumetric::compress_block;
+
+    fn header(data_shape: StorageShape, checksum: Option<u64>) -> VolHeader {
+        VolHeader {
+            version: VOL_VERSION,
+            sample_type: SampleType::U16,
+            endian: Endian::Big,
+            size: vector![1, 2, 3],
+            scale: vector![0.5, 1.0, 2.0],
+            position: point![-1.0, 0.0, 1.0],
+            data_shape,
+            block_overlap: match data_shape {
+                StorageShape::Linear => 0,
+                StorageShape::Z(_) => 1,
+            },
+            checksum,
+            lod_levels: 1,
+            block_offsets: None,
+            sparse_table: None,
+            header_length: VOL_HEADER_LEN,
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let linear = header(StorageShape::Linear, None);
+        let bytes = linear.to_bytes();
+        assert_eq!(bytes.len(), VOL_HEADER_LEN);
+        assert_eq!(VolHeader::parse(&bytes).unwrap(), linear);
+
+        let mut z = header(StorageShape::Z(4), Some(0x0123_4567_89ab_cdef));
+        assert_eq!(VolHeader::parse(&z.to_bytes()).unwrap(), z);
+        z.lod_levels = 3;
+        assert_eq!(VolHeader::parse(&z.to_bytes()).unwrap(), z);
+
+        // Size 1x2x3 is a single block
+        z.lod_levels = 1;
+        z.block_offsets = Some(vec![0, 10]);
+        z.header_length = VOL_HEADER_LEN + 16;
+        let bytes = z.to_bytes();
+        assert_eq!(bytes.len(), VOL_HEADER_LEN + 16);
+        assert_eq!(VolHeader::parse(&bytes).unwrap(), z);
+        assert!(VolHeader::parse(&bytes[..VOL_HEADER_LEN + 8]).is_err());
+    }
+
+    #[test]
+    fn read_sparse() {
+        // 3x3x5 in blocks of side 3, second block is stored
+        let stored: Vec<u8> = (0..54).collect();
+        let mut checksum = VolChecksum::new();
+        checksum.update(&stored);
+
+        let mut h = header(StorageShape::Z(3), Some(checksum.finish()));
+        h.size = vector![3, 3, 5];
+        h.sparse_table = Some(vec![VOL_SPARSE_UNIFORM | 0x0102, 0]);
+        h.header_length = VOL_HEADER_LEN + 16;
+        let mut file = h.to_bytes();
+        assert_eq!(VolHeader::parse(&file).unwrap(), h);
+        file.extend(&stored);
+
+        let meta: VolumeMetadata<u16> = vol_parser(DataSource::Vec(file.clone())).unwrap();
+        assert!(meta.data.is_none());
+        let blocks = meta.sparse_blocks.unwrap();
+        // Big endian samples
+        assert_eq!(
+            blocks.table,
+            [SparseBlock::Uniform(0x0201), SparseBlock::Stored(0)]
+        );
+        assert_eq!(blocks.data.get_slice()[..2], [1, 515]);
+
+        file.pop();
+        assert!(vol_parser::<u16>(DataSource::Vec(file)).is_err());
+    }
+
+    #[test]
+    fn read_compressed() {
+        let samples: Vec<u8> = (0..8).collect();
+        let compressed = compress_block(&samples);
+        let mut checksum = VolChecksum::new();
+        checksum.update(&compressed);
+
+        let mut h = header(StorageShape::Z(2), Some(checksum.finish()));
+        h.sample_type = SampleType::U8;
+        h.size = vector![2, 2, 2];
+        h.block_offsets = Some(vec![0, compressed.len() as u64]);
+        let mut file = h.to_bytes();
+        file.extend(&compressed);
+
+        let meta: VolumeMetadata<u8> = vol_parser(DataSource::Vec(file.clone())).unwrap();
+        assert!(meta.data.is_none());
+        let blocks = meta.compressed_blocks.unwrap();
+        assert_eq!(blocks.block(0), &compressed[..]);
+
+        file.pop();
+        assert!(vol_parser::<u8>(DataSource::Vec(file)).is_err());
+
+        h.checksum = None;
+        h.block_offsets = Some(vec![0, u64::MAX]);
+        assert!(matches!(
+            vol_parser::<u8>(DataSource::Vec(h.to_bytes())),
+            Err(Error::Shape(_))
+        ));
+    }
+
+    #[test]
+    fn read_v2() {
+        let samples: Vec<u8> = (0..12).collect();
+        let mut checksum = VolChecksum::new();
+        checksum.update(&samples);
+
+        let mut file = header(StorageShape::Linear, Some(checksum.finish())).to_bytes();
+        file.extend(&samples);
+
+        let meta: VolumeMetadata<u16> = vol_parser(DataSource::Vec(file.clone())).unwrap();
+        assert_eq!(meta.size, Some(vector![1, 2, 3]));
+        assert_eq!(meta.position, Some(point![-1.0, 0.0, 1.0]));
+        assert_eq!(
+            meta.data.unwrap().get_slice(),
+            &[1, 515, 1029, 1543, 2057, 2571]
+        );
+
+        assert!(vol_parser::<u8>(DataSource::Vec(file.clone())).is_err());
+
+        let last = file.len() - 1;
+        file[last] += 1;
+        assert!(matches!(
+            vol_parser::<u16>(DataSource::Vec(file)),
+            Err(Error::Invalid("Checksum does not match"))
+        ));
+    }
+
+    #[test]
+    fn read_v1() {
+        let mut file = vec![];
+        for v in [2_u32, 2, 2] {
+            file.extend(v.to_le_bytes());
+        }
+        for v in [1.0_f32, 2.0, 3.0] {
+            file.extend(v.to_le_bytes());
+        }
+        file.extend([1, 0]);
+        file.extend([5; 8]);
+
+        let meta: VolumeMetadata<u8> = vol_parser(DataSource::Vec(file)).unwrap();
+        assert_eq!(meta.scale, Some(vector![1.0, 2.0, 3.0]));
+        assert_eq!(meta.data_shape, Some(StorageShape::Linear));
+        assert_eq!(meta.data.unwrap().get_slice(), &[5; 8]);
+    }
+
+    #[test]
+    fn z_sample_count() {
+        let mut h = header(StorageShape::Z(3), None);
+        h.size = vector![5, 5, 5];
+        assert_eq!(h.sample_count().unwrap(), 8 * 27);
+        h.size = vector![5, 5, 6];
+        assert_eq!(h.sample_count().unwrap(), 12 * 27);
+        h.lod_levels = 2;
+        assert_eq!(h.sample_count().unwrap(), 12 * (27 + 8));
+    }
+
+    #[test]
+    fn size_overflow() {
+        let size = Vector3::repeat(u32::MAX as usize);
+        let mut h = header(StorageShape::Linear, None);
+        h.size = size;
+        assert!(matches!(h.sample_count(), Err(Error::Shape(_))));
+        let mut h = header(StorageShape::Z(3), None);
+        h.size = size;
+        assert!(matches!(h.block_count(), Err(Error::Shape(_))));
+        assert!(matches!(
+            h.metadata::<u16>(DataSource::Vec(vec![])),
+            Err(Error::Shape(_))
+        ));
+        assert!(matches!(
+            read_block_table(&[], usize::MAX, usize::MAX),
+            Err(Error::Shape(_))
+        ));
+    }
+}
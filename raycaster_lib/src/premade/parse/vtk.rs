@@ -0,0 +1,375 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! [VTK legacy](https://kitware.github.io/vtk-examples/site/VTKFileFormats/) `STRUCTURED_POINTS` files.
+//!
+//! Supported are binary files (big endian) with scalars of type
+//! `unsigned_char`, `unsigned_short`, `short` or `float`.
+//! Scalars with more components are read as multi-channel volumes.
+//!
+//! Order of axes is reversed, see [`reverse_axes`].
+
+use std::io::{self, Write};
+
+use nalgebra::{vector, Point3, Vector3};
+
+use crate::{
+    volumetric::{ChannelLayout, DataSource, Sample, StorageShape, Volume, VolumeMetadata},
+    Error,
+};
+
+const FORMAT: &str = "VTK";
+
+use super::{read_samples, reverse_axes, sample_count, Endian, SampleType};
+
+/// Fields of VTK header, needed to read a volume
+#[derive(Debug, Clone, PartialEq)]
+pub struct VtkHeader {
+    pub dimensions: Vector3<usize>,
+    pub spacing: Vector3<f32>,
+    pub origin: Point3<f32>,
+    pub sample_type: SampleType,
+    /// Components of scalars
+    pub components: usize,
+    /// Length of header in bytes
+    pub header_length: usize,
+}
+
+impl VtkHeader {
+    /// Parse header at the beginning of `bytes`
+    pub fn parse(bytes: &[u8]) -> Result<VtkHeader, Error> {
+        let mut dimensions = None;
+        let mut spacing = vector![1.0, 1.0, 1.0];
+        let mut origin = vector![0.0, 0.0, 0.0];
+        let mut scalars = None;
+        let mut header_length = None;
+
+        let mut line_start = 0;
+        let mut line_number = 0;
+        while header_length.is_none() {
+            if line_start >= bytes.len() {
+                return Err(Error::parse(FORMAT, line_start, "Missing LOOKUP_TABLE"));
+            }
+            let invalid = |reason| Error::parse(FORMAT, line_start, reason);
+            let line_end = bytes[line_start..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(bytes.len(), |end| line_start + end + 1);
+            let line = std::str::from_utf8(&bytes[line_start..line_end])
+                .map_err(|_| invalid("Header is not text"))?
+                .trim();
+            line_number += 1;
+
+            match line_number {
+                1 if !line.starts_with("# vtk DataFile") => return Err(invalid("Not a VTK file")),
+                1 | 2 => {
+                    // version and title
+                    line_start = line_end;
+                    continue;
+                }
+                3 if line != "BINARY" => return Err(Error::Unsupported("ASCII VTK files")),
+                4 if line != "DATASET STRUCTURED_POINTS" => {
+                    return Err(Error::Unsupported(
+                        "VTK datasets other than STRUCTURED_POINTS",
+                    ))
+                }
+                _ => (),
+            }
+
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("DIMENSIONS") => dimensions = Some(parse_vector(words).map_err(invalid)?),
+                Some("SPACING") | Some("ASPECT_RATIO") => {
+                    spacing = parse_vector(words).map_err(invalid)?
+                }
+                Some("ORIGIN") => origin = parse_vector(words).map_err(invalid)?,
+                Some("SCALARS") => {
+                    let _name = words.next();
+                    let sample_type = match words.next() {
+                        Some("unsigned_char") => SampleType::U8,
+                        Some("unsigned_short") => SampleType::U16,
+                        Some("short") => SampleType::I16,
+                        Some("float") => SampleType::F32,
+                        _ => return Err(Error::Unsupported("VTK scalar type")),
+                    };
+                    let components = match words.next() {
+                        Some(c) => c.parse().map_err(|_| invalid("Invalid number"))?,
+                        None => 1,
+                    };
+                    scalars = Some((sample_type, components));
+                }
+                Some("LOOKUP_TABLE") => header_length = Some(line_end),
+                _ => (), // Not needed for rendering
+            }
+            line_start = line_end;
+        }
+
+        let end = line_start;
+        let (sample_type, components) =
+            scalars.ok_or_else(|| Error::parse(FORMAT, end, "Missing SCALARS"))?;
+        if !(1..=4).contains(&components) {
+            return Err(Error::Unsupported("Number of VTK scalar components"));
+        }
+
+        Ok(VtkHeader {
+            dimensions: dimensions
+                .ok_or_else(|| Error::parse(FORMAT, end, "Missing DIMENSIONS"))?,
+            spacing,
+            origin: origin.into(),
+            sample_type,
+            components,
+            header_length: header_length.unwrap_or_default(),
+        })
+    }
+
+    /// Build metadata, samples are read from `data_source` after the header
+    pub fn metadata<T: Sample>(
+        &self,
+        data_source: DataSource<u8>,
+    ) -> Result<VolumeMetadata<T>, Error> {
+        self.sample_type.check::<T>()?;
+
+        let count = sample_count(self.dimensions, self.components)?;
+        let data = read_samples(data_source, self.header_length, count, Endian::Big)?;
+
+        let (channels, channel_layout) = if self.components > 1 {
+            (Some(self.components), Some(ChannelLayout::Interleaved))
+        } else {
+            (None, None)
+        };
+
+        Ok(VolumeMetadata {
+            position: Some(reverse_axes(self.origin.coords).into()),
+            size: Some(reverse_axes(self.dimensions)),
+            scale: Some(reverse_axes(self.spacing)),
+            data: Some(data),
+            data_shape: Some(StorageShape::Linear),
+            channels,
+            channel_layout,
+            ..Default::default()
+        })
+    }
+}
+
+/// VTK legacy file, binary `STRUCTURED_POINTS` dataset.
+/// Transfer function is not set.
+pub fn vtk_parser<T: Sample>(data_source: DataSource<u8>) -> Result<VolumeMetadata<T>, Error> {
+    let header = VtkHeader::parse(data_source.get_slice())?;
+    header.metadata(data_source)
+}
+
+/// Write `volume` as VTK legacy file, binary `STRUCTURED_POINTS` dataset.
+/// Samples are rounded and clamped to `sample_type`.
+/// Dataset is axis-aligned, error of kind `Unsupported` is returned for oriented volumes.
+///
+/// # Example
+/// ```no_run
+/// # use raycaster_lib::{premade::parse::{write_vtk, SampleType}, volumetric::volumes::FloatVolume};
+/// # fn export(volume: &FloatVolume) -> std::io::Result<()> {
+/// let file = std::fs::File::create("volume.vtk")?;
+/// write_vtk(volume, std::io::BufWriter::new(file), SampleType::U8)
+/// # }
+/// ```
+pub fn write_vtk<V, W>(volume: &V, mut writer: W, sample_type: SampleType) -> io::Result<()>
+where
+    V: Volume,
+    W: Write,
+{
+    let size = volume.get_size();
+    let bound_box = volume.get_bound_box();
+    if bound_box.orientation.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            Error::Unsupported("Oriented volumes in VTK files"),
+        ));
+    }
+    // Not every volume implements `get_scale`, spacing is derived from bounding box instead
+    let spacing = bound_box.dims().zip_map(
+        &size,
+        |dim, n| if n > 1 { dim / (n - 1) as f32 } else { 1.0 },
+    );
+
+    let dimensions = reverse_axes(size);
+    let spacing = reverse_axes(spacing);
+    let origin = reverse_axes(bound_box.lower.coords);
+    let type_name = match sample_type {
+        SampleType::U8 => "unsigned_char",
+        SampleType::U16 => "unsigned_short",
+        SampleType::I16 => "short",
+        SampleType::F32 => "float",
+    };
+
+    let header = format!(
+        "# vtk DataFile Version 3.0\n\
+        {}\n\
+        BINARY\n\
+        DATASET STRUCTURED_POINTS\n\
+        DIMENSIONS {} {} {}\n\
+        SPACING {} {} {}\n\
+        ORIGIN {} {} {}\n\
+        POINT_DATA {}\n\
+        SCALARS scalars {} 1\n\
+        LOOKUP_TABLE default\n",
+        V::get_name(),
+        dimensions.x,
+        dimensions.y,
+        dimensions.z,
+        spacing.x,
+        spacing.y,
+        spacing.z,
+        origin.x,
+        origin.y,
+        origin.z,
+        size.product(),
+        type_name,
+    );
+    writer.write_all(header.as_bytes())?;
+
+    // Volumes store z axis fastest, the same as reversed axes of the file
+    let mut bytes = Vec::with_capacity(size.z * sample_type.size());
+    for x in 0..size.x {
+        for y in 0..size.y {
+            bytes.clear();
+            for z in 0..size.z {
+                let value = volume.get_data(x, y, z).unwrap_or_default();
+                match sample_type {
+                    SampleType::U8 => bytes.push(value.round() as u8),
+                    SampleType::U16 => bytes.extend((value.round() as u16).to_be_bytes()),
+                    SampleType::I16 => bytes.extend((value.round() as i16).to_be_bytes()),
+                    SampleType::F32 => bytes.extend(value.to_be_bytes()),
+                }
+            }
+            writer.write_all(&bytes)?;
+        }
+    }
+
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+/// Three whitespace separated numbers
+fn parse_vector<'a, N, I>(words: I) -> Result<Vector3<N>, &'static str>
+where
+    N: std::str::FromStr + nalgebra::Scalar,
+    I: Iterator<Item = &'a str>,
+{
+    let numbers = words
+        .map(|w| w.parse().map_err(|_| "Invalid number"))
+        .collect::<Result<Vec<N>, _>>()?;
+    match <[N; 3]>::try_from(numbers) {
+        Ok(v) => Ok(v.into()),
+        Err(_) => Err("Dataset must be 3D"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::{
+        test_helpers::{empty_vol_meta, header_file},
+        volumetric::{volumes::FloatVolume, BuildVolume},
+    };
+    use nalgebra::point;
+
+    #[test]
+    fn read() {
+        let header = "# vtk DataFile Version 3.0\n\
+            CT scan\n\
+            BINARY\n\
+            DATASET STRUCTURED_POINTS\n\
+            DIMENSIONS 2 1 1\n\
+            SPACING 0.5 1 2\n\
+            ORIGIN 10 20 30\n\
+            POINT_DATA 2\n\
+            SCALARS density short\n\
+            LOOKUP_TABLE default\n";
+
+        let meta: VolumeMetadata<i16> =
+            vtk_parser(header_file(header, &[0xff, 0x00, 0x01, 0x00])).unwrap();
+
+        // Axes are reversed
+        assert_eq!(meta.size, Some(vector![1, 1, 2]));
+        assert_eq!(meta.scale, Some(vector![2.0, 1.0, 0.5]));
+        assert_eq!(meta.position, Some(point![30.0, 20.0, 10.0]));
+        assert_eq!(meta.data.unwrap().get_slice(), &[-256, 256]);
+    }
+
+    #[test]
+    fn invalid() {
+        let parse = |header: &str| vtk_parser::<u8>(header_file(header, &[0; 8])).is_ok();
+        let header = |format: &str, dataset: &str, scalars: &str| {
+            format!(
+                "# vtk DataFile Version 2.0\ntitle\n{}\nDATASET {}\nDIMENSIONS 2 2 2\n\
+                POINT_DATA 8\nSCALARS s {}\nLOOKUP_TABLE default\n",
+                format, dataset, scalars
+            )
+        };
+
+        assert!(parse(&header(
+            "BINARY",
+            "STRUCTURED_POINTS",
+            "unsigned_char"
+        )));
+        assert!(!parse(&header(
+            "ASCII",
+            "STRUCTURED_POINTS",
+            "unsigned_char"
+        )));
+        assert!(!parse(&header("BINARY", "POLYDATA", "unsigned_char")));
+        assert!(!parse(&header("BINARY", "STRUCTURED_POINTS", "double")));
+        assert!(!parse(&header("BINARY", "STRUCTURED_POINTS", "float")));
+
+        let big = "# vtk DataFile Version 2.0\ntitle\nBINARY\nDATASET STRUCTURED_POINTS\n\
+            DIMENSIONS 4294967295 4294967295 4294967295\nSCALARS s unsigned_char\n\
+            LOOKUP_TABLE default\n";
+        assert!(matches!(
+            vtk_parser::<u8>(header_file(big, &[0; 8])),
+            Err(Error::Shape(_))
+        ));
+    }
+
+    #[test]
+    fn write_read() {
+        let samples: Vec<u8> = (0..24).collect();
+        let mut meta = empty_vol_meta::<u8>(vector![2, 3, 4]);
+        meta.set_data(DataSource::Vec(samples.clone()))
+            .set_position(point![1.0, 2.0, 3.0])
+            .set_scale(vector![0.5, 1.0, 1.5]);
+        let volume: FloatVolume = BuildVolume::build(meta).unwrap();
+
+        let mut bytes = vec![];
+        write_vtk(&volume, &mut bytes, SampleType::F32).unwrap();
+
+        let meta: VolumeMetadata<f32> = vtk_parser(DataSource::Vec(bytes)).unwrap();
+        let floats: Vec<f32> = samples.iter().map(|&s| s as f32).collect();
+
+        assert_eq!(meta.size, Some(vector![2, 3, 4]));
+        assert_eq!(meta.scale, Some(vector![0.5, 1.0, 1.5]));
+        assert_eq!(meta.position, Some(point![1.0, 2.0, 3.0]));
+        assert_eq!(meta.data.unwrap().get_slice(), floats.as_slice());
+    }
+
+    #[test]
+    fn write_oriented() {
+        let mut meta = empty_vol_meta::<u8>(vector![2, 2, 2]);
+        meta.set_data(DataSource::Vec(vec![0; 8]))
+            .set_world_transform(
+                nalgebra::Rotation3::from_euler_angles(0.0, 1.0, 0.0).to_homogeneous(),
+            );
+        let volume: FloatVolume = BuildVolume::build(meta).unwrap();
+
+        let mut bytes = vec![];
+        let e = write_vtk(&volume, &mut bytes, SampleType::U8).unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+        assert!(matches!(
+            e.get_ref().and_then(|e| e.downcast_ref()),
+            Some(Error::Unsupported(_))
+        ));
+        assert!(bytes.is_empty());
+    }
+}
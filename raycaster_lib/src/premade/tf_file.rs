@@ -0,0 +1,387 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Transfer function file format.
+//!
+//! Text format, one directive per line.
+//! Text after `#` is a comment, blank lines are ignored.
+//!
+//! ```text
+//! raycaster_tf 1          # header with format version, must be first
+//! domain 0 255            # range of sample values the function is made for
+//! color_space rgb         # `rgb` (channels <0;255>) or `hsv` (hue in degrees, s and v <0;1>)
+//! color 0 0 0 0           # color control point: value c1 c2 c3
+//! color 255 227 218 201
+//! opacity 40 0            # opacity control point: value opacity <0;1>
+//! opacity 60 1
+//! ```
+//!
+//! `color_space` is optional, default is `rgb`.
+//! Control points must be sorted by value and lie inside of the domain.
+//! Colors are interpolated in RGB, see [`PiecewiseLinearTf::from_color_opacity`].
+
+use std::{fmt::Write, path::Path};
+
+use nalgebra::{vector, Vector3};
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{space1, u32},
+    combinator::{all_consuming, map, value},
+    number::complete::float,
+    sequence::{preceded, tuple},
+    IResult,
+};
+
+use crate::{color, common::ValueRange, tf::PiecewiseLinearTf, Error};
+
+/// Current version of the format
+const VERSION: u32 = 1;
+/// Name of the format in errors
+const FORMAT: &str = "Transfer function";
+
+/// Color space of color control points
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Red, green, blue, range `<0;255>`
+    Rgb,
+    /// Hue in degrees, saturation and value in range `<0;1>`
+    Hsv,
+}
+
+/// Transfer function as stored in a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct TfDescription {
+    /// Range of sample values
+    pub domain: ValueRange,
+    pub color_space: ColorSpace,
+    /// Color control points `(sample value, color)`
+    pub color_points: Vec<(f32, Vector3<f32>)>,
+    /// Opacity control points `(sample value, opacity)`
+    pub opacity_points: Vec<(f32, f32)>,
+}
+
+impl TfDescription {
+    /// Describe piecewise-linear transfer function.
+    /// Both color and opacity control points are placed on control points of `tf`.
+    pub fn from_piecewise(tf: &PiecewiseLinearTf, domain: ValueRange) -> TfDescription {
+        let points = tf.get_points();
+        TfDescription {
+            domain,
+            color_space: ColorSpace::Rgb,
+            color_points: points.iter().map(|(v, c)| (*v, c.xyz())).collect(),
+            opacity_points: points.iter().map(|(v, c)| (*v, c.w)).collect(),
+        }
+    }
+
+    /// Check that the description is valid
+    pub fn validate(&self) -> Result<(), Error> {
+        let ValueRange { low, high } = self.domain;
+        if !low.is_finite() || !high.is_finite() || low >= high {
+            return Err(Error::Invalid("Invalid domain"));
+        }
+
+        let values = self
+            .color_points
+            .iter()
+            .map(|p| p.0)
+            .chain(self.opacity_points.iter().map(|p| p.0));
+        for v in values {
+            if !self.domain.contains(v) {
+                return Err(Error::Invalid("Control point outside of domain"));
+            }
+        }
+
+        if self.color_points.windows(2).any(|w| w[0].0 > w[1].0)
+            || self.opacity_points.windows(2).any(|w| w[0].0 > w[1].0)
+        {
+            return Err(Error::Invalid("Control points are not sorted"));
+        }
+
+        if self
+            .opacity_points
+            .iter()
+            .any(|&(_, a)| !(0.0..=1.0).contains(&a))
+        {
+            return Err(Error::Invalid("Opacity out of range"));
+        }
+
+        let color_valid = |c: &Vector3<f32>| match self.color_space {
+            ColorSpace::Rgb => c.iter().all(|ch| (0.0..=255.0).contains(ch)),
+            ColorSpace::Hsv => {
+                (0.0..=360.0).contains(&c.x)
+                    && (0.0..=1.0).contains(&c.y)
+                    && (0.0..=1.0).contains(&c.z)
+            }
+        };
+        if !self.color_points.iter().all(|(_, c)| color_valid(c)) {
+            return Err(Error::Invalid("Color out of range"));
+        }
+
+        Ok(())
+    }
+
+    /// Construct transfer function
+    pub fn to_tf(&self) -> Result<PiecewiseLinearTf, Error> {
+        self.validate()?;
+
+        let color_points: Vec<_> = match self.color_space {
+            ColorSpace::Rgb => self.color_points.clone(),
+            ColorSpace::Hsv => self
+                .color_points
+                .iter()
+                .map(|&(v, c)| (v, color::from_hsv(c.x, c.y, c.z, 0.0).xyz()))
+                .collect(),
+        };
+
+        PiecewiseLinearTf::from_color_opacity(&color_points, &self.opacity_points)
+    }
+}
+
+/// Load transfer function description from file
+pub fn load_tf<P>(path: P) -> Result<TfDescription, Error>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    parse_tf(&text)
+}
+
+/// Save transfer function description to file
+pub fn save_tf<P>(path: P, desc: &TfDescription) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let text = serialize_tf(desc)?;
+    std::fs::write(path, text).map_err(|e| Error::io(path, e))
+}
+
+/// Serialize transfer function description into text format
+pub fn serialize_tf(desc: &TfDescription) -> Result<String, Error> {
+    desc.validate()?;
+
+    let space = match desc.color_space {
+        ColorSpace::Rgb => "rgb",
+        ColorSpace::Hsv => "hsv",
+    };
+
+    let mut out = String::new();
+    writeln!(out, "raycaster_tf {VERSION}").unwrap();
+    writeln!(out, "domain {} {}", desc.domain.low, desc.domain.high).unwrap();
+    writeln!(out, "color_space {space}").unwrap();
+    for (v, c) in &desc.color_points {
+        writeln!(out, "color {} {} {} {}", v, c.x, c.y, c.z).unwrap();
+    }
+    for (v, a) in &desc.opacity_points {
+        writeln!(out, "opacity {} {}", v, a).unwrap();
+    }
+
+    Ok(out)
+}
+
+/// Parse transfer function description from text format
+pub fn parse_tf(input: &str) -> Result<TfDescription, Error> {
+    // Lines with offsets of their beginning
+    let mut offset = 0;
+    let mut lines = input.split_inclusive('\n').filter_map(|raw| {
+        let start = offset;
+        offset += raw.len();
+        let line = raw.split('#').next().unwrap_or("").trim();
+        (!line.is_empty()).then_some((start, line))
+    });
+
+    match lines.next().map(|(offset, line)| parse_line(offset, line)) {
+        Some(Ok(Directive::Header(VERSION))) => (),
+        Some(Ok(Directive::Header(_))) => {
+            return Err(Error::Unsupported("Transfer function file version"))
+        }
+        _ => return Err(Error::parse(FORMAT, 0, "Missing header")),
+    }
+
+    let mut domain = None;
+    let mut color_space = ColorSpace::Rgb;
+    let mut color_points = vec![];
+    let mut opacity_points = vec![];
+
+    for (offset, line) in lines {
+        match parse_line(offset, line)? {
+            Directive::Header(_) => return Err(Error::parse(FORMAT, offset, "Duplicate header")),
+            Directive::Domain(low, high) => domain = Some((low..high).into()),
+            Directive::ColorSpace(space) => color_space = space,
+            Directive::Color(v, c) => color_points.push((v, c)),
+            Directive::Opacity(v, a) => opacity_points.push((v, a)),
+        }
+    }
+
+    let desc = TfDescription {
+        domain: domain.ok_or(Error::Missing("domain"))?,
+        color_space,
+        color_points,
+        opacity_points,
+    };
+    desc.validate()?;
+
+    Ok(desc)
+}
+
+/// One line of the file
+#[derive(Debug, PartialEq)]
+enum Directive {
+    Header(u32),
+    Domain(f32, f32),
+    ColorSpace(ColorSpace),
+    Color(f32, Vector3<f32>),
+    Opacity(f32, f32),
+}
+
+fn parse_line(offset: usize, line: &str) -> Result<Directive, Error> {
+    match all_consuming(directive)(line) {
+        Ok((_, d)) => Ok(d),
+        Err(_) => Err(Error::parse(FORMAT, offset, "Invalid directive")),
+    }
+}
+
+fn number(s: &str) -> IResult<&str, f32> {
+    preceded(space1, float)(s)
+}
+
+fn directive(s: &str) -> IResult<&str, Directive> {
+    let color_space = alt((
+        value(ColorSpace::Rgb, tag("rgb")),
+        value(ColorSpace::Hsv, tag("hsv")),
+    ));
+
+    alt((
+        map(
+            preceded(tag("raycaster_tf"), preceded(space1, u32)),
+            Directive::Header,
+        ),
+        map(
+            preceded(tag("domain"), tuple((number, number))),
+            |(l, h)| Directive::Domain(l, h),
+        ),
+        // Before `color`, which is its prefix
+        map(
+            preceded(tag("color_space"), preceded(space1, color_space)),
+            Directive::ColorSpace,
+        ),
+        map(
+            preceded(tag("color"), tuple((number, number, number, number))),
+            |(v, a, b, c)| Directive::Color(v, vector![a, b, c]),
+        ),
+        map(
+            preceded(tag("opacity"), tuple((number, number))),
+            |(v, a)| Directive::Opacity(v, a),
+        ),
+    ))(s)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::TransferFunction;
+
+    const SKULL: &str = "
+# Skull, bone only
+raycaster_tf 1
+domain 0 255
+
+color 0 227 218 201
+color 255 255 255 255
+opacity 40 0   # fade in
+opacity 60 1
+opacity 240 1
+opacity 241 0
+";
+
+    #[test]
+    fn parse() {
+        let desc = parse_tf(SKULL).unwrap();
+
+        assert_eq!(desc.domain, (0.0..255.0).into());
+        assert_eq!(desc.color_space, ColorSpace::Rgb);
+        assert_eq!(desc.color_points.len(), 2);
+        assert_eq!(desc.color_points[0], (0.0, vector![227.0, 218.0, 201.0]));
+        assert_eq!(desc.opacity_points.len(), 4);
+        assert_eq!(desc.opacity_points[1], (60.0, 1.0));
+    }
+
+    #[test]
+    fn roundtrip() {
+        let desc = TfDescription {
+            domain: (-1000.0..3000.0).into(),
+            color_space: ColorSpace::Hsv,
+            color_points: vec![(-1000.0, vector![30.5, 0.25, 1.0])],
+            opacity_points: vec![(-200.0, 0.0), (0.125, 0.75)],
+        };
+
+        let text = serialize_tf(&desc).unwrap();
+
+        assert_eq!(parse_tf(&text).unwrap(), desc);
+    }
+
+    #[test]
+    fn to_tf() {
+        let tf = parse_tf(SKULL).unwrap().to_tf().unwrap();
+
+        assert_eq!(tf.color(20.0).w, 0.0);
+        assert_eq!(tf.color(50.0).w, 0.5);
+        assert_eq!(tf.color(100.0).w, 1.0);
+        assert_eq!(tf.color(250.0).w, 0.0);
+    }
+
+    #[test]
+    fn hsv_colors() {
+        let text = "raycaster_tf 1
+domain 0 100
+color_space hsv
+color 0 120 1 1
+opacity 0 1";
+        let tf = parse_tf(text).unwrap().to_tf().unwrap();
+
+        assert_eq!(tf.color(50.0), color::new(0.0, 255.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn piecewise_roundtrip() {
+        let tf = PiecewiseLinearTf::new(vec![
+            (10.0, color::new(0.0, 0.0, 0.0, 0.0)),
+            (20.0, color::new(200.0, 100.0, 0.0, 1.0)),
+        ])
+        .unwrap();
+
+        let desc = TfDescription::from_piecewise(&tf, (0.0..255.0).into());
+        let loaded = parse_tf(&serialize_tf(&desc).unwrap())
+            .unwrap()
+            .to_tf()
+            .unwrap();
+
+        assert_eq!(loaded.get_points(), tf.get_points());
+    }
+
+    #[test]
+    fn invalid_files() {
+        // No header
+        assert!(parse_tf("domain 0 255").is_err());
+        // Unknown version
+        assert!(parse_tf("raycaster_tf 2\ndomain 0 255").is_err());
+        // No domain
+        assert!(parse_tf("raycaster_tf 1\nopacity 0 1").is_err());
+        // Unknown directive
+        assert!(parse_tf("raycaster_tf 1\ndomain 0 255\ngamma 2").is_err());
+        // Missing component
+        assert!(parse_tf("raycaster_tf 1\ndomain 0 255\ncolor 0 1 1").is_err());
+        // Outside of domain
+        assert!(parse_tf("raycaster_tf 1\ndomain 0 255\nopacity 300 1").is_err());
+        // Unsorted
+        assert!(parse_tf("raycaster_tf 1\ndomain 0 255\nopacity 50 1\nopacity 10 0").is_err());
+        // Opacity out of range
+        assert!(parse_tf("raycaster_tf 1\ndomain 0 255\nopacity 50 2").is_err());
+    }
+}
@@ -19,7 +19,12 @@ use nalgebra::{vector, Vector2, Vector3};
 use parking_lot::Mutex;
 use render_options::RenderOptions;
 
-use crate::{common::PixelBox, render::render_options, volumetric::Volume, PerspectiveCamera};
+use crate::{
+    common::{PixelBox, ViewportBox},
+    render::render_options,
+    volumetric::{select_lod_level, Blocked, Volume},
+    PerspectiveCamera,
+};
 
 use super::{
     communication::CompWorkerComms,
@@ -31,8 +36,8 @@ use super::{
 /// Subcanvas is the target of rendering.
 /// It has its own queue of subvolumes visible in it.
 pub struct SubCanvas {
-    /// Items of queue are indexes into current volume.
-    queue: VecDeque<u32>,
+    /// Items of queue are indexes into current volume and level of detail of the block.
+    queue: VecDeque<(u32, u8)>,
     pub pixels: PixelBox,
     pub colors: Vec<Vector3<f32>>,
     pub opacities: Vec<f32>,
@@ -128,20 +133,24 @@ impl Canvas {
     /// * Their distance from camera is measured.
     /// * Blocks are sorted in ascending order by their distance from camera.
     /// * For each block, tiles through which block can be seen are found.
+    /// * Level of detail is picked by the size of the block on screen.
     /// * The block is added to the queues of 'affected' tiles.
+    /// * Volume is asked to prefetch blocks in the order they are going to be rendered.
     ///
     /// # Safety
     ///
     /// uses interior mutability, exclusive access to `Canvas` must be provided.
-    pub fn build_queues<V>(
+    pub fn build_queues<BV>(
         &self,
         camera: &PerspectiveCamera,
-        blocks: &[V],
-        empty_blocks: &[bool],
+        volume: &BV,
         render_options: RenderOptions,
     ) where
-        V: Volume,
+        BV: Blocked,
     {
+        let blocks = volume.get_blocks();
+        let empty_blocks = volume.get_empty_blocks();
+        let lod_count = volume.get_lod_count();
         let dont_skip_empty = !render_options.empty_space_skipping;
         let mut block_infos = Vec::with_capacity(blocks.len());
         for (i, (block, empty)) in blocks.iter().zip(empty_blocks).enumerate() {
@@ -167,14 +176,23 @@ impl Canvas {
             );
         }
 
+        // Blocks in order of rendering, front to back
+        let mut order = Vec::with_capacity(block_infos.len());
         for (block_id, _) in block_infos {
-            let vpbox = camera.project_box(blocks[block_id as usize].get_bound_box());
-            let pixel_box = vpbox.get_pixel_range(res);
+            let block = &blocks[block_id as usize];
+            let projected = camera.project_box_unclamped(block.get_bound_box());
+            let pixel_box = projected.clamped().get_pixel_range(res);
+
+            let footprint = footprint(&projected, res);
+            let level = select_lod_level(footprint, block.get_size().max(), lod_count);
             // Count which pixelboxes intersect
             // Assume all tiles are the same size
 
             let (tiles_x_range, tiles_y_range) =
                 Canvas::get_affected_tiles(pixel_box, self.tile_side);
+            if !tiles_x_range.is_empty() && !tiles_y_range.is_empty() {
+                order.push((block_id, level));
+            }
 
             for y in tiles_y_range {
                 for x in tiles_x_range.clone() {
@@ -183,10 +201,12 @@ impl Canvas {
                     // Safety: build phase, only master has access
                     let tile =
                         unsafe { self.sub_canvases[tile_id as usize].get().as_mut().unwrap() };
-                    tile.queue.push_back(block_id);
+                    tile.queue.push_back((block_id, level));
                 }
             }
         }
+
+        volume.prefetch(&order);
     }
 
     /// Get overlap of area in which block is visible with tile grid.
@@ -304,7 +324,9 @@ impl CompWorker {
 
                 let order = subcanvas.queue.pop_front();
                 match order {
-                    Some(block_id) => self.send_task(block_id, tile_id, subcanvas_ptr),
+                    Some((block_id, level)) => {
+                        self.send_task(block_id, level, tile_id, subcanvas_ptr)
+                    }
                     None => self.tile_finished(subcanvas),
                 }
                 tile_id += self.compositor_count as u32;
@@ -340,7 +362,9 @@ impl CompWorker {
                 let subcanvas = subcanvas_ptr.as_mut().unwrap();
 
                 match subcanvas.queue.pop_front() {
-                    Some(block_id) => self.send_task(block_id, result.tile_id, subcanvas_ptr),
+                    Some((block_id, level)) => {
+                        self.send_task(block_id, level, result.tile_id, subcanvas_ptr)
+                    }
                     None => self.tile_finished(subcanvas),
                 }
             }
@@ -384,9 +408,9 @@ impl CompWorker {
         }
     }
 
-    /// Create task to render block with id `block_id` into tile with id `tile_id`.
-    fn send_task(&self, block_id: u32, tile_id: u32, subcanvas: *mut SubCanvas) {
-        let task = RenderTask::new(block_id, tile_id, subcanvas);
+    /// Create task to render block with id `block_id` at `level` of detail into tile with id `tile_id`.
+    fn send_task(&self, block_id: u32, level: u8, tile_id: u32, subcanvas: *mut SubCanvas) {
+        let task = RenderTask::new(block_id, level, tile_id, subcanvas);
         #[cfg(debug_assertions)]
         println!(
             "Comp {}: sent task block {} tile {}",
@@ -419,6 +443,13 @@ impl CompWorker {
     }
 }
 
+/// Size of block on screen in pixels, `vpbox` is not clamped to the viewport
+fn footprint(vpbox: &ViewportBox, resolution: Vector2<u16>) -> f32 {
+    (vpbox.upper - vpbox.lower)
+        .component_mul(&resolution.cast::<f32>())
+        .max()
+}
+
 /// Subcanvas data (floats) gets converted to 1-byte integer values.
 fn convert_to_bytes(subcanvas_rgb: &[Vector3<f32>]) -> Vec<u8> {
     // todo optimisation potential - extra allocation
@@ -473,4 +504,13 @@ mod test {
         assert_eq!(tiles.0, 0..2);
         assert_eq!(tiles.1, 0..2);
     }
+
+    #[test]
+    fn footprint_outside_viewport() {
+        // Block crossing the left edge, mostly outside
+        let vpbox = ViewportBox::from_points(vector![-1.5, 0.2], vector![0.1, 0.6]);
+        let pixel_box = vpbox.clamped().get_pixel_range(vector![100, 100]);
+        assert_eq!(pixel_box.width(), 10);
+        assert!((footprint(&vpbox, vector![100, 100]) - 160.0).abs() < 1e-3);
+    }
 }
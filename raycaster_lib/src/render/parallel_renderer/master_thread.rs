@@ -211,16 +211,9 @@ where
 
                     // Prepare canvas (mainly queues)
                     {
-                        let blocks = volume.get_blocks();
-                        let empty_blocks_index = volume.get_empty_blocks();
                         let cam_ref = unsafe { self.camera.get().as_ref().unwrap() };
 
-                        canvas.build_queues(
-                            cam_ref,
-                            blocks,
-                            empty_blocks_index,
-                            self.render_options,
-                        );
+                        canvas.build_queues(cam_ref, volume, self.render_options);
                     }
 
                     #[cfg(debug_assertions)]
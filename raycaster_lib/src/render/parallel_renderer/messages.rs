@@ -9,14 +9,17 @@ use super::composition::SubCanvas;
 /// Structure describing rendering task.
 pub struct RenderTask {
     pub block_id: u32,
+    /// Level of detail of the block
+    pub level: u8,
     pub tile_id: u32,
     pub subcanvas: *mut SubCanvas,
 }
 
 impl RenderTask {
-    pub fn new(block_id: u32, tile_id: u32, subcanvas: *mut SubCanvas) -> Self {
+    pub fn new(block_id: u32, level: u8, tile_id: u32, subcanvas: *mut SubCanvas) -> Self {
         Self {
             block_id,
+            level,
             tile_id,
             subcanvas,
         }
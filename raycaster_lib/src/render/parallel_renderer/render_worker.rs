@@ -11,9 +11,13 @@ use nalgebra::{vector, Vector3};
 
 use crate::{
     common::Ray,
-    render::RenderOptions,
-    volumetric::{Blocked, Volume},
-    PerspectiveCamera,
+    render::{
+        renderer::{gradient_tf, pre_integrated_tf},
+        RenderOptions,
+    },
+    tf::{LutTransferFunction, PreIntegratedTf},
+    volumetric::{Blocked, Volume, GRADIENT_STEP},
+    PerspectiveCamera, TransferFunction, TF,
 };
 
 use super::{
@@ -50,6 +54,12 @@ where
     render_options: RenderOptions,
     comms: RenderWorkerComms,
     volume: &'a BV,
+    /// Transfer function of `volume`, baked on construction
+    tf: LutTransferFunction,
+    /// Transfer function of `volume`, if it depends on gradient
+    gradient_tf: Option<TF>,
+    /// Pre-integrated transfer function, if enabled
+    pre_integrated: Option<PreIntegratedTf>,
 }
 
 impl<'a, BV> RenderWorker<'a, BV>
@@ -65,6 +75,8 @@ where
         comms: RenderWorkerComms,
         volume: &'a BV,
     ) -> Self {
+        let tf = LutTransferFunction::for_range(&**volume.get_tf(), volume.get_value_range());
+        let pre_integrated = pre_integrated_tf(&render_options, volume.get_tf(), &tf);
         Self {
             renderer_id,
             camera,
@@ -72,6 +84,9 @@ where
             render_options,
             comms,
             volume,
+            tf,
+            gradient_tf: gradient_tf(volume.get_tf()),
+            pre_integrated,
         }
     }
 
@@ -107,8 +122,6 @@ where
         #[cfg(debug_assertions)]
         println!("Render {}: entering main loop", self.renderer_id);
 
-        let blocks = self.volume.get_blocks();
-
         let cam_ref = unsafe { self.camera.get().as_ref().unwrap() };
 
         loop {
@@ -126,10 +139,13 @@ where
             // Safety: ref is unique
             let subcanvas = unsafe { task.subcanvas.as_mut().unwrap() };
 
-            let block = &blocks[block_id as usize];
-
-            // Render task
-            self.render_block(cam_ref, subcanvas, block);
+            // Render task, block failing to load is left out
+            let rendered = self.volume.with_block(task.level, block_id, |block| {
+                self.render_block(cam_ref, subcanvas, block)
+            });
+            if let Err(e) = rendered {
+                eprintln!("Render {}: skipped block {block_id}: {e}", self.renderer_id);
+            }
             // Opacities have been mutated
 
             #[cfg(debug_assertions)]
@@ -213,7 +229,11 @@ where
 
         let mut pos = obj_ray.origin;
 
-        let tf = self.volume.get_tf();
+        let tf = &self.tf;
+        let gradient_tf = self.gradient_tf.as_deref();
+        let pre_integrated = self.pre_integrated.as_ref();
+        // Front sample of current segment
+        let mut prev_sample = None;
 
         // Source:
         // https://developer.nvidia.com/gpugems/gpugems/part-vi-beyond-triangles/chapter-39-volume-rendering-techniques
@@ -229,14 +249,10 @@ where
             }
 
             let (sample, grad_samples) = block.sample_at_gradient(pos);
+            let classified = block.classify(pos);
 
             pos += step;
 
-            let color_b = tf(sample);
-            if color_b.w == 0.0 {
-                continue;
-            }
-
             // Inverted, as low values indicate outside
             let grad = vector![
                 sample - grad_samples.x,
@@ -244,6 +260,21 @@ where
                 sample - grad_samples.z
             ];
 
+            let color_b = match (classified, gradient_tf) {
+                (Some(color), _) => color,
+                (None, Some(gradient_tf)) => {
+                    gradient_tf.color_gradient(sample, grad.magnitude() / GRADIENT_STEP)
+                }
+                (None, None) => match pre_integrated {
+                    Some(pre) => pre.segment(prev_sample.unwrap_or(sample), sample),
+                    None => tf.color(sample),
+                },
+            };
+            prev_sample = Some(sample);
+            if color_b.w == 0.0 {
+                continue;
+            }
+
             let grad_magnitude = grad.magnitude();
             const GRAD_MAG_THRESH: f32 = 0.01; // todo tweak
 
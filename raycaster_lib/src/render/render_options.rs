@@ -10,6 +10,7 @@ const DEFAULT_RAY_STEP_QUALITY: f32 = 0.5;
 const DEFAULT_RAY_STEP_FAST: f32 = 0.5;
 const DEFAULT_EARLY_RAY_TERMINATION: bool = true;
 const DEFAULT_EMPTY_SPACE_SKIPPING: bool = true;
+const DEFAULT_PRE_INTEGRATION: bool = false;
 
 /// Renderer settings.
 ///
@@ -26,6 +27,9 @@ pub struct RenderOptions {
     pub ray_step_quality: f32,
     /// Length of sampling step in fast render mode
     pub ray_step_fast: f32,
+    /// Classify ray segments between samples using pre-integrated transfer function.
+    /// Has no effect for transfer functions using gradient.
+    pub pre_integration: bool,
 }
 
 impl RenderOptions {
@@ -36,6 +40,7 @@ impl RenderOptions {
         empty_space_skipping: bool,
         ray_step_quality: f32,
         ray_step_fast: f32,
+        pre_integration: bool,
     ) -> Self {
         Self {
             resolution,
@@ -43,6 +48,7 @@ impl RenderOptions {
             empty_space_skipping,
             ray_step_quality,
             ray_step_fast,
+            pre_integration,
         }
     }
 
@@ -69,12 +75,15 @@ pub struct RenderOptionsBuilder {
     ray_step_quality: Option<f32>,
     /// Length of sampling step in fast render mode
     ray_step_fast: Option<f32>,
+    /// Use pre-integrated transfer function
+    pre_integration: Option<bool>,
 }
 
 impl RenderOptionsBuilder {
     /// New builder
     ///
-    /// Default values are `0.5` for sample steps and true for all optimisations
+    /// Default values are `0.5` for sample steps and true for all optimisations.
+    /// Pre-integration is off by default.
     pub fn new() -> Self {
         Default::default()
     }
@@ -111,6 +120,12 @@ impl RenderOptionsBuilder {
         self
     }
 
+    /// Set pre-integrated classification on or off.
+    pub fn pre_integration(&mut self, on: bool) -> &mut Self {
+        self.pre_integration = Some(on);
+        self
+    }
+
     /// Build the options.
     ///
     /// Fails only if resolution is not specified.
@@ -128,6 +143,7 @@ impl RenderOptionsBuilder {
             .unwrap_or(DEFAULT_EMPTY_SPACE_SKIPPING);
         let ray_step_quality = self.ray_step_quality.unwrap_or(DEFAULT_RAY_STEP_QUALITY);
         let ray_step_fast = self.ray_step_fast.unwrap_or(DEFAULT_RAY_STEP_FAST);
+        let pre_integration = self.pre_integration.unwrap_or(DEFAULT_PRE_INTEGRATION);
 
         Some(RenderOptions::new(
             resolution,
@@ -135,6 +151,7 @@ impl RenderOptionsBuilder {
             empty_space_skipping,
             ray_step_quality,
             ray_step_fast,
+            pre_integration,
         ))
     }
 
@@ -155,6 +172,7 @@ impl RenderOptionsBuilder {
             .unwrap_or(DEFAULT_EMPTY_SPACE_SKIPPING);
         let ray_step_quality = self.ray_step_quality.unwrap_or(DEFAULT_RAY_STEP_QUALITY);
         let ray_step_fast = self.ray_step_fast.unwrap_or(DEFAULT_RAY_STEP_FAST);
+        let pre_integration = self.pre_integration.unwrap_or(DEFAULT_PRE_INTEGRATION);
 
         RenderOptions::new(
             resolution,
@@ -162,6 +180,7 @@ impl RenderOptionsBuilder {
             empty_space_skipping,
             ray_step_quality,
             ray_step_fast,
+            pre_integration,
         )
     }
 }
@@ -6,17 +6,62 @@
 
 use nalgebra::{vector, Vector3};
 
-use crate::{color::RGBA, common::Ray, volumetric::Volume, PerspectiveCamera};
+use crate::{
+    color::RGBA,
+    common::Ray,
+    tf::{LutTransferFunction, PreIntegratedTf},
+    volumetric::{Volume, GRADIENT_STEP},
+    PerspectiveCamera, TransferFunction, TF,
+};
 
 use super::RenderOptions;
 
 /// light direction (normalized)
 const LIGHT_DIR: Vector3<f32> = vector![-0.74278, -0.55708, -0.37139];
 
+/// Returns `tf`, if it classifies samples by gradient.
+pub(crate) fn gradient_tf(tf: &TF) -> Option<TF> {
+    if tf.uses_gradient() {
+        Some(tf.clone())
+    } else {
+        None
+    }
+}
+
+/// Returns pre-integrated `lut`, if enabled by `render_options`.
+/// Transfer functions using gradient cannot be pre-integrated.
+///
+/// Table grows quadratically, larger lookup tables are resampled to `u8` resolution first.
+pub(crate) fn pre_integrated_tf(
+    render_options: &RenderOptions,
+    tf: &TF,
+    lut: &LutTransferFunction,
+) -> Option<PreIntegratedTf> {
+    const MAX_ENTRIES: usize = 256;
+
+    if !render_options.pre_integration || tf.uses_gradient() {
+        return None;
+    }
+
+    if lut.get_table().len() > MAX_ENTRIES {
+        let resampled = LutTransferFunction::new(lut, lut.get_range(), MAX_ENTRIES)
+            .expect("Range of lookup table is valid");
+        Some(PreIntegratedTf::new(&resampled))
+    } else {
+        Some(PreIntegratedTf::new(lut))
+    }
+}
+
 /// Single threaded, synchronous renderer.
 pub struct Renderer<V: Volume> {
     volume: V,
     render_options: RenderOptions,
+    /// Transfer function of `volume`, baked on construction
+    tf: LutTransferFunction,
+    /// Transfer function of `volume`, if it depends on gradient
+    gradient_tf: Option<TF>,
+    /// Pre-integrated transfer function, if enabled
+    pre_integrated: Option<PreIntegratedTf>,
 }
 
 impl<V> Renderer<V>
@@ -29,9 +74,15 @@ where
     /// * `volume` - volume object implementing [`Volume`] trait.
     /// * `render_options` - Parameters for rendering.
     pub fn new(volume: V, render_options: RenderOptions) -> Renderer<V> {
+        let tf = LutTransferFunction::for_range(&**volume.get_tf(), volume.get_value_range());
+        let gradient_tf = gradient_tf(volume.get_tf());
+        let pre_integrated = pre_integrated_tf(&render_options, volume.get_tf(), &tf);
         Renderer {
             volume,
             render_options,
+            tf,
+            gradient_tf,
+            pre_integrated,
         }
     }
 
@@ -120,12 +171,21 @@ where
 
         // Setup iterating along ray
         let begin = obj_ray.origin;
-        let direction = ray.direction;
+        // Oriented volume is iterated in the frame of its bound box, in voxels
+        let direction = self
+            .volume
+            .get_bound_box()
+            .direction_to_frame(&ray.direction)
+            .component_div(&self.volume.get_scale());
 
         let step = direction * step_size; // normalized
         let mut pos = begin;
 
-        let tf = self.volume.get_tf();
+        let tf = &self.tf;
+        let gradient_tf = self.gradient_tf.as_deref();
+        let pre_integrated = self.pre_integrated.as_ref();
+        // Front sample of current segment
+        let mut prev_sample = None;
 
         // Source:
         // https://developer.nvidia.com/gpugems/gpugems/part-vi-beyond-triangles/chapter-39-volume-rendering-techniques
@@ -142,21 +202,17 @@ where
             // Empty space skipping
             if self.render_options.empty_space_skipping && self.volume.is_empty(pos) {
                 pos += step;
+                prev_sample = None;
                 continue;
             }
 
             // Sample with gradient
             let (sample, grad_samples) = self.volume.sample_at_gradient(pos);
+            // Color, if volume classifies samples itself
+            let classified = self.volume.classify(pos);
 
             pos += step;
 
-            // Color sample
-            let color_b = tf(sample);
-            if color_b.w == 0.0 {
-                continue;
-            }
-            let mut sample_rgb = color_b.xyz();
-
             // Inverted gradient, as low values indicate outside of an object
             let grad = vector![
                 sample - grad_samples.x,
@@ -164,6 +220,23 @@ where
                 sample - grad_samples.z
             ];
 
+            // Color sample
+            let color_b = match (classified, gradient_tf) {
+                (Some(color), _) => color,
+                (None, Some(gradient_tf)) => {
+                    gradient_tf.color_gradient(sample, grad.magnitude() / GRADIENT_STEP)
+                }
+                (None, None) => match pre_integrated {
+                    Some(pre) => pre.segment(prev_sample.unwrap_or(sample), sample),
+                    None => tf.color(sample),
+                },
+            };
+            prev_sample = Some(sample);
+            if color_b.w == 0.0 {
+                continue;
+            }
+            let mut sample_rgb = color_b.xyz();
+
             let grad_magnitude = grad.magnitude();
             const GRAD_MAG_THRESH: f32 = 0.01; // todo tweak
 
@@ -204,7 +277,143 @@ where
 mod test {
 
     use super::*;
-    use nalgebra::Vector3;
+    use crate::{
+        color,
+        test_helpers::empty_vol_meta,
+        tf::{Tf2D, Widget},
+        volumetric::{
+            volumes::{FloatVolume, MultiChannelVolume},
+            BuildVolume, DataSource, Sample,
+        },
+    };
+    use nalgebra::{point, Vector3};
+    use std::sync::Arc;
+
+    /// Render 8×8×8 volume, with camera looking at it diagonally
+    fn render_volume<T: Sample>(
+        data: Vec<T>,
+        tf: TF,
+        render_options: RenderOptions,
+        ray_step: f32,
+    ) -> Vec<u8> {
+        let mut meta = empty_vol_meta(vector![8, 8, 8]);
+        meta.data = Some(DataSource::Vec(data));
+        meta.scale = Some(vector![1.0, 1.0, 1.0]);
+        meta.tf = Some(tf);
+        let volume: FloatVolume = BuildVolume::build(meta).unwrap();
+
+        let camera = PerspectiveCamera::new(point![20.0, 20.0, 20.0], vector![-1.0, -1.0, -1.0]);
+
+        let mut renderer = Renderer::new(volume, render_options);
+        let mut buffer = vec![0; 3 * 16 * 16];
+        renderer.render_to_buffer(&camera, &mut buffer, ray_step);
+        buffer
+    }
+
+    /// Render uniform volume (no boundaries) with transfer function `tf`
+    fn render_uniform(tf: TF) -> Vec<u8> {
+        let render_options = RenderOptions::builder()
+            .resolution(vector![16, 16])
+            .build_unchecked();
+        render_volume(vec![100u8; 8 * 8 * 8], tf, render_options, 0.2)
+    }
+
+    #[test]
+    fn gradient_classification() {
+        let interior = Tf2D::new(vec![Widget::rectangle(
+            (90.0..110.0).into(),
+            (0.0..1.0).into(),
+            color::mono(255.0, 1.0),
+        )]);
+        let boundary = Tf2D::new(vec![Widget::triangle(
+            100.0,
+            20.0,
+            50.0,
+            color::mono(255.0, 1.0),
+        )]);
+
+        assert!(render_uniform(Arc::new(interior)).iter().any(|&b| b != 0));
+        assert!(render_uniform(Arc::new(boundary)).iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn pre_integration_thin_band() {
+        // Values rise by 30 per voxel along one axis
+        let data = (0..8 * 8 * 8)
+            .map(|i| (i / 64 * 30) as u8)
+            .collect::<Vec<_>>();
+        // Visible only around value 90
+        let band_tf = |sample: f32| {
+            if sample > 89.5 && sample < 90.5 {
+                color::mono(255.0, 1.0)
+            } else {
+                color::zero()
+            }
+        };
+
+        let visible_pixels = |pre_integration: bool| {
+            let render_options = RenderOptions::builder()
+                .resolution(vector![16, 16])
+                .pre_integration(pre_integration)
+                .build_unchecked();
+            let buffer = render_volume(data.clone(), Arc::new(band_tf), render_options, 1.0);
+            buffer
+                .chunks(3)
+                .filter(|px| px.iter().any(|&b| b != 0))
+                .count()
+        };
+
+        let sampled = visible_pixels(false);
+        let pre_integrated = visible_pixels(true);
+
+        assert!(pre_integrated > 0);
+        assert!(pre_integrated > sampled);
+    }
+
+    #[test]
+    fn wide_samples() {
+        // 12-bit values, tf is transparent in u8 range
+        let data: Vec<u16> = vec![3500; 8 * 8 * 8];
+        let tf = |sample: f32| {
+            if sample > 3000.0 {
+                color::mono(255.0, 1.0)
+            } else {
+                color::zero()
+            }
+        };
+
+        let render_options = RenderOptions::builder()
+            .resolution(vector![16, 16])
+            .pre_integration(true)
+            .build_unchecked();
+        let buffer = render_volume(data, Arc::new(tf), render_options, 0.2);
+
+        assert!(buffer.iter().any(|&b| b != 0));
+    }
+
+    #[test]
+    fn multi_channel() {
+        // Interleaved channels, only the first one is visible
+        let data: Vec<u8> = [100, 0].repeat(8 * 8 * 8);
+        let mut meta = empty_vol_meta(vector![8, 8, 8]);
+        meta.set_data(DataSource::Vec(data)).set_channels(2);
+        let mut volume: MultiChannelVolume = BuildVolume::build(meta).unwrap();
+        volume.set_channel_tf(0, Arc::new(|_| color::new(255.0, 0.0, 0.0, 0.5)));
+        volume.set_channel_tf(1, Arc::new(|_| color::zero()));
+
+        let render_options = RenderOptions::builder()
+            .resolution(vector![16, 16])
+            .build_unchecked();
+        let camera = PerspectiveCamera::new(point![20.0, 20.0, 20.0], vector![-1.0, -1.0, -1.0]);
+        let mut renderer = Renderer::new(volume, render_options);
+        let mut buffer = vec![0; 3 * 16 * 16];
+        renderer.render_to_buffer(&camera, &mut buffer, 0.2);
+
+        let red: u32 = buffer.iter().step_by(3).map(|&b| b as u32).sum();
+        let green: u32 = buffer.iter().skip(1).step_by(3).map(|&b| b as u32).sum();
+        assert!(red > 0);
+        assert!(red > green);
+    }
 
     #[test]
     fn understanding_phong() {
@@ -7,12 +7,16 @@
 //! Module with helper functions
 //! Saves repetition in unit tests
 
+use std::{path::PathBuf, sync::Arc};
+
 use nalgebra::{point, vector, Vector3};
 
 use crate::{
     color::RGBA,
     premade::parse::skull_parser,
-    volumetric::{BuildVolume, DataSource, MemoryType, StorageShape, Volume, VolumeMetadata},
+    volumetric::{
+        BuildVolume, DataSource, MemoryType, Sample, StorageShape, Volume, VolumeMetadata,
+    },
 };
 
 pub fn white_tf(sample: f32) -> RGBA {
@@ -27,25 +31,23 @@ pub fn white_vol_meta() -> VolumeMetadata<u8> {
         scale: Some(vector![100.0, 100.0, 100.0]), // shape of voxels
         position: Some(point![0.0, 0.0, 0.0]),
         data: Some(data_source),
-        tf: Some(white_tf),
+        tf: Some(Arc::new(white_tf)),
         data_shape: Some(StorageShape::Linear),
-        memory_type: None,
-        desired_data_shape: None,
+        ..Default::default()
     }
 }
 
-pub fn empty_vol_meta(size: Vector3<usize>) -> VolumeMetadata<u8> {
-    let data = vec![0; size.x * size.y * size.z];
+pub fn empty_vol_meta<T: Sample>(size: Vector3<usize>) -> VolumeMetadata<T> {
+    let data = vec![T::default(); size.x * size.y * size.z];
     let data_source = DataSource::Vec(data);
     VolumeMetadata {
         size: Some(size),
         scale: Some(vector![100.0, 100.0, 100.0]), // shape of voxels
         position: Some(point![0.0, 0.0, 0.0]),
         data: Some(data_source),
-        tf: Some(white_tf),
+        tf: Some(Arc::new(white_tf)),
         data_shape: Some(StorageShape::Linear),
-        memory_type: None,
-        desired_data_shape: None,
+        ..Default::default()
     }
 }
 
@@ -53,7 +55,7 @@ pub fn empty_volume<V>(size: Vector3<usize>) -> V
 where
     V: Volume + BuildVolume<u8>,
 {
-    let meta = empty_vol_meta(size);
+    let meta = empty_vol_meta::<u8>(size);
     BuildVolume::build(meta).unwrap()
 }
 
@@ -83,3 +85,28 @@ where
     }
     BuildVolume::build(meta).unwrap()
 }
+
+/// Text header followed by binary data
+pub fn header_file(header: &str, data: &[u8]) -> DataSource<u8> {
+    let mut bytes = header.as_bytes().to_vec();
+    bytes.extend_from_slice(data);
+    DataSource::Vec(bytes)
+}
+
+/// File in temporary directory, removed when dropped
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    /// Write `bytes` to a file, `name` has to be unique among tests
+    pub fn new(name: &str, bytes: &[u8]) -> TempFile {
+        let path = std::env::temp_dir().join(format!("raycaster_{}_{}", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        TempFile(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
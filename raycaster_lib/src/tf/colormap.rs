@@ -0,0 +1,292 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+use nalgebra::{vector, Vector3};
+
+use crate::common::ValueRange;
+
+/// Perceptual colormaps.
+///
+/// Colormaps map position in range `<0;1>` to color.
+/// Use [`super::PiecewiseLinearTf::from_colormap`] to make a transfer function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colormap {
+    Viridis,
+    Magma,
+    Inferno,
+    Plasma,
+    Cividis,
+    Grayscale,
+    /// Diverging blue to red colormap by Kenneth Moreland
+    CoolWarm,
+}
+
+impl Colormap {
+    /// All available colormaps
+    pub const ALL: [Colormap; 7] = [
+        Colormap::Viridis,
+        Colormap::Magma,
+        Colormap::Inferno,
+        Colormap::Plasma,
+        Colormap::Cividis,
+        Colormap::Grayscale,
+        Colormap::CoolWarm,
+    ];
+
+    /// Name of colormap
+    pub fn name(&self) -> &'static str {
+        match self {
+            Colormap::Viridis => "viridis",
+            Colormap::Magma => "magma",
+            Colormap::Inferno => "inferno",
+            Colormap::Plasma => "plasma",
+            Colormap::Cividis => "cividis",
+            Colormap::Grayscale => "grayscale",
+            Colormap::CoolWarm => "cool-warm",
+        }
+    }
+
+    /// Colors evenly spaced over range `<0;1>`
+    pub fn stops(&self) -> &'static [[u8; 3]] {
+        match self {
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+            Colormap::Inferno => &INFERNO,
+            Colormap::Plasma => &PLASMA,
+            Colormap::Cividis => &CIVIDIS,
+            Colormap::Grayscale => &GRAYSCALE,
+            Colormap::CoolWarm => &COOL_WARM,
+        }
+    }
+
+    /// Returns color at position `t`.
+    /// Position is clamped to range `<0;1>`, color channels are in range `<0;255>`.
+    pub fn color(&self, t: f32) -> Vector3<f32> {
+        let stops = self.stops();
+        let last = stops.len() - 1;
+
+        let pos = t.clamp(0.0, 1.0) * last as f32;
+        let index = (pos as usize).min(last - 1);
+        let t = pos - index as f32;
+
+        let c0 = stop_color(stops[index]);
+        let c1 = stop_color(stops[index + 1]);
+        c0 * (1.0 - t) + c1 * t
+    }
+}
+
+fn stop_color(stop: [u8; 3]) -> Vector3<f32> {
+    vector![stop[0] as f32, stop[1] as f32, stop[2] as f32]
+}
+
+/// Window of sample values, defined by center (`level`) and `width`.
+/// Window covers values `<level - width/2; level + width/2>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub level: f32,
+    pub width: f32,
+}
+
+impl Window {
+    /// Construct window from center and width
+    pub fn new(level: f32, width: f32) -> Window {
+        Window { level, width }
+    }
+
+    /// Window covering `range`
+    pub fn from_range(range: ValueRange) -> Window {
+        Window {
+            level: (range.low + range.high) / 2.0,
+            width: range.high - range.low,
+        }
+    }
+
+    /// Lowest value of window
+    pub fn low(&self) -> f32 {
+        self.level - self.width / 2.0
+    }
+
+    /// Highest value of window
+    pub fn high(&self) -> f32 {
+        self.level + self.width / 2.0
+    }
+
+    /// Sample value at relative position `t` in the window
+    pub fn value_at(&self, t: f32) -> f32 {
+        self.low() + t * self.width
+    }
+}
+
+// Stops of matplotlib colormaps approximated by polynomial fits (error within a few units).
+// Cividis by d3-scale-chromatic polynomial fit.
+
+const VIRIDIS: [[u8; 3]; 17] = [
+    [71, 1, 85],
+    [72, 24, 106],
+    [71, 45, 123],
+    [67, 64, 134],
+    [61, 82, 140],
+    [52, 99, 142],
+    [43, 114, 142],
+    [35, 129, 141],
+    [31, 144, 139],
+    [33, 159, 135],
+    [42, 174, 128],
+    [61, 188, 116],
+    [90, 200, 97],
+    [128, 211, 73],
+    [172, 220, 48],
+    [216, 226, 29],
+    [252, 231, 33],
+];
+
+const MAGMA: [[u8; 3]; 17] = [
+    [0, 0, 0],
+    [10, 8, 38],
+    [30, 13, 73],
+    [54, 17, 102],
+    [79, 22, 122],
+    [105, 27, 132],
+    [131, 34, 134],
+    [158, 42, 129],
+    [183, 53, 119],
+    [207, 67, 108],
+    [228, 84, 99],
+    [244, 106, 95],
+    [254, 132, 99],
+    [255, 163, 111],
+    [254, 197, 132],
+    [250, 228, 159],
+    [254, 249, 186],
+];
+
+const INFERNO: [[u8; 3]; 17] = [
+    [0, 0, 0],
+    [11, 6, 44],
+    [33, 9, 74],
+    [59, 12, 93],
+    [86, 17, 104],
+    [112, 23, 108],
+    [138, 31, 105],
+    [162, 41, 96],
+    [186, 54, 82],
+    [207, 69, 62],
+    [226, 88, 42],
+    [241, 111, 24],
+    [249, 138, 15],
+    [250, 169, 19],
+    [247, 203, 44],
+    [243, 234, 93],
+    [250, 255, 168],
+];
+
+const PLASMA: [[u8; 3]; 17] = [
+    [15, 6, 139],
+    [47, 5, 152],
+    [76, 0, 164],
+    [102, 0, 171],
+    [126, 5, 170],
+    [149, 17, 164],
+    [169, 33, 153],
+    [188, 51, 139],
+    [205, 71, 124],
+    [219, 90, 109],
+    [232, 109, 95],
+    [242, 128, 81],
+    [249, 148, 67],
+    [254, 171, 53],
+    [255, 197, 41],
+    [250, 224, 34],
+    [238, 247, 38],
+];
+
+const CIVIDIS: [[u8; 3]; 17] = [
+    [0, 32, 81],
+    [1, 43, 101],
+    [17, 54, 108],
+    [38, 66, 110],
+    [60, 77, 110],
+    [81, 88, 110],
+    [98, 100, 111],
+    [114, 112, 113],
+    [127, 124, 117],
+    [140, 136, 119],
+    [154, 148, 120],
+    [169, 161, 119],
+    [187, 175, 113],
+    [206, 189, 104],
+    [226, 203, 92],
+    [243, 218, 79],
+    [253, 234, 69],
+];
+
+const GRAYSCALE: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+
+// Sampled from Moreland, "Diverging Color Maps for Scientific Visualization"
+const COOL_WARM: [[u8; 3]; 9] = [
+    [59, 76, 192],
+    [98, 130, 234],
+    [141, 176, 254],
+    [184, 208, 249],
+    [221, 221, 221],
+    [245, 196, 173],
+    [244, 154, 123],
+    [222, 96, 77],
+    [180, 4, 38],
+];
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn endpoints() {
+        assert_eq!(Colormap::Grayscale.color(0.0), vector![0.0, 0.0, 0.0]);
+        assert_eq!(Colormap::Grayscale.color(0.5), vector![127.5, 127.5, 127.5]);
+        assert_eq!(Colormap::Grayscale.color(1.0), vector![255.0, 255.0, 255.0]);
+        assert_eq!(Colormap::Viridis.color(1.0), vector![252.0, 231.0, 33.0]);
+    }
+
+    #[test]
+    fn clamps() {
+        for map in Colormap::ALL {
+            assert_eq!(map.color(-1.0), map.color(0.0));
+            assert_eq!(map.color(2.0), map.color(1.0));
+        }
+    }
+
+    #[test]
+    fn sequential_maps_increase_in_lightness() {
+        // Rough luma, perceptual maps are monotonic
+        let luma = |c: Vector3<f32>| 0.3 * c.x + 0.59 * c.y + 0.11 * c.z;
+
+        let sequential = [
+            Colormap::Viridis,
+            Colormap::Magma,
+            Colormap::Inferno,
+            Colormap::Plasma,
+            Colormap::Cividis,
+            Colormap::Grayscale,
+        ];
+        for map in sequential {
+            for i in 0..32 {
+                let t = i as f32 / 32.0;
+                assert!(luma(map.color(t)) < luma(map.color(t + 1.0 / 32.0)));
+            }
+        }
+    }
+
+    #[test]
+    fn window() {
+        let window = Window::new(40.0, 400.0);
+
+        assert_eq!(window.low(), -160.0);
+        assert_eq!(window.high(), 240.0);
+        assert_eq!(window.value_at(0.5), 40.0);
+        assert_eq!(Window::from_range((-160.0..240.0).into()), window);
+    }
+}
@@ -0,0 +1,294 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+use crate::{color::RGBA, common::ValueRange, Error};
+
+use super::{linear_visible_ranges, TransferFunction};
+
+/// Maximal number of entries of a table made by [`LutTransferFunction::for_range`]
+const MAX_ENTRIES: usize = 4096;
+
+/// Maximal number of entries of a table made by [`LutTransferFunction::per_value`]
+const MAX_VALUE_ENTRIES: usize = u16::MAX as usize + 1;
+
+/// Transfer function baked into a lookup table.
+///
+/// Table entries are evenly spaced over a range of sample values.
+/// Colors between entries are interpolated linearly,
+/// samples outside of the range get the color of the nearest entry.
+///
+/// Renderers bake volume's transfer function on construction,
+/// so that sampling does not go through dynamic dispatch.
+#[derive(Debug, Clone)]
+pub struct LutTransferFunction {
+    table: Vec<RGBA>,
+    /// Sample value of the first entry
+    low: f32,
+    /// Sample value of the last entry
+    high: f32,
+    /// Number of entries per unit of sample value
+    density: f32,
+}
+
+impl LutTransferFunction {
+    /// Bake `tf` into table of `entries` colors.
+    /// First entry is at sample value `range.low`, last at `range.high`.
+    pub fn new<T>(tf: &T, range: ValueRange, entries: usize) -> Result<LutTransferFunction, Error>
+    where
+        T: TransferFunction + ?Sized,
+    {
+        if entries < 2 {
+            return Err(Error::Invalid("Lookup table needs at least 2 entries"));
+        }
+        if !range.low.is_finite() || !range.high.is_finite() || range.low >= range.high {
+            return Err(Error::Invalid("Invalid range of lookup table"));
+        }
+
+        let density = (entries - 1) as f32 / (range.high - range.low);
+        let table = (0..entries)
+            .map(|i| tf.color(range.low + i as f32 / density))
+            .collect();
+
+        Ok(LutTransferFunction {
+            table,
+            low: range.low,
+            high: range.high,
+            density,
+        })
+    }
+
+    /// Bake `tf` into one entry per `u8` value.
+    /// Integer samples get exactly the color of the original transfer function.
+    pub fn from_u8<T>(tf: &T) -> LutTransferFunction
+    where
+        T: TransferFunction + ?Sized,
+    {
+        let table: Vec<RGBA> = (0..=u8::MAX).map(|v| tf.color(v as f32)).collect();
+
+        LutTransferFunction {
+            table,
+            low: 0.0,
+            high: u8::MAX as f32,
+            density: 1.0,
+        }
+    }
+
+    /// Bake `tf` for samples in `range`, usually value range of a volume.
+    ///
+    /// Table spans `range` rounded to integers.
+    /// Wide ranges get one entry per integer value, at most 4096 entries.
+    /// Narrow ranges (fractional samples) get an integer number of entries per value, about 256 in total.
+    /// Integer samples get exactly the color of the original transfer function,
+    /// unless the range is wider than the maximal table.
+    ///
+    /// Empty range gets [`LutTransferFunction::from_u8`].
+    pub fn for_range<T>(tf: &T, range: ValueRange) -> LutTransferFunction
+    where
+        T: TransferFunction + ?Sized,
+    {
+        let low = range.low.floor();
+        let high = range.high.ceil();
+        if !low.is_finite() || !high.is_finite() {
+            return LutTransferFunction::from_u8(tf);
+        }
+
+        let width = f32::max(high - low, 1.0) as usize;
+        let per_value = (u8::MAX as usize / width).max(1);
+        let entries = (width * per_value + 1).min(MAX_ENTRIES);
+        LutTransferFunction::new(tf, (low..low + width as f32).into(), entries)
+            .expect("Range is finite and nonempty")
+    }
+
+    /// Bake `tf` with one entry per integer value of `range` rounded to integers.
+    /// Returns `None` if the range is not finite or has more than 65536 values.
+    pub fn per_value<T>(tf: &T, range: ValueRange) -> Option<LutTransferFunction>
+    where
+        T: TransferFunction + ?Sized,
+    {
+        let low = range.low.floor();
+        let high = range.high.ceil();
+        if !low.is_finite() || !high.is_finite() || high - low >= MAX_VALUE_ENTRIES as f32 {
+            return None;
+        }
+        let entries = (high - low) as usize + 1;
+        LutTransferFunction::new(tf, (low..high).into(), entries).ok()
+    }
+
+    /// Range of sample values covered by the table
+    pub fn get_range(&self) -> ValueRange {
+        (self.low..self.high).into()
+    }
+
+    /// Table entries getter
+    pub fn get_table(&self) -> &[RGBA] {
+        &self.table
+    }
+
+    /// Difference of sample values of neighbouring entries
+    pub fn entry_step(&self) -> f32 {
+        1.0 / self.density
+    }
+
+    /// Sample value of entry `index`
+    fn entry_value(&self, index: usize) -> f32 {
+        self.low + index as f32 / self.density
+    }
+}
+
+impl TransferFunction for LutTransferFunction {
+    #[inline]
+    fn color(&self, sample: f32) -> RGBA {
+        let pos = (sample - self.low) * self.density;
+        let last = self.table.len() - 1;
+
+        if pos.is_nan() || pos <= 0.0 {
+            return self.table[0];
+        }
+
+        let index = pos as usize;
+        if index >= last {
+            return self.table[last];
+        }
+
+        let t = pos - index as f32;
+        self.table[index] * (1.0 - t) + self.table[index + 1] * t
+    }
+
+    /// Ranges are calculated from table entries, the result is exact.
+    fn visible_ranges(&self, _: ValueRange) -> Vec<ValueRange> {
+        let points = self
+            .table
+            .iter()
+            .enumerate()
+            .map(|(i, color)| (self.entry_value(i), color.w));
+
+        linear_visible_ranges(points)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::{color, premade::transfer_functions::skull_tf};
+
+    fn band_tf(sample: f32) -> RGBA {
+        if sample > 10.5 && sample < 20.5 {
+            color::new(100.0, 200.0, 0.0, 1.0)
+        } else {
+            color::zero()
+        }
+    }
+
+    #[test]
+    fn u8_samples_match() {
+        let lut = LutTransferFunction::from_u8(&skull_tf);
+
+        for v in 0..=255 {
+            let v = v as f32;
+            assert_eq!(lut.color(v), skull_tf(v));
+        }
+    }
+
+    #[test]
+    fn interpolates() {
+        let lut = LutTransferFunction::from_u8(&band_tf);
+
+        assert_eq!(lut.color(10.0), color::zero());
+        assert_eq!(lut.color(10.25), color::new(25.0, 50.0, 0.0, 0.25));
+        assert_eq!(lut.color(15.5), color::new(100.0, 200.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn clamps() {
+        let tf = |_: f32| color::mono(255.0, 1.0);
+        let lut = LutTransferFunction::new(&tf, (100.0..200.0).into(), 11).unwrap();
+
+        assert_eq!(lut.color(-10.0), color::mono(255.0, 1.0));
+        assert_eq!(lut.color(1000.0), color::mono(255.0, 1.0));
+        assert_eq!(lut.color(f32::NAN), color::mono(255.0, 1.0));
+    }
+
+    #[test]
+    fn wide_range() {
+        let tf = |sample: f32| color::mono(255.0, sample / 65535.0);
+        let lut = LutTransferFunction::new(&tf, (0.0..65535.0).into(), 4096).unwrap();
+
+        assert_eq!(lut.get_table().len(), 4096);
+        assert_eq!(lut.color(0.0).w, 0.0);
+        assert_eq!(lut.color(65535.0).w, 1.0);
+        assert!((lut.color(30000.0).w - 30000.0 / 65535.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn for_range() {
+        let tf = |sample: f32| color::mono(255.0, if sample > 3000.0 { 1.0 } else { 0.0 });
+
+        // Samples of u8 volume
+        let lut = LutTransferFunction::for_range(&skull_tf, (3.0..200.0).into());
+        assert_eq!(lut.get_range(), (3.0..200.0).into());
+        assert_eq!(lut.get_table().len(), 198);
+        for v in 3..=200 {
+            assert_eq!(lut.color(v as f32), skull_tf(v as f32));
+        }
+
+        // Fractional samples
+        let lut = LutTransferFunction::for_range(&tf, (0.1..0.9).into());
+        assert_eq!(lut.get_range(), (0.0..1.0).into());
+        assert_eq!(lut.get_table().len(), 256);
+
+        // Signed samples
+        let lut = LutTransferFunction::for_range(&tf, (-1024.0..3071.0).into());
+        assert_eq!(lut.get_table().len(), 4096);
+        assert_eq!(lut.color(-1024.0).w, 0.0);
+        assert_eq!(lut.color(3001.0).w, 1.0);
+
+        // 12-bit samples, entry per value
+        let lut = LutTransferFunction::for_range(&tf, (0.0..4095.0).into());
+        assert_eq!(lut.get_table().len(), 4096);
+        assert_eq!(lut.color(3000.0).w, 0.0);
+        assert_eq!(lut.color(3001.0).w, 1.0);
+
+        // 16-bit samples, capped
+        let lut = LutTransferFunction::for_range(&tf, (0.0..65535.0).into());
+        assert_eq!(lut.get_table().len(), 4096);
+        assert_eq!(lut.get_range(), (0.0..65535.0).into());
+
+        // Empty range
+        let lut = LutTransferFunction::for_range(&tf, ValueRange::empty());
+        assert_eq!(lut.get_range(), (0.0..255.0).into());
+    }
+
+    #[test]
+    fn invalid_table() {
+        let range: ValueRange = (0.0..255.0).into();
+
+        assert!(LutTransferFunction::new(&band_tf, range, 1).is_err());
+        assert!(LutTransferFunction::new(&band_tf, (5.0..5.0).into(), 10).is_err());
+    }
+
+    #[test]
+    fn visible_ranges() {
+        let lut = LutTransferFunction::from_u8(&band_tf);
+
+        let ranges = lut.visible_ranges(ValueRange::empty());
+
+        assert_eq!(ranges.len(), 1);
+        assert!(!ranges[0].contains(10.0));
+        assert!(ranges[0].contains(10.01));
+        assert!(ranges[0].contains(20.99));
+        assert!(!ranges[0].contains(21.0));
+
+        let opaque_end = |sample: f32| color::mono(255.0, if sample > 250.0 { 1.0 } else { 0.0 });
+        let lut = LutTransferFunction::from_u8(&opaque_end);
+
+        let ranges = lut.visible_ranges(ValueRange::empty());
+
+        assert_eq!(ranges.len(), 1);
+        assert!(!ranges[0].contains(250.0));
+        assert_eq!(ranges[0].high, f32::INFINITY);
+    }
+}
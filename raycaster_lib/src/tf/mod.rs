@@ -0,0 +1,254 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Transfer functions
+//!
+//! Transfer function maps sample values to color and opacity.
+//!
+//! # `TransferFunction` trait
+//!
+//! Renderers and volumes work with transfer functions through [`TransferFunction`] trait.
+//! The trait is implemented for all functions and closures of type `Fn(f32) -> RGBA`,
+//! so functions from [`crate::premade::transfer_functions`] can be used directly.
+//! Types holding state (control points loaded from file, edited in GUI...)
+//! implement the trait themselves.
+//!
+//! Volumes hold transfer function as a shared handle, [`TF`].
+//!
+//! # Two-dimensional transfer functions
+//!
+//! Transfer function can also depend on gradient magnitude,
+//! see [`TransferFunction::color_gradient`] and [`Tf2D`].
+//!
+//! # Provided transfer functions
+//!
+//! * [`PiecewiseLinearTf`] - linear interpolation between control points
+//! * [`LutTransferFunction`] - any transfer function baked into a lookup table
+//! * [`Tf2D`] - sample value × gradient magnitude, composed of widgets
+//!
+//! [`PreIntegratedTf`] classifies whole ray segments instead of single samples,
+//! enabled by [`crate::render::RenderOptions::pre_integration`].
+//!
+//! Perceptual colormaps ([`Colormap`]) can be combined with an opacity ramp
+//! using [`PiecewiseLinearTf::from_colormap`].
+
+mod colormap;
+mod lut;
+mod piecewise_linear;
+mod pre_integrated;
+mod tf_2d;
+
+use std::sync::Arc;
+
+use crate::{color::RGBA, common::ValueRange};
+
+pub use colormap::{Colormap, Window};
+pub use lut::LutTransferFunction;
+pub use piecewise_linear::PiecewiseLinearTf;
+pub use pre_integrated::PreIntegratedTf;
+pub use tf_2d::{Tf2D, Widget};
+
+/// Interface for all transfer functions
+///
+/// Must be object safe, volumes store transfer functions as `dyn TransferFunction`.
+pub trait TransferFunction: Send + Sync {
+    /// Returns color of `sample`.
+    /// Color channels are in range `<0;255>`, opacity (`w`) in range `<0;1>`.
+    ///
+    /// Transfer functions using gradient return color of `sample` at the most opaque gradient.
+    fn color(&self, sample: f32) -> RGBA;
+
+    /// Returns ranges of samples with nonzero opacity.
+    /// Used by empty space skipping, ranges must not be narrower than the actual ones.
+    ///
+    /// `domain` is the range of sample values in volume.
+    /// Default implementation bakes the function into a lookup table spanning `domain`,
+    /// see [`LutTransferFunction::for_range`].
+    /// If entries of the table are further apart than one sample value,
+    /// narrow visible bands could fall between them. The function is then sampled
+    /// at every integer value of `domain` ([`LutTransferFunction::per_value`]),
+    /// or the whole `domain` is visible if it is too wide.
+    /// Implementations knowing their ranges exactly ignore `domain`.
+    fn visible_ranges(&self, domain: ValueRange) -> Vec<ValueRange> {
+        let lut = LutTransferFunction::for_range(self, domain);
+        if lut.entry_step() <= 1.0 {
+            return lut.visible_ranges(domain);
+        }
+        match LutTransferFunction::per_value(self, domain) {
+            Some(lut) => lut.visible_ranges(domain),
+            None => vec![domain],
+        }
+    }
+
+    /// Returns color of `sample` with gradient magnitude `gradient`.
+    /// Gradient magnitude is the change of sample value per voxel.
+    ///
+    /// Default implementation ignores gradient.
+    fn color_gradient(&self, sample: f32, gradient: f32) -> RGBA {
+        let _ = gradient;
+        self.color(sample)
+    }
+
+    /// Returns `true` if color depends on gradient magnitude.
+    /// Renderers then classify samples using [`TransferFunction::color_gradient`].
+    fn uses_gradient(&self) -> bool {
+        false
+    }
+}
+
+/// Any function (or closure) can serve as a transfer function
+impl<F> TransferFunction for F
+where
+    F: Fn(f32) -> RGBA + Send + Sync,
+{
+    fn color(&self, sample: f32) -> RGBA {
+        self(sample)
+    }
+}
+
+impl std::fmt::Debug for dyn TransferFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TransferFunction")
+    }
+}
+
+/// Visible ranges of a function interpolated linearly between `points`
+/// and clamped outside of them.
+///
+/// Points with zero opacity are not part of any range.
+///
+/// # Params
+/// * `points` - `(sample value, opacity)` pairs, sorted by sample value
+pub(crate) fn linear_visible_ranges<I>(points: I) -> Vec<ValueRange>
+where
+    I: IntoIterator<Item = (f32, f32)>,
+{
+    let mut ranges: Vec<ValueRange> = vec![];
+    let mut push = |range: ValueRange| match ranges.last_mut() {
+        Some(prev) if prev.high >= range.low => prev.high = f32::max(prev.high, range.high),
+        _ => ranges.push(range),
+    };
+
+    let mut points = points.into_iter();
+    let mut prev = match points.next() {
+        Some(p) => p,
+        None => return vec![],
+    };
+
+    // Clamped part below first point
+    if prev.1 != 0.0 {
+        push((f32::NEG_INFINITY..prev.0).into());
+    }
+
+    // Segment is visible, if any of its points is.
+    // Invisible endpoint is excluded from the range.
+    for point in points {
+        if prev.0 < point.0 && (prev.1 != 0.0 || point.1 != 0.0) {
+            let low = if prev.1 == 0.0 {
+                next_up(prev.0)
+            } else {
+                prev.0
+            };
+            let high = if point.1 == 0.0 {
+                next_down(point.0)
+            } else {
+                point.0
+            };
+            push((low..high).into());
+        }
+        prev = point;
+    }
+
+    // Clamped part above last point
+    if prev.1 != 0.0 {
+        push((prev.0..f32::INFINITY).into());
+    }
+
+    ranges
+}
+
+/// Smallest float greater than finite `v`
+fn next_up(v: f32) -> f32 {
+    if v == 0.0 {
+        return f32::from_bits(1);
+    }
+    let bits = v.to_bits();
+    f32::from_bits(if v > 0.0 { bits + 1 } else { bits - 1 })
+}
+
+/// Greatest float lower than finite `v`
+fn next_down(v: f32) -> f32 {
+    -next_up(-v)
+}
+
+/// Shared handle to a transfer function.
+/// Cloning the handle does not clone the transfer function.
+pub type TF = Arc<dyn TransferFunction>;
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::premade::transfer_functions::skull_tf;
+
+    #[test]
+    fn fn_pointer_is_tf() {
+        let tf: TF = Arc::new(skull_tf);
+
+        assert_eq!(tf.color(10.0), skull_tf(10.0));
+        assert_eq!(tf.color(100.0), skull_tf(100.0));
+    }
+
+    #[test]
+    fn closure_with_state_is_tf() {
+        let threshold = 50.0;
+        let tf: TF = Arc::new(move |sample: f32| {
+            if sample > threshold {
+                crate::color::mono(255.0, 1.0)
+            } else {
+                crate::color::zero()
+            }
+        });
+
+        assert_eq!(tf.color(10.0).w, 0.0);
+        assert_eq!(tf.color(60.0).w, 1.0);
+    }
+
+    #[test]
+    fn one_value_band() {
+        let band =
+            |sample: f32| crate::color::mono(255.0, if sample == 3001.0 { 1.0 } else { 0.0 });
+
+        // Entry per value
+        let ranges = band.visible_ranges((0.0..4095.0).into());
+        assert_eq!(ranges.len(), 1);
+        assert!(ranges[0].contains(3001.0));
+        assert!(!ranges[0].contains(3000.0));
+
+        // Whole u16 domain does not fit into the table, every value is sampled
+        let ranges = band.visible_ranges((0.0..65535.0).into());
+        assert_eq!(ranges.len(), 1);
+        assert!(ranges[0].contains(3001.0));
+        assert!(!ranges[0].contains(3000.0));
+
+        // Too many values to sample
+        let domain: ValueRange = (0.0..1e6).into();
+        assert_eq!(band.visible_ranges(domain), vec![domain]);
+    }
+
+    #[test]
+    fn invisible_points_excluded() {
+        let ranges = linear_visible_ranges([(0.0, 0.0), (1.0, 1.0), (2.0, 0.0), (3.0, 0.5)]);
+
+        assert_eq!(ranges.len(), 2);
+        assert!(!ranges[0].contains(0.0));
+        assert!(ranges[0].contains(0.001));
+        assert!(ranges[0].contains(1.999));
+        assert!(!ranges[0].contains(2.0));
+        assert!(!ranges[1].contains(2.0));
+        assert!(ranges[1].contains(1000.0));
+    }
+}
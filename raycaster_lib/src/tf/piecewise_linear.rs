@@ -0,0 +1,343 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+use nalgebra::{vector, Vector3};
+
+use crate::{
+    color::{self, RGBA},
+    common::ValueRange,
+    Error,
+};
+
+use super::{linear_visible_ranges, Colormap, TransferFunction, Window};
+
+/// Transfer function defined by control points.
+///
+/// Color and opacity are interpolated linearly between neighbouring control points.
+/// Samples outside of the control points get the color of the nearest control point.
+///
+/// # Example
+/// ```
+/// # use raycaster_lib::{color, tf::PiecewiseLinearTf, TransferFunction};
+/// let tf = PiecewiseLinearTf::new(vec![
+///     (80.0, color::new(255.0, 0.0, 0.0, 0.0)),
+///     (100.0, color::new(255.0, 0.0, 0.0, 0.5)),
+/// ])
+/// .unwrap();
+///
+/// assert_eq!(tf.color(90.0).w, 0.25);
+/// assert_eq!(tf.color(200.0).w, 0.5);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PiecewiseLinearTf {
+    /// Control points, sorted by sample value.
+    points: Vec<(f32, RGBA)>,
+}
+
+impl PiecewiseLinearTf {
+    /// Construct transfer function from control points `(sample value, color)`.
+    ///
+    /// Points must be sorted by sample value.
+    /// Two points with the same value make a step in the function.
+    pub fn new(points: Vec<(f32, RGBA)>) -> Result<PiecewiseLinearTf, Error> {
+        if points.iter().any(|(v, _)| !v.is_finite()) {
+            return Err(Error::Invalid("Control point value is not finite"));
+        }
+        if points.windows(2).any(|w| w[0].0 > w[1].0) {
+            return Err(Error::Invalid("Control points are not sorted"));
+        }
+        Ok(PiecewiseLinearTf { points })
+    }
+
+    /// Construct transfer function from separate color and opacity control points.
+    ///
+    /// Both sets of points must be sorted by sample value.
+    /// Color and opacity are interpolated independently,
+    /// resulting control points are placed on values of both sets.
+    /// Steps of either set are kept as two points with the same value.
+    pub fn from_color_opacity(
+        color_points: &[(f32, Vector3<f32>)],
+        opacity_points: &[(f32, f32)],
+    ) -> Result<PiecewiseLinearTf, Error> {
+        let colors = color_points
+            .iter()
+            .map(|&(v, c)| (v, vector![c.x, c.y, c.z, 0.0]))
+            .collect();
+        let colors = PiecewiseLinearTf::new(colors)?;
+
+        let opacities = opacity_points
+            .iter()
+            .map(|&(v, a)| (v, vector![0.0, 0.0, 0.0, a]))
+            .collect();
+        let opacities = PiecewiseLinearTf::new(opacities)?;
+
+        let mut values: Vec<f32> = color_points
+            .iter()
+            .map(|p| p.0)
+            .chain(opacity_points.iter().map(|p| p.0))
+            .collect();
+        values.sort_unstable_by(f32::total_cmp);
+        values.dedup();
+
+        let mut points = Vec::with_capacity(values.len());
+        for v in values {
+            let below = colors.color_below(v).xyz().push(opacities.color_below(v).w);
+            let at = colors.color(v).xyz().push(opacities.color(v).w);
+            if below != at {
+                points.push((v, below));
+            }
+            points.push((v, at));
+        }
+
+        PiecewiseLinearTf::new(points)
+    }
+
+    /// Construct transfer function from `colormap` stretched over `window`
+    /// and a separate opacity ramp.
+    /// Samples outside of the window get the color of the nearest end of the window.
+    ///
+    /// # Params
+    /// * `opacity_ramp` - opacity control points `(position in window, opacity)`, sorted by position.
+    ///   Position `0.0` is the low end of the window, `1.0` the high end.
+    ///
+    /// # Example
+    /// ```
+    /// # use raycaster_lib::{tf::{Colormap, PiecewiseLinearTf, Window}, TransferFunction};
+    /// // Bone window, opacity rising over the lower half
+    /// let window = Window::new(400.0, 1800.0);
+    /// let tf = PiecewiseLinearTf::from_colormap(Colormap::Grayscale, window, &[(0.0, 0.0), (0.5, 1.0)])
+    ///     .unwrap();
+    ///
+    /// assert_eq!(tf.color(-500.0).w, 0.0);
+    /// assert_eq!(tf.color(400.0).w, 1.0);
+    /// ```
+    pub fn from_colormap(
+        colormap: Colormap,
+        window: Window,
+        opacity_ramp: &[(f32, f32)],
+    ) -> Result<PiecewiseLinearTf, Error> {
+        if window.width.is_nan() || window.width <= 0.0 {
+            return Err(Error::Invalid("Window width must be positive"));
+        }
+        if opacity_ramp.iter().any(|&(t, _)| !(0.0..=1.0).contains(&t)) {
+            return Err(Error::Invalid("Opacity ramp position out of window"));
+        }
+
+        let last = (colormap.stops().len() - 1) as f32;
+        let color_points: Vec<_> = (0..colormap.stops().len())
+            .map(|i| {
+                let t = i as f32 / last;
+                (window.value_at(t), colormap.color(t))
+            })
+            .collect();
+
+        let opacity_points: Vec<_> = opacity_ramp
+            .iter()
+            .map(|&(t, a)| (window.value_at(t), a))
+            .collect();
+
+        PiecewiseLinearTf::from_color_opacity(&color_points, &opacity_points)
+    }
+
+    /// Control points getter
+    pub fn get_points(&self) -> &[(f32, RGBA)] {
+        &self.points
+    }
+
+    /// Color approaching `sample` from lower values, differs from [`TransferFunction::color`] at steps
+    fn color_below(&self, sample: f32) -> RGBA {
+        self.interpolate(sample, self.points.partition_point(|p| p.0 < sample))
+    }
+
+    /// Color between control points `upper - 1` and `upper`
+    fn interpolate(&self, sample: f32, upper: usize) -> RGBA {
+        if upper == 0 {
+            return match self.points.first() {
+                Some(p) => p.1,
+                None => color::zero(),
+            };
+        }
+        if upper == self.points.len() {
+            return self.points[upper - 1].1;
+        }
+
+        let (v0, c0) = self.points[upper - 1];
+        let (v1, c1) = self.points[upper];
+
+        // v0 <= sample <= v1
+        let t = (sample - v0) / (v1 - v0);
+        c0 * (1.0 - t) + c1 * t
+    }
+}
+
+impl TransferFunction for PiecewiseLinearTf {
+    fn color(&self, sample: f32) -> RGBA {
+        // Number of points with value lower or equal to sample
+        self.interpolate(sample, self.points.partition_point(|p| p.0 <= sample))
+    }
+
+    /// Ranges are calculated from control points, not by sampling.
+    fn visible_ranges(&self, _: ValueRange) -> Vec<ValueRange> {
+        linear_visible_ranges(self.points.iter().map(|(v, color)| (*v, color.w)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::{
+        common::BoundBox,
+        volumetric::volumes::{FloatBlock, FloatBlockVolume},
+    };
+
+    fn band_tf() -> PiecewiseLinearTf {
+        PiecewiseLinearTf::new(vec![
+            (10.0, color::new(0.0, 0.0, 0.0, 0.0)),
+            (20.0, color::new(200.0, 100.0, 0.0, 1.0)),
+            (30.0, color::new(0.0, 0.0, 0.0, 0.0)),
+            (60.0, color::new(0.0, 0.0, 0.0, 0.0)),
+            (70.0, color::new(100.0, 100.0, 100.0, 0.5)),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn interpolates() {
+        let tf = band_tf();
+
+        assert_eq!(tf.color(15.0), color::new(100.0, 50.0, 0.0, 0.5));
+        assert_eq!(tf.color(20.0), color::new(200.0, 100.0, 0.0, 1.0));
+        assert_eq!(tf.color(45.0), color::zero());
+    }
+
+    #[test]
+    fn clamps() {
+        let tf = band_tf();
+
+        assert_eq!(tf.color(-5.0), color::zero());
+        assert_eq!(tf.color(255.0), color::new(100.0, 100.0, 100.0, 0.5));
+    }
+
+    #[test]
+    fn unsorted_points() {
+        let tf =
+            PiecewiseLinearTf::new(vec![(20.0, color::zero()), (10.0, color::mono(255.0, 1.0))]);
+
+        assert!(tf.is_err());
+    }
+
+    #[test]
+    fn step() {
+        let tf =
+            PiecewiseLinearTf::new(vec![(50.0, color::zero()), (50.0, color::mono(255.0, 1.0))])
+                .unwrap();
+
+        assert_eq!(tf.color(49.9).w, 0.0);
+        assert_eq!(tf.color(50.0).w, 1.0);
+    }
+
+    #[test]
+    fn visible_ranges() {
+        let tf = band_tf();
+
+        let ranges = tf.visible_ranges(ValueRange::empty());
+
+        assert_eq!(ranges.len(), 2);
+        assert!(!ranges[0].contains(10.0));
+        assert!(ranges[0].contains(10.01));
+        assert!(ranges[0].contains(29.99));
+        assert!(!ranges[0].contains(30.0));
+        assert!(!ranges[1].contains(60.0));
+        assert_eq!(ranges[1].high, f32::INFINITY);
+    }
+
+    #[test]
+    fn color_opacity_points() {
+        let tf = PiecewiseLinearTf::from_color_opacity(
+            &[
+                (0.0, vector![0.0, 0.0, 0.0]),
+                (100.0, vector![100.0, 0.0, 0.0]),
+            ],
+            &[(50.0, 0.0), (60.0, 1.0)],
+        )
+        .unwrap();
+
+        assert_eq!(tf.get_points().len(), 4);
+        assert_eq!(tf.color(55.0), color::new(55.0, 0.0, 0.0, 0.5));
+        assert_eq!(tf.color(80.0), color::new(80.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn color_opacity_step() {
+        let tf = PiecewiseLinearTf::from_color_opacity(
+            &[
+                (0.0, vector![0.0, 0.0, 0.0]),
+                (200.0, vector![200.0, 0.0, 0.0]),
+            ],
+            &[(100.0, 0.0), (100.0, 1.0)],
+        )
+        .unwrap();
+
+        assert_eq!(tf.get_points().len(), 4);
+        assert_eq!(tf.color(99.9).w, 0.0);
+        assert_eq!(tf.color(100.0), color::new(100.0, 0.0, 0.0, 1.0));
+        assert_eq!(tf.color(150.0).w, 1.0);
+        let ranges = tf.visible_ranges(ValueRange::empty());
+        assert!(!ranges[0].contains(99.9));
+        assert!(ranges[0].contains(100.0));
+    }
+
+    #[test]
+    fn colormap_window() {
+        let window = Window::new(100.0, 100.0);
+        let tf =
+            PiecewiseLinearTf::from_colormap(Colormap::Viridis, window, &[(0.5, 0.0), (1.0, 0.8)])
+                .unwrap();
+
+        // Colormap spans the window
+        assert_eq!(tf.color(50.0).xyz(), Colormap::Viridis.color(0.0));
+        assert_eq!(tf.color(150.0).xyz(), Colormap::Viridis.color(1.0));
+        assert_eq!(tf.color(0.0).xyz(), Colormap::Viridis.color(0.0));
+
+        // Opacity ramp
+        assert_eq!(tf.color(60.0).w, 0.0);
+        assert_eq!(tf.color(125.0).w, 0.4);
+        assert_eq!(tf.color(200.0).w, 0.8);
+        assert_eq!(
+            tf.visible_ranges(ValueRange::empty())[0].high,
+            f32::INFINITY
+        );
+    }
+
+    #[test]
+    fn colormap_invalid_window() {
+        let ramp = &[(0.0, 1.0)];
+
+        assert!(
+            PiecewiseLinearTf::from_colormap(Colormap::Magma, Window::new(0.0, 0.0), ramp).is_err()
+        );
+        assert!(PiecewiseLinearTf::from_colormap(
+            Colormap::Magma,
+            Window::new(0.0, 10.0),
+            &[(1.5, 1.0)]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn empty_blocks() {
+        let tf = band_tf();
+        let block = |v: f32| {
+            FloatBlock::from_data(vec![v], BoundBox::empty(), vector![1.0, 1.0, 1.0], 1, &tf)
+        };
+        let blocks = &[block(5.0), block(25.0), block(45.0), block(100.0)];
+
+        let empty = FloatBlockVolume::build_empty(blocks, &tf);
+
+        assert_eq!(empty, vec![true, false, true, false]);
+    }
+}
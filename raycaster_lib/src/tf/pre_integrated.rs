@@ -0,0 +1,186 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+use nalgebra::vector;
+
+use crate::color::RGBA;
+
+use super::{LutTransferFunction, TransferFunction};
+
+/// Opacity is capped, so that extinction stays finite.
+const MAX_OPACITY: f32 = 0.9999;
+
+/// Pre-integrated transfer function.
+///
+/// Table indexed by (front sample, back sample) holds color and opacity
+/// integrated over a ray segment of unit length, along which the sample value changes linearly.
+/// Features of the transfer function lying between the two samples are not missed,
+/// no matter how long the sampling step is.
+///
+/// Opacity of a segment of length `d` is `1 - (1 - a)^d`,
+/// the same correction renderers apply to single samples.
+/// Self-attenuation inside of the segment is neglected when integrating color.
+#[derive(Debug, Clone)]
+pub struct PreIntegratedTf {
+    /// `entries × entries` table, row is the front sample
+    table: Vec<RGBA>,
+    entries: usize,
+    /// Sample value of the first entry
+    low: f32,
+    /// Number of entries per unit of sample value
+    density: f32,
+}
+
+impl PreIntegratedTf {
+    /// Integrate lookup table `lut`.
+    /// Resulting table has the same resolution and range as `lut`.
+    pub fn new(lut: &LutTransferFunction) -> PreIntegratedTf {
+        let colors = lut.get_table();
+        let entries = colors.len();
+        let range = lut.get_range();
+
+        // Extinction coefficient and extinction-weighted color of entries
+        let tau: Vec<f32> = colors
+            .iter()
+            .map(|c| -f32::ln(1.0 - c.w.min(MAX_OPACITY)))
+            .collect();
+
+        // Integrals from first entry, trapezoidal rule
+        let mut tau_integral = vec![0.0; entries];
+        let mut color_integral = vec![vector![0.0, 0.0, 0.0]; entries];
+        for i in 1..entries {
+            tau_integral[i] = tau_integral[i - 1] + (tau[i - 1] + tau[i]) / 2.0;
+            color_integral[i] = color_integral[i - 1]
+                + (colors[i - 1].xyz() * tau[i - 1] + colors[i].xyz() * tau[i]) / 2.0;
+        }
+
+        let mut table = Vec::with_capacity(entries * entries);
+        for front in 0..entries {
+            for back in 0..entries {
+                let (lo, hi) = if front < back {
+                    (front, back)
+                } else {
+                    (back, front)
+                };
+
+                let (tau_sum, color_sum, len) = if lo == hi {
+                    (tau[lo], colors[lo].xyz() * tau[lo], 1.0)
+                } else {
+                    (
+                        tau_integral[hi] - tau_integral[lo],
+                        color_integral[hi] - color_integral[lo],
+                        (hi - lo) as f32,
+                    )
+                };
+
+                let entry = if tau_sum > 0.0 {
+                    let rgb = color_sum / tau_sum;
+                    let opacity = 1.0 - f32::exp(-tau_sum / len);
+                    vector![rgb.x, rgb.y, rgb.z, opacity]
+                } else {
+                    vector![0.0, 0.0, 0.0, 0.0]
+                };
+                table.push(entry);
+            }
+        }
+
+        PreIntegratedTf {
+            table,
+            entries,
+            low: range.low,
+            density: (entries - 1) as f32 / (range.high - range.low),
+        }
+    }
+
+    /// Integrate `tf`, sampled in `u8` values
+    pub fn from_u8<T>(tf: &T) -> PreIntegratedTf
+    where
+        T: TransferFunction + ?Sized,
+    {
+        PreIntegratedTf::new(&LutTransferFunction::from_u8(tf))
+    }
+
+    /// Color and opacity of unit length segment from `front` to `back` sample
+    #[inline]
+    pub fn segment(&self, front: f32, back: f32) -> RGBA {
+        let front = self.index(front);
+        let back = self.index(back);
+        self.table[front * self.entries + back]
+    }
+
+    /// Nearest entry of `sample`
+    #[inline]
+    fn index(&self, sample: f32) -> usize {
+        let pos = (sample - self.low) * self.density + 0.5;
+        if pos.is_nan() || pos <= 0.0 {
+            return 0;
+        }
+        (pos as usize).min(self.entries - 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::color;
+
+    /// Visible only around value 90
+    fn band_tf(sample: f32) -> RGBA {
+        if sample > 89.5 && sample < 90.5 {
+            color::new(255.0, 0.0, 0.0, 0.8)
+        } else {
+            color::zero()
+        }
+    }
+
+    #[test]
+    fn point_segment() {
+        let tf = |sample: f32| color::mono(sample, 0.5);
+        let pre = PreIntegratedTf::from_u8(&tf);
+
+        let seg = pre.segment(100.0, 100.0);
+
+        assert!((seg.x - 100.0).abs() < 0.001);
+        assert!((seg.w - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn constant_tf() {
+        let tf = |_: f32| color::mono(200.0, 0.3);
+        let pre = PreIntegratedTf::from_u8(&tf);
+
+        let seg = pre.segment(10.0, 250.0);
+
+        assert!((seg.x - 200.0).abs() < 0.001);
+        assert!((seg.w - 0.3).abs() < 0.001);
+    }
+
+    #[test]
+    fn band_between_samples() {
+        let pre = PreIntegratedTf::from_u8(&band_tf);
+
+        assert_eq!(pre.segment(80.0, 85.0).w, 0.0);
+        assert_eq!(pre.segment(95.0, 120.0).w, 0.0);
+
+        // Band is crossed, even though both samples are transparent
+        let seg = pre.segment(80.0, 100.0);
+        assert!(seg.w > 0.0);
+        assert_eq!(seg.xyz(), vector![255.0, 0.0, 0.0]);
+
+        // Narrower segment is more opaque
+        assert!(pre.segment(85.0, 95.0).w > seg.w);
+    }
+
+    #[test]
+    fn symmetric() {
+        let pre = PreIntegratedTf::from_u8(&crate::premade::transfer_functions::shapes_tf);
+
+        for (front, back) in [(0.0, 255.0), (80.0, 100.0), (93.0, 97.5)] {
+            assert_eq!(pre.segment(front, back), pre.segment(back, front));
+        }
+    }
+}
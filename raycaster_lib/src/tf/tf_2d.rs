@@ -0,0 +1,332 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+use nalgebra::vector;
+
+use crate::{
+    color::{self, RGBA},
+    common::ValueRange,
+};
+
+use super::TransferFunction;
+
+/// Area of the (sample value × gradient magnitude) domain with assigned color.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Widget {
+    /// Constant color inside of a rectangle.
+    Rectangle {
+        /// Sample values covered
+        value: ValueRange,
+        /// Gradient magnitudes covered
+        gradient: ValueRange,
+        color: RGBA,
+    },
+    /// Triangle with apex in `value` at zero gradient,
+    /// widening to `value ± half_width` at gradient `max_gradient`.
+    /// Opacity fades out from the center towards the edges.
+    ///
+    /// Selects boundaries of material with value `value`,
+    /// homogeneous interior (zero gradient) is not selected.
+    Triangle {
+        value: f32,
+        half_width: f32,
+        max_gradient: f32,
+        color: RGBA,
+    },
+}
+
+impl Widget {
+    /// Rectangle widget constructor
+    pub fn rectangle(value: ValueRange, gradient: ValueRange, color: RGBA) -> Widget {
+        Widget::Rectangle {
+            value,
+            gradient,
+            color,
+        }
+    }
+
+    /// Triangle widget constructor
+    pub fn triangle(value: f32, half_width: f32, max_gradient: f32, color: RGBA) -> Widget {
+        Widget::Triangle {
+            value,
+            half_width,
+            max_gradient,
+            color,
+        }
+    }
+
+    /// Color of widget
+    pub fn get_color(&self) -> RGBA {
+        match self {
+            Widget::Rectangle { color, .. } | Widget::Triangle { color, .. } => *color,
+        }
+    }
+
+    /// Opacity of widget in point (`sample`, `gradient`).
+    pub fn opacity(&self, sample: f32, gradient: f32) -> f32 {
+        match *self {
+            Widget::Rectangle {
+                value,
+                gradient: grad_range,
+                color,
+            } => {
+                if value.contains(sample) && grad_range.contains(gradient) {
+                    color.w
+                } else {
+                    0.0
+                }
+            }
+            Widget::Triangle {
+                value,
+                half_width,
+                max_gradient,
+                color,
+            } => {
+                if !(0.0..=max_gradient).contains(&gradient) {
+                    return 0.0;
+                }
+                let width = half_width * gradient / max_gradient;
+                let distance = (sample - value).abs();
+                if distance < width {
+                    color.w * (1.0 - distance / width)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    /// Highest opacity of widget in `sample` over all gradients.
+    pub fn max_opacity(&self, sample: f32) -> f32 {
+        match *self {
+            Widget::Rectangle { value, color, .. } => {
+                if value.contains(sample) {
+                    color.w
+                } else {
+                    0.0
+                }
+            }
+            Widget::Triangle { max_gradient, .. } => self.opacity(sample, max_gradient),
+        }
+    }
+
+    /// Sample values covered by widget
+    pub fn value_range(&self) -> ValueRange {
+        match *self {
+            Widget::Rectangle { value, .. } => value,
+            Widget::Triangle {
+                value, half_width, ..
+            } => (value - half_width..value + half_width).into(),
+        }
+    }
+}
+
+/// Two-dimensional transfer function.
+/// Color depends on sample value and gradient magnitude.
+///
+/// Function is composed of widgets.
+/// Colors of overlapping widgets are averaged, weighted by opacity.
+///
+/// [`TransferFunction::color`] returns the most opaque color over all gradients,
+/// so that empty space skipping treats sample as visible if it is visible at any gradient.
+#[derive(Debug, Clone, Default)]
+pub struct Tf2D {
+    widgets: Vec<Widget>,
+}
+
+impl Tf2D {
+    /// Construct transfer function from widgets
+    pub fn new(widgets: Vec<Widget>) -> Tf2D {
+        Tf2D { widgets }
+    }
+
+    /// Add widget
+    pub fn push_widget(&mut self, widget: Widget) {
+        self.widgets.push(widget);
+    }
+
+    /// Widgets getter
+    pub fn get_widgets(&self) -> &[Widget] {
+        &self.widgets
+    }
+
+    /// Blend widgets, `opacity` returns opacity of a widget
+    fn blend<F>(&self, opacity: F) -> RGBA
+    where
+        F: Fn(&Widget) -> f32,
+    {
+        let mut rgb = vector![0.0, 0.0, 0.0];
+        let mut weight = 0.0;
+        let mut transparency = 1.0;
+
+        for widget in &self.widgets {
+            let a = opacity(widget);
+            if a > 0.0 {
+                rgb += widget.get_color().xyz() * a;
+                weight += a;
+                transparency *= 1.0 - a;
+            }
+        }
+
+        if weight == 0.0 {
+            return color::zero();
+        }
+
+        let rgb = rgb / weight;
+        vector![rgb.x, rgb.y, rgb.z, 1.0 - transparency]
+    }
+}
+
+impl TransferFunction for Tf2D {
+    fn color(&self, sample: f32) -> RGBA {
+        self.blend(|w| w.max_opacity(sample))
+    }
+
+    /// Union of visible widgets' value ranges.
+    fn visible_ranges(&self, _: ValueRange) -> Vec<ValueRange> {
+        let mut ranges: Vec<ValueRange> = self
+            .widgets
+            .iter()
+            .filter(|w| w.get_color().w > 0.0)
+            .map(|w| w.value_range())
+            .collect();
+        ranges.sort_unstable_by(|a, b| a.low.total_cmp(&b.low));
+
+        let mut merged: Vec<ValueRange> = vec![];
+        for range in ranges {
+            match merged.last_mut() {
+                Some(prev) if prev.high >= range.low => prev.high = f32::max(prev.high, range.high),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    fn color_gradient(&self, sample: f32, gradient: f32) -> RGBA {
+        self.blend(|w| w.opacity(sample, gradient))
+    }
+
+    fn uses_gradient(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn boundary_tf() -> Tf2D {
+        Tf2D::new(vec![
+            Widget::triangle(100.0, 20.0, 50.0, color::new(255.0, 0.0, 0.0, 1.0)),
+            Widget::rectangle(
+                (200.0..220.0).into(),
+                (0.0..10.0).into(),
+                color::new(0.0, 0.0, 255.0, 0.5),
+            ),
+        ])
+    }
+
+    #[test]
+    fn rectangle() {
+        let tf = boundary_tf();
+
+        assert_eq!(
+            tf.color_gradient(210.0, 5.0),
+            color::new(0.0, 0.0, 255.0, 0.5)
+        );
+        assert_eq!(tf.color_gradient(210.0, 15.0), color::zero());
+        assert_eq!(tf.color_gradient(230.0, 5.0), color::zero());
+    }
+
+    #[test]
+    fn triangle() {
+        let tf = boundary_tf();
+
+        // Interior is not visible
+        assert_eq!(tf.color_gradient(100.0, 0.0).w, 0.0);
+        // Boundary is
+        assert_eq!(tf.color_gradient(100.0, 25.0).w, 1.0);
+        assert_eq!(tf.color_gradient(105.0, 25.0).w, 0.5);
+        assert_eq!(tf.color_gradient(115.0, 25.0).w, 0.0);
+        assert_eq!(tf.color_gradient(100.0, 60.0).w, 0.0);
+    }
+
+    #[test]
+    fn overlapping_widgets() {
+        let tf = Tf2D::new(vec![
+            Widget::rectangle(
+                (0.0..10.0).into(),
+                (0.0..10.0).into(),
+                color::new(255.0, 0.0, 0.0, 0.5),
+            ),
+            Widget::rectangle(
+                (0.0..10.0).into(),
+                (0.0..10.0).into(),
+                color::new(0.0, 255.0, 0.0, 0.5),
+            ),
+        ]);
+
+        assert_eq!(
+            tf.color_gradient(5.0, 5.0),
+            color::new(127.5, 127.5, 0.0, 0.75)
+        );
+    }
+
+    #[test]
+    fn value_projection_conservative() {
+        let tf = boundary_tf();
+
+        for v in 0..=255 {
+            let sample = v as f32;
+            let projected = tf.color(sample).w;
+            for g in 0..=100 {
+                let opacity = tf.color_gradient(sample, g as f32).w;
+                assert!(projected >= opacity);
+            }
+        }
+    }
+
+    #[test]
+    fn visible_ranges() {
+        let mut tf = boundary_tf();
+        tf.push_widget(Widget::triangle(
+            215.0,
+            10.0,
+            50.0,
+            color::new(0.0, 255.0, 0.0, 1.0),
+        ));
+        tf.push_widget(Widget::triangle(
+            50.0,
+            10.0,
+            50.0,
+            color::new(0.0, 255.0, 0.0, 0.0),
+        ));
+
+        assert_eq!(
+            tf.visible_ranges(ValueRange::empty()),
+            vec![(80.0..120.0).into(), (200.0..225.0).into()]
+        );
+
+        // Invalid widget does not break sorting
+        tf.push_widget(Widget::triangle(
+            f32::NAN,
+            10.0,
+            50.0,
+            color::new(0.0, 255.0, 0.0, 1.0),
+        ));
+        assert_eq!(tf.visible_ranges(ValueRange::empty()).len(), 3);
+    }
+
+    #[test]
+    fn value_tf_ignores_gradient() {
+        let tf = |sample: f32| color::mono(255.0, sample / 255.0);
+
+        assert!(!tf.uses_gradient());
+        assert_eq!(tf.color_gradient(51.0, 0.0), tf.color(51.0));
+        assert_eq!(tf.color_gradient(51.0, 1000.0), tf.color(51.0));
+    }
+}
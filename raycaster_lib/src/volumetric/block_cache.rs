@@ -0,0 +1,381 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Cache of blocks loaded on demand
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io,
+    mem::size_of,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use crossbeam::channel::{self, Sender};
+use parking_lot::Mutex;
+
+use crate::Error;
+
+use super::Sample;
+
+/// Storage blocks of [`BlockCache`] are loaded from
+pub trait BlockSource<T>: Send + Sync {
+    /// Name of volume type reading blocks from this source
+    const VOLUME_NAME: &'static str;
+
+    /// Number of blocks in source
+    fn block_count(&self) -> usize;
+
+    /// Load samples of block `block_id` into `samples`
+    fn read_block(&self, block_id: u32, samples: &mut [T]) -> Result<(), Error>;
+}
+
+/// Blocks stored one after another in file, starting at `data_offset`.
+/// They are read by positioned reads, the file is not mapped.
+pub struct FileBlocks {
+    file: File,
+    path: PathBuf,
+    data_offset: u64,
+    block_count: usize,
+}
+
+impl FileBlocks {
+    pub fn open(path: &Path, data_offset: u64, block_count: usize) -> Result<FileBlocks, Error> {
+        let file = File::open(path).map_err(|e| Error::io(path, e))?;
+        Ok(FileBlocks {
+            file,
+            path: path.to_owned(),
+            data_offset,
+            block_count,
+        })
+    }
+}
+
+impl<T: Sample> BlockSource<T> for FileBlocks {
+    const VOLUME_NAME: &'static str = "StreamBlockVolume";
+
+    fn block_count(&self) -> usize {
+        self.block_count
+    }
+
+    fn read_block(&self, block_id: u32, samples: &mut [T]) -> Result<(), Error> {
+        let bytes = unsafe {
+            std::slice::from_raw_parts_mut(
+                samples.as_mut_ptr() as *mut u8,
+                std::mem::size_of_val(samples),
+            )
+        };
+        let offset = self.data_offset + (block_id as usize * bytes.len()) as u64;
+        read_at(&self.file, bytes, offset).map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+/// Counters of block cache accesses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Requested block was in memory
+    pub hits: u64,
+    /// Requested block had to be loaded
+    pub misses: u64,
+    /// Block was loaded ahead of request
+    pub prefetches: u64,
+    /// Block was dropped to stay within the budget
+    pub evictions: u64,
+    /// Requested block failed to load
+    pub errors: u64,
+}
+
+/// Resident blocks, ordered by last use
+struct CacheState<T> {
+    /// Block id -> (samples, time of last use)
+    entries: HashMap<u32, (Arc<[T]>, u64)>,
+    /// Time of last use -> block id
+    lru: BTreeMap<u64, u32>,
+    clock: u64,
+}
+
+impl<T> CacheState<T> {
+    /// Mark block as used now
+    fn touch(&mut self, block_id: u32) -> Option<Arc<[T]>> {
+        self.clock += 1;
+        let (data, used) = self.entries.get_mut(&block_id)?;
+        self.lru.remove(used);
+        *used = self.clock;
+        self.lru.insert(self.clock, block_id);
+        Some(data.clone())
+    }
+}
+
+/// Fixed size cache of blocks with least recently used eviction.
+///
+/// Blocks of `block_len` samples are loaded from source `S`, by default read from file.
+/// Blocks handed out stay valid after eviction, memory is freed once the last user drops them.
+pub struct BlockCache<T, S = FileBlocks> {
+    source: S,
+    block_len: usize,
+    capacity: usize,
+    state: Mutex<CacheState<T>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    prefetches: AtomicU64,
+    evictions: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl<T: Sample> BlockCache<T> {
+    /// Open file with `block_count` blocks, keeping at most `budget` bytes of them in memory
+    pub fn open(
+        path: &Path,
+        data_offset: u64,
+        block_len: usize,
+        block_count: usize,
+        budget: usize,
+    ) -> Result<BlockCache<T>, Error> {
+        let source = FileBlocks::open(path, data_offset, block_count)?;
+        BlockCache::new(source, block_len, budget)
+    }
+}
+
+impl<T: Sample, S: BlockSource<T>> BlockCache<T, S> {
+    /// Cache of blocks of `source`, keeping at most `budget` bytes of them in memory
+    pub fn new(source: S, block_len: usize, budget: usize) -> Result<BlockCache<T, S>, Error> {
+        let capacity = budget / (block_len * size_of::<T>());
+        if capacity == 0 {
+            return Err(Error::Invalid(
+                "Block cache budget is smaller than one block",
+            ));
+        }
+
+        Ok(BlockCache {
+            source,
+            block_len,
+            capacity,
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                lru: BTreeMap::new(),
+                clock: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            prefetches: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        })
+    }
+
+    /// Number of blocks fitting into the budget
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Samples of block `block_id`, loaded if not resident
+    pub fn get(&self, block_id: u32) -> Result<Arc<[T]>, Error> {
+        if let Some(data) = self.state.lock().touch(block_id) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(data);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        self.load(block_id).inspect_err(|_| {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        })
+    }
+
+    /// Load block `block_id` ahead of time, if not resident
+    pub fn prefetch(&self, block_id: u32) -> Result<(), Error> {
+        if self.state.lock().entries.contains_key(&block_id) {
+            return Ok(());
+        }
+        self.prefetches.fetch_add(1, Ordering::Relaxed);
+        self.load(block_id).map(|_| ())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            prefetches: self.prefetches.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Load block and insert it as the most recently used.
+    /// Source is read without holding the lock, a block loaded by another thread meanwhile is kept.
+    fn load(&self, block_id: u32) -> Result<Arc<[T]>, Error> {
+        let data = self.read_block(block_id)?;
+
+        let mut state = self.state.lock();
+        if let Some(resident) = state.touch(block_id) {
+            return Ok(resident);
+        }
+        state.clock += 1;
+        let now = state.clock;
+        state.entries.insert(block_id, (data.clone(), now));
+        state.lru.insert(now, block_id);
+
+        while state.entries.len() > self.capacity {
+            let (_, oldest) = state.lru.pop_first().unwrap();
+            state.entries.remove(&oldest);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(data)
+    }
+
+    /// Load one block, bypassing the cache
+    pub fn read_block(&self, block_id: u32) -> Result<Arc<[T]>, Error> {
+        if block_id as usize >= self.source.block_count() {
+            return Err(Error::Invalid("Block id out of range"));
+        }
+        let mut data = vec![T::default(); self.block_len];
+        self.source.read_block(block_id, &mut data)?;
+        Ok(data.into())
+    }
+}
+
+/// Background thread loading blocks into cache ahead of rendering
+pub(crate) struct Prefetcher {
+    sender: Option<Sender<Vec<u32>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Prefetcher {
+    pub fn spawn<T, S>(cache: Arc<BlockCache<T, S>>) -> Prefetcher
+    where
+        T: Sample,
+        S: BlockSource<T> + 'static,
+    {
+        let (sender, receiver) = channel::unbounded::<Vec<u32>>();
+        let handle = thread::Builder::new()
+            .name("Prefetch".into())
+            .spawn(move || {
+                while let Ok(mut order) = receiver.recv() {
+                    let mut next = 0;
+                    while next < order.len() {
+                        // Newer order replaces the one in progress
+                        if let Some(newer) = receiver.try_iter().last() {
+                            order = newer;
+                            next = 0;
+                            continue;
+                        }
+                        // Errors are reported once the block is requested by renderer
+                        let _ = cache.prefetch(order[next]);
+                        next += 1;
+                    }
+                }
+            })
+            .unwrap();
+
+        Prefetcher {
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+
+    /// Load blocks in `order`, replacing the previous request
+    pub fn request(&self, order: Vec<u32>) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(order);
+        }
+    }
+}
+
+impl Drop for Prefetcher {
+    fn drop(&mut self) {
+        // Closing the channel stops the thread
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    let mut read = 0;
+    while read < buf.len() {
+        match file.seek_read(&mut buf[read..], offset + read as u64)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => read += n,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::test_helpers::TempFile;
+
+    // 2 byte header, 4 blocks of 8 samples, block `i` filled with `i`
+    fn block_file(name: &str) -> TempFile {
+        let mut bytes = vec![0xff, 0xff];
+        for i in 0..4 {
+            bytes.extend([i; 8]);
+        }
+        TempFile::new(&format!("cache_{}", name), &bytes)
+    }
+
+    #[test]
+    fn lru_eviction() {
+        let file = block_file("lru");
+        let cache = BlockCache::<u8>::open(&file.0, 2, 8, 4, 16).unwrap();
+        assert_eq!(cache.capacity(), 2);
+
+        assert_eq!(&*cache.get(0).unwrap(), &[0; 8]);
+        assert_eq!(&*cache.get(1).unwrap(), &[1; 8]);
+        cache.get(0).unwrap();
+        // Evicts 1, the least recently used
+        assert_eq!(&*cache.get(3).unwrap(), &[3; 8]);
+        cache.get(0).unwrap();
+        cache.get(1).unwrap();
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 4);
+        assert_eq!(stats.evictions, 2);
+    }
+
+    #[test]
+    fn prefetch_and_errors() {
+        let file = block_file("prefetch");
+        let cache = BlockCache::<u8>::open(&file.0, 2, 8, 4, 64).unwrap();
+
+        cache.prefetch(2).unwrap();
+        cache.prefetch(2).unwrap();
+        assert_eq!(&*cache.get(2).unwrap(), &[2; 8]);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 0,
+                prefetches: 1,
+                evictions: 0,
+                errors: 0
+            }
+        );
+
+        assert!(matches!(cache.get(4), Err(Error::Invalid(_))));
+        assert_eq!(cache.stats().errors, 1);
+        assert!(matches!(
+            BlockCache::<u8>::open(&file.0, 2, 8, 4, 7),
+            Err(Error::Invalid(_))
+        ));
+        // Fifth block would end after the file
+        let cache = BlockCache::<u8>::open(&file.0, 2, 8, 5, 64).unwrap();
+        assert!(matches!(cache.get(4), Err(Error::Io { .. })));
+    }
+}
@@ -9,25 +9,28 @@ use nalgebra::{point, vector, Matrix4, Point3, Vector3, Vector4};
 use crate::{
     common::{blockify, tf_visible_range, BoundBox, Ray, ValueRange},
     volumetric::{DataSource, MemoryType, StorageShape},
-    TF,
+    Error, TransferFunction, TF,
 };
 
 use super::{
-    vol_builder::{BuildVolume, VolumeMetadata},
+    vol_builder::{BuildVolume, Rescale, VolumeMetadata},
     volume::Blocked,
-    EmptyIndex, Volume,
+    EmptyIndex, Sample, Volume,
 };
 
-pub struct Block {
+pub struct Block<T = u8> {
     pub block_side: usize, // todo empty index
     pub value_range: ValueRange,
     pub bound_box: BoundBox,
     pub transform: Matrix4<f32>,
-    pub data: *const u8,
+    pub data: *const T,
+    /// Value of every sample of uniform block, `data` is not read
+    pub constant: Option<T>,
+    pub rescale: Rescale,
     empty_index: EmptyIndex<4>,
 }
 
-impl Block {
+impl<T: Sample> Block<T> {
     /// # Safety
     ///
     /// data has to be pointer into the beginning of memory mapped file
@@ -35,31 +38,68 @@ impl Block {
         block_side: usize,
         bound_box: BoundBox,
         scale: Vector3<f32>,
-        data: *const u8,
-        _: TF,
+        data: *const T,
+        rescale: Rescale,
+        _: &dyn TransferFunction,
     ) -> Self {
         let elements = block_side.pow(3);
         let slice = std::slice::from_raw_parts(data, elements);
-        let value_range = ValueRange::from_samples(slice);
-
-        let scale_inv = vector![1.0, 1.0, 1.0].component_div(&scale);
-        let lower_vec = point![0.0, 0.0, 0.0] - bound_box.lower; // todo type workaround
-
-        let transform = Matrix4::identity()
-            .append_translation(&lower_vec)
-            .append_nonuniform_scaling(&scale_inv);
+        let value_range = rescale.apply_range(ValueRange::from_samples(slice));
 
         Self {
             block_side,
             value_range,
             bound_box,
-            transform,
+            transform: block_transform(bound_box, scale),
             data,
+            constant: None,
+            rescale,
+            empty_index: EmptyIndex::dummy(),
+        }
+    }
+
+    /// Block with all samples equal to `value`, sampled without memory access
+    pub fn uniform(
+        block_side: usize,
+        bound_box: BoundBox,
+        scale: Vector3<f32>,
+        value: T,
+        rescale: Rescale,
+    ) -> Self {
+        Self {
+            block_side,
+            value_range: rescale.apply_range(ValueRange::from_samples(&[value])),
+            bound_box,
+            transform: block_transform(bound_box, scale),
+            data: std::ptr::null(),
+            constant: Some(value),
+            rescale,
+            empty_index: EmptyIndex::dummy(),
+        }
+    }
+
+    /// Copy of block, with samples at `data`
+    ///
+    /// # Safety
+    ///
+    /// data has to point to `block_side`^3 samples, living as long as the copy
+    pub(crate) unsafe fn with_data(&self, data: *const T) -> Self {
+        Self {
+            block_side: self.block_side,
+            value_range: self.value_range,
+            bound_box: self.bound_box,
+            transform: self.transform,
+            data,
+            constant: self.constant,
+            rescale: self.rescale,
             empty_index: EmptyIndex::dummy(),
         }
     }
 
     fn get_block_data_half(&self, start_index: usize) -> Vector4<f32> {
+        if let Some(value) = self.constant {
+            return Vector4::repeat(value.into());
+        }
         unsafe {
             let ptr = self.data.add(start_index);
             let d0 = ptr.read();
@@ -73,19 +113,27 @@ impl Block {
             let ptr = ptr.add(1);
             let d3 = ptr.read();
 
-            vector![d0 as f32, d1 as f32, d2 as f32, d3 as f32]
+            vector![d0.into(), d1.into(), d2.into(), d3.into()]
         }
     }
 
     fn get_3d_index(&self, x: usize, y: usize, z: usize) -> usize {
         z + y * self.block_side + x * self.block_side * self.block_side
     }
+
+    /// Sample at `index` (not rescaled), index has to be inside of block
+    fn get_sample(&self, index: usize) -> T {
+        match self.constant {
+            Some(value) => value,
+            None => unsafe { self.data.add(index).read() },
+        }
+    }
 }
 
 // Safety: pointer points to memory mapped file, which lives as long as BlockVolume lives
-unsafe impl Send for Block {}
+unsafe impl<T: Sample> Send for Block<T> {}
 
-impl Volume for Block {
+impl<T: Sample> Volume for Block<T> {
     // A more optimal specialization
     fn transform_ray(&self, ray: &Ray) -> Option<(Ray, f32)> {
         let (t0, t1) = match self.bound_box.intersect(ray) {
@@ -93,19 +141,20 @@ impl Volume for Block {
             None => return None,
         };
 
-        let obj_origin = ray.point_from_t(t0);
+        let obj_origin = self.bound_box.to_frame(&ray.point_from_t(t0));
         let obj_origin = self.transform.transform_point(&obj_origin);
+        let direction = self.bound_box.direction_to_frame(&ray.direction);
 
         let t = t1 - t0;
 
-        Some((Ray::new(obj_origin, ray.direction), t))
+        Some((Ray::new(obj_origin, direction), t))
     }
 
     fn get_size(&self) -> Vector3<usize> {
         vector![self.block_side, self.block_side, self.block_side]
     }
 
-    fn get_tf(&self) -> TF {
+    fn get_tf(&self) -> &TF {
         unimplemented!()
     }
 
@@ -114,6 +163,10 @@ impl Volume for Block {
     }
 
     fn sample_at(&self, pos: Point3<f32>) -> f32 {
+        if let Some(value) = self.constant {
+            return self.rescale.apply(value.into());
+        }
+
         let x = pos.x as usize;
         let y = pos.y as usize;
         let z = pos.z as usize;
@@ -147,13 +200,17 @@ impl Volume for Block {
         let c0: f32 = x_low_vec.x + x_low_vec.z;
         let c1: f32 = x_low_vec.y + x_low_vec.w;
 
-        c0 * (1.0 - z_t) + c1 * z_t
+        self.rescale.apply(c0 * (1.0 - z_t) + c1 * z_t)
     }
 
     fn get_bound_box(&self) -> BoundBox {
         self.bound_box
     }
 
+    fn get_value_range(&self) -> ValueRange {
+        self.value_range
+    }
+
     fn get_scale(&self) -> Vector3<f32> {
         unimplemented!()
     }
@@ -163,8 +220,7 @@ impl Volume for Block {
         if index >= self.block_side * self.block_side * self.block_side {
             return None;
         }
-        let sample = unsafe { self.data.add(index).read() };
-        Some(sample as f32)
+        Some(self.rescale.apply(self.get_sample(index).into()))
     }
 
     fn get_name() -> &'static str {
@@ -181,21 +237,23 @@ impl Volume for Block {
 }
 
 // Default overlap == 1
-pub struct BlockVolume {
+pub struct BlockVolume<T = u8> {
     block_side: usize,
     bound_box: BoundBox,
     data_size: Vector3<usize>,
     pub empty_blocks: Vec<bool>,
     block_size: Vector3<usize>, // Number of blocks in structure (.data)
-    _data_owner: DataSource<u8>,
-    _blocked_data_owner: Vec<Vec<u8>>,
-    pub data: Vec<Block>,
+    _data_owner: DataSource<T>,
+    _blocked_data_owner: Vec<Vec<T>>,
+    pub data: Vec<Block<T>>,
+    value_range: ValueRange,
+    rescale: Rescale,
     tf: TF,
 }
 
-unsafe impl Sync for BlockVolume {}
+unsafe impl<T: Sample> Sync for BlockVolume<T> {}
 
-impl BlockVolume {
+impl<T: Sample> BlockVolume<T> {
     // returns (block index, block offset)
     fn get_indexes(&self, x: usize, y: usize, z: usize) -> (usize, usize) {
         let jump_per_block = self.block_side - 1; // implicit block overlap of 1
@@ -209,13 +267,12 @@ impl BlockVolume {
     }
 
     // get voxel
-    fn get_3d_data(&self, x: usize, y: usize, z: usize) -> Option<u8> {
+    fn get_3d_data(&self, x: usize, y: usize, z: usize) -> Option<T> {
         let (block_index, block_offset) = self.get_indexes(x, y, z);
         match self.data.get(block_index) {
             Some(b) => {
                 if block_offset < self.block_side.pow(3) {
-                    let val = unsafe { std::ptr::read(b.data.add(block_offset)) };
-                    Some(val)
+                    Some(b.get_sample(block_offset))
                 } else {
                     None
                 }
@@ -225,9 +282,14 @@ impl BlockVolume {
     }
 
     /// True == block is empty
-    pub fn build_empty(blocks: &[Block], tf: TF) -> Vec<bool> {
+    pub fn build_empty(blocks: &[Block<T>], tf: &dyn TransferFunction) -> Vec<bool> {
         let mut v = Vec::with_capacity(blocks.len());
-        let vis_ranges = tf_visible_range(tf);
+
+        let mut domain = ValueRange::empty();
+        for block in blocks {
+            domain.union(&block.value_range);
+        }
+        let vis_ranges = tf_visible_range(tf, domain);
 
         for block in blocks {
             let visible = vis_ranges.iter().any(|r| r.intersects(&block.value_range));
@@ -237,8 +299,8 @@ impl BlockVolume {
     }
 }
 
-impl Blocked for BlockVolume {
-    type BlockType = Block;
+impl<T: Sample> Blocked for BlockVolume<T> {
+    type BlockType = Block<T>;
 
     fn get_blocks(&self) -> &[Self::BlockType] {
         &self.data
@@ -249,7 +311,7 @@ impl Blocked for BlockVolume {
     }
 }
 
-impl Volume for BlockVolume {
+impl<T: Sample> Volume for BlockVolume<T> {
     fn get_size(&self) -> Vector3<usize> {
         self.data_size
     }
@@ -289,29 +351,33 @@ impl Volume for BlockVolume {
         let c0: f32 = x_low_vec.x + x_low_vec.z;
         let c1: f32 = x_low_vec.y + x_low_vec.w;
 
-        c0 * (1.0 - z_t) + c1 * z_t
+        block.rescale.apply(c0 * (1.0 - z_t) + c1 * z_t)
     }
 
     fn get_data(&self, x: usize, y: usize, z: usize) -> Option<f32> {
         let sample = self.get_3d_data(x, y, z); // todo bounds check
-        sample.map(|v| v as f32)
+        sample.map(|v| self.rescale.apply(v.into()))
     }
 
-    fn get_tf(&self) -> TF {
-        self.tf
+    fn get_tf(&self) -> &TF {
+        &self.tf
     }
 
     fn get_bound_box(&self) -> BoundBox {
         self.bound_box
     }
 
+    fn get_value_range(&self) -> ValueRange {
+        self.value_range
+    }
+
     fn get_scale(&self) -> Vector3<f32> {
         vector![1.0, 1.0, 1.0] // todo
     }
 
     fn set_tf(&mut self, tf: TF) {
         self.tf = tf;
-        self.empty_blocks = BlockVolume::build_empty(&self.data, self.tf);
+        self.empty_blocks = BlockVolume::build_empty(&self.data, &*self.tf);
     }
 
     fn get_name() -> &'static str {
@@ -327,37 +393,40 @@ impl Volume for BlockVolume {
     }
 }
 
-impl BuildVolume<u8> for BlockVolume {
-    fn build(metadata: VolumeMetadata<u8>) -> Result<BlockVolume, &'static str> {
+impl<T: Sample> BuildVolume<T> for BlockVolume<T> {
+    fn build(metadata: VolumeMetadata<T>) -> Result<BlockVolume<T>, Error> {
+        metadata.validate_data()?;
+        let orientation = metadata.orientation()?;
         let position = metadata.position.unwrap_or_else(|| point![0.0, 0.0, 0.0]);
-        let size = metadata.size.ok_or("No size")?;
-        let scale = metadata.scale.ok_or("No scale")?;
-        let tf = metadata.tf.ok_or("No transfer function")?;
-        let data = metadata.data.ok_or("No data")?;
+        let size = metadata.size.ok_or(Error::Missing("size"))?;
+        let scale = metadata.scale.ok_or(Error::Missing("scale"))?;
+        let tf = metadata.tf.ok_or(Error::Missing("transfer function"))?;
+        let data = metadata.data.ok_or(Error::Missing("data"))?;
         let memory_type = metadata.memory_type.unwrap_or(MemoryType::Stream);
+        let rescale = metadata.rescale.unwrap_or_default();
 
-        let data_shape = metadata.data_shape.ok_or("No data shape")?;
+        let data_shape = metadata.data_shape.ok_or(Error::Missing("data shape"))?;
         let desired_data_shape = metadata.desired_data_shape;
 
         let vol_dims = (size - vector![1, 1, 1]) // side length is n-1 times the point
             .cast::<f32>();
         let vol_dims = (vol_dims).component_mul(&scale); // todo workaround
 
-        let bound_box = BoundBox::from_position_dims(position, vol_dims);
+        let bound_box = BoundBox::from_position_dims(position, vol_dims).oriented(orientation);
 
         let block_side = match data_shape {
             StorageShape::Linear => match desired_data_shape {
                 Some(StorageShape::Z(s)) => s,
-                _ => return Err("Bad shape"),
+                _ => return Err(Error::Shape("Block volume needs Z order storage shape")),
             },
             StorageShape::Z(b) => match desired_data_shape {
                 Some(StorageShape::Z(s)) => {
                     if s != b {
-                        return Err("Not compatible block sides #2");
+                        return Err(Error::Shape("Block side of data does not match"));
                     }
                     s
                 }
-                _ => return Err("Bad shape"),
+                _ => return Err(Error::Shape("Block volume needs Z order storage shape")),
             },
         } as usize;
 
@@ -365,7 +434,7 @@ impl BuildVolume<u8> for BlockVolume {
             StorageShape::Linear => false,
             StorageShape::Z(s) => {
                 if s as usize != block_side {
-                    return Err("Not compatible block sides");
+                    return Err(Error::Shape("Block side of data does not match"));
                 }
                 true
             }
@@ -390,7 +459,8 @@ impl BuildVolume<u8> for BlockVolume {
                 for z in 0..block_size.z {
                     let block_off = point![x, y, z];
                     let block_start = step_size * block_off;
-                    let block_bound_box = get_bound_box(position, scale, block_start, block_side);
+                    let block_bound_box = get_bound_box(position, scale, block_start, block_side)
+                        .oriented(orientation);
 
                     let block_data_ptr = match (memory_type, is_blocked) {
                         (MemoryType::Stream, true) => {
@@ -399,7 +469,9 @@ impl BuildVolume<u8> for BlockVolume {
                             unsafe { base_ptr.add(block_data_offset) }
                         }
                         (MemoryType::Stream, false) => {
-                            return Err("Incompatible combination: Stream and unblocked volume")
+                            return Err(Error::Unsupported(
+                                "Streaming of data not stored in blocks",
+                            ))
                         }
                         (MemoryType::Ram, true) => {
                             let block_data_offset =
@@ -424,14 +496,26 @@ impl BuildVolume<u8> for BlockVolume {
                     };
 
                     let block = unsafe {
-                        Block::new(block_side, block_bound_box, scale, block_data_ptr, tf)
+                        Block::new(
+                            block_side,
+                            block_bound_box,
+                            scale,
+                            block_data_ptr,
+                            rescale,
+                            &*tf,
+                        )
                     };
                     blocks.push(block);
                 }
             }
         }
 
-        let empty_blocks = BlockVolume::build_empty(&blocks, tf);
+        let empty_blocks = BlockVolume::build_empty(&blocks, &*tf);
+
+        let mut value_range = ValueRange::empty();
+        for block in &blocks {
+            value_range.union(&block.value_range);
+        }
 
         println!(
             "Built {} blocks of dims {} blocks ({},{},{}) blocks ({},{},{}) memory {:?}",
@@ -452,6 +536,8 @@ impl BuildVolume<u8> for BlockVolume {
             block_size,
             data: blocks,
             _data_owner: data,
+            value_range,
+            rescale,
             tf,
             block_side,
             empty_blocks,
@@ -462,12 +548,12 @@ impl BuildVolume<u8> for BlockVolume {
 }
 
 // todo redo
-pub fn get_block_data(
-    slice: &[u8],
+pub fn get_block_data<T: Sample>(
+    slice: &[T],
     size: Vector3<usize>,
     block_start: Point3<usize>,
     side: usize,
-) -> Vec<u8> {
+) -> Vec<T> {
     let elements = side * side * side;
     let mut data = Vec::with_capacity(elements); // todo background value
     for off_x in 0..side {
@@ -479,7 +565,7 @@ pub fn get_block_data(
                     let value = slice[index];
                     data.push(value);
                 } else {
-                    data.push(0);
+                    data.push(T::default());
                 }
             }
         }
@@ -487,6 +573,16 @@ pub fn get_block_data(
     data
 }
 
+/// Transformation from world coordinates into coordinates of block samples
+fn block_transform(bound_box: BoundBox, scale: Vector3<f32>) -> Matrix4<f32> {
+    let scale_inv = vector![1.0, 1.0, 1.0].component_div(&scale);
+    let lower_vec = point![0.0, 0.0, 0.0] - bound_box.lower; // todo type workaround
+
+    Matrix4::identity()
+        .append_translation(&lower_vec)
+        .append_nonuniform_scaling(&scale_inv)
+}
+
 fn get_bound_box(
     vol_position: Point3<f32>,
     vol_scale: Vector3<f32>,
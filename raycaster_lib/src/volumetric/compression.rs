@@ -0,0 +1,134 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Blocks compressed independently of each other.
+//!
+//! Blocks are compressed by LZ4 (block format, without size prefix).
+//! Length of decompressed block is given by side of block and type of samples.
+
+use std::mem::size_of_val;
+
+use crate::Error;
+
+use super::{block_cache::BlockSource, DataSource, Sample};
+
+/// Compress samples of one block
+pub fn compress_block<T: Sample>(samples: &[T]) -> Vec<u8> {
+    let bytes =
+        unsafe { std::slice::from_raw_parts(samples.as_ptr() as *const u8, size_of_val(samples)) };
+    lz4_flex::block::compress(bytes)
+}
+
+/// Decompress one block into `samples`, `compressed` has to hold exactly `samples.len()` samples
+pub fn decompress_block<T: Sample>(compressed: &[u8], samples: &mut [T]) -> Result<(), Error> {
+    let bytes = unsafe {
+        std::slice::from_raw_parts_mut(samples.as_mut_ptr() as *mut u8, size_of_val(samples))
+    };
+    let written = lz4_flex::block::decompress_into(compressed, bytes)
+        .map_err(|_| Error::Invalid("Corrupted compressed block"))?;
+    if written != bytes.len() {
+        return Err(Error::Truncated {
+            expected: bytes.len(),
+            found: written,
+        });
+    }
+    Ok(())
+}
+
+/// Compressed blocks of volume, see [`CompressedBlockVolume`](super::volumes::CompressedBlockVolume)
+#[derive(Debug)]
+pub struct CompressedBlocks {
+    /// Compressed bytes of all blocks
+    pub data: DataSource<u8>,
+    /// Start of every block in `data`, followed by the end of the last block
+    pub offsets: Vec<usize>,
+}
+
+impl CompressedBlocks {
+    pub fn new(data: DataSource<u8>, offsets: Vec<usize>) -> CompressedBlocks {
+        CompressedBlocks { data, offsets }
+    }
+
+    /// Check that offsets describe `block_count` blocks inside data
+    pub fn validate(&self, block_count: usize) -> Result<(), Error> {
+        if self.offsets.len() != block_count + 1 {
+            return Err(Error::Shape("Number of compressed blocks does not match"));
+        }
+        if self.offsets.windows(2).any(|w| w[0] > w[1]) {
+            return Err(Error::Invalid(
+                "Offsets of compressed blocks are not ascending",
+            ));
+        }
+        let expected = self.offsets[block_count];
+        let found = self.data.byte_len();
+        Error::check_length(expected, found)?;
+        if found > expected {
+            return Err(Error::Oversized { expected, found });
+        }
+        Ok(())
+    }
+
+    /// Compressed bytes of block `block_id`
+    pub fn block(&self, block_id: u32) -> &[u8] {
+        let i = block_id as usize;
+        &self.data.get_slice()[self.offsets[i]..self.offsets[i + 1]]
+    }
+}
+
+impl<T: Sample> BlockSource<T> for CompressedBlocks {
+    const VOLUME_NAME: &'static str = "CompressedBlockVolume";
+
+    fn block_count(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    fn read_block(&self, block_id: u32, samples: &mut [T]) -> Result<(), Error> {
+        decompress_block(self.block(block_id), samples)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut samples = vec![0_u16; 27];
+        samples[13] = 1000;
+        let compressed = compress_block(&samples);
+        assert!(compressed.len() < 54);
+
+        let mut out = vec![0_u16; 27];
+        decompress_block(&compressed, &mut out).unwrap();
+        assert_eq!(out, samples);
+
+        let mut longer = vec![0_u16; 28];
+        assert!(decompress_block(&compressed, &mut longer).is_err());
+    }
+
+    #[test]
+    fn validate_offsets() {
+        let blocks: Vec<Vec<u8>> = [[0_u8; 8], [7; 8]]
+            .iter()
+            .map(|b| compress_block(b))
+            .collect();
+        let offsets = vec![0, blocks[0].len(), blocks[0].len() + blocks[1].len()];
+        let compressed = CompressedBlocks::new(DataSource::Vec(blocks.concat()), offsets);
+        assert!(compressed.validate(2).is_ok());
+        assert!(compressed.validate(3).is_err());
+
+        let mut samples = [0_u8; 8];
+        BlockSource::<u8>::read_block(&compressed, 1, &mut samples).unwrap();
+        assert_eq!(samples, [7; 8]);
+
+        let truncated = CompressedBlocks::new(DataSource::Vec(vec![0; 3]), vec![0, 2, 4]);
+        assert!(matches!(
+            truncated.validate(2),
+            Err(Error::Truncated { .. })
+        ));
+    }
+}
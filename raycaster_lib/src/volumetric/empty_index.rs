@@ -6,7 +6,7 @@
 
 use nalgebra::{point, vector, Point3, Vector3};
 
-use crate::{common::blockify, TF};
+use crate::{common::blockify, TransferFunction};
 
 use super::Volume;
 
@@ -39,7 +39,7 @@ impl<const S: usize> EmptyIndex<S> {
         let cell_count = index_size.iter().product();
         let mut blocks = Vec::with_capacity(cell_count);
 
-        let tf = volume.get_tf();
+        let tf = &**volume.get_tf();
 
         for x in 0..index_size.x {
             for y in 0..index_size.y {
@@ -62,12 +62,12 @@ impl<const S: usize> EmptyIndex<S> {
         volume: &impl Volume,
         base: Point3<usize>,
         side: usize,
-        tf: TF,
+        tf: &dyn TransferFunction,
     ) -> bool {
         let block_iter = volume.get_block(side + 1, base); // side in voxels vs side in blocks
 
         // True if empty
-        !block_iter.map(tf).any(|f| f.w != 0.0)
+        !block_iter.map(|v| tf.color(v)).any(|f| f.w != 0.0)
     }
 
     fn index_3d(&self, x: usize, y: usize, z: usize) -> usize {
@@ -94,7 +94,10 @@ impl<const S: usize> EmptyIndex<S> {
         self.blocks[index]
     }
 
-    pub fn from_volume_without_tf(volume: &impl Volume, tf: TF) -> EmptyIndex<4> {
+    pub fn from_volume_without_tf(
+        volume: &impl Volume,
+        tf: &dyn TransferFunction,
+    ) -> EmptyIndex<4> {
         let vol_size = volume.get_size();
         let index_size = blockify(vol_size, S, 1);
 
@@ -133,6 +136,7 @@ mod test {
     use super::*;
     use crate::{color::RGBA, test_helpers::*, volumetric::float_volume::FloatVolume};
     use nalgebra::vector;
+    use std::sync::Arc;
 
     use crate::volumetric::vol_builder::{BuildVolume, DataSource};
 
@@ -141,7 +145,7 @@ mod test {
     }
 
     fn volume_dims_nonempty(dims: Vector3<usize>, non_empty_indexes: &[usize]) -> FloatVolume {
-        let mut vol = empty_vol_meta(dims);
+        let mut vol = empty_vol_meta::<u8>(dims);
         if let Some(ref mut data) = vol.data {
             match data {
                 DataSource::Vec(ref mut v) => {
@@ -218,8 +222,8 @@ mod test {
         // Index takes into account resulting opacity, not values of samples
         #[test]
         fn empty_dark_tf() {
-            let mut meta = empty_vol_meta(vector![7, 7, 7]);
-            meta.set_tf(dark_tf);
+            let mut meta = empty_vol_meta::<u8>(vector![7, 7, 7]);
+            meta.set_tf(Arc::new(dark_tf));
 
             if let Some(ref mut data) = meta.data {
                 match data {
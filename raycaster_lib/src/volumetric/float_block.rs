@@ -8,7 +8,7 @@ use nalgebra::{point, vector, Matrix4, Point3, Vector3, Vector4};
 
 use crate::{
     common::{BoundBox, Ray, ValueRange},
-    TF,
+    TransferFunction, TF,
 };
 
 use super::{EmptyIndex, Volume};
@@ -28,7 +28,7 @@ impl FloatBlock {
         bound_box: BoundBox,
         scale: Vector3<f32>,
         block_side: usize,
-        tf: TF,
+        tf: &dyn TransferFunction,
     ) -> FloatBlock {
         // todo boundbox and scale has redundant info
         assert_eq!(data.len(), block_side.pow(3));
@@ -50,10 +50,15 @@ impl FloatBlock {
             empty_index: EmptyIndex::dummy(),
         };
 
-        block.empty_index = EmptyIndex::<4>::from_volume_without_tf(&block, tf);
+        block.build_empty_index_tf(tf);
         block
     }
 
+    /// Rebuild empty index for transfer function `tf`
+    pub fn build_empty_index_tf(&mut self, tf: &dyn TransferFunction) {
+        self.empty_index = EmptyIndex::<4>::from_volume_without_tf(self, tf);
+    }
+
     pub fn get_block_data_half(&self, start_index: usize) -> Vector4<f32> {
         if start_index + self.block_side + 1 >= self.data.len() {
             vector![0.0, 0.0, 0.0, 0.0]
@@ -82,19 +87,20 @@ impl Volume for FloatBlock {
             None => return None,
         };
 
-        let obj_origin = ray.point_from_t(t0);
+        let obj_origin = self.bound_box.to_frame(&ray.point_from_t(t0));
         let obj_origin = self.transform.transform_point(&obj_origin);
+        let direction = self.bound_box.direction_to_frame(&ray.direction);
 
         let t = t1 - t0;
 
-        Some((Ray::new(obj_origin, ray.direction), t))
+        Some((Ray::new(obj_origin, direction), t))
     }
 
     fn get_size(&self) -> Vector3<usize> {
         vector![self.block_side, self.block_side, self.block_side]
     }
 
-    fn get_tf(&self) -> TF {
+    fn get_tf(&self) -> &TF {
         unimplemented!()
     }
 
@@ -143,6 +149,10 @@ impl Volume for FloatBlock {
         self.bound_box
     }
 
+    fn get_value_range(&self) -> ValueRange {
+        self.value_range
+    }
+
     fn get_scale(&self) -> Vector3<f32> {
         unimplemented!()
     }
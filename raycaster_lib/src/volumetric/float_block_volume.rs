@@ -7,15 +7,15 @@
 use nalgebra::{point, vector, Point3, Vector3};
 
 use crate::{
-    common::{blockify, tf_visible_range, BoundBox},
-    TF,
+    common::{blockify, tf_visible_range, BoundBox, ValueRange},
+    Error, TransferFunction, TF,
 };
 
 use super::{
     float_block::FloatBlock,
-    vol_builder::{BuildVolume, VolumeMetadata},
+    vol_builder::{BuildVolume, Rescale, VolumeMetadata},
     volume::Blocked,
-    Volume,
+    Sample, Volume,
 };
 
 // Default overlap == 1
@@ -26,6 +26,7 @@ pub struct FloatBlockVolume {
     pub empty_blocks: Vec<bool>, // todo use empty index, but first remove generics from emptyindex
     block_size: Vector3<usize>,  // Number of blocks in structure (.data)
     pub data: Vec<FloatBlock>,
+    value_range: ValueRange,
     tf: TF,
 }
 
@@ -52,9 +53,14 @@ impl FloatBlockVolume {
         }
     }
 
-    pub fn build_empty(blocks: &[FloatBlock], tf: TF) -> Vec<bool> {
+    pub fn build_empty(blocks: &[FloatBlock], tf: &dyn TransferFunction) -> Vec<bool> {
         let mut v = Vec::with_capacity(blocks.len());
-        let vis_ranges = tf_visible_range(tf);
+
+        let mut domain = ValueRange::empty();
+        for block in blocks {
+            domain.union(&block.value_range);
+        }
+        let vis_ranges = tf_visible_range(tf, domain);
 
         for block in blocks {
             let visible = vis_ranges.iter().any(|r| r.intersects(&block.value_range));
@@ -123,21 +129,25 @@ impl Volume for FloatBlockVolume {
         self.get_3d_data(x, y, z)
     }
 
-    fn get_tf(&self) -> TF {
-        self.tf
+    fn get_tf(&self) -> &TF {
+        &self.tf
     }
 
     fn get_bound_box(&self) -> BoundBox {
         self.bound_box
     }
 
+    fn get_value_range(&self) -> ValueRange {
+        self.value_range
+    }
+
     fn get_scale(&self) -> Vector3<f32> {
         vector![1.0, 1.0, 1.0] // todo
     }
 
     fn set_tf(&mut self, tf: TF) {
         self.tf = tf;
-        self.empty_blocks = FloatBlockVolume::build_empty(&self.data, self.tf);
+        self.empty_blocks = FloatBlockVolume::build_empty(&self.data, &*self.tf);
     }
 
     fn get_name() -> &'static str {
@@ -156,20 +166,23 @@ impl Volume for FloatBlockVolume {
     }
 }
 
-impl BuildVolume<u8> for FloatBlockVolume {
-    fn build(metadata: VolumeMetadata<u8>) -> Result<FloatBlockVolume, &'static str> {
+impl<T: Sample> BuildVolume<T> for FloatBlockVolume {
+    fn build(metadata: VolumeMetadata<T>) -> Result<FloatBlockVolume, Error> {
+        metadata.validate_data()?;
+        let orientation = metadata.orientation()?;
         let position = metadata.position.unwrap_or_else(|| point![0.0, 0.0, 0.0]);
-        let size = metadata.size.ok_or("No size")?;
-        let scale = metadata.scale.ok_or("No scale")?;
-        let data = metadata.data.ok_or("No data")?;
-        let tf = metadata.tf.ok_or("No transfer function")?;
+        let size = metadata.size.ok_or(Error::Missing("size"))?;
+        let scale = metadata.scale.ok_or(Error::Missing("scale"))?;
+        let data = metadata.data.ok_or(Error::Missing("data"))?;
+        let tf = metadata.tf.ok_or(Error::Missing("transfer function"))?;
+        let rescale: Rescale = metadata.rescale.unwrap_or_default();
         let block_side = 16; // todo
 
         let vol_dims = (size - vector![1, 1, 1]) // side length is n-1 times the point
             .cast::<f32>();
         let vol_dims = (vol_dims).component_mul(&scale); // todo workaround
 
-        let bound_box = BoundBox::from_position_dims(position, vol_dims);
+        let bound_box = BoundBox::from_position_dims(position, vol_dims).oriented(orientation);
 
         let step_size = block_side - 1;
         let block_size = blockify(size, block_side, 1);
@@ -182,16 +195,23 @@ impl BuildVolume<u8> for FloatBlockVolume {
             for y in 0..block_size.y {
                 for z in 0..block_size.z {
                     let block_start = step_size * point![x, y, z];
-                    let block_data = get_block_data(slice, size, block_start, block_side);
-                    let block_bound_box = get_bound_box(position, scale, block_start, block_side);
+                    let mut block_data = get_block_data(slice, size, block_start, block_side);
+                    block_data.iter_mut().for_each(|v| *v = rescale.apply(*v));
+                    let block_bound_box = get_bound_box(position, scale, block_start, block_side)
+                        .oriented(orientation);
                     let block =
-                        FloatBlock::from_data(block_data, block_bound_box, scale, block_side, tf);
+                        FloatBlock::from_data(block_data, block_bound_box, scale, block_side, &*tf);
                     blocks.push(block);
                 }
             }
         }
 
-        let empty_blocks = FloatBlockVolume::build_empty(&blocks, tf);
+        let empty_blocks = FloatBlockVolume::build_empty(&blocks, &*tf);
+
+        let mut value_range = ValueRange::empty();
+        for block in &blocks {
+            value_range.union(&block.value_range);
+        }
 
         println!(
             "Built {} blocks of dims {} blocks ({},{},{}) -> ({},{},{})",
@@ -210,6 +230,7 @@ impl BuildVolume<u8> for FloatBlockVolume {
             data_size: size,
             block_size,
             data: blocks,
+            value_range,
             tf,
             block_side,
             empty_blocks,
@@ -217,7 +238,7 @@ impl BuildVolume<u8> for FloatBlockVolume {
     }
 }
 
-fn get_bound_box(
+pub(super) fn get_bound_box(
     vol_position: Point3<f32>,
     vol_scale: Vector3<f32>,
     block_start: Point3<usize>,
@@ -238,8 +259,8 @@ fn get_bound_box(
 }
 
 // todo redo
-pub fn get_block_data(
-    volume: &[u8],
+pub fn get_block_data<T: Sample>(
+    volume: &[T],
     size: Vector3<usize>,
     block_start: Point3<usize>,
     side: usize,
@@ -252,7 +273,7 @@ pub fn get_block_data(
                 if pos.x < size.x && pos.y < size.y && pos.z < size.z {
                     let index = get_3d_index(size, pos);
                     let value = volume[index];
-                    data.push(value as f32);
+                    data.push(value.into());
                 } else {
                     data.push(0.0);
                 }
@@ -297,14 +318,14 @@ mod test {
     fn build_empty() {
         let tf = |v: f32| vector![1.0, 1.0, 1.0, v];
         let block1 =
-            FloatBlock::from_data(vec![0.0], BoundBox::empty(), vector![1.0, 1.0, 1.0], 1, tf);
+            FloatBlock::from_data(vec![0.0], BoundBox::empty(), vector![1.0, 1.0, 1.0], 1, &tf);
         let block2 =
-            FloatBlock::from_data(vec![1.0], BoundBox::empty(), vector![1.0, 1.0, 1.0], 1, tf);
+            FloatBlock::from_data(vec![1.0], BoundBox::empty(), vector![1.0, 1.0, 1.0], 1, &tf);
         let block3 =
-            FloatBlock::from_data(vec![2.0], BoundBox::empty(), vector![1.0, 1.0, 1.0], 1, tf);
+            FloatBlock::from_data(vec![2.0], BoundBox::empty(), vector![1.0, 1.0, 1.0], 1, &tf);
         let blocks = &[block1, block2, block3];
 
-        let empty = FloatBlockVolume::build_empty(blocks, tf);
+        let empty = FloatBlockVolume::build_empty(blocks, &tf);
 
         assert_eq!(empty.len(), 3);
         assert!(empty[0]);
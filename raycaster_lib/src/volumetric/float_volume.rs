@@ -6,11 +6,14 @@
 
 use nalgebra::{point, vector, Point3, Vector3, Vector4};
 
-use crate::{common::BoundBox, TF};
+use crate::{
+    common::{BoundBox, ValueRange},
+    Error, TF,
+};
 
 use super::{
-    vol_builder::{BuildVolume, VolumeMetadata},
-    EmptyIndex, Volume,
+    vol_builder::{BuildVolume, Rescale, VolumeMetadata},
+    EmptyIndex, Sample, Volume,
 };
 
 /// Samples are stored in slices, but the slices are converted to floats in preprocessing stage.
@@ -18,7 +21,9 @@ use super::{
 pub struct FloatVolume {
     bound_box: BoundBox, // lower and upper point in world coordinates; lower == position; upper - lower = size
     size: Vector3<usize>,
+    scale: Vector3<f32>, // Shape of cells
     data: Vec<f32>,
+    value_range: ValueRange,
     tf: TF,
     empty_index: EmptyIndex<4>,
 }
@@ -103,16 +108,20 @@ impl Volume for FloatVolume {
         self.size
     }
 
-    fn get_tf(&self) -> TF {
-        self.tf
+    fn get_tf(&self) -> &TF {
+        &self.tf
     }
 
     fn get_bound_box(&self) -> BoundBox {
         self.bound_box
     }
 
+    fn get_value_range(&self) -> ValueRange {
+        self.value_range
+    }
+
     fn get_scale(&self) -> Vector3<f32> {
-        vector![1.0, 1.0, 1.0]
+        self.scale
     }
 
     fn set_tf(&mut self, tf: TF) {
@@ -132,37 +141,43 @@ impl Volume for FloatVolume {
     }
 }
 
-impl BuildVolume<u8> for FloatVolume {
-    fn build(metadata: VolumeMetadata<u8>) -> Result<FloatVolume, &'static str> {
+impl<T: Sample> BuildVolume<T> for FloatVolume {
+    fn build(metadata: VolumeMetadata<T>) -> Result<FloatVolume, Error> {
         println!("Build started");
+        metadata.validate_data()?;
+        let orientation = metadata.orientation()?;
 
-        let data = metadata.data.ok_or("No volumetric data passed")?;
+        let data = metadata.data.ok_or(Error::Missing("data"))?;
         let slice = data.get_slice();
 
-        let data: Vec<f32> = slice.iter().map(|&val| val.into()).collect();
+        let rescale: Rescale = metadata.rescale.unwrap_or_default();
+        let data: Vec<f32> = slice.iter().map(|&val| rescale.apply(val.into())).collect();
+        let value_range = ValueRange::from_samples(&data);
 
         // let data_range_max = data.iter().fold(-10000.0, |cum, &v| f32::max(v, cum));
         // let data_range_min = data.iter().fold(100000.0, |cum, &v| f32::min(v, cum));
 
         // println!("Build data range: {data_range_min} to {data_range_max}");
 
-        let size = metadata.size.ok_or("No size")?;
+        let size = metadata.size.ok_or(Error::Missing("size"))?;
         let scale = metadata.scale.unwrap_or_else(|| vector![1.0, 1.0, 1.0]);
 
-        let tf = metadata.tf.ok_or("No transfer function")?;
+        let tf = metadata.tf.ok_or(Error::Missing("transfer function"))?;
 
         let vol_dims = size.map(|v| (v - 1) as f32).component_mul(&scale);
 
         let position = metadata.position.unwrap_or_else(|| point![0.0, 0.0, 0.0]);
 
-        let bound_box = BoundBox::from_position_dims(position, vol_dims);
+        let bound_box = BoundBox::from_position_dims(position, vol_dims).oriented(orientation);
 
         println!("New linear volume, size {size:?} scale {scale:?} bound_box {bound_box:?}");
 
         let mut volume = FloatVolume {
             bound_box,
             size,
+            scale,
             data,
+            value_range,
             tf,
             empty_index: EmptyIndex::dummy(),
         };
@@ -178,10 +193,14 @@ impl BuildVolume<u8> for FloatVolume {
 mod test {
     // todo move tests to boundbox
 
-    use nalgebra::{point, vector};
+    use nalgebra::{point, vector, Matrix4, Rotation3};
 
     use super::*;
-    use crate::{common::Ray, test_helpers::*};
+    use crate::{
+        common::Ray,
+        test_helpers::*,
+        volumetric::{volumes::LinearVolume, DataSource},
+    };
 
     #[test]
     fn intersect_works() {
@@ -221,4 +240,55 @@ mod test {
 
         assert!(inter.is_none());
     }
+
+    #[test]
+    fn oriented_transform_ray() {
+        // Rotated by 90° around y, voxel x axis points to world -z
+        let rotation = Rotation3::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2);
+        let mut meta = empty_vol_meta::<u8>(vector![3, 3, 3]);
+        meta.scale = Some(vector![1.0, 1.0, 1.0]);
+        meta.set_world_transform(
+            Matrix4::new_translation(&vector![10.0, 0.0, 0.0]) * rotation.to_homogeneous(),
+        );
+        let vol: FloatVolume = BuildVolume::build(meta).unwrap();
+
+        let ray = Ray::new(point![0.0, 1.0, -1.0], vector![1.0, 0.0, 0.0]);
+        let (obj_ray, t) = vol.transform_ray(&ray).unwrap();
+        assert!((obj_ray.origin - point![1.0, 1.0, 0.0]).norm() < 1e-5);
+        assert!((obj_ray.direction - vector![0.0, 0.0, 1.0]).norm() < 1e-5);
+        assert!((t - 2.0).abs() < 1e-5);
+
+        // Scaled voxels, world transformation includes the spacing
+        let scaled = || {
+            let mut meta = empty_vol_meta::<u8>(vector![3, 3, 3]);
+            meta.scale = Some(vector![2.0, 2.0, 2.0]);
+            meta.set_world_transform(
+                Matrix4::new_translation(&vector![10.0, 0.0, 0.0])
+                    * rotation.to_homogeneous()
+                    * Matrix4::new_scaling(2.0),
+            );
+            meta
+        };
+        let float: FloatVolume = BuildVolume::build(scaled()).unwrap();
+        // LinearVolume needs mapped file
+        let path = std::env::temp_dir().join(format!("raycaster_oriented_{}", std::process::id()));
+        std::fs::write(&path, [0; 27]).unwrap();
+        let mut meta = scaled();
+        meta.set_data(DataSource::from_file(&path).unwrap());
+        let linear: Result<LinearVolume, _> = BuildVolume::build(meta);
+        std::fs::remove_file(&path).unwrap();
+        let linear = linear.unwrap();
+        let ray = Ray::new(point![0.0, 2.0, -2.0], vector![1.0, 0.0, 0.0]);
+        for transformed in [float.transform_ray(&ray), linear.transform_ray(&ray)] {
+            let (obj_ray, t) = transformed.unwrap();
+            assert!((obj_ray.origin - point![1.0, 1.0, 0.0]).norm() < 1e-5);
+            assert!((obj_ray.direction - vector![0.0, 0.0, 1.0]).norm() < 1e-5);
+            assert!((t - 4.0).abs() < 1e-5);
+        }
+
+        let mut singular = empty_vol_meta::<u8>(vector![3, 3, 3]);
+        singular.set_world_transform(Matrix4::zeros());
+        let vol: Result<FloatVolume, _> = BuildVolume::build(singular);
+        assert!(matches!(vol, Err(Error::Invalid(_))));
+    }
 }
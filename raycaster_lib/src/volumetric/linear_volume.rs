@@ -7,26 +7,32 @@
 use nalgebra::{point, vector, Point3, Vector3, Vector4};
 
 use crate::{
-    common::BoundBox,
+    common::{BoundBox, ValueRange},
     volumetric::{
         vol_builder::{DataSource, MemoryType},
         EmptyIndex,
     },
-    TF,
+    Error, TF,
 };
 
-use super::{vol_builder::VolumeMetadata, BuildVolume, Volume};
+use super::{
+    vol_builder::{Rescale, VolumeMetadata},
+    BuildVolume, Sample, Volume,
+};
 
 #[derive(Debug)]
-pub struct LinearVolume {
+pub struct LinearVolume<T = u8> {
     bound_box: BoundBox,
     size: Vector3<usize>,
+    scale: Vector3<f32>,
     empty_index: EmptyIndex<4>,
-    data: DataSource<u8>,
+    data: DataSource<T>,
+    value_range: ValueRange,
+    rescale: Rescale,
     tf: TF,
 }
 
-impl LinearVolume {
+impl<T: Sample> LinearVolume<T> {
     fn get_3d_index(&self, x: usize, y: usize, z: usize) -> usize {
         z + y * self.size.z + x * self.size.y * self.size.z
     }
@@ -34,7 +40,7 @@ impl LinearVolume {
     fn get_3d_data(&self, x: usize, y: usize, z: usize) -> Option<f32> {
         let index = self.get_3d_index(x, y, z);
         let val = self.data.get(index);
-        val.map(|v| v as f32)
+        val.map(|v| self.rescale.apply(v.into()))
     }
 
     fn get_block_data_half(&self, base: usize) -> Vector4<f32> {
@@ -43,20 +49,22 @@ impl LinearVolume {
             vector![0.0, 0.0, 0.0, 0.0]
         } else {
             vector![
-                buf[base] as f32,
-                buf[base + 1] as f32,
-                buf[base + self.size.z] as f32,
-                buf[base + self.size.z + 1] as f32
+                buf[base].into(),
+                buf[base + 1].into(),
+                buf[base + self.size.z].into(),
+                buf[base + self.size.z + 1].into()
             ]
         }
     }
 }
 
-impl BuildVolume<u8> for LinearVolume {
-    fn build(metadata: VolumeMetadata<u8>) -> Result<LinearVolume, &'static str> {
+impl<T: Sample> BuildVolume<T> for LinearVolume<T> {
+    fn build(metadata: VolumeMetadata<T>) -> Result<LinearVolume<T>, Error> {
         println!("Build started");
+        metadata.validate_data()?;
+        let orientation = metadata.orientation()?;
 
-        let data = metadata.data.ok_or("No data")?;
+        let data = metadata.data.ok_or(Error::Missing("data"))?;
         let memory_type = metadata.memory_type.unwrap_or(MemoryType::Ram);
 
         match data {
@@ -70,26 +78,32 @@ impl BuildVolume<u8> for LinearVolume {
         };
 
         let position = metadata.position.unwrap_or_else(|| point![0.0, 0.0, 0.0]);
-        let size = metadata.size.ok_or("No size")?;
-        let scale = metadata.scale.ok_or("No scale")?;
-        let tf = metadata.tf.ok_or("No tf")?;
+        let size = metadata.size.ok_or(Error::Missing("size"))?;
+        let scale = metadata.scale.ok_or(Error::Missing("scale"))?;
+        let tf = metadata.tf.ok_or(Error::Missing("transfer function"))?;
 
         let vol_dims = (size - vector![1, 1, 1]) // side length is n-1 times the point
-            .cast::<f32>();
-        //.component_mul(&scale); // todo fix scale
+            .cast::<f32>()
+            .component_mul(&scale);
 
-        let bound_box = BoundBox::from_position_dims(position, vol_dims);
+        let bound_box = BoundBox::from_position_dims(position, vol_dims).oriented(orientation);
 
         println!(
             "Constructed LinearVolume ({}x{}x{}) scale ({}x{}x{}) memory {:?}",
             size.x, size.y, size.z, scale.x, scale.y, scale.z, memory_type
         );
 
+        let rescale = metadata.rescale.unwrap_or_default();
+        let value_range = rescale.apply_range(ValueRange::from_samples(data.get_slice()));
+
         let volume = LinearVolume {
             bound_box,
             size,
+            scale,
             tf,
             data,
+            value_range,
+            rescale,
             empty_index: EmptyIndex::dummy(),
         };
 
@@ -100,7 +114,7 @@ impl BuildVolume<u8> for LinearVolume {
     }
 }
 
-impl Volume for LinearVolume {
+impl<T: Sample> Volume for LinearVolume<T> {
     fn get_size(&self) -> Vector3<usize> {
         self.size
     }
@@ -139,15 +153,15 @@ impl Volume for LinearVolume {
         let c0: f32 = x_low_vec.x + x_low_vec.z;
         let c1: f32 = x_low_vec.y + x_low_vec.w;
 
-        c0 * (1.0 - z_t) + c1 * z_t
+        self.rescale.apply(c0 * (1.0 - z_t) + c1 * z_t)
     }
 
     fn get_data(&self, x: usize, y: usize, z: usize) -> Option<f32> {
         self.get_3d_data(x, y, z)
     }
 
-    fn get_tf(&self) -> TF {
-        self.tf
+    fn get_tf(&self) -> &TF {
+        &self.tf
     }
 
     fn set_tf(&mut self, tf: TF) {
@@ -158,8 +172,12 @@ impl Volume for LinearVolume {
         self.bound_box
     }
 
+    fn get_value_range(&self) -> ValueRange {
+        self.value_range
+    }
+
     fn get_scale(&self) -> Vector3<f32> {
-        vector![1.0, 1.0, 1.0]
+        self.scale
     }
 
     fn get_name() -> &'static str {
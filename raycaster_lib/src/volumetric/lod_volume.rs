@@ -0,0 +1,520 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Multi-resolution volume.
+//!
+//! Every block keeps a pyramid of levels of detail, each level covering the same region.
+//! Level 0 has full resolution, each next level halves the number of cells along every axis
+//! (rounded up), down to a single cell.
+//! Renderer picks the level per block by the size of the block on screen.
+//!
+//! Stored levels of block `i` follow full resolution blocks, level by level:
+//! all blocks of level 1, then all blocks of level 2 etc.
+//! Levels missing in data are computed on build.
+
+use std::ops::Range;
+
+use nalgebra::{point, vector, Point3, Vector3};
+
+use crate::{
+    common::{blockify, tf_visible_range, BoundBox, Ray, ValueRange},
+    volumetric::{DataSource, MemoryType, StorageShape},
+    Error, TransferFunction, TF,
+};
+
+use super::{
+    block_volume::{get_block_data, Block},
+    float_block_volume::get_bound_box,
+    vol_builder::{BuildVolume, VolumeMetadata},
+    volume::Blocked,
+    Sample, Volume,
+};
+
+/// Number of samples on side of block at `level`, level 0 has `side` samples
+pub fn lod_side(side: usize, level: u8) -> usize {
+    let cells = side.saturating_sub(1);
+    let divisor = 1_usize.checked_shl(level as u32).unwrap_or(usize::MAX);
+    cells.div_ceil(divisor).max(1) + 1
+}
+
+/// Number of levels of block with `side` samples, the last level has a single cell
+pub fn lod_level_count(side: usize) -> u8 {
+    let mut levels = 1;
+    while lod_side(side, levels - 1) > 2 {
+        levels += 1;
+    }
+    levels
+}
+
+/// Number of samples of one block, all `levels` included
+pub fn lod_block_len(side: usize, levels: u8) -> usize {
+    (0..levels.max(1)).map(|l| lod_side(side, l).pow(3)).sum()
+}
+
+/// Downsample full resolution `block` to `level`.
+///
+/// Samples are averages of the surrounding samples.
+/// Samples on faces of block are averaged only from the face,
+/// so that neighbouring blocks match.
+pub fn downsample_block<T: Sample>(block: &[T], side: usize, level: u8) -> Vec<T> {
+    let lod = lod_side(side, level);
+    let ratio = (side - 1) as f32 / (lod - 1) as f32;
+
+    // Window of full resolution samples, per axis
+    let windows: Vec<Range<usize>> = (0..lod)
+        .map(|i| {
+            if i == 0 {
+                0..1
+            } else if i == lod - 1 {
+                side - 1..side
+            } else {
+                let center = i as f32 * ratio;
+                let low = (center - ratio / 2.0).ceil() as usize;
+                let high = ((center + ratio / 2.0).floor() as usize).min(side - 1);
+                low..high + 1
+            }
+        })
+        .collect();
+
+    let mut data = Vec::with_capacity(lod.pow(3));
+    for wx in &windows {
+        for wy in &windows {
+            for wz in &windows {
+                let mut sum = 0.0;
+                let mut count = 0;
+                for x in wx.clone() {
+                    for y in wy.clone() {
+                        for z in wz.clone() {
+                            sum += block[z + y * side + x * side * side].into();
+                            count += 1;
+                        }
+                    }
+                }
+                data.push(T::from_f32(sum / count as f32));
+            }
+        }
+    }
+    data
+}
+
+/// Coarsest level, which still has at least one cell per pixel.
+///
+/// # Params
+/// * `footprint` - size of block on screen in pixels
+/// * `block_side` - samples on side of full resolution block
+/// * `levels` - number of levels of block
+pub fn select_lod_level(footprint: f32, block_side: usize, levels: u8) -> u8 {
+    (1..levels)
+        .rev()
+        .find(|&l| (lod_side(block_side, l) - 1) as f32 >= footprint)
+        .unwrap_or(0)
+}
+
+/// Block of [`LodVolume`] at one level of detail
+pub struct LodBlock<T = u8> {
+    pub block: Block<T>,
+    /// Cells of full resolution block per cell of this level
+    pub ratio: f32,
+}
+
+impl<T: Sample> Volume for LodBlock<T> {
+    // Ray is scaled to the coarser grid, steps keep their length in volume
+    fn transform_ray(&self, ray: &Ray) -> Option<(Ray, f32)> {
+        let (obj_ray, t) = self.block.transform_ray(ray)?;
+        Some((Ray::new(obj_ray.origin, obj_ray.direction / self.ratio), t))
+    }
+
+    fn get_size(&self) -> Vector3<usize> {
+        self.block.get_size()
+    }
+
+    fn get_tf(&self) -> &TF {
+        self.block.get_tf()
+    }
+
+    fn set_tf(&mut self, tf: TF) {
+        self.block.set_tf(tf)
+    }
+
+    fn sample_at(&self, pos: Point3<f32>) -> f32 {
+        self.block.sample_at(pos)
+    }
+
+    fn get_bound_box(&self) -> BoundBox {
+        self.block.get_bound_box()
+    }
+
+    fn get_value_range(&self) -> ValueRange {
+        self.block.get_value_range()
+    }
+
+    fn get_scale(&self) -> Vector3<f32> {
+        self.block.get_scale()
+    }
+
+    fn get_data(&self, x: usize, y: usize, z: usize) -> Option<f32> {
+        self.block.get_data(x, y, z)
+    }
+
+    fn get_name() -> &'static str {
+        "LodBlock"
+    }
+
+    fn is_empty(&self, pos: Point3<f32>) -> bool {
+        self.block.is_empty(pos)
+    }
+
+    fn build_empty_index(&mut self) {
+        self.block.build_empty_index()
+    }
+}
+
+/// Block volume with levels of detail.
+/// Default overlap == 1
+pub struct LodVolume<T = u8> {
+    block_side: usize,
+    bound_box: BoundBox,
+    data_size: Vector3<usize>,
+    scale: Vector3<f32>,
+    pub empty_blocks: Vec<bool>,
+    block_size: Vector3<usize>, // Number of blocks in structure
+    _data_owner: DataSource<T>,
+    _blocked_data_owner: Vec<Vec<T>>,
+    /// Blocks of each level, level 0 has full resolution
+    pub levels: Vec<Vec<LodBlock<T>>>,
+    value_range: ValueRange,
+    tf: TF,
+}
+
+unsafe impl<T: Sample> Sync for LodVolume<T> {}
+
+impl<T: Sample> LodVolume<T> {
+    // returns (block index, position in block)
+    fn get_indexes(&self, x: usize, y: usize, z: usize) -> (usize, Point3<usize>) {
+        let jump_per_block = self.block_side - 1; // implicit block overlap of 1
+        let offset = point![x % jump_per_block, y % jump_per_block, z % jump_per_block];
+        let block_index = (z / jump_per_block)
+            + (y / jump_per_block) * self.block_size.z
+            + (x / jump_per_block) * self.block_size.y * self.block_size.z;
+        (block_index, offset)
+    }
+
+    /// True == block is empty
+    pub fn build_empty(blocks: &[LodBlock<T>], tf: &dyn TransferFunction) -> Vec<bool> {
+        let mut domain = ValueRange::empty();
+        for block in blocks {
+            domain.union(&block.block.value_range);
+        }
+        let vis_ranges = tf_visible_range(tf, domain);
+
+        blocks
+            .iter()
+            .map(|block| {
+                !vis_ranges
+                    .iter()
+                    .any(|r| r.intersects(&block.block.value_range))
+            })
+            .collect()
+    }
+}
+
+impl<T: Sample> Blocked for LodVolume<T> {
+    type BlockType = LodBlock<T>;
+
+    fn get_blocks(&self) -> &[Self::BlockType] {
+        &self.levels[0]
+    }
+
+    fn get_empty_blocks(&self) -> &[bool] {
+        &self.empty_blocks
+    }
+
+    fn get_lod_count(&self) -> u8 {
+        self.levels.len() as u8
+    }
+
+    fn get_lod_blocks(&self, level: u8) -> &[Self::BlockType] {
+        let level = (level as usize).min(self.levels.len() - 1);
+        &self.levels[level]
+    }
+}
+
+impl<T: Sample> Volume for LodVolume<T> {
+    fn get_size(&self) -> Vector3<usize> {
+        self.data_size
+    }
+
+    fn sample_at(&self, pos: Point3<f32>) -> f32 {
+        let (block_index, offset) =
+            self.get_indexes(pos.x as usize, pos.y as usize, pos.z as usize);
+        let fract = pos.coords.map(f32::fract);
+        self.levels[0][block_index]
+            .block
+            .sample_at(offset.cast::<f32>() + fract)
+    }
+
+    fn get_data(&self, x: usize, y: usize, z: usize) -> Option<f32> {
+        let (block_index, offset) = self.get_indexes(x, y, z);
+        let block = self.levels[0].get(block_index)?;
+        block.get_data(offset.x, offset.y, offset.z)
+    }
+
+    fn get_tf(&self) -> &TF {
+        &self.tf
+    }
+
+    fn get_bound_box(&self) -> BoundBox {
+        self.bound_box
+    }
+
+    fn get_value_range(&self) -> ValueRange {
+        self.value_range
+    }
+
+    fn get_scale(&self) -> Vector3<f32> {
+        self.scale
+    }
+
+    fn set_tf(&mut self, tf: TF) {
+        self.tf = tf;
+        self.empty_blocks = LodVolume::build_empty(&self.levels[0], &*self.tf);
+    }
+
+    fn get_name() -> &'static str {
+        "LodVolume"
+    }
+
+    fn is_empty(&self, _: Point3<f32>) -> bool {
+        false
+    }
+
+    fn build_empty_index(&mut self) {
+        // noop
+    }
+}
+
+impl<T: Sample> BuildVolume<T> for LodVolume<T> {
+    fn build(metadata: VolumeMetadata<T>) -> Result<LodVolume<T>, Error> {
+        metadata.validate_data()?;
+        let orientation = metadata.orientation()?;
+        let position = metadata.position.unwrap_or_else(|| point![0.0, 0.0, 0.0]);
+        let size = metadata.size.ok_or(Error::Missing("size"))?;
+        let scale = metadata.scale.ok_or(Error::Missing("scale"))?;
+        let tf = metadata.tf.ok_or(Error::Missing("transfer function"))?;
+        let data = metadata.data.ok_or(Error::Missing("data"))?;
+        let memory_type = metadata.memory_type.unwrap_or(MemoryType::Stream);
+        let rescale = metadata.rescale.unwrap_or_default();
+        let stored_levels = metadata.lod_levels.unwrap_or(1).max(1);
+
+        let data_shape = metadata.data_shape.ok_or(Error::Missing("data shape"))?;
+        let block_side = match (data_shape, metadata.desired_data_shape) {
+            (StorageShape::Z(b), Some(StorageShape::Z(s))) if b != s => {
+                return Err(Error::Shape("Block side of data does not match"))
+            }
+            (StorageShape::Z(s), _) | (StorageShape::Linear, Some(StorageShape::Z(s))) => s,
+            (StorageShape::Linear, _) => {
+                return Err(Error::Shape("Block volume needs Z order storage shape"))
+            }
+        } as usize;
+        let is_blocked = matches!(data_shape, StorageShape::Z(_));
+        if block_side < 2 {
+            return Err(Error::Shape("Invalid block side"));
+        }
+        if !is_blocked && matches!(memory_type, MemoryType::Stream) {
+            return Err(Error::Unsupported("Streaming of data not stored in blocks"));
+        }
+
+        let vol_dims = (size - vector![1, 1, 1]) // side length is n-1 times the point
+            .cast::<f32>()
+            .component_mul(&scale);
+        let bound_box = BoundBox::from_position_dims(position, vol_dims).oriented(orientation);
+
+        let step_size = block_side - 1;
+        let block_size = blockify(size, block_side, 1);
+        let block_count = block_size.product();
+        let level_count = if stored_levels > 1 {
+            stored_levels
+        } else {
+            lod_level_count(block_side)
+        };
+
+        let data_slice = data.get_slice();
+        let mut blocked_data_owner = vec![];
+
+        // Data of every block at every level
+        // Points into mapped data or into `blocked_data_owner`
+        let mut level_ptrs: Vec<Vec<*const T>> = Vec::with_capacity(level_count as usize);
+        let mut level_start = 0;
+        for level in 0..level_count {
+            let elements = lod_side(block_side, level).pow(3);
+            let mut own = |v: Vec<T>| {
+                let ptr = v.as_ptr();
+                blocked_data_owner.push(v);
+                ptr
+            };
+
+            let ptrs: Vec<*const T> = if level < stored_levels && is_blocked {
+                data_slice[level_start..level_start + block_count * elements]
+                    .chunks_exact(elements)
+                    .map(|stored| match memory_type {
+                        MemoryType::Stream => stored.as_ptr(),
+                        MemoryType::Ram => own(stored.to_vec()),
+                    })
+                    .collect()
+            } else if level == 0 {
+                (0..block_count)
+                    .map(|index| {
+                        let block_off = point![
+                            index / (block_size.y * block_size.z),
+                            index / block_size.z % block_size.y,
+                            index % block_size.z
+                        ];
+                        let block_start = step_size * block_off;
+                        own(get_block_data(data_slice, size, block_start, block_side))
+                    })
+                    .collect()
+            } else {
+                level_ptrs[0]
+                    .iter()
+                    .map(|&ptr| {
+                        // Safety: full resolution blocks are never moved or freed while building
+                        let full = unsafe { std::slice::from_raw_parts(ptr, block_side.pow(3)) };
+                        own(downsample_block(full, block_side, level))
+                    })
+                    .collect()
+            };
+
+            level_start += block_count * elements;
+            level_ptrs.push(ptrs);
+        }
+
+        let mut levels = Vec::with_capacity(level_count as usize);
+        for (level, ptrs) in level_ptrs.into_iter().enumerate() {
+            let side = lod_side(block_side, level as u8);
+            let ratio = (block_side - 1) as f32 / (side - 1) as f32;
+            let mut blocks = Vec::with_capacity(block_count);
+
+            for x in 0..block_size.x {
+                for y in 0..block_size.y {
+                    for z in 0..block_size.z {
+                        let block_start = step_size * point![x, y, z];
+                        let block_bound_box =
+                            get_bound_box(position, scale, block_start, block_side)
+                                .oriented(orientation);
+                        let ptr = ptrs[blocks.len()];
+                        let block = unsafe {
+                            Block::new(side, block_bound_box, scale * ratio, ptr, rescale, &*tf)
+                        };
+                        blocks.push(LodBlock { block, ratio });
+                    }
+                }
+            }
+            levels.push(blocks);
+        }
+
+        let empty_blocks = LodVolume::build_empty(&levels[0], &*tf);
+
+        let mut value_range = ValueRange::empty();
+        for block in &levels[0] {
+            value_range.union(&block.block.value_range);
+        }
+
+        println!(
+            "Built {} blocks of dims {} levels {} ({},{},{}) blocks ({},{},{}) memory {:?}",
+            block_count,
+            block_side,
+            level_count,
+            size.x,
+            size.y,
+            size.z,
+            block_size.x,
+            block_size.y,
+            block_size.z,
+            memory_type
+        );
+
+        Ok(LodVolume {
+            block_side,
+            bound_box,
+            data_size: size,
+            scale,
+            empty_blocks,
+            block_size,
+            _data_owner: data,
+            _blocked_data_owner: blocked_data_owner,
+            levels,
+            value_range,
+            tf,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::{test_helpers::empty_vol_meta, volumetric::volumes::BlockVolume};
+
+    #[test]
+    fn level_sides() {
+        let sides: Vec<usize> = (0..5).map(|l| lod_side(16, l)).collect();
+        assert_eq!(sides, [16, 9, 5, 3, 2]);
+        assert_eq!(lod_level_count(16), 5);
+        assert_eq!(lod_level_count(2), 1);
+        assert_eq!(lod_block_len(5, 2), 125 + 27);
+
+        assert_eq!(select_lod_level(100.0, 16, 5), 0);
+        assert_eq!(select_lod_level(8.0, 16, 5), 1);
+        assert_eq!(select_lod_level(3.0, 16, 5), 2);
+        assert_eq!(select_lod_level(0.5, 16, 5), 4);
+        assert_eq!(select_lod_level(0.5, 16, 1), 0);
+    }
+
+    #[test]
+    fn downsample_faces() {
+        let side = 5;
+        let block: Vec<u8> = (0..side * side * side).map(|i| (i % 7) as u8).collect();
+        let level = downsample_block(&block, side, 1);
+        assert_eq!(level.len(), 27);
+
+        // Corners are kept
+        assert_eq!(level[0], block[0]);
+        assert_eq!(level[26], block[124]);
+
+        let solid = vec![40_u16; side * side * side];
+        assert!(downsample_block(&solid, side, 2).iter().all(|&v| v == 40));
+    }
+
+    #[test]
+    fn build_levels() {
+        let size = vector![9, 9, 9];
+        let samples: Vec<u8> = (0..size.product()).map(|i| (i % 251) as u8).collect();
+        let meta = || {
+            let mut meta = empty_vol_meta::<u8>(size);
+            meta.set_data(DataSource::Vec(samples.clone()))
+                .set_memory_type(MemoryType::Ram)
+                .set_desired_data_shape(StorageShape::Z(5));
+            meta
+        };
+
+        let lod: LodVolume = BuildVolume::build(meta()).unwrap();
+        let block: BlockVolume = BuildVolume::build(meta()).unwrap();
+
+        assert_eq!(lod.get_lod_count(), 3);
+        assert_eq!(lod.get_lod_blocks(1).len(), 8);
+        assert_eq!(lod.get_lod_blocks(2)[0].get_size(), vector![2, 2, 2]);
+        assert_eq!(lod.get_data(3, 8, 5), block.get_data(3, 8, 5));
+        let pos = point![2.5, 7.25, 4.0];
+        assert!((lod.sample_at(pos) - block.sample_at(pos)).abs() < 0.001);
+
+        // Coarse blocks cover the same region
+        let full = &lod.get_blocks()[7];
+        let coarse = &lod.get_lod_blocks(1)[7];
+        assert_eq!(full.get_bound_box().lower, coarse.get_bound_box().lower);
+        assert_eq!(full.get_bound_box().upper, coarse.get_bound_box().upper);
+        assert_eq!(coarse.get_data(2, 2, 2), full.get_data(4, 4, 4));
+    }
+}
@@ -20,22 +20,55 @@
 //!
 //! Another trait used for volumes is `Blocked`.
 //! This trait allows renderer to access volume blocks (where applicable) and is used for parallel rendering.
+//!
+//! # Sample types
+//!
+//! Volume types are generic over type of stored samples, see [`Sample`].
+//! Supported sample types are `u8`, `u16`, `i16` and `f32`.
+//!
+//! `StreamBlockVolume` keeps only recently used blocks in memory, within a set budget.
+//! `CompressedBlockVolume` does the same with blocks compressed independently, decompressing them on first touch.
+//! `SparseBlockVolume` does not store uniform blocks, they are kept as a single value.
+//!
+//! `LodVolume` keeps blocks in several levels of detail,
+//! the parallel renderer picks the level by the size of block on screen.
+//!
+//! Volumes with several channels per voxel are represented by `MultiChannelVolume`,
+//! each channel is classified by its own transfer function.
+//!
+//! Stored samples can be mapped to physical values (e.g. Hounsfield units) by a linear [`Rescale`].
+//! Renderers and transfer functions work with the rescaled values.
 
+mod block_cache;
 mod block_volume;
+mod compression;
 mod empty_index;
 mod float_block;
 mod float_block_volume;
 mod float_volume;
 mod linear_volume;
+mod lod_volume;
+mod multi_channel_volume;
+mod sparse_block_volume;
+mod stream_block_volume;
 mod vol_builder;
 mod volume;
 
 // Exports
 
+pub use block_cache::{BlockCache, BlockSource, CacheStats, FileBlocks};
+pub use compression::{compress_block, decompress_block, CompressedBlocks};
 pub use empty_index::EmptyIndex;
+pub use lod_volume::{
+    downsample_block, lod_block_len, lod_level_count, lod_side, select_lod_level,
+};
+pub use sparse_block_volume::{uniform_value, SparseBlock, SparseBlocks};
+pub use stream_block_volume::DEFAULT_CACHE_BUDGET;
 pub use vol_builder::DataSource;
-pub use vol_builder::{BuildVolume, MemoryType, StorageShape, VolumeMetadata}; // todo move
-pub use volume::{Blocked, Volume};
+pub use vol_builder::{
+    BuildVolume, ChannelLayout, MemoryType, Rescale, StorageShape, VolumeMetadata,
+}; // todo move
+pub use volume::{Blocked, Sample, Volume, GRADIENT_STEP};
 
 pub mod volumes {
     use super::*;
@@ -45,14 +78,19 @@ pub mod volumes {
     pub use float_block_volume::FloatBlockVolume;
     pub use float_volume::FloatVolume;
     pub use linear_volume::LinearVolume;
+    pub use lod_volume::{LodBlock, LodVolume};
+    pub use multi_channel_volume::{MultiChannelBlock, MultiChannelVolume};
+    pub use sparse_block_volume::SparseBlockVolume;
+    pub use stream_block_volume::{CompressedBlockVolume, StreamBlockVolume};
 }
 
 #[cfg(test)]
 mod test {
 
     use super::*;
-    use crate::test_helpers::*;
+    use crate::{color, premade::parse::raw_parser, test_helpers::*};
     use nalgebra::{point, vector};
+    use std::sync::Arc;
     use volumes::*;
 
     /// Float comparison for optional values
@@ -91,6 +129,103 @@ mod test {
         }
     }
 
+    /// Expected: 16-bit samples are not truncated, blocks are classified by 16-bit values
+    #[test]
+    fn u16_volumes() {
+        let size = vector![10, 10, 10];
+        let data: Vec<u16> = (0..size.product()).map(|i| (i * 5) as u16).collect();
+        let tf = |sample: f32| color::mono(255.0, if sample > 3000.0 { 1.0 } else { 0.0 });
+
+        let meta = || {
+            let mut meta = empty_vol_meta::<u16>(size);
+            meta.set_data(DataSource::Vec(data.clone()))
+                .set_tf(Arc::new(tf))
+                .set_memory_type(MemoryType::Ram)
+                .set_desired_data_shape(StorageShape::Z(5));
+            meta
+        };
+        let float: FloatVolume = BuildVolume::build(meta()).unwrap();
+        let float_block: FloatBlockVolume = BuildVolume::build(meta()).unwrap();
+        let block: BlockVolume<u16> = BuildVolume::build(meta()).unwrap();
+
+        for x in 0..size.x {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    let expected = Some(data[z + y * size.z + x * size.y * size.z] as f32);
+                    assert!(compare_samples(float.get_data(x, y, z), expected));
+                    assert!(compare_samples(float_block.get_data(x, y, z), expected));
+                    assert!(compare_samples(block.get_data(x, y, z), expected));
+                }
+            }
+        }
+
+        assert_eq!(float.get_value_range(), (0.0..4995.0).into());
+        assert_eq!(block.get_value_range(), (0.0..4995.0).into());
+
+        // Lowest block has values up to 2220, highest block up to 4995
+        assert!(block.empty_blocks[0]);
+        assert!(!block.empty_blocks[block.empty_blocks.len() - 1]);
+    }
+
+    /// Expected: signed samples are rescaled, blocks are classified by rescaled values
+    #[test]
+    fn i16_rescaled_volumes() {
+        let size = vector![10, 10, 10];
+        let samples: Vec<i16> = (0..size.product()).map(|i| i as i16 - 500).collect();
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_ne_bytes()).collect();
+        // Values -1500 to 498
+        let tf = |sample: f32| color::mono(255.0, if sample > 200.0 { 1.0 } else { 0.0 });
+
+        let meta = || {
+            let mut meta = raw_parser::<i16>(DataSource::Vec(bytes.clone()), size).unwrap();
+            meta.set_tf(Arc::new(tf))
+                .set_rescale(Rescale::new(2.0, -500.0))
+                .set_memory_type(MemoryType::Ram)
+                .set_desired_data_shape(StorageShape::Z(5));
+            meta
+        };
+        let float: FloatVolume = BuildVolume::build(meta()).unwrap();
+        let block: BlockVolume<i16> = BuildVolume::build(meta()).unwrap();
+
+        for (i, &sample) in samples.iter().enumerate() {
+            let (x, y, z) = (i / 100, i / 10 % 10, i % 10);
+            let expected = Some(sample as f32 * 2.0 - 500.0);
+            assert!(compare_samples(float.get_data(x, y, z), expected));
+            assert!(compare_samples(block.get_data(x, y, z), expected));
+        }
+        let pos = point![1.0, 2.0, 3.5];
+        assert_eq!(float.sample_at(pos), block.sample_at(pos));
+
+        assert_eq!(float.get_value_range(), (-1500.0..498.0).into());
+        assert!(block.empty_blocks[0]);
+        assert!(!block.empty_blocks[block.empty_blocks.len() - 1]);
+
+        assert!(raw_parser::<i16>(DataSource::Vec(vec![0; 10]), size).is_err());
+        assert!(matches!(
+            raw_parser::<i16>(DataSource::Vec(vec![]), vector![usize::MAX, 2, 1]),
+            Err(crate::Error::Shape(_))
+        ));
+    }
+
+    /// Expected: fractional samples are classified by transfer function
+    #[test]
+    fn f32_volume() {
+        let size = vector![10, 10, 10];
+        let samples: Vec<f32> = (0..size.product()).map(|i| i as f32 / 1000.0).collect();
+        let tf = |sample: f32| color::mono(255.0, if sample > 0.7 { 1.0 } else { 0.0 });
+
+        let mut meta = empty_vol_meta::<f32>(size);
+        meta.set_data(DataSource::Vec(samples))
+            .set_tf(Arc::new(tf))
+            .set_memory_type(MemoryType::Ram)
+            .set_desired_data_shape(StorageShape::Z(5));
+        let block: BlockVolume<f32> = BuildVolume::build(meta).unwrap();
+
+        assert!(compare_samples(block.get_data(9, 9, 9), Some(0.999)));
+        assert!(block.empty_blocks[0]);
+        assert!(!block.empty_blocks[block.empty_blocks.len() - 1]);
+    }
+
     /// Same test as above, with bigger volume
     #[test]
     #[ignore]
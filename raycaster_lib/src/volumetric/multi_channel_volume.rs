@@ -0,0 +1,510 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+use std::sync::Arc;
+
+use nalgebra::{point, vector, Point3, Vector3};
+
+use crate::{
+    color::{self, RGBA},
+    common::{blockify, tf_visible_range, BoundBox, Ray, ValueRange},
+    tf::LutTransferFunction,
+    Error, TransferFunction, TF,
+};
+
+use super::{
+    float_block::FloatBlock,
+    float_block_volume::{get_block_data, get_bound_box},
+    vol_builder::{BuildVolume, ChannelLayout, VolumeMetadata},
+    volume::Blocked,
+    Sample, Volume,
+};
+
+/// Maximal number of channels
+const MAX_CHANNELS: usize = 4;
+
+/// Blend colors of channels.
+/// Colors are averaged, weighted by opacity.
+fn blend<I>(colors: I) -> RGBA
+where
+    I: IntoIterator<Item = RGBA>,
+{
+    let mut rgb = vector![0.0, 0.0, 0.0];
+    let mut weight = 0.0;
+    let mut transparency = 1.0;
+
+    for color in colors {
+        if color.w > 0.0 {
+            rgb += color.xyz() * color.w;
+            weight += color.w;
+            transparency *= 1.0 - color.w;
+        }
+    }
+
+    if weight == 0.0 {
+        return color::zero();
+    }
+
+    let rgb = rgb / weight;
+    vector![rgb.x, rgb.y, rgb.z, 1.0 - transparency]
+}
+
+/// Bake transfer function of every channel, over value range of the channel
+fn bake_luts(tfs: &[TF], channel_ranges: &[ValueRange]) -> Vec<LutTransferFunction> {
+    tfs.iter()
+        .zip(channel_ranges)
+        .map(|(tf, range)| LutTransferFunction::for_range(&**tf, *range))
+        .collect()
+}
+
+/// Block of [`MultiChannelVolume`].
+/// Every channel is stored as a separate `FloatBlock`.
+pub struct MultiChannelBlock {
+    pub channels: Vec<FloatBlock>,
+    /// Transfer functions of channels, shared by all blocks
+    luts: Arc<Vec<LutTransferFunction>>,
+}
+
+impl MultiChannelBlock {
+    /// Blended color of channels in voxel
+    fn get_color(&self, x: usize, y: usize, z: usize) -> Option<RGBA> {
+        let colors: Option<Vec<RGBA>> = self
+            .channels
+            .iter()
+            .zip(self.luts.iter())
+            .map(|(channel, lut)| channel.get_data(x, y, z).map(|v| lut.color(v)))
+            .collect();
+        colors.map(blend)
+    }
+
+    /// Blended color of channels in `pos`
+    fn color_at(&self, pos: Point3<f32>) -> RGBA {
+        blend(
+            self.channels
+                .iter()
+                .zip(self.luts.iter())
+                .map(|(channel, lut)| lut.color(channel.sample_at(pos))),
+        )
+    }
+}
+
+impl Volume for MultiChannelBlock {
+    fn transform_ray(&self, ray: &Ray) -> Option<(Ray, f32)> {
+        self.channels[0].transform_ray(ray)
+    }
+
+    fn get_size(&self) -> Vector3<usize> {
+        self.channels[0].get_size()
+    }
+
+    fn get_tf(&self) -> &TF {
+        unimplemented!()
+    }
+
+    fn set_tf(&mut self, _tf: TF) {
+        unimplemented!()
+    }
+
+    fn is_empty(&self, pos: Point3<f32>) -> bool {
+        self.channels.iter().all(|channel| channel.is_empty(pos))
+    }
+
+    fn sample_at(&self, pos: Point3<f32>) -> f32 {
+        self.color_at(pos).w * 255.0
+    }
+
+    fn classify(&self, pos: Point3<f32>) -> Option<RGBA> {
+        Some(self.color_at(pos))
+    }
+
+    fn get_bound_box(&self) -> BoundBox {
+        self.channels[0].get_bound_box()
+    }
+
+    fn get_value_range(&self) -> ValueRange {
+        (0.0..255.0).into()
+    }
+
+    fn get_scale(&self) -> Vector3<f32> {
+        unimplemented!()
+    }
+
+    fn get_data(&self, x: usize, y: usize, z: usize) -> Option<f32> {
+        self.get_color(x, y, z).map(|color| color.w * 255.0)
+    }
+
+    fn get_name() -> &'static str {
+        "MultiChannelBlock"
+    }
+
+    fn build_empty_index(&mut self) {
+        for (channel, lut) in self.channels.iter_mut().zip(self.luts.iter()) {
+            channel.build_empty_index_tf(lut);
+        }
+    }
+}
+
+/// Volume with several channels (up to 4) per voxel, such as fluorescence microscopy data.
+///
+/// Every channel is classified by its own transfer function,
+/// colors of channels are blended per sample (see [`Volume::classify`]).
+/// Samples of the volume are blended opacities scaled to `<0;255>`, used for shading.
+///
+/// [`Volume::get_tf`] and [`Volume::set_tf`] work with the transfer function of the first channel.
+/// After construction, all channels share the transfer function from metadata.
+///
+/// Default overlap of blocks is 1.
+pub struct MultiChannelVolume {
+    block_side: usize,
+    bound_box: BoundBox,
+    data_size: Vector3<usize>,
+    pub empty_blocks: Vec<bool>,
+    block_size: Vector3<usize>, // Number of blocks in structure (.data)
+    pub data: Vec<MultiChannelBlock>,
+    tfs: Vec<TF>,
+    /// Value ranges of channels
+    channel_ranges: Vec<ValueRange>,
+}
+
+impl MultiChannelVolume {
+    /// Number of channels
+    pub fn get_channels(&self) -> usize {
+        self.tfs.len()
+    }
+
+    /// Returns transfer function of `channel`
+    pub fn get_channel_tf(&self, channel: usize) -> &TF {
+        &self.tfs[channel]
+    }
+
+    /// Sets transfer function of `channel`, indexes are rebuilt.
+    /// Panics if `channel` is out of range.
+    pub fn set_channel_tf(&mut self, channel: usize, tf: TF) {
+        self.tfs[channel] = tf;
+
+        let luts = Arc::new(bake_luts(&self.tfs, &self.channel_ranges));
+        for block in &mut self.data {
+            block.luts = luts.clone();
+            block.channels[channel].build_empty_index_tf(&luts[channel]);
+        }
+        self.empty_blocks = MultiChannelVolume::build_empty(&self.data, &self.tfs);
+    }
+
+    /// True == block is empty in all channels
+    pub fn build_empty(blocks: &[MultiChannelBlock], tfs: &[TF]) -> Vec<bool> {
+        let vis_ranges: Vec<Vec<ValueRange>> = (0..tfs.len())
+            .map(|c| {
+                let mut domain = ValueRange::empty();
+                for block in blocks {
+                    domain.union(&block.channels[c].value_range);
+                }
+                tf_visible_range(&*tfs[c], domain)
+            })
+            .collect();
+
+        blocks
+            .iter()
+            .map(|block| {
+                let visible = block
+                    .channels
+                    .iter()
+                    .zip(&vis_ranges)
+                    .any(|(channel, ranges)| {
+                        ranges.iter().any(|r| r.intersects(&channel.value_range))
+                    });
+                !visible
+            })
+            .collect()
+    }
+
+    /// Returns index of block containing voxel `(x, y, z)` and the lowest voxel of the block
+    fn block_of(&self, x: usize, y: usize, z: usize) -> (usize, Point3<usize>) {
+        let jump_per_block = self.block_side - 1; // implicit block overlap of 1
+        let block = vector![x, y, z]
+            .map(|v| v / jump_per_block)
+            .zip_map(&self.block_size, |b, count| b.min(count - 1));
+        let block_index =
+            block.z + block.y * self.block_size.z + block.x * self.block_size.y * self.block_size.z;
+        (block_index, (block * jump_per_block).into())
+    }
+
+    /// Returns block containing `pos` and `pos` in coordinates of the block
+    fn locate(&self, pos: Point3<f32>) -> (&MultiChannelBlock, Point3<f32>) {
+        let (block_index, start) = self.block_of(pos.x as usize, pos.y as usize, pos.z as usize);
+        (&self.data[block_index], pos - start.coords.cast::<f32>())
+    }
+}
+
+impl Blocked for MultiChannelVolume {
+    type BlockType = MultiChannelBlock;
+
+    fn get_blocks(&self) -> &[Self::BlockType] {
+        &self.data
+    }
+
+    fn get_empty_blocks(&self) -> &[bool] {
+        &self.empty_blocks
+    }
+}
+
+impl Volume for MultiChannelVolume {
+    fn get_size(&self) -> Vector3<usize> {
+        self.data_size
+    }
+
+    fn sample_at(&self, pos: Point3<f32>) -> f32 {
+        let (block, block_pos) = self.locate(pos);
+        block.sample_at(block_pos)
+    }
+
+    fn classify(&self, pos: Point3<f32>) -> Option<RGBA> {
+        let (block, block_pos) = self.locate(pos);
+        block.classify(block_pos)
+    }
+
+    fn get_data(&self, x: usize, y: usize, z: usize) -> Option<f32> {
+        if x >= self.data_size.x || y >= self.data_size.y || z >= self.data_size.z {
+            return None;
+        }
+        let (block_index, start) = self.block_of(x, y, z);
+        self.data[block_index].get_data(x - start.x, y - start.y, z - start.z)
+    }
+
+    fn get_tf(&self) -> &TF {
+        &self.tfs[0]
+    }
+
+    fn get_bound_box(&self) -> BoundBox {
+        self.bound_box
+    }
+
+    fn get_value_range(&self) -> ValueRange {
+        (0.0..255.0).into()
+    }
+
+    fn get_scale(&self) -> Vector3<f32> {
+        vector![1.0, 1.0, 1.0]
+    }
+
+    fn set_tf(&mut self, tf: TF) {
+        self.set_channel_tf(0, tf);
+    }
+
+    fn get_name() -> &'static str {
+        "MultiChannelVolume"
+    }
+
+    fn is_empty(&self, pos: Point3<f32>) -> bool {
+        let (block_index, _) = self.block_of(pos.x as usize, pos.y as usize, pos.z as usize);
+        self.empty_blocks[block_index]
+    }
+
+    fn build_empty_index(&mut self) {
+        // noop
+    }
+}
+
+impl<T: Sample> BuildVolume<T> for MultiChannelVolume {
+    fn build(metadata: VolumeMetadata<T>) -> Result<MultiChannelVolume, Error> {
+        let channels = metadata.channels.ok_or(Error::Missing("channel count"))?;
+        if channels == 0 || channels > MAX_CHANNELS {
+            return Err(Error::Unsupported("Number of channels"));
+        }
+        metadata.validate_data()?;
+        let orientation = metadata.orientation()?;
+
+        let position = metadata.position.unwrap_or_else(|| point![0.0, 0.0, 0.0]);
+        let size = metadata.size.ok_or(Error::Missing("size"))?;
+        let scale = metadata.scale.unwrap_or_else(|| vector![1.0, 1.0, 1.0]);
+        let data = metadata.data.ok_or(Error::Missing("data"))?;
+        let tf = metadata.tf.ok_or(Error::Missing("transfer function"))?;
+        let layout = metadata
+            .channel_layout
+            .unwrap_or(ChannelLayout::Interleaved);
+        let rescale = metadata.rescale.unwrap_or_default();
+        let block_side = 16;
+
+        let voxels = size.product();
+        let slice = data.get_slice();
+
+        // Separate channels
+        let channel_data: Vec<Vec<f32>> = (0..channels)
+            .map(|c| {
+                (0..voxels)
+                    .map(|i| {
+                        let index = match layout {
+                            ChannelLayout::Interleaved => i * channels + c,
+                            ChannelLayout::Planar => c * voxels + i,
+                        };
+                        rescale.apply(slice[index].into())
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let channel_ranges: Vec<ValueRange> =
+            channel_data.iter().map(ValueRange::from_samples).collect();
+        let tfs = vec![tf; channels];
+        let luts = Arc::new(bake_luts(&tfs, &channel_ranges));
+
+        let vol_dims = (size - vector![1, 1, 1]) // side length is n-1 times the point
+            .cast::<f32>()
+            .component_mul(&scale);
+        let bound_box = BoundBox::from_position_dims(position, vol_dims).oriented(orientation);
+
+        let step_size = block_side - 1;
+        let block_size = blockify(size, block_side, 1);
+
+        let mut blocks = Vec::with_capacity(block_size.product());
+
+        for x in 0..block_size.x {
+            for y in 0..block_size.y {
+                for z in 0..block_size.z {
+                    let block_start = step_size * point![x, y, z];
+                    let block_bound_box = get_bound_box(position, scale, block_start, block_side)
+                        .oriented(orientation);
+                    let channels = channel_data
+                        .iter()
+                        .zip(luts.iter())
+                        .map(|(data, lut)| {
+                            let block_data = get_block_data(data, size, block_start, block_side);
+                            FloatBlock::from_data(
+                                block_data,
+                                block_bound_box,
+                                scale,
+                                block_side,
+                                lut,
+                            )
+                        })
+                        .collect();
+                    blocks.push(MultiChannelBlock {
+                        channels,
+                        luts: luts.clone(),
+                    });
+                }
+            }
+        }
+
+        let empty_blocks = MultiChannelVolume::build_empty(&blocks, &tfs);
+
+        Ok(MultiChannelVolume {
+            block_side,
+            bound_box,
+            data_size: size,
+            empty_blocks,
+            block_size,
+            data: blocks,
+            tfs,
+            channel_ranges,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::{test_helpers::empty_vol_meta, volumetric::DataSource};
+
+    /// Visible above 100, in color `rgb`
+    fn channel_tf(r: f32, g: f32) -> TF {
+        Arc::new(move |sample: f32| {
+            if sample > 100.0 {
+                color::new(r, g, 0.0, 0.5)
+            } else {
+                color::zero()
+            }
+        })
+    }
+
+    /// Two channels, first rises along x, second along z, from 0 to 250
+    fn two_channels(size: usize, layout: ChannelLayout) -> MultiChannelVolume {
+        let voxels = size * size * size;
+        let value = |i: usize, c: usize| {
+            let axis = if c == 0 { i / size / size } else { i % size };
+            (axis * 250 / (size - 1)) as u8
+        };
+        let data: Vec<u8> = match layout {
+            ChannelLayout::Interleaved => (0..voxels * 2).map(|i| value(i / 2, i % 2)).collect(),
+            ChannelLayout::Planar => (0..voxels * 2)
+                .map(|i| value(i % voxels, i / voxels))
+                .collect(),
+        };
+
+        let mut meta = empty_vol_meta::<u8>(vector![size, size, size]);
+        meta.set_data(DataSource::Vec(data))
+            .set_scale(vector![1.0, 1.0, 1.0])
+            .set_channels(2)
+            .set_channel_layout(layout);
+        let mut volume: MultiChannelVolume = BuildVolume::build(meta).unwrap();
+        volume.set_channel_tf(0, channel_tf(255.0, 0.0));
+        volume.set_channel_tf(1, channel_tf(0.0, 255.0));
+        volume
+    }
+
+    #[test]
+    fn channels_blend() {
+        let volume = two_channels(10, ChannelLayout::Interleaved);
+
+        assert_eq!(volume.get_channels(), 2);
+        assert_eq!(volume.classify(point![0.0, 0.0, 0.0]), Some(color::zero()));
+        assert_eq!(
+            volume.classify(point![9.0, 0.0, 0.0]),
+            Some(color::new(255.0, 0.0, 0.0, 0.5))
+        );
+        assert_eq!(
+            volume.classify(point![9.0, 3.0, 9.0]),
+            Some(color::new(127.5, 127.5, 0.0, 0.75))
+        );
+        assert_eq!(volume.get_data(9, 3, 9), Some(0.75 * 255.0));
+        assert_eq!(volume.get_data(10, 3, 9), None);
+    }
+
+    #[test]
+    fn layouts_match() {
+        let interleaved = two_channels(20, ChannelLayout::Interleaved);
+        let planar = two_channels(20, ChannelLayout::Planar);
+
+        for x in 0..20 {
+            for y in (0..20).step_by(7) {
+                for z in 0..20 {
+                    assert_eq!(interleaved.get_data(x, y, z), planar.get_data(x, y, z));
+                    let pos = point![x as f32 * 0.95, y as f32, z as f32 * 0.95];
+                    assert_eq!(interleaved.classify(pos), planar.classify(pos));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_blocks() {
+        let mut volume = two_channels(20, ChannelLayout::Planar);
+
+        // Lowest block has values up to 15 * 250 / 19 in both channels
+        assert_eq!(volume.empty_blocks, vec![false; 8]);
+
+        let hidden = Arc::new(|_: f32| color::zero());
+        volume.set_channel_tf(0, hidden.clone());
+        volume.set_channel_tf(1, hidden);
+
+        assert_eq!(volume.empty_blocks, vec![true; 8]);
+    }
+
+    #[test]
+    fn invalid_metadata() {
+        let build = |channels: usize, len: usize| {
+            let mut meta = empty_vol_meta::<u8>(vector![4, 4, 4]);
+            meta.set_data(DataSource::Vec(vec![0; len]))
+                .set_channels(channels);
+            <MultiChannelVolume as BuildVolume<u8>>::build(meta).is_ok()
+        };
+
+        assert!(build(3, 64 * 3));
+        assert!(!build(3, 64 * 2));
+        assert!(!build(0, 64));
+        assert!(!build(5, 64 * 5));
+    }
+}
@@ -0,0 +1,428 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Block volume without storage of uniform blocks.
+//!
+//! Blocks with all samples equal are kept as a single value in the table of blocks,
+//! only the other blocks are stored. Uniform blocks are sampled without memory access.
+
+use nalgebra::{point, vector, Point3, Vector3};
+
+use crate::{
+    common::{blockify, BoundBox, ValueRange},
+    volumetric::StorageShape,
+    Error, TF,
+};
+
+use super::{
+    block_volume::{Block, BlockVolume},
+    float_block_volume::get_bound_box,
+    vol_builder::{BuildVolume, VolumeMetadata},
+    volume::Blocked,
+    DataSource, Sample, Volume,
+};
+
+/// Entry of the table of sparse blocks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SparseBlock<T> {
+    /// All samples of block have this value
+    Uniform(T),
+    /// Index of block among stored blocks
+    Stored(usize),
+}
+
+/// Blocks of sparse volume, see [`SparseBlockVolume`]
+#[derive(Debug)]
+pub struct SparseBlocks<T> {
+    /// Samples of stored blocks, one after another
+    pub data: DataSource<T>,
+    /// Entry of every block of volume
+    pub table: Vec<SparseBlock<T>>,
+}
+
+impl<T: Sample> SparseBlocks<T> {
+    pub fn new(data: DataSource<T>, table: Vec<SparseBlock<T>>) -> SparseBlocks<T> {
+        SparseBlocks { data, table }
+    }
+
+    /// Split blocks of `block_len` samples stored one after another, uniform blocks are not stored
+    pub fn from_blocks(samples: &[T], block_len: usize) -> SparseBlocks<T> {
+        let mut data = vec![];
+        let mut table = vec![];
+        for block in samples.chunks(block_len) {
+            match uniform_value(block) {
+                Some(value) => table.push(SparseBlock::Uniform(value)),
+                None => {
+                    table.push(SparseBlock::Stored(data.len() / block_len));
+                    data.extend_from_slice(block);
+                }
+            }
+        }
+        SparseBlocks::new(DataSource::Vec(data), table)
+    }
+
+    /// Number of blocks with stored samples
+    pub fn stored_count(&self) -> usize {
+        self.table
+            .iter()
+            .filter(|b| matches!(b, SparseBlock::Stored(_)))
+            .count()
+    }
+
+    /// Check that table describes `block_count` blocks and data holds the stored ones
+    pub fn validate(&self, block_count: usize, block_len: usize) -> Result<(), Error> {
+        if self.table.len() != block_count {
+            return Err(Error::Shape("Number of sparse blocks does not match"));
+        }
+        let stored = self.stored_count();
+        if self
+            .table
+            .iter()
+            .any(|b| matches!(b, SparseBlock::Stored(i) if *i >= stored))
+        {
+            return Err(Error::Invalid("Index of stored block out of range"));
+        }
+        let expected = stored * block_len * std::mem::size_of::<T>();
+        let found = self.data.byte_len();
+        Error::check_length(expected, found)?;
+        if found > expected {
+            return Err(Error::Oversized { expected, found });
+        }
+        Ok(())
+    }
+}
+
+/// Value of samples, if they are all equal
+pub fn uniform_value<T: Sample>(samples: &[T]) -> Option<T> {
+    let first = *samples.first()?;
+    let value: f32 = first.into();
+    samples
+        .iter()
+        .all(|&s| Into::<f32>::into(s) == value)
+        .then_some(first)
+}
+
+/// Block volume storing only blocks which are not uniform, see [`SparseBlocks`].
+/// Default overlap == 1
+pub struct SparseBlockVolume<T = u8> {
+    block_side: usize,
+    bound_box: BoundBox,
+    data_size: Vector3<usize>,
+    scale: Vector3<f32>,
+    pub empty_blocks: Vec<bool>,
+    block_size: Vector3<usize>, // Number of blocks in structure
+    _data_owner: DataSource<T>,
+    blocks: Vec<Block<T>>,
+    stored_blocks: usize,
+    value_range: ValueRange,
+    tf: TF,
+}
+
+unsafe impl<T: Sample> Sync for SparseBlockVolume<T> {}
+
+impl<T: Sample> SparseBlockVolume<T> {
+    // returns (block index, position in block)
+    fn get_indexes(&self, x: usize, y: usize, z: usize) -> (usize, Point3<usize>) {
+        let jump_per_block = self.block_side - 1; // implicit block overlap of 1
+        let offset = point![x % jump_per_block, y % jump_per_block, z % jump_per_block];
+        let block_index = (z / jump_per_block)
+            + (y / jump_per_block) * self.block_size.z
+            + (x / jump_per_block) * self.block_size.y * self.block_size.z;
+        (block_index, offset)
+    }
+
+    /// Number of blocks with samples in memory, the other ones are uniform
+    pub fn stored_block_count(&self) -> usize {
+        self.stored_blocks
+    }
+}
+
+impl<T: Sample> Blocked for SparseBlockVolume<T> {
+    type BlockType = Block<T>;
+
+    fn get_blocks(&self) -> &[Self::BlockType] {
+        &self.blocks
+    }
+
+    fn get_empty_blocks(&self) -> &[bool] {
+        &self.empty_blocks
+    }
+}
+
+impl<T: Sample> Volume for SparseBlockVolume<T> {
+    fn get_size(&self) -> Vector3<usize> {
+        self.data_size
+    }
+
+    fn sample_at(&self, pos: Point3<f32>) -> f32 {
+        let (block_index, offset) =
+            self.get_indexes(pos.x as usize, pos.y as usize, pos.z as usize);
+        let fract = pos.coords.map(f32::fract);
+        self.blocks[block_index].sample_at(offset.cast::<f32>() + fract)
+    }
+
+    fn get_data(&self, x: usize, y: usize, z: usize) -> Option<f32> {
+        let (block_index, offset) = self.get_indexes(x, y, z);
+        self.blocks
+            .get(block_index)?
+            .get_data(offset.x, offset.y, offset.z)
+    }
+
+    fn get_tf(&self) -> &TF {
+        &self.tf
+    }
+
+    fn get_bound_box(&self) -> BoundBox {
+        self.bound_box
+    }
+
+    fn get_value_range(&self) -> ValueRange {
+        self.value_range
+    }
+
+    fn get_scale(&self) -> Vector3<f32> {
+        self.scale
+    }
+
+    fn set_tf(&mut self, tf: TF) {
+        self.tf = tf;
+        self.empty_blocks = BlockVolume::build_empty(&self.blocks, &*self.tf);
+    }
+
+    fn get_name() -> &'static str {
+        "SparseBlockVolume"
+    }
+
+    fn is_empty(&self, _: Point3<f32>) -> bool {
+        false
+    }
+
+    fn build_empty_index(&mut self) {
+        // noop
+    }
+}
+
+impl<T: Sample> BuildVolume<T> for SparseBlockVolume<T> {
+    fn build(mut metadata: VolumeMetadata<T>) -> Result<SparseBlockVolume<T>, Error> {
+        let sparse = metadata
+            .sparse_blocks
+            .take()
+            .ok_or(Error::Missing("sparse blocks"))?;
+        let position = metadata.position.unwrap_or_else(|| point![0.0, 0.0, 0.0]);
+        let orientation = metadata.orientation()?;
+        let size = metadata.size.ok_or(Error::Missing("size"))?;
+        let scale = metadata.scale.ok_or(Error::Missing("scale"))?;
+        let tf = metadata.tf.ok_or(Error::Missing("transfer function"))?;
+        let rescale = metadata.rescale.unwrap_or_default();
+
+        let block_side = match metadata.data_shape {
+            Some(StorageShape::Z(s)) if s >= 2 => s as usize,
+            _ => {
+                return Err(Error::Shape(
+                    "Sparse block volume needs data stored in Z order",
+                ))
+            }
+        };
+        let block_len = block_side.pow(3);
+        let block_size = blockify(size, block_side, 1);
+        sparse.validate(block_size.product(), block_len)?;
+
+        let vol_dims = (size - vector![1, 1, 1]) // side length is n-1 times the point
+            .cast::<f32>()
+            .component_mul(&scale);
+        let bound_box = BoundBox::from_position_dims(position, vol_dims).oriented(orientation);
+
+        let step_size = block_side - 1;
+        let base_ptr = sparse.data.as_ptr();
+
+        let mut blocks = Vec::with_capacity(sparse.table.len());
+        for x in 0..block_size.x {
+            for y in 0..block_size.y {
+                for z in 0..block_size.z {
+                    let block_start = step_size * point![x, y, z];
+                    let block_bound_box = get_bound_box(position, scale, block_start, block_side)
+                        .oriented(orientation);
+                    let block = match sparse.table[blocks.len()] {
+                        SparseBlock::Uniform(value) => {
+                            Block::uniform(block_side, block_bound_box, scale, value, rescale)
+                        }
+                        SparseBlock::Stored(i) => unsafe {
+                            Block::new(
+                                block_side,
+                                block_bound_box,
+                                scale,
+                                base_ptr.add(i * block_len),
+                                rescale,
+                                &*tf,
+                            )
+                        },
+                    };
+                    blocks.push(block);
+                }
+            }
+        }
+
+        let empty_blocks = BlockVolume::build_empty(&blocks, &*tf);
+
+        let mut value_range = ValueRange::empty();
+        for block in &blocks {
+            value_range.union(&block.value_range);
+        }
+
+        let stored_blocks = sparse.stored_count();
+        println!(
+            "Built {} blocks of dims {} ({},{},{}) blocks ({},{},{}) stored {}",
+            blocks.len(),
+            block_side,
+            size.x,
+            size.y,
+            size.z,
+            block_size.x,
+            block_size.y,
+            block_size.z,
+            stored_blocks
+        );
+
+        Ok(SparseBlockVolume {
+            block_side,
+            bound_box,
+            data_size: size,
+            scale,
+            empty_blocks,
+            block_size,
+            _data_owner: sparse.data,
+            blocks,
+            stored_blocks,
+            value_range,
+            tf,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::{
+        test_helpers::empty_vol_meta,
+        volumetric::{block_volume::get_block_data, MemoryType},
+    };
+
+    // Volume 5x5x5 in blocks of side 3, zero except for the last block
+    fn volume_data() -> (Vec<u8>, Vec<u8>) {
+        let size = vector![5, 5, 5];
+        let linear: Vec<u8> = (0..125)
+            .map(|i| {
+                if i / 25 > 2 && i / 5 % 5 > 2 && i % 5 > 2 {
+                    i as u8
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        let mut blocked = vec![];
+        for x in 0..2 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    blocked.extend(get_block_data(&linear, size, point![x, y, z] * 2, 3));
+                }
+            }
+        }
+        (linear, blocked)
+    }
+
+    #[test]
+    fn samples_match_block_volume() {
+        let (linear, blocked) = volume_data();
+        let sparse = SparseBlocks::from_blocks(&blocked, 27);
+        assert_eq!(sparse.stored_count(), 1);
+        assert_eq!(sparse.table[0], SparseBlock::Uniform(0));
+        assert_eq!(sparse.table[7], SparseBlock::Stored(0));
+
+        let mut meta = empty_vol_meta::<u8>(vector![5, 5, 5]);
+        meta.set_data_shape(StorageShape::Z(3))
+            .set_sparse_blocks(sparse);
+        let volume = SparseBlockVolume::build(meta).unwrap();
+        assert_eq!(volume.stored_block_count(), 1);
+
+        let mut meta = empty_vol_meta::<u8>(vector![5, 5, 5]);
+        meta.set_data(DataSource::Vec(linear))
+            .set_desired_data_shape(StorageShape::Z(3))
+            .set_memory_type(MemoryType::Ram);
+        let reference = BlockVolume::build(meta).unwrap();
+
+        for pos in [
+            point![0.5, 0.5, 0.5],
+            point![1.5, 2.5, 0.5],
+            point![3.2, 3.7, 3.9],
+            point![2.5, 3.5, 3.5],
+        ] {
+            assert_eq!(volume.sample_at(pos), reference.sample_at(pos));
+        }
+        assert_eq!(volume.get_data(3, 3, 3), Some(93.0));
+        assert_eq!(volume.get_value_range(), reference.get_value_range());
+        assert_eq!(volume.get_empty_blocks(), reference.get_empty_blocks());
+    }
+
+    #[test]
+    fn uniform_block_without_data() {
+        let bound_box = BoundBox::from_position_dims(point![0.0, 0.0, 0.0], vector![2.0, 2.0, 2.0]);
+        let block =
+            Block::<u16>::uniform(3, bound_box, vector![1.0, 1.0, 1.0], 7, Default::default());
+        assert!(block.data.is_null());
+        assert_eq!(block.sample_at(point![1.5, 0.2, 1.9]), 7.0);
+        assert_eq!(block.get_data(2, 2, 2), Some(7.0));
+        assert_eq!(block.get_data(3, 0, 0), None);
+        assert_eq!(block.value_range, (7.0..7.0).into());
+    }
+
+    #[test]
+    fn uniform_block_in_block_volume() {
+        let (linear, _) = volume_data();
+        let mut meta = empty_vol_meta::<u8>(vector![5, 5, 5]);
+        meta.set_data(DataSource::Vec(linear))
+            .set_desired_data_shape(StorageShape::Z(3))
+            .set_memory_type(MemoryType::Ram);
+        let mut volume = BlockVolume::build(meta).unwrap();
+
+        let first = &volume.data[0];
+        volume.data[0] =
+            Block::uniform(3, first.bound_box, vector![1.0, 1.0, 1.0], 5, first.rescale);
+
+        assert_eq!(volume.get_data(1, 1, 1), Some(5.0));
+        assert_eq!(volume.sample_at(point![0.5, 1.5, 0.2]), 5.0);
+    }
+
+    #[test]
+    fn validate_table() {
+        let mut meta = empty_vol_meta::<u8>(vector![3, 3, 3]);
+        meta.set_data_shape(StorageShape::Z(3))
+            .set_sparse_blocks(SparseBlocks::new(
+                DataSource::Vec(vec![0; 26]),
+                vec![SparseBlock::Stored(0)],
+            ));
+        assert!(matches!(
+            SparseBlockVolume::build(meta),
+            Err(Error::Truncated { .. })
+        ));
+
+        let sparse = SparseBlocks::<u8>::new(DataSource::Vec(vec![]), vec![SparseBlock::Stored(1)]);
+        assert!(matches!(sparse.validate(1, 27), Err(Error::Invalid(_))));
+        assert!(matches!(sparse.validate(2, 27), Err(Error::Shape(_))));
+
+        let mut meta = empty_vol_meta::<u8>(vector![3, 3, 3]);
+        meta.set_data_shape(StorageShape::Z(3))
+            .set_sparse_blocks(SparseBlocks::new(
+                DataSource::Vec(vec![]),
+                vec![SparseBlock::Uniform(0)],
+            ));
+        assert!(matches!(
+            BlockVolume::build(meta),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
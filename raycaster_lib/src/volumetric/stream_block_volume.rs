@@ -0,0 +1,449 @@
+/*
+    raycaster_lib
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Out-of-core block volumes.
+//!
+//! Blocks are loaded on demand into a [`BlockCache`] of bounded size,
+//! either by positioned reads from file or by decompression of compressed blocks.
+//! Renderer announces blocks it is going to render with [`Blocked::prefetch`],
+//! they are loaded ahead by a background thread.
+
+use std::sync::Arc;
+
+use nalgebra::{point, vector, Point3, Vector3};
+
+use crate::{
+    common::{blockify, BoundBox, ValueRange},
+    volumetric::StorageShape,
+    Error, TF,
+};
+
+use super::{
+    block_cache::{BlockCache, BlockSource, CacheStats, FileBlocks, Prefetcher},
+    block_volume::{Block, BlockVolume},
+    compression::CompressedBlocks,
+    float_block_volume::get_bound_box,
+    vol_builder::{BuildVolume, VolumeMetadata},
+    volume::Blocked,
+    Sample, Volume,
+};
+
+/// Memory of block cache, if not set in metadata
+pub const DEFAULT_CACHE_BUDGET: usize = 256 * 1024 * 1024;
+
+/// Block volume loading blocks from source `S` on demand, by default read from file.
+/// Default overlap == 1
+///
+/// Only blocks in cache are kept in memory, the budget is set by [`VolumeMetadata::set_cache_budget`].
+/// Blocks returned by [`Blocked::get_blocks`] hold bounds and value ranges only,
+/// samples are reached through [`Blocked::with_block`].
+pub struct StreamBlockVolume<T = u8, S = FileBlocks> {
+    block_side: usize,
+    bound_box: BoundBox,
+    data_size: Vector3<usize>,
+    scale: Vector3<f32>,
+    pub empty_blocks: Vec<bool>,
+    block_size: Vector3<usize>, // Number of blocks in structure
+    blocks: Vec<Block<T>>,
+    cache: Arc<BlockCache<T, S>>,
+    prefetcher: Prefetcher,
+    value_range: ValueRange,
+    tf: TF,
+}
+
+/// Block volume decompressing blocks on first touch, see [`CompressedBlocks`]
+pub type CompressedBlockVolume<T = u8> = StreamBlockVolume<T, CompressedBlocks>;
+
+unsafe impl<T: Sample, S: BlockSource<T>> Sync for StreamBlockVolume<T, S> {}
+
+impl<T: Sample, S: BlockSource<T> + 'static> StreamBlockVolume<T, S> {
+    // returns (block index, position in block)
+    fn get_indexes(&self, x: usize, y: usize, z: usize) -> (usize, Point3<usize>) {
+        let jump_per_block = self.block_side - 1; // implicit block overlap of 1
+        let offset = point![x % jump_per_block, y % jump_per_block, z % jump_per_block];
+        let block_index = (z / jump_per_block)
+            + (y / jump_per_block) * self.block_size.z
+            + (x / jump_per_block) * self.block_size.y * self.block_size.z;
+        (block_index, offset)
+    }
+
+    /// Counters of block cache
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+}
+
+impl<T: Sample, S: BlockSource<T> + 'static> Blocked for StreamBlockVolume<T, S> {
+    type BlockType = Block<T>;
+
+    fn get_blocks(&self) -> &[Self::BlockType] {
+        &self.blocks
+    }
+
+    fn get_empty_blocks(&self) -> &[bool] {
+        &self.empty_blocks
+    }
+
+    fn with_block<R>(
+        &self,
+        _: u8,
+        block_id: u32,
+        f: impl FnOnce(&Self::BlockType) -> R,
+    ) -> Result<R, Error> {
+        let data = self.cache.get(block_id)?;
+        // Safety: `data` lives until the end of function, longer than the copy
+        let block = unsafe { self.blocks[block_id as usize].with_data(data.as_ptr()) };
+        Ok(f(&block))
+    }
+
+    fn prefetch(&self, order: &[(u32, u8)]) {
+        // Blocks past the capacity would evict the first ones before they are rendered
+        let order = order
+            .iter()
+            .take(self.cache.capacity())
+            .map(|&(block_id, _)| block_id)
+            .collect();
+        self.prefetcher.request(order);
+    }
+}
+
+impl<T: Sample, S: BlockSource<T> + 'static> Volume for StreamBlockVolume<T, S> {
+    fn get_size(&self) -> Vector3<usize> {
+        self.data_size
+    }
+
+    /// Samples of blocks failing to load are 0, failures are counted in [`CacheStats::errors`]
+    fn sample_at(&self, pos: Point3<f32>) -> f32 {
+        let (block_index, offset) =
+            self.get_indexes(pos.x as usize, pos.y as usize, pos.z as usize);
+        let fract = pos.coords.map(f32::fract);
+        self.with_block(0, block_index as u32, |block| {
+            block.sample_at(offset.cast::<f32>() + fract)
+        })
+        .unwrap_or(0.0)
+    }
+
+    fn get_data(&self, x: usize, y: usize, z: usize) -> Option<f32> {
+        let (block_index, offset) = self.get_indexes(x, y, z);
+        if block_index >= self.blocks.len() {
+            return None;
+        }
+        self.with_block(0, block_index as u32, |block| {
+            block.get_data(offset.x, offset.y, offset.z)
+        })
+        .ok()
+        .flatten()
+    }
+
+    fn get_tf(&self) -> &TF {
+        &self.tf
+    }
+
+    fn get_bound_box(&self) -> BoundBox {
+        self.bound_box
+    }
+
+    fn get_value_range(&self) -> ValueRange {
+        self.value_range
+    }
+
+    fn get_scale(&self) -> Vector3<f32> {
+        self.scale
+    }
+
+    fn set_tf(&mut self, tf: TF) {
+        self.tf = tf;
+        self.empty_blocks = BlockVolume::build_empty(&self.blocks, &*self.tf);
+    }
+
+    fn get_name() -> &'static str {
+        S::VOLUME_NAME
+    }
+
+    fn is_empty(&self, _: Point3<f32>) -> bool {
+        false
+    }
+
+    fn build_empty_index(&mut self) {
+        // noop
+    }
+}
+
+impl<T: Sample, S: BlockSource<T> + 'static> StreamBlockVolume<T, S> {
+    /// Build volume over blocks of `source`.
+    /// Every block is loaded once, to get its value range.
+    fn from_source(
+        metadata: VolumeMetadata<T>,
+        block_side: usize,
+        source: S,
+    ) -> Result<StreamBlockVolume<T, S>, Error> {
+        let position = metadata.position.unwrap_or_else(|| point![0.0, 0.0, 0.0]);
+        let orientation = metadata.orientation()?;
+        let size = metadata.size.ok_or(Error::Missing("size"))?;
+        let scale = metadata.scale.ok_or(Error::Missing("scale"))?;
+        let tf = metadata.tf.ok_or(Error::Missing("transfer function"))?;
+        let rescale = metadata.rescale.unwrap_or_default();
+        let budget = metadata.cache_budget.unwrap_or(DEFAULT_CACHE_BUDGET);
+
+        let vol_dims = (size - vector![1, 1, 1]) // side length is n-1 times the point
+            .cast::<f32>()
+            .component_mul(&scale);
+        let bound_box = BoundBox::from_position_dims(position, vol_dims).oriented(orientation);
+
+        let step_size = block_side - 1;
+        let block_size = blockify(size, block_side, 1);
+        let block_count = block_size.product();
+
+        let cache = Arc::new(BlockCache::new(source, block_side.pow(3), budget)?);
+
+        let mut blocks = Vec::with_capacity(block_count);
+        for x in 0..block_size.x {
+            for y in 0..block_size.y {
+                for z in 0..block_size.z {
+                    let block_start = step_size * point![x, y, z];
+                    let block_bound_box = get_bound_box(position, scale, block_start, block_side)
+                        .oriented(orientation);
+                    let samples = cache.read_block(blocks.len() as u32)?;
+                    let mut block = unsafe {
+                        Block::new(
+                            block_side,
+                            block_bound_box,
+                            scale,
+                            samples.as_ptr(),
+                            rescale,
+                            &*tf,
+                        )
+                    };
+                    block.data = std::ptr::null();
+                    blocks.push(block);
+                }
+            }
+        }
+
+        let empty_blocks = BlockVolume::build_empty(&blocks, &*tf);
+
+        let mut value_range = ValueRange::empty();
+        for block in &blocks {
+            value_range.union(&block.value_range);
+        }
+
+        println!(
+            "Built {} blocks of dims {} ({},{},{}) blocks ({},{},{}) cache {} blocks",
+            block_count,
+            block_side,
+            size.x,
+            size.y,
+            size.z,
+            block_size.x,
+            block_size.y,
+            block_size.z,
+            cache.capacity()
+        );
+
+        let prefetcher = Prefetcher::spawn(cache.clone());
+
+        Ok(StreamBlockVolume {
+            block_side,
+            bound_box,
+            data_size: size,
+            scale,
+            empty_blocks,
+            block_size,
+            blocks,
+            cache,
+            prefetcher,
+            value_range,
+            tf,
+        })
+    }
+}
+
+/// Side of Z order blocks of data
+fn z_block_side<T>(metadata: &VolumeMetadata<T>) -> Result<usize, Error> {
+    match metadata.data_shape {
+        Some(StorageShape::Z(s)) if s >= 2 => Ok(s as usize),
+        _ => Err(Error::Shape(
+            "Stream block volume needs data stored in Z order",
+        )),
+    }
+}
+
+impl<T: Sample> BuildVolume<T> for StreamBlockVolume<T> {
+    fn build(mut metadata: VolumeMetadata<T>) -> Result<StreamBlockVolume<T>, Error> {
+        metadata.validate_data()?;
+        let block_side = z_block_side(&metadata)?;
+        let size = metadata.size.ok_or(Error::Missing("size"))?;
+        let block_count = blockify(size, block_side, 1).product();
+
+        // Mapped data is only used to find the file, it is unmapped before build
+        let data = metadata.data.take().ok_or(Error::Missing("data"))?;
+        let (path, data_offset) = data.file_region().ok_or(Error::Unsupported(
+            "Streaming of data not mapped from a file",
+        ))?;
+        let source = FileBlocks::open(path, data_offset, block_count)?;
+        drop(data);
+
+        StreamBlockVolume::from_source(metadata, block_side, source)
+    }
+}
+
+impl<T: Sample> BuildVolume<T> for CompressedBlockVolume<T> {
+    fn build(mut metadata: VolumeMetadata<T>) -> Result<CompressedBlockVolume<T>, Error> {
+        let compressed = metadata
+            .compressed_blocks
+            .take()
+            .ok_or(Error::Missing("compressed blocks"))?;
+        let block_side = z_block_side(&metadata)?;
+        let size = metadata.size.ok_or(Error::Missing("size"))?;
+        compressed.validate(blockify(size, block_side, 1).product())?;
+
+        StreamBlockVolume::from_source(metadata, block_side, compressed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::{
+        test_helpers::{empty_vol_meta, TempFile},
+        volumetric::{block_volume::get_block_data, compress_block, DataSource, MemoryType},
+    };
+
+    // Volume 5x5x5 in blocks of side 3, after 4 byte header
+    fn block_file(name: &str) -> (TempFile, Vec<u8>) {
+        let size = vector![5, 5, 5];
+        let linear: Vec<u8> = (0..125).map(|v| (v * 2) as u8).collect();
+
+        let mut bytes = vec![0; 4];
+        for x in 0..2 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    let start = point![x, y, z] * 2;
+                    bytes.extend(get_block_data(&linear, size, start, 3));
+                }
+            }
+        }
+
+        (TempFile::new(&format!("stream_{}", name), &bytes), linear)
+    }
+
+    fn stream_meta(file: &TempFile, budget: usize) -> VolumeMetadata<u8> {
+        let mut meta = empty_vol_meta::<u8>(vector![5, 5, 5]);
+        meta.set_data(DataSource::from_file(&file.0).unwrap().clone_with_offset(4))
+            .set_data_shape(StorageShape::Z(3))
+            .set_cache_budget(budget);
+        meta
+    }
+
+    #[test]
+    fn samples_match_block_volume() {
+        let (file, linear) = block_file("samples");
+        let volume = StreamBlockVolume::<u8>::build(stream_meta(&file, 2 * 27)).unwrap();
+
+        let mut meta = empty_vol_meta::<u8>(vector![5, 5, 5]);
+        meta.set_data(DataSource::Vec(linear))
+            .set_desired_data_shape(StorageShape::Z(3))
+            .set_memory_type(MemoryType::Ram);
+        let reference = BlockVolume::build(meta).unwrap();
+
+        for pos in [
+            point![0.0, 0.0, 0.0],
+            point![1.5, 2.5, 0.5],
+            point![3.2, 0.7, 3.9],
+            point![2.0, 3.5, 2.0],
+            point![3.5, 3.5, 3.5],
+        ] {
+            assert!((volume.sample_at(pos) - reference.sample_at(pos)).abs() < 0.001);
+        }
+        assert_eq!(volume.get_data(3, 1, 2), Some(164.0));
+        assert_eq!(volume.get_value_range().high, 248.0);
+
+        let stats = volume.cache_stats();
+        assert!(stats.hits > 0);
+        assert!(stats.evictions > 0);
+        assert_eq!(stats.misses, stats.evictions + 2);
+    }
+
+    #[test]
+    fn compressed_samples_match() {
+        let (file, _) = block_file("compressed");
+        let stream = StreamBlockVolume::<u8>::build(stream_meta(&file, 4 * 27)).unwrap();
+
+        let bytes = std::fs::read(&file.0).unwrap();
+        let mut compressed = vec![];
+        let mut offsets = vec![0];
+        for block in bytes[4..].chunks(27) {
+            compressed.extend(compress_block(block));
+            offsets.push(compressed.len());
+        }
+        let blocks = CompressedBlocks::new(DataSource::Vec(compressed), offsets);
+
+        let mut meta = empty_vol_meta::<u8>(vector![5, 5, 5]);
+        meta.set_data_shape(StorageShape::Z(3))
+            .set_compressed_blocks(blocks)
+            .set_cache_budget(4 * 27);
+        let volume = CompressedBlockVolume::build(meta).unwrap();
+
+        assert_eq!(
+            CompressedBlockVolume::<u8>::get_name(),
+            "CompressedBlockVolume"
+        );
+        assert_eq!(volume.get_empty_blocks(), stream.get_empty_blocks());
+        for pos in [point![0.5, 0.5, 0.5], point![3.2, 1.7, 3.9]] {
+            assert_eq!(volume.sample_at(pos), stream.sample_at(pos));
+        }
+        assert_eq!(volume.cache_stats().misses, 2);
+    }
+
+    #[test]
+    fn compressed_need_compressed_volume() {
+        let mut meta = empty_vol_meta::<u8>(vector![3, 3, 3]);
+        meta.set_data_shape(StorageShape::Z(3))
+            .set_compressed_blocks(CompressedBlocks::new(DataSource::Vec(vec![]), vec![0, 0]));
+        assert!(matches!(
+            BlockVolume::build(meta),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn read_error() {
+        let (file, _) = block_file("error");
+        let volume = StreamBlockVolume::<u8>::build(stream_meta(&file, 27)).unwrap();
+        // Blocks are not resident after build
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&file.0)
+            .unwrap()
+            .set_len(4)
+            .unwrap();
+
+        assert!(matches!(
+            volume.with_block(0, 0, |_| ()),
+            Err(Error::Io { .. })
+        ));
+        assert_eq!(volume.sample_at(point![0.5, 0.5, 0.5]), 0.0);
+        assert_eq!(volume.get_data(0, 0, 0), None);
+        assert_eq!(volume.cache_stats().errors, 3);
+    }
+
+    #[test]
+    fn needs_file() {
+        let mut meta = empty_vol_meta::<u8>(vector![3, 3, 3]);
+        meta.set_data_shape(StorageShape::Z(3));
+        assert!(matches!(
+            StreamBlockVolume::<u8>::build(meta),
+            Err(Error::Unsupported(_))
+        ));
+
+        let (file, _) = block_file("budget");
+        assert!(matches!(
+            StreamBlockVolume::<u8>::build(stream_meta(&file, 26)),
+            Err(Error::Invalid(_))
+        ));
+    }
+}
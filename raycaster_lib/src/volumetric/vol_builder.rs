@@ -4,12 +4,24 @@
     Date: 2022-05-05
 */
 
-use std::{fs::File, mem::size_of, path::Path};
-
-use crate::TF;
+use std::{
+    fs::File,
+    io,
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    common::{blockify, Orientation, ValueRange},
+    Error, TF,
+};
+
+use super::{
+    compression::CompressedBlocks, lod_volume::lod_block_len, sparse_block_volume::SparseBlocks,
+};
 
 use memmap::{Mmap, MmapOptions};
-use nalgebra::{Point3, Vector3};
+use nalgebra::{Matrix4, Point3, Vector3};
 
 // Build Volume this trait is defined on from the metadata object
 // T is the type of sample
@@ -17,7 +29,7 @@ pub trait BuildVolume<T>
 where
     Self: Sized,
 {
-    fn build(metadata: VolumeMetadata<T>) -> Result<Self, &'static str>;
+    fn build(metadata: VolumeMetadata<T>) -> Result<Self, Error>;
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -26,12 +38,79 @@ pub enum MemoryType {
     Ram,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StorageShape {
     Linear,
     Z(u8),
 }
 
+impl StorageShape {
+    /// Number of samples storing volume of `size`.
+    /// Z order is padded to whole blocks, overlapping by one sample.
+    pub fn sample_count(&self, size: Vector3<usize>) -> Result<usize, Error> {
+        let overflow = Error::Shape("Size of volume overflows");
+        match *self {
+            StorageShape::Linear => size
+                .iter()
+                .try_fold(1_usize, |acc, &s| acc.checked_mul(s))
+                .ok_or(overflow),
+            StorageShape::Z(side) if side < 2 => Err(Error::Shape("Invalid block side")),
+            StorageShape::Z(side) => {
+                let side = side as usize;
+                let blocks = blockify(size.map(|s| s.max(1)), side, 1).map(|b| b.max(1));
+                blocks
+                    .iter()
+                    .chain(&[side, side, side])
+                    .try_fold(1_usize, |acc, &s| acc.checked_mul(s))
+                    .ok_or(overflow)
+            }
+        }
+    }
+}
+
+/// Arrangement of channels of multi-channel data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    /// Channels of one voxel are stored next to each other
+    Interleaved,
+    /// Channels are stored one after another, each as a whole volume
+    Planar,
+}
+
+/// Linear mapping of stored samples to values, `value = slope * sample + intercept`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rescale {
+    pub slope: f32,
+    pub intercept: f32,
+}
+
+impl Rescale {
+    pub fn new(slope: f32, intercept: f32) -> Rescale {
+        Rescale { slope, intercept }
+    }
+
+    #[inline]
+    pub fn apply(&self, sample: f32) -> f32 {
+        sample * self.slope + self.intercept
+    }
+
+    /// Rescale range of samples.
+    /// Bounds are swapped for negative slope.
+    pub fn apply_range(&self, range: ValueRange) -> ValueRange {
+        let mut rescaled = ValueRange::empty();
+        rescaled.union(&ValueRange::seed(self.apply(range.low)));
+        rescaled.union(&ValueRange::seed(self.apply(range.high)));
+        rescaled
+    }
+}
+
+/// Identity
+impl Default for Rescale {
+    fn default() -> Self {
+        Rescale::new(1.0, 0.0)
+    }
+}
+
 #[derive(Default)]
 pub struct VolumeMetadata<T> {
     // Shape
@@ -44,6 +123,20 @@ pub struct VolumeMetadata<T> {
     pub data_shape: Option<StorageShape>,
     pub desired_data_shape: Option<StorageShape>,
     pub tf: Option<TF>, // Transfer function
+    pub rescale: Option<Rescale>,
+    // Channels
+    pub channels: Option<usize>,
+    pub channel_layout: Option<ChannelLayout>,
+    // Levels of detail stored after full resolution blocks
+    pub lod_levels: Option<u8>,
+    // Memory of block cache in bytes, used by volumes reading blocks on demand
+    pub cache_budget: Option<usize>,
+    // Blocks compressed independently, instead of `data`
+    pub compressed_blocks: Option<CompressedBlocks>,
+    // Stored blocks and table of uniform blocks, instead of `data`
+    pub sparse_blocks: Option<SparseBlocks<T>>,
+    // Affine transformation of voxel coordinates into world space
+    pub world_transform: Option<Matrix4<f32>>,
 }
 
 impl<T> VolumeMetadata<T> {
@@ -80,6 +173,53 @@ impl<T> VolumeMetadata<T> {
         self
     }
 
+    pub fn set_rescale(&mut self, rescale: Rescale) -> &mut Self {
+        self.rescale = Some(rescale);
+        self
+    }
+
+    /// Number of channels per voxel
+    pub fn set_channels(&mut self, channels: usize) -> &mut Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    pub fn set_channel_layout(&mut self, channel_layout: ChannelLayout) -> &mut Self {
+        self.channel_layout = Some(channel_layout);
+        self
+    }
+
+    /// Number of levels of detail in data, see [`LodVolume`](super::volumes::LodVolume)
+    pub fn set_lod_levels(&mut self, lod_levels: u8) -> &mut Self {
+        self.lod_levels = Some(lod_levels);
+        self
+    }
+
+    /// Memory budget of block cache in bytes, see [`StreamBlockVolume`](super::volumes::StreamBlockVolume)
+    pub fn set_cache_budget(&mut self, cache_budget: usize) -> &mut Self {
+        self.cache_budget = Some(cache_budget);
+        self
+    }
+
+    /// Compressed blocks, see [`CompressedBlockVolume`](super::volumes::CompressedBlockVolume)
+    pub fn set_compressed_blocks(&mut self, compressed_blocks: CompressedBlocks) -> &mut Self {
+        self.compressed_blocks = Some(compressed_blocks);
+        self
+    }
+
+    /// Sparse blocks, see [`SparseBlockVolume`](super::volumes::SparseBlockVolume)
+    pub fn set_sparse_blocks(&mut self, sparse_blocks: SparseBlocks<T>) -> &mut Self {
+        self.sparse_blocks = Some(sparse_blocks);
+        self
+    }
+
+    /// Place volume into world space by affine transformation of voxel coordinates.
+    /// Volume is then placed by this transformation only, position and scale still give its frame.
+    pub fn set_world_transform(&mut self, world_transform: Matrix4<f32>) -> &mut Self {
+        self.world_transform = Some(world_transform);
+        self
+    }
+
     pub fn set_memory_type(&mut self, memory_type: MemoryType) -> &mut Self {
         self.memory_type = Some(memory_type);
         self
@@ -96,22 +236,98 @@ impl<T> VolumeMetadata<T> {
         self.desired_data_shape = Some(desired_data_shape);
         self
     }
+
+    /// Orientation of bound box placing the volume by world transformation.
+    /// Returns `None` if volume has no world transformation and is axis-aligned.
+    pub fn orientation(&self) -> Result<Option<Orientation>, Error> {
+        let world_transform = match self.world_transform {
+            Some(world_transform) => world_transform,
+            None => return Ok(None),
+        };
+        let position = self.position.unwrap_or_else(Point3::origin);
+        let scale = self.scale.unwrap_or_else(|| Vector3::repeat(1.0));
+        // Bound box frame from voxel coordinates
+        let frame =
+            Matrix4::new_translation(&position.coords) * Matrix4::new_nonuniform_scaling(&scale);
+        let voxel_from_frame = frame
+            .try_inverse()
+            .ok_or(Error::Invalid("Scale of volume is zero"))?;
+        Orientation::new(world_transform * voxel_from_frame)
+            .map(Some)
+            .ok_or(Error::Invalid(
+                "World transformation is not an invertible affine transformation",
+            ))
+    }
+
+    /// Check that data holds exactly the samples of the volume.
+    /// Expected length is given by size, data shape (linear if not set),
+    /// levels of detail and number of channels.
+    /// Compressed and sparse blocks are rejected, they are checked on build of their volumes.
+    pub fn validate_data(&self) -> Result<(), Error> {
+        if self.compressed_blocks.is_some() {
+            return Err(Error::Unsupported(
+                "Compressed blocks need CompressedBlockVolume",
+            ));
+        }
+        if self.sparse_blocks.is_some() {
+            return Err(Error::Unsupported("Sparse blocks need SparseBlockVolume"));
+        }
+        let size = self.size.ok_or(Error::Missing("size"))?;
+        let data = self.data.as_ref().ok_or(Error::Missing("data"))?;
+        let data_shape = self.data_shape.unwrap_or(StorageShape::Linear);
+
+        let samples = match (data_shape, self.lod_levels.unwrap_or(1)) {
+            (_, 0 | 1) => data_shape.sample_count(size)?,
+            (StorageShape::Z(side), levels) => {
+                let blocks = data_shape.sample_count(size)? / (side as usize).pow(3);
+                blocks * lod_block_len(side as usize, levels)
+            }
+            (StorageShape::Linear, _) => {
+                return Err(Error::Shape("Levels of detail need Z order storage shape"))
+            }
+        };
+        let expected = samples
+            .checked_mul(self.channels.unwrap_or(1) * size_of::<T>())
+            .ok_or(Error::Shape("Size of volume overflows"))?;
+        let found = data.byte_len();
+
+        Error::check_length(expected, found)?;
+        if found > expected {
+            return Err(Error::Oversized { expected, found });
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub struct TypedMmap {
     mmap: Mmap,
     offset: usize,
+    end: usize,
+    /// Mapped file, if known
+    path: Option<PathBuf>,
 }
 
 impl TypedMmap {
     pub fn from_map(mmap: Mmap) -> TypedMmap {
-        TypedMmap { mmap, offset: 0 }
+        let end = mmap.len();
+        TypedMmap {
+            mmap,
+            offset: 0,
+            end,
+            path: None,
+        }
     }
 
+    /// Path of mapped file and offset of data in it, in bytes
+    pub fn file_region(&self) -> Option<(&Path, u64)> {
+        self.path.as_deref().map(|path| (path, self.offset as u64))
+    }
+
+    /// Offset is in bytes
     pub fn as_ptr<T>(&self) -> *const T {
-        let ptr = self.mmap.as_ptr() as *const T;
-        unsafe { ptr.add(self.offset) }
+        let ptr = unsafe { self.mmap.as_ptr().add(self.offset) };
+        ptr as *const T
     }
 
     pub fn into_inner(self) -> (Mmap, usize) {
@@ -122,31 +338,28 @@ impl TypedMmap {
         self.offset = offset;
     }
 
+    /// Keep at most `length` bytes after offset
+    pub fn truncate(&mut self, length: usize) {
+        self.end = self.end.min(self.offset.saturating_add(length));
+    }
+
     pub fn get_raw(&self) -> &[u8] {
-        &self.mmap[self.offset..]
+        &self.mmap[self.offset..self.end]
     }
 
     pub fn get_all<T>(&self) -> &[T] {
-        let s = &self.mmap[self.offset..];
+        let s = self.get_raw();
         let slice =
             unsafe { std::slice::from_raw_parts(s.as_ptr() as *const T, s.len() / size_of::<T>()) };
         slice
     }
 
     pub fn get<T: Copy>(&self, index: usize) -> T {
-        let s = &self.mmap[self.offset..];
-        let index = index * size_of::<T>();
-        let slice =
-            unsafe { std::slice::from_raw_parts(s.as_ptr() as *const T, s.len() / size_of::<T>()) };
-        slice[index]
+        self.get_all::<T>()[index]
     }
 
     pub fn get_ref<T>(&self, index: usize) -> &T {
-        let s = &self.mmap[self.offset..];
-        let index = index * size_of::<T>();
-        let slice =
-            unsafe { std::slice::from_raw_parts(s.as_ptr() as *const T, s.len() / size_of::<T>()) };
-        &slice[index]
+        &self.get_all::<T>()[index]
     }
 }
 
@@ -156,6 +369,33 @@ pub enum DataSource<T> {
     Mmap(TypedMmap),
 }
 
+impl<T> DataSource<T> {
+    /// Keep at most `length` elements
+    pub fn truncate(&mut self, length: usize) {
+        match self {
+            DataSource::Vec(v) => v.truncate(length),
+            DataSource::Mmap(m) => m.truncate(length * size_of::<T>()),
+        }
+    }
+
+    /// Length of data in bytes
+    pub fn byte_len(&self) -> usize {
+        match self {
+            DataSource::Vec(v) => std::mem::size_of_val(v.as_slice()),
+            DataSource::Mmap(m) => m.get_raw().len(),
+        }
+    }
+
+    /// File the data is mapped from and offset of data in it, in bytes.
+    /// `None` for data in memory.
+    pub fn file_region(&self) -> Option<(&Path, u64)> {
+        match self {
+            DataSource::Vec(_) => None,
+            DataSource::Mmap(m) => m.file_region(),
+        }
+    }
+}
+
 impl<T: Clone> DataSource<T> {
     pub fn into<U>(self) -> DataSource<U>
     where
@@ -187,7 +427,7 @@ impl<T: Clone> DataSource<T> {
                 DataSource::Vec(new_vec)
             }
             DataSource::Mmap(mut m) => {
-                m.offset += offset;
+                m.offset += offset * size_of::<T>();
                 DataSource::Mmap(m)
             }
         }
@@ -196,14 +436,18 @@ impl<T: Clone> DataSource<T> {
     pub fn into_transmute<U>(self) -> DataSource<U> {
         match self {
             DataSource::Vec(v) => {
-                let ptr = v.as_ptr();
-                let elements = v.len();
-                let allocated_elements = v.capacity();
-                //let (ptr, elements, allocated_elements) = v.into_raw_parts();
-                let growth = size_of::<T>() / size_of::<U>();
-                let new_length = elements * growth;
-                let new_allocated = allocated_elements * growth;
-                let new = unsafe { Vec::from_raw_parts(ptr as *mut U, new_length, new_allocated) };
+                // Copy, allocation of `T` may not be aligned for `U`
+                let bytes = std::mem::size_of_val(v.as_slice());
+                let new_length = bytes / size_of::<U>();
+                let mut new: Vec<U> = Vec::with_capacity(new_length);
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        v.as_ptr() as *const u8,
+                        new.as_mut_ptr() as *mut u8,
+                        new_length * size_of::<U>(),
+                    );
+                    new.set_len(new_length);
+                }
                 DataSource::Vec(new)
             }
             DataSource::Mmap(m) => DataSource::Mmap(m),
@@ -217,9 +461,7 @@ impl<T: Clone> DataSource<T> {
         };
 
         let ptr = slice.as_ptr();
-        let len = slice.len();
-        let growth = size_of::<T>() / size_of::<U>();
-        let new_length = len * growth;
+        let new_length = std::mem::size_of_val(slice) / size_of::<U>();
 
         unsafe { std::slice::from_raw_parts(ptr as *mut U, new_length) }
     }
@@ -256,30 +498,84 @@ impl<T: Clone> DataSource<T> {
         }
     }
 
-    pub fn from_file<P>(path: P) -> Result<DataSource<T>, &'static str>
+    pub fn from_file<P>(path: P) -> Result<DataSource<T>, Error>
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref();
 
         if !path.is_file() {
-            return Err("Path does not lead to a file");
+            let source =
+                io::Error::new(io::ErrorKind::InvalidInput, "Path does not lead to a file");
+            return Err(Error::io(path, source));
         }
 
-        let file = File::open(path);
-
-        let file = match file {
-            Ok(f) => f,
-            Err(_) => return Err("Cannot open file"),
-        };
+        let file = File::open(path).map_err(|e| Error::io(path, e))?;
 
         let mmap = unsafe { MmapOptions::new().map(&file) };
-        let mmap = match mmap {
-            Ok(mmap) => mmap,
-            Err(_) => return Err("Cannot create memory map"),
-        };
+        let mmap = mmap.map_err(|e| Error::io(path, e))?;
+
+        let mut typed_map = TypedMmap::from_map(mmap);
+        typed_map.path = Some(path.to_owned());
+        Ok(DataSource::Mmap(typed_map))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use nalgebra::vector;
+
+    #[test]
+    fn transmute_u16() {
+        let bytes: Vec<u8> = vec![0, 0, 1, 0, 0, 1, 255, 15];
+        let ds = DataSource::from_vec(bytes).clone_with_offset(2);
+
+        assert_eq!(ds.get_slice_transmute::<u16>(), &[1, 256, 4095]);
+
+        let ds: DataSource<u16> = ds.into_transmute();
+        assert_eq!(ds.get_slice(), &[1, 256, 4095]);
+    }
+
+    #[test]
+    fn sample_count() {
+        let size = vector![3, 5, 9];
+        assert_eq!(StorageShape::Linear.sample_count(size).unwrap(), 135);
+        // Blocks 1x2x4, padded to whole blocks
+        assert_eq!(StorageShape::Z(3).sample_count(size).unwrap(), 8 * 27);
+        assert!(StorageShape::Z(1).sample_count(size).is_err());
+        assert!(StorageShape::Linear
+            .sample_count(vector![usize::MAX, 2, 1])
+            .is_err());
+    }
 
-        let data_source = DataSource::from_mmap(mmap);
-        Ok(data_source)
+    #[test]
+    fn validate_data_length() {
+        let mut meta = VolumeMetadata::<u16>::default();
+        meta.set_size(vector![2, 2, 3])
+            .set_data(DataSource::Vec(vec![0; 12]));
+        assert!(meta.validate_data().is_ok());
+
+        meta.set_data(DataSource::Vec(vec![0; 11]));
+        assert!(matches!(
+            meta.validate_data(),
+            Err(Error::Truncated {
+                expected: 24,
+                found: 22
+            })
+        ));
+
+        meta.set_data(DataSource::Vec(vec![0; 13]));
+        assert!(matches!(meta.validate_data(), Err(Error::Oversized { .. })));
+
+        meta.set_data_shape(StorageShape::Z(2))
+            .set_data(DataSource::Vec(vec![0; 2 * 8]));
+        assert!(meta.validate_data().is_ok());
+
+        meta.set_data_shape(StorageShape::Linear)
+            .set_channels(2)
+            .set_data(DataSource::Vec(vec![0; 24]));
+        assert!(meta.validate_data().is_ok());
     }
 }
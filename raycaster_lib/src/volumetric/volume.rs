@@ -4,11 +4,46 @@
     Date: 2022-05-05
 */
 
-use crate::common::{BoundBox, Ray};
+use crate::common::{BoundBox, Ray, ValueRange};
 
-use crate::TF;
+use crate::{color::RGBA, Error, TF};
 use nalgebra::{point, vector, Matrix4, Point3, Vector3};
 
+/// Distance of samples used to compute gradient in [`Volume::sample_at_gradient`].
+pub const GRADIENT_STEP: f32 = 0.01;
+
+/// Type of samples stored in volume.
+///
+/// Volume types are generic over sample type, samples are converted to `f32` when sampled.
+pub trait Sample: Copy + Default + Into<f32> + Send + Sync + 'static {
+    /// Convert from `f32`, rounded and saturated to the range of the type
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Sample for u8 {
+    fn from_f32(value: f32) -> Self {
+        value.round() as u8
+    }
+}
+
+impl Sample for u16 {
+    fn from_f32(value: f32) -> Self {
+        value.round() as u16
+    }
+}
+
+impl Sample for i16 {
+    fn from_f32(value: f32) -> Self {
+        value.round() as i16
+    }
+}
+
+impl Sample for f32 {
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
 /// Interface for blocked volume types
 ///
 /// Used by multithreaded renderer
@@ -21,6 +56,36 @@ pub trait Blocked: Send + Sync {
 
     /// Getter for block visibility information
     fn get_empty_blocks(&self) -> &[bool];
+
+    /// Number of levels of detail, level 0 is full resolution
+    fn get_lod_count(&self) -> u8 {
+        1
+    }
+
+    /// Blocks at level of detail `level`.
+    /// Blocks of all levels cover the same regions as [`Blocked::get_blocks`], coarser levels have fewer samples.
+    fn get_lod_blocks(&self, level: u8) -> &[Self::BlockType] {
+        let _ = level;
+        self.get_blocks()
+    }
+
+    /// Run `f` on block `block_id` at level of detail `level`.
+    /// Volumes keeping blocks out of memory load the block first,
+    /// if loading fails the error is returned and `f` is not run.
+    fn with_block<R>(
+        &self,
+        level: u8,
+        block_id: u32,
+        f: impl FnOnce(&Self::BlockType) -> R,
+    ) -> Result<R, Error> {
+        Ok(f(&self.get_lod_blocks(level)[block_id as usize]))
+    }
+
+    /// Hint that blocks are going to be rendered in `order`.
+    /// Items are block id and level of detail.
+    fn prefetch(&self, order: &[(u32, u8)]) {
+        let _ = order;
+    }
 }
 
 /// Interface for all volume types
@@ -44,11 +109,13 @@ pub trait Volume: Send {
             .append_translation(&-lower_vec)
             .append_nonuniform_scaling(&scale_inv);
 
-        let obj_origin = ray.point_from_t(t0);
+        // Oriented volume is sampled in the frame of its bound box
+        let obj_origin = bbox.to_frame(&ray.point_from_t(t0));
 
         let origin = transform.transform_point(&obj_origin);
 
-        let direction = ray.direction.component_mul(&scale_inv);
+        let direction = bbox.direction_to_frame(&ray.direction);
+        let direction = direction.component_mul(&scale_inv);
         let direction = direction.normalize();
 
         let obj_ray = Ray::new(origin, direction);
@@ -63,7 +130,7 @@ pub trait Volume: Send {
     }
 
     /// Returns transfer function
-    fn get_tf(&self) -> TF;
+    fn get_tf(&self) -> &TF;
 
     /// Sets new transfer function
     /// If volume uses indexing, the indexes are rebuilt
@@ -79,11 +146,21 @@ pub trait Volume: Send {
     /// * `pos` - Sampling point in volume coordinates (grid coordinates)
     fn sample_at(&self, pos: Point3<f32>) -> f32;
 
+    /// Color of the volume at `pos`, if the volume classifies samples itself.
+    /// Returns `None` if samples are to be classified by volume's transfer function,
+    /// which is the case for all single channel volumes.
+    ///
+    /// Renderers call this for every sample, before classifying it.
+    fn classify(&self, pos: Point3<f32>) -> Option<RGBA> {
+        let _ = pos;
+        None
+    }
+
     /// Sample the volume at `pos` and sample surroundings to get gradient.
     fn sample_at_gradient(&self, pos: Point3<f32>) -> (f32, Vector3<f32>) {
         // Default implementation, can be replaced with a more effective one for concrete volume types
         let sample = self.sample_at(pos);
-        let grad_dir = 0.01;
+        let grad_dir = GRADIENT_STEP;
 
         // Samples are taken on higher coordinates, cap safe sample coord range
         let safe_size = self.get_size().map(|v| v as f32 - 1.01);
@@ -115,6 +192,9 @@ pub trait Volume: Send {
     /// Returns bounding box of volume
     fn get_bound_box(&self) -> BoundBox;
 
+    /// Returns range of sample values in volume
+    fn get_value_range(&self) -> ValueRange;
+
     /// Returns shape of voxels/cells
     fn get_scale(&self) -> Vector3<f32>;
 
@@ -4,6 +4,8 @@
     Date: 2022-05-05
 */
 
+use std::sync::Arc;
+
 use nalgebra::{Vector2, Vector3};
 use raycaster_lib::{
     premade::{
@@ -11,7 +13,7 @@ use raycaster_lib::{
         transfer_functions,
     },
     volumetric::{DataSource, VolumeMetadata},
-    TF,
+    Error, TF,
 };
 
 /// Types of camera movement
@@ -38,7 +40,7 @@ pub enum PrewrittenParser {
 impl PrewrittenParser {
     /// Mapping from enum variant to the actual parser
     /// Returns function pointer
-    pub fn get_parser_fn(&self) -> fn(DataSource<u8>) -> Result<VolumeMetadata<u8>, &'static str> {
+    pub fn get_parser_fn(&self) -> fn(DataSource<u8>) -> Result<VolumeMetadata<u8>, Error> {
         match self {
             PrewrittenParser::MyVolParser => generator_parser,
             PrewrittenParser::SkullParser => skull_parser,
@@ -65,13 +67,13 @@ pub enum PrewrittenTF {
 
 impl PrewrittenTF {
     /// Mapping from enum variant to the actual transfer function
-    /// Returns function pointer
+    /// Returns shared handle to transfer function
     pub fn get_tf(&self) -> TF {
         match self {
-            PrewrittenTF::Skull => transfer_functions::skull_tf,
-            PrewrittenTF::Gray => transfer_functions::anything_tf,
-            PrewrittenTF::White => transfer_functions::white_tf,
-            PrewrittenTF::Shapes => transfer_functions::shapes_tf,
+            PrewrittenTF::Skull => Arc::new(transfer_functions::skull_tf),
+            PrewrittenTF::Gray => Arc::new(transfer_functions::anything_tf),
+            PrewrittenTF::White => Arc::new(transfer_functions::white_tf),
+            PrewrittenTF::Shapes => Arc::new(transfer_functions::shapes_tf),
         }
     }
 
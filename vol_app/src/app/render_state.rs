@@ -8,7 +8,7 @@ use raycaster_lib::{
     premade::parse::from_file,
     render::{ParalelRenderer, RenderOptions, RendererFront, RendererMessage, SerialRenderer},
     volumetric::{volumes::*, Blocked, BuildVolume, DataSource, MemoryType, StorageShape, Volume},
-    ParserFn, PerspectiveCamera, TF,
+    Error, ParserFn, PerspectiveCamera, TF,
 };
 use std::{path::Path, time::Instant};
 
@@ -168,7 +168,7 @@ impl RenderState {
         path: &Path,
         parser: PrewrittenParser,
         mem_type: PickedMemoryType,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), Error> {
         // todo no params are needed
         print!(
             "GUI: starting renderer: MT {} | ERT {} | EI {} | ",
@@ -276,7 +276,7 @@ fn volume_setup_paralel<V>(
     render_options: RenderOptions,
     tf: PrewrittenTF,
     memory: MemoryType,
-) -> Result<ParalelRenderer<V>, &'static str>
+) -> Result<ParalelRenderer<V>, Error>
 where
     V: Volume + Blocked + BuildVolume<u8> + 'static,
 {
@@ -312,7 +312,7 @@ fn volume_setup_linear<V>(
     render_options: RenderOptions,
     tf: PrewrittenTF,
     memory: MemoryType,
-) -> Result<SerialRenderer<V>, &'static str>
+) -> Result<SerialRenderer<V>, Error>
 where
     V: Volume + BuildVolume<u8>,
 {
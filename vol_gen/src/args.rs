@@ -50,6 +50,7 @@ pub fn is_float_number(num: &str) -> Result<(), String> {
 
 const GENERATOR_NAMES: &[&str] = &["shapes", "noise", "solid"];
 const LAYOUT_NAMES: &[&str] = &["linear", "z"];
+const HEADER_NAMES: &[&str] = &["v2", "v1"];
 
 pub fn get_command<'a>() -> Command<'a> {
     Command::new("Vol-gen")
@@ -107,6 +108,37 @@ pub fn get_command<'a>() -> Command<'a> {
                 .value_name("SHAPE")
                 .possible_values(LAYOUT_NAMES),
         )
+        .arg(
+            Arg::new("header")
+                .help("Format of header")
+                .long("header")
+                .default_value("v2")
+                .value_name("VERSION")
+                .possible_values(HEADER_NAMES),
+        )
+        .arg(
+            Arg::new("checksum")
+                .help("Write checksum of samples into header (v2 only)")
+                .long("checksum"),
+        )
+        .arg(
+            Arg::new("compress")
+                .help("Compress blocks independently (z layout, v2 only)")
+                .long("compress"),
+        )
+        .arg(
+            Arg::new("sparse-blocks")
+                .help("Store uniform blocks as a single value (z layout, v2 only)")
+                .long("sparse-blocks"),
+        )
+        .arg(
+            Arg::new("lod")
+                .help("Levels of detail written after blocks (z layout, v2 only)")
+                .long("lod")
+                .value_name("LEVELS")
+                .default_value("1")
+                .validator(|s| is_positive_number(s).and(can_fit_u8(s))),
+        )
         .arg(
             Arg::new("seed")
                 .help("Seed for RNG, leave out for random seed")
@@ -0,0 +1,165 @@
+/*
+    vol_gen
+    Author: Michal Majer
+    Date: 2022-05-05
+*/
+
+//! Encoding of generated blocks, compressed or sparse
+
+use raycaster_lib::{
+    premade::parse::VOL_SPARSE_UNIFORM,
+    volumetric::{compress_block, uniform_value},
+};
+use rayon::{iter::ParallelIterator, slice::ParallelSlice};
+
+/// Encodes samples of Z order blocks, as they are generated.
+/// Samples of unfinished block are kept until the block is complete.
+pub trait BlockEncoder {
+    /// Add samples, returns encoded bytes of completed blocks
+    fn push(&mut self, samples: &[u8]) -> Vec<u8>;
+
+    /// Table of blocks, written after header
+    fn finish(self: Box<Self>) -> Vec<u64>;
+}
+
+/// Encoder of blocks, if enabled by config
+pub fn block_encoder(
+    compress: bool,
+    sparse_blocks: bool,
+    side: u8,
+) -> Option<Box<dyn BlockEncoder>> {
+    match (compress, sparse_blocks) {
+        (true, _) => Some(Box::new(BlockCompressor::new(side))),
+        (_, true) => Some(Box::new(SparseEncoder::new(side))),
+        _ => None,
+    }
+}
+
+/// Compresses samples block by block
+pub struct BlockCompressor {
+    block_len: usize,
+    pending: Vec<u8>,
+    /// Start of every written block, followed by the end of data
+    offsets: Vec<u64>,
+}
+
+impl BlockCompressor {
+    pub fn new(side: u8) -> BlockCompressor {
+        BlockCompressor {
+            block_len: (side as usize).pow(3),
+            pending: vec![],
+            offsets: vec![0],
+        }
+    }
+}
+
+impl BlockEncoder for BlockCompressor {
+    fn push(&mut self, samples: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(samples);
+        let complete = self.pending.len() / self.block_len * self.block_len;
+
+        let blocks: Vec<Vec<u8>> = self.pending[..complete]
+            .par_chunks(self.block_len)
+            .map(compress_block)
+            .collect();
+        self.pending.drain(..complete);
+
+        let mut end = *self.offsets.last().unwrap();
+        for block in &blocks {
+            end += block.len() as u64;
+            self.offsets.push(end);
+        }
+        blocks.concat()
+    }
+
+    /// Offsets of blocks relative to the start of data, followed by the end of data
+    fn finish(self: Box<Self>) -> Vec<u64> {
+        assert!(self.pending.is_empty(), "Unfinished block");
+        self.offsets
+    }
+}
+
+/// Drops uniform blocks, they are kept in table of blocks as a single value
+pub struct SparseEncoder {
+    block_len: usize,
+    pending: Vec<u8>,
+    /// Entry of every finished block
+    table: Vec<u64>,
+    stored: u64,
+}
+
+impl SparseEncoder {
+    pub fn new(side: u8) -> SparseEncoder {
+        SparseEncoder {
+            block_len: (side as usize).pow(3),
+            pending: vec![],
+            table: vec![],
+            stored: 0,
+        }
+    }
+}
+
+impl BlockEncoder for SparseEncoder {
+    fn push(&mut self, samples: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(samples);
+        let complete = self.pending.len() / self.block_len * self.block_len;
+
+        let mut stored = vec![];
+        for block in self.pending[..complete].chunks(self.block_len) {
+            match uniform_value(block) {
+                Some(value) => self.table.push(VOL_SPARSE_UNIFORM | value as u64),
+                None => {
+                    self.table.push(self.stored);
+                    self.stored += 1;
+                    stored.extend_from_slice(block);
+                }
+            }
+        }
+        self.pending.drain(..complete);
+        stored
+    }
+
+    /// Entries of blocks, see `raycaster_lib::premade::parse::VOL_SPARSE_UNIFORM`
+    fn finish(self: Box<Self>) -> Vec<u64> {
+        assert!(self.pending.is_empty(), "Unfinished block");
+        self.table
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use raycaster_lib::volumetric::decompress_block;
+
+    #[test]
+    fn blocks_split_across_pushes() {
+        let mut compressor = Box::new(BlockCompressor::new(2));
+        let mut data = compressor.push(&[1; 5]);
+        assert!(data.is_empty());
+        data.extend(compressor.push(&[2; 11]));
+
+        let offsets = compressor.finish();
+        assert_eq!(offsets.len(), 3);
+        assert_eq!(offsets[2], data.len() as u64);
+
+        let mut block = [0_u8; 8];
+        decompress_block(&data[offsets[1] as usize..], &mut block).unwrap();
+        assert_eq!(block, [2; 8]);
+    }
+
+    #[test]
+    fn sparse_stores_varying_blocks() {
+        let mut encoder = block_encoder(false, true, 2).unwrap();
+        let mut samples = [4; 24];
+        samples[10] = 5;
+        let mut data = encoder.push(&samples[..12]);
+        data.extend(encoder.push(&samples[12..]));
+
+        assert_eq!(data, &samples[8..16]);
+        assert_eq!(
+            encoder.finish(),
+            [VOL_SPARSE_UNIFORM | 4, 0, VOL_SPARSE_UNIFORM | 4]
+        );
+    }
+}
@@ -8,6 +8,7 @@ use std::{ffi::OsString, str::FromStr};
 
 use clap::ArgMatches;
 use nalgebra::{vector, Vector3};
+use raycaster_lib::volumetric::lod_level_count;
 
 use crate::{header::HeaderFormat, orders::SampleOrder};
 
@@ -46,6 +47,14 @@ pub struct Config {
     pub sparse_file: bool,
     /// Optional seed for RNG, to replicate results
     pub seed: Option<u64>,
+    /// Write checksum of samples into header
+    pub checksum: bool,
+    /// Levels of detail written after full resolution blocks, 1 means none
+    pub lod_levels: u8,
+    /// Compress blocks independently
+    pub compress: bool,
+    /// Store uniform blocks as a single value
+    pub sparse_blocks: bool,
 }
 
 impl Config {
@@ -57,7 +66,15 @@ impl Config {
         // Generator
         let generator = GeneratorConfig::from_args(&args);
         // Header
-        let header_format = HeaderFormat::Default;
+        let header_format = match args.value_of("header").unwrap() {
+            "v1" => HeaderFormat::V1,
+            "v2" => HeaderFormat::V2,
+            _ => panic!("Error parsing header format"),
+        };
+        let checksum = args.is_present("checksum");
+        if checksum && header_format == HeaderFormat::V1 {
+            return Err("checksum requires header v2".into());
+        }
         // Order linear/z
         let layout = args.value_of("layout").unwrap();
         let save_buffer_order = match layout {
@@ -79,6 +96,49 @@ impl Config {
             }
             _ => panic!("Error parsing buffer orser"),
         };
+        // Levels of detail
+        let lod_levels: u8 = args.value_of("lod").unwrap().parse().unwrap();
+        if lod_levels > 1 {
+            let side = match save_buffer_order {
+                SampleOrder::Z(side) => side,
+                SampleOrder::Linear => return Err("lod requires z layout".into()),
+            };
+            if header_format == HeaderFormat::V1 {
+                return Err("lod requires header v2".into());
+            }
+            let max_levels = lod_level_count(side as usize);
+            if lod_levels > max_levels {
+                return Err(format!(
+                    "block-size {side} allows at most {max_levels} levels of detail"
+                ));
+            }
+        }
+        // Compression
+        let compress = args.is_present("compress");
+        if compress {
+            if !matches!(save_buffer_order, SampleOrder::Z(_)) {
+                return Err("compress requires z layout".into());
+            }
+            if header_format == HeaderFormat::V1 {
+                return Err("compress requires header v2".into());
+            }
+            if lod_levels > 1 {
+                return Err("compress cannot be combined with lod".into());
+            }
+        }
+        // Sparse blocks
+        let sparse_blocks = args.is_present("sparse-blocks");
+        if sparse_blocks {
+            if !matches!(save_buffer_order, SampleOrder::Z(_)) {
+                return Err("sparse-blocks requires z layout".into());
+            }
+            if header_format == HeaderFormat::V1 {
+                return Err("sparse-blocks requires header v2".into());
+            }
+            if lod_levels > 1 || compress {
+                return Err("sparse-blocks cannot be combined with lod or compress".into());
+            }
+        }
         // File name
         let file_name = args.value_of_os("output-file").unwrap().into(); // Unwrap safe, has default value
                                                                          // Sparse
@@ -95,6 +155,10 @@ impl Config {
             file_name,
             sparse_file,
             seed,
+            checksum,
+            lod_levels,
+            compress,
+            sparse_blocks,
         })
     }
 }
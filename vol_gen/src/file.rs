@@ -16,7 +16,11 @@ pub fn open_create_file<P>(path: P) -> Result<File, std::io::Error>
 where
     P: AsRef<Path>,
 {
-    let file = OpenOptions::new().write(true).create(true).open(path);
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path);
 
     file
 }
@@ -8,17 +8,25 @@
 //!
 //! Types implmenting `SampleGenerator` can be used to generate volumes
 
-use std::{error::Error, io::Write};
+use std::{error::Error, fs::File, io::Write};
 
 use indicatif::ProgressBar;
 use nalgebra::Vector3;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use raycaster_lib::{
+    premade::parse::{VolChecksum, VOL_HEADER_LEN},
+    volumetric::{downsample_block, DataSource, StorageShape},
+};
+use rayon::{
+    iter::{IntoParallelRefIterator, ParallelIterator},
+    slice::ParallelSlice,
+};
 
 use crate::{
+    compress::block_encoder,
     config::{Config, GeneratorConfig},
     file::open_create_file,
     generators::{shapes::ShapesGenerator, solid::SolidGenerator},
-    header::generate_header,
+    header::{generate_header, write_block_table, write_checksum},
     orders::{LinearCoordIterator, OrderGenerator, SampleOrder, ZCoordIterator},
 };
 
@@ -63,6 +71,12 @@ pub fn generate_order<SG: SampleGenerator, OG: OrderGenerator>(
         return Err("Writing header error".into());
     }
 
+    let mut checksum = VolChecksum::new();
+    let mut encoder = match config.save_buffer_order {
+        SampleOrder::Z(side) => block_encoder(config.compress, config.sparse_blocks, side),
+        SampleOrder::Linear => None,
+    };
+
     loop {
         // Get batch of coordinates
         let mut batch = vec![];
@@ -85,16 +99,70 @@ pub fn generate_order<SG: SampleGenerator, OG: OrderGenerator>(
             .map(|&pos| sample_generator.sample_at(pos))
             .collect();
 
+        let output_samples = match &mut encoder {
+            Some(encoder) => encoder.push(&output_samples),
+            None => output_samples,
+        };
+
         // Write into file
         let written = file.write(&output_samples)?;
         if written != output_samples.len() {
             return Err("Writing error".into());
         }
+        checksum.update(&output_samples);
 
         let (current, _) = ord_iter.get_progress();
         bar.set_position(current);
     }
 
+    if let Some(encoder) = encoder {
+        write_block_table(&mut file, &encoder.finish())?;
+    }
+
+    if let SampleOrder::Z(side) = config.save_buffer_order {
+        if config.lod_levels > 1 {
+            write_lod_levels(&mut file, config, side, &mut checksum)?;
+        }
+    }
+
+    if config.checksum {
+        write_checksum(&mut file, checksum.finish())?;
+    }
+
+    Ok(())
+}
+
+/// Append levels of detail after full resolution blocks
+/// Levels are computed from blocks already written into file.
+fn write_lod_levels(
+    file: &mut File,
+    config: &Config,
+    side: u8,
+    checksum: &mut VolChecksum,
+) -> Result<(), Box<dyn Error>> {
+    let block_len = (side as usize).pow(3);
+    let level0_len = StorageShape::Z(side).sample_count(config.dims.map(|v| v as usize))?;
+
+    let mapped: DataSource<u8> = DataSource::from_file(&config.file_name)?;
+    let level0 = &mapped.get_slice()[VOL_HEADER_LEN..VOL_HEADER_LEN + level0_len];
+
+    for level in 1..config.lod_levels {
+        println!("Writing level of detail {level}");
+        let bar = ProgressBar::new((level0_len / block_len) as u64);
+
+        for batch in level0.chunks(block_len * 256) {
+            let output_samples: Vec<u8> = batch
+                .par_chunks(block_len)
+                .map(|block| downsample_block(block, side as usize, level))
+                .collect::<Vec<_>>()
+                .concat();
+
+            file.write_all(&output_samples)?;
+            checksum.update(&output_samples);
+            bar.inc((batch.len() / block_len) as u64);
+        }
+    }
+
     Ok(())
 }
 
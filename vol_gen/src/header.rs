@@ -4,19 +4,32 @@
     Date: 2022-05-05
 */
 
+use std::io::{Seek, SeekFrom, Write};
+
 use byteorder::{ByteOrder, LittleEndian};
+use nalgebra::point;
+use raycaster_lib::{
+    premade::parse::{
+        Endian, SampleType, VolHeader, VOL_FLAGS_OFFSET, VOL_HEADER_LEN, VOL_VERSION,
+    },
+    volumetric::StorageShape,
+};
 
 use crate::{config::Config, orders::SampleOrder};
 
 // Describe header
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HeaderFormat {
-    Default,
+    /// Original header, without magic and version
+    V1,
+    /// Versioned header, see `raycaster_lib::premade::parse::VolHeader`
+    V2,
 }
 
 pub fn generate_header(cfg: &Config) -> Vec<u8> {
     match cfg.header_format {
-        HeaderFormat::Default => generate_default_header(cfg),
+        HeaderFormat::V1 => generate_default_header(cfg),
+        HeaderFormat::V2 => generate_v2_header(cfg),
     }
 }
 /// Length of default header
@@ -60,3 +73,169 @@ fn generate_default_header(cfg: &Config) -> Vec<u8> {
 
     vec
 }
+
+/// Version 2 header
+///
+/// Samples are `u8`, blocks of Z order overlap by one sample.
+/// Checksum is not known yet, it is flagged as present and written by `write_checksum` after samples.
+/// Table of compressed or sparse blocks is zeroed, it is written by `write_block_table`.
+fn generate_v2_header(cfg: &Config) -> Vec<u8> {
+    let (data_shape, block_overlap) = match cfg.save_buffer_order {
+        SampleOrder::Linear => (StorageShape::Linear, 0),
+        SampleOrder::Z(s) => (StorageShape::Z(s), 1),
+    };
+
+    let mut header = VolHeader {
+        version: VOL_VERSION,
+        sample_type: SampleType::U8,
+        endian: Endian::Little,
+        size: cfg.dims.map(|v| v as usize),
+        scale: cfg.cell_shape,
+        position: point![0.0, 0.0, 0.0],
+        data_shape,
+        block_overlap,
+        lod_levels: cfg.lod_levels.max(1),
+        checksum: cfg.checksum.then_some(0),
+        block_offsets: None,
+        sparse_table: None,
+        header_length: VOL_HEADER_LEN,
+    };
+    if cfg.compress {
+        // Validated by config, compression needs Z order
+        let blocks = header.block_count().unwrap();
+        header.block_offsets = Some(vec![0; blocks + 1]);
+    }
+    if cfg.sparse_blocks {
+        // Validated by config, sparse blocks need Z order
+        let blocks = header.block_count().unwrap();
+        header.sparse_table = Some(vec![0; blocks]);
+    }
+    header.to_bytes()
+}
+
+/// Write table of compressed or sparse blocks into version 2 header at the start of `writer`
+/// Position of `writer` is moved to the end
+pub fn write_block_table<W: Write + Seek>(writer: &mut W, table: &[u64]) -> std::io::Result<()> {
+    writer.seek(SeekFrom::Start(VOL_HEADER_LEN as u64))?;
+    for entry in table {
+        writer.write_all(&entry.to_le_bytes())?;
+    }
+    writer.seek(SeekFrom::End(0))?;
+    Ok(())
+}
+
+/// Write checksum of samples into version 2 header at the start of `writer`
+/// Position of `writer` is moved to the end
+pub fn write_checksum<W: Write + Seek>(writer: &mut W, checksum: u64) -> std::io::Result<()> {
+    // Checksum follows flags, the flag is set by header
+    writer.seek(SeekFrom::Start(VOL_FLAGS_OFFSET as u64 + 1))?;
+    writer.write_all(&checksum.to_le_bytes())?;
+    writer.seek(SeekFrom::End(0))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::config::GeneratorConfig;
+    use nalgebra::vector;
+    use raycaster_lib::{
+        premade::parse::{VolChecksum, VOL_SPARSE_UNIFORM},
+        volumetric::DataSource,
+    };
+    use std::io::Cursor;
+
+    fn config(header_format: HeaderFormat, save_buffer_order: SampleOrder) -> Config {
+        Config {
+            dims: vector![4, 5, 6],
+            cell_shape: vector![1.0, 0.5, 2.0],
+            generator: GeneratorConfig::Solid { sample: 1 },
+            header_format,
+            save_buffer_order,
+            file_name: "a.vol".into(),
+            sparse_file: false,
+            seed: None,
+            checksum: true,
+            lod_levels: 1,
+            compress: false,
+            sparse_blocks: false,
+        }
+    }
+
+    #[test]
+    fn v1_readable() {
+        let cfg = config(HeaderFormat::V1, SampleOrder::Z(4));
+        let header = VolHeader::parse(&generate_header(&cfg)).unwrap();
+
+        assert_eq!(header.version, 1);
+        assert_eq!(header.size, vector![4, 5, 6]);
+        assert_eq!(header.data_shape, StorageShape::Z(4));
+    }
+
+    #[test]
+    fn v2_checksum() {
+        let cfg = config(HeaderFormat::V2, SampleOrder::Linear);
+        let samples = [3; 4 * 5 * 6];
+        let mut checksum = VolChecksum::new();
+        checksum.update(&samples);
+
+        let mut file = Cursor::new(generate_header(&cfg));
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(&samples).unwrap();
+        write_checksum(&mut file, checksum.finish()).unwrap();
+
+        let header = VolHeader::parse(file.get_ref()).unwrap();
+        assert_eq!(header.version, VOL_VERSION);
+        assert_eq!(header.scale, vector![1.0, 0.5, 2.0]);
+        assert_eq!(header.checksum, Some(checksum.finish()));
+        assert!(header
+            .metadata::<u8>(DataSource::Vec(file.into_inner()))
+            .is_ok());
+    }
+
+    #[test]
+    fn v2_lod_levels() {
+        let mut cfg = config(HeaderFormat::V2, SampleOrder::Z(2));
+        cfg.lod_levels = 2;
+        let header = VolHeader::parse(&generate_header(&cfg)).unwrap();
+
+        assert_eq!(header.lod_levels, 2);
+        assert!(header.metadata::<u8>(DataSource::Vec(vec![])).is_err());
+    }
+
+    #[test]
+    fn v2_checksum_keeps_compression() {
+        let mut cfg = config(HeaderFormat::V2, SampleOrder::Z(4));
+        cfg.compress = true;
+
+        let mut file = Cursor::new(generate_header(&cfg));
+        write_checksum(&mut file, 5).unwrap();
+
+        let header = VolHeader::parse(file.get_ref()).unwrap();
+        assert_eq!(header.checksum, Some(5));
+        assert!(header.block_offsets.is_some());
+    }
+
+    #[test]
+    fn v2_sparse_blocks() {
+        let mut cfg = config(HeaderFormat::V2, SampleOrder::Z(4));
+        cfg.sparse_blocks = true;
+        cfg.checksum = false;
+
+        // 4x5x6 is 1x2x2 blocks, one of them stored
+        let mut table = vec![VOL_SPARSE_UNIFORM | 1; 4];
+        table[3] = 0;
+        let mut file = Cursor::new(generate_header(&cfg));
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(&[2; 64]).unwrap();
+        write_block_table(&mut file, &table).unwrap();
+
+        let header = VolHeader::parse(file.get_ref()).unwrap();
+        assert_eq!(header.sparse_table.as_ref(), Some(&table));
+        let meta = header
+            .metadata::<u8>(DataSource::Vec(file.into_inner()))
+            .unwrap();
+        assert_eq!(meta.sparse_blocks.unwrap().stored_count(), 1);
+    }
+}
@@ -11,6 +11,11 @@
 //! * `-d, --dims=<X>,<Y>,<Z>` - Dimensions of volume
 //! * `-g, --generator <NAME>` - Type of generator [possible values: `shapes`, `noise`, `solid`]
 //! * `-h, --help` - Print help information
+//! * `--header <VERSION>` - Format of header [default: `v2`] [possible values: `v2`, `v1`]
+//! * `--checksum` - Write checksum of samples into header (v2 only)
+//! * `--compress` - Compress blocks independently (z layout, v2 only)
+//! * `--sparse-blocks` - Store uniform blocks as a single value (z layout, v2 only)
+//! * `--lod <LEVELS>` - Levels of detail written after blocks [default: 1] (z layout, v2 only)
 //! * `-l, --layout <SHAPE>` - Layout of samples in memory [default: linear] [possible values: `linear`, `z`]
 //! * `-o, --output-file <FILE>` - File name to output [default: `a.vol`]
 //! * `-s, --shape=<X>,<Y>,<Z>` - Shape of cell [default: 1 1 1]
@@ -18,6 +23,7 @@
 use config::Config;
 
 mod args;
+mod compress;
 mod config;
 mod file;
 mod generators;